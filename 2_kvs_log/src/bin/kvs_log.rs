@@ -1,12 +1,19 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use log;
 use simple_logger;
-use std::path::Path;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 use std::time;
 
+use kvs_common::export;
+use rust_kvs_log::histogram::Histogram;
 use rust_kvs_log::kv_log::KvStore;
 use rust_kvs_log::models::Result;
 
+/// Number of imported records written with a single sync.
+const IMPORT_BATCH_SIZE: usize = 1000;
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
@@ -39,11 +46,79 @@ enum Commands {
     },
     /// Reset storage by removing all of the stored values
     Reset {},
+    /// Compact the log files by dropping the overwritten and removed values
+    Compact {},
     /// Benchmark storage operations speed by running many get and set operations
     Benchmark {
         /// Number of operations to run during the benchmark.
         operations_count: u32,
     },
+    /// Export all the key/value pairs to the file `file`
+    Export {
+        /// File to write the pairs to
+        file: PathBuf,
+        /// File format
+        #[arg(short, long, default_value = "json")]
+        format: Format,
+    },
+    /// Import key/value pairs from the file `file`. Existing keys are overwritten.
+    Import {
+        /// File to read the pairs from
+        file: PathBuf,
+        /// File format
+        #[arg(short, long, default_value = "json")]
+        format: Format,
+    },
+}
+
+#[derive(Clone, ValueEnum)]
+enum Format {
+    /// A JSON object mapping keys to values
+    Json,
+    /// CSV with a key,value header
+    Csv,
+}
+
+impl From<Format> for export::Format {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Json => export::Format::Json,
+            Format::Csv => export::Format::Csv,
+        }
+    }
+}
+
+fn export_records(storage: &KvStore, file: &Path, format: export::Format) -> Result<usize> {
+    let mut writer = BufWriter::new(File::create(file)?);
+    let records = storage.keys().map(|key| {
+        let value = storage.get(key.clone())?.unwrap_or_default();
+        Ok((key.clone(), value))
+    });
+    export::write_records(&mut writer, format, records)
+}
+
+fn import_records(storage: &mut KvStore, file: &Path, format: export::Format) -> Result<usize> {
+    let reader = BufReader::new(File::open(file)?);
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let count = export::read_records(reader, format, |key, value| {
+        batch.push((key, value));
+        if batch.len() == IMPORT_BATCH_SIZE {
+            storage.set_batch(std::mem::take(&mut batch))?;
+        }
+        Ok(())
+    })?;
+    storage.set_batch(batch)?;
+    Ok(count)
+}
+
+/// Prints the total time and the latency percentiles of the benchmark operations.
+fn print_timings(name: &str, operations_count: u32, total_elapsed: time::Duration, timings: &Histogram) {
+    println!(
+        "Running {} {} commands. Total: {}ms. Avg {:.1}us; Min {}us; p50 {}us; p95 {}us; p99 {}us; p999 {}us; Max {}us.",
+        operations_count, name, total_elapsed.as_millis(), timings.mean(), timings.min(),
+        timings.value_at_percentile(50.0), timings.value_at_percentile(95.0),
+        timings.value_at_percentile(99.0), timings.value_at_percentile(99.9), timings.max(),
+    );
 }
 
 fn benchmark(storage: &mut KvStore, operations_count: u32) -> Result<()> {
@@ -52,35 +127,25 @@ fn benchmark(storage: &mut KvStore, operations_count: u32) -> Result<()> {
         keys_to_insert.push(format!("key{}", i).to_string());
     }
 
-    let mut set_timings = Vec::new();
+    let mut set_timings = Histogram::new();
     let start_set_total = time::Instant::now();
     for key in &keys_to_insert {
         let key_to_set = key.clone();
         let single_set_start = time::Instant::now();
         storage.set(key_to_set, "value".to_string())?;
-        set_timings.push(single_set_start.elapsed().as_millis());
+        set_timings.record(single_set_start.elapsed().as_micros() as u64);
     }
-    let total_set_elapsed = start_set_total.elapsed().as_millis();
+    print_timings("set", operations_count, start_set_total.elapsed(), &set_timings);
 
-    let set_avg = set_timings.iter().sum::<u128>() / set_timings.len() as u128;
-    let set_min = set_timings.iter().min().unwrap();
-    let set_max = set_timings.iter().max().unwrap();
-    print!("Running {} set commands. Total: {}ms. Avg {}ms; Min {}ms; Max {}ms.\n", operations_count, total_set_elapsed, set_avg, set_min, set_max);
-
-    let mut get_timings = Vec::new();
+    let mut get_timings = Histogram::new();
     let start_get_total = time::Instant::now();
     for key in &keys_to_insert {
         let key_to_get = key.clone();
         let single_get_start = time::Instant::now();
         storage.get(key_to_get)?;
-        get_timings.push(single_get_start.elapsed().as_millis());
+        get_timings.record(single_get_start.elapsed().as_micros() as u64);
     }
-    let total_get_elapsed = start_get_total.elapsed().as_millis();
-
-    let get_avg = get_timings.iter().sum::<u128>() / get_timings.len() as u128;
-    let get_min = get_timings.iter().min().unwrap();
-    let get_max = get_timings.iter().max().unwrap();
-    print!("Running {} get commands. Total: {}ms. Avg {}ms; Min {}ms; Max {}ms.\n", operations_count, total_get_elapsed, get_avg, get_min, get_max);
+    print_timings("get", operations_count, start_get_total.elapsed(), &get_timings);
 
     Ok(())
 }
@@ -114,6 +179,9 @@ fn main() -> Result<()>{
         Some(Commands::Reset {}) => {
             store.reset()?;
         },
+        Some(Commands::Compact {}) => {
+            store.compact()?;
+        },
         Some(Commands::Benchmark { operations_count }) => {
             if operations_count == 0 {
                 eprintln!("operations_count must be positive.");
@@ -121,6 +189,14 @@ fn main() -> Result<()>{
             }
             benchmark(&mut store, operations_count)?;
         },
+        Some(Commands::Export { file, format }) => {
+            let count = export_records(&store, &file, format.into())?;
+            log::info!("{} records exported to {}", count, file.display());
+        },
+        Some(Commands::Import { file, format }) => {
+            let count = import_records(&mut store, &file, format.into())?;
+            log::info!("{} records imported from {}", count, file.display());
+        },
         None => {
             eprintln!("Use --help for usage information.");
             std::process::exit(1);
@@ -98,9 +98,12 @@ impl KvStore {
             log::info!("No files to compact!");
             return Ok(());
         }
+        self.compact_file(self.files.len() - 1)
+    }
 
-        let file_path = &self.active_file;
-        let file_idx = self.files.len() - 1;
+    /// Compacts the log file with the index `file_idx`.
+    fn compact_file(&mut self, file_idx: usize) -> Result<()> {
+        let file_path = &self.files[file_idx].clone();
         log::info!("Compacting log file {}", file_path.display());
         let mut log_file_commands: Vec<Command> = Vec::new();
 
@@ -127,7 +130,7 @@ impl KvStore {
                         match self.storage_index.get(&key) {
                             Some(position) => {
                                 let value_offset = file_offset + value_offset_opt.unwrap_or(0);
-                                if value_offset == position.file_offset {
+                                if position.file_idx == file_idx && value_offset == position.file_offset {
                                     log_file_commands.push(Command::Set { key, value });
                                     continue;
                                 }
@@ -214,6 +217,17 @@ impl KvStore {
         Ok(())
     }
 
+    /// Compacts all the log files on demand, including the active one. The files are compacted from the oldest
+    /// to the newest, so the overwritten and removed values are dropped before the remove records hiding them.
+    pub fn compact(&mut self) -> Result<()> {
+        for file_idx in 0..self.files.len() {
+            if self.files[file_idx].exists() {
+                self.compact_file(file_idx)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Sets active file path to the next value.
     fn rotate_file(&mut self) -> Result<()> {
         self.compact_log_file()?;
@@ -225,7 +239,8 @@ impl KvStore {
 
     /// Writes a command to the log storage.
     /// If the command contains a value, it's position is returned.
-    fn write(&mut self, cmd: Command) -> Result<Option<KvStorePosition>> {
+    /// With `sync` unset the active file is not synced, see `sync_active_file`.
+    fn write(&mut self, cmd: Command, sync: bool) -> Result<Option<KvStorePosition>> {
         let serialized_command = serialize::serialize(&cmd);
         let command_size = serialized_command.len() as u64;
         if command_size > MAX_SEGMENT_SIZE {
@@ -244,6 +259,10 @@ impl KvStore {
             // If the current active file exceeds max allowed size - try writing to the next file.
             let file_size = File::metadata(&file)?.len();
             if file_size + command_size > MAX_SEGMENT_SIZE {
+                // The file is not written anymore, so the unsynced records are synced before the rotation.
+                if !sync {
+                    file.sync_data()?;
+                }
                 self.rotate_file()?;
                 file_idx += 1;
                 continue;
@@ -265,7 +284,9 @@ impl KvStore {
                     )
                 );
             }
-            file.sync_data()?;
+            if sync {
+                file.sync_data()?;
+            }
             data_is_written = true;
         }
 
@@ -355,17 +376,35 @@ impl KvStore {
 
     /// Set key `key` to value `value`.
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
-        let pos = self.write(Command::Set { key: key.clone(), value: value })?.unwrap();
+        let pos = self.write(Command::Set { key: key.clone(), value: value }, true)?.unwrap();
         self.storage_index.insert(key, pos);
         Ok(())
     }
 
+    /// Sets multiple keys with a single sync of the log file.
+    /// The batch is not atomic: the keys written before a failure stay set.
+    pub fn set_batch(&mut self, values: Vec<(String, String)>) -> Result<()> {
+        for (key, value) in values {
+            let pos = self.write(Command::Set { key: key.clone(), value }, false)?.unwrap();
+            self.storage_index.insert(key, pos);
+        }
+        self.sync_active_file()
+    }
+
+    /// Syncs the active log file to the disk.
+    fn sync_active_file(&self) -> Result<()> {
+        if self.active_file.exists() {
+            OpenOptions::new().append(true).open(&self.active_file)?.sync_data()?;
+        }
+        Ok(())
+    }
+
     /// Removes key `key` from the storage.
     /// Returns `true` if the key existed.
     pub fn remove(&mut self, key: String) -> Result<bool> {
         match self.storage_index.remove(&key) {
             Some(_) => {
-                self.write(Command::Remove { key: key })?;
+                self.write(Command::Remove { key: key }, true)?;
                 return Ok(true);
             },
             None => {
@@ -385,6 +424,11 @@ impl KvStore {
         }
     }
 
+    /// Returns an iterator over the stored keys in arbitrary order.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.storage_index.keys()
+    }
+
     /// Removes all records in the storage.
     pub fn reset(&mut self) -> Result<()> {
         for file_path in &self.files {
@@ -1,5 +1,6 @@
 pub use kv_log::KvStore;
 pub use models::{Command, Result};
+pub use kvs_common::histogram;
 
 pub mod kv_log;
 pub mod models;
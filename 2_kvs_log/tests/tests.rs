@@ -332,3 +332,126 @@ fn compaction() -> Result<()> {
 
     panic!("No compaction detected");
 }
+
+// `kvs compact` should drop the overwritten and removed values and keep the latest ones.
+#[test]
+fn cli_compact() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for iter in 0..10 {
+        store.set("key1".to_owned(), format!("value{}", iter))?;
+        store.set("key2".to_owned(), format!("value{}", iter))?;
+    }
+    store.remove("key2".to_owned())?;
+    drop(store);
+    let log_path = temp_dir.path().join("kv_1.log");
+    let initial_size = std::fs::metadata(&log_path)?.len();
+
+    Command::cargo_bin("kvs_log")
+        .unwrap()
+        .args(&["compact"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+    assert!(std::fs::metadata(&log_path)?.len() < initial_size);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value9".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+// `kvs export` and `kvs import` should move all of the stored pairs between storages.
+#[test]
+fn cli_export_import() -> Result<()> {
+    for format in ["json", "csv"] {
+        let source_dir = TempDir::new().expect("unable to create temporary working directory");
+        let target_dir = TempDir::new().expect("unable to create temporary working directory");
+        let export_path = target_dir.path().join("export");
+
+        // The values are chosen to need escaping in both formats.
+        let values = [
+            ("key1", "value1"),
+            ("key,2", "value \"quoted\", with\nnew line"),
+            ("key3", "unicode \u{1F600} and \\ tab\t"),
+            ("", ""),
+        ];
+        let mut store = KvStore::open(source_dir.path())?;
+        for (key, value) in values {
+            store.set(key.to_owned(), value.to_owned())?;
+        }
+        drop(store);
+
+        Command::cargo_bin("kvs_log")
+            .unwrap()
+            .args(&["export", export_path.to_str().unwrap(), "--format", format])
+            .current_dir(&source_dir)
+            .assert()
+            .success()
+            .stdout(is_empty());
+
+        Command::cargo_bin("kvs_log")
+            .unwrap()
+            .args(&["import", export_path.to_str().unwrap(), "--format", format])
+            .current_dir(&target_dir)
+            .assert()
+            .success()
+            .stdout(is_empty());
+
+        let store = KvStore::open(target_dir.path())?;
+        for (key, value) in values {
+            assert_eq!(store.get(key.to_owned())?, Some(value.to_owned()));
+        }
+        assert_eq!(store.keys().count(), values.len());
+    }
+    Ok(())
+}
+
+// `kvs import` should fail on a malformed file.
+#[test]
+fn cli_import_invalid() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let import_path = temp_dir.path().join("import.json");
+    std::fs::write(&import_path, "{\"key1\": \"value1\", \"key2\"}").unwrap();
+
+    Command::cargo_bin("kvs_log")
+        .unwrap()
+        .args(&["import", import_path.to_str().unwrap()])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// Batched writes should be persisted and span several log files.
+#[test]
+fn set_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let values: Vec<(String, String)> = (0..100)
+        .map(|idx| (format!("key{}", idx), idx.to_string().repeat(100_000)))
+        .collect();
+    store.set_batch(values.clone())?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    for (key, value) in values {
+        assert_eq!(store.get(key)?, Some(value));
+    }
+    Ok(())
+}
+
+// `kvs benchmark <COUNT>` should print the latency percentiles of the set and get commands.
+#[test]
+fn cli_benchmark() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs_log")
+        .unwrap()
+        .args(&["benchmark", "100"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("Running 100 set commands"))
+        .stdout(contains("Running 100 get commands"))
+        .stdout(contains("p999"));
+}
@@ -2,11 +2,14 @@ use clap::{Parser, ValueEnum};
 use log;
 use simple_logger;
 
-use rust_kvs_server::{models, server, storage};
+use rust_kvs_server::{config, models, server, storage};
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_override_self = true)]
 struct Cli {
+    /// Read the options from a TOML file. The command line options override the file ones.
+    #[arg(long)]
+    config: Option<String>,
     /// Server hostname
     #[arg(short = 'H', long, default_value = "127.0.0.1")]
     host: String,
@@ -30,6 +33,8 @@ enum EngineType {
     Kvs,
     /// Sled storage
     Sled,
+    /// In-memory storage, the data is lost on the server stop
+    Memory,
 }
 
 impl std::fmt::Display for EngineType {
@@ -37,6 +42,7 @@ impl std::fmt::Display for EngineType {
         write!(f, "{}", match &self {
             EngineType::Kvs => "kvs",
             EngineType::Sled => "sled",
+            EngineType::Memory => "memory",
         })
     }
 }
@@ -50,7 +56,14 @@ enum LogLevel {
 }
 
 fn main() -> models::Result<()>{
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    if let Some(config_path) = &cli.config {
+        // The file options go first, so the command line options override them.
+        let mut args: Vec<std::ffi::OsString> = std::env::args_os().take(1).collect();
+        args.extend(config::config_args(std::path::Path::new(config_path))?);
+        args.extend(std::env::args_os().skip(1));
+        cli = Cli::parse_from(args);
+    }
 
     let log_level = match cli.log_level {
         LogLevel::Debug => log::LevelFilter::Debug,
@@ -66,9 +79,15 @@ fn main() -> models::Result<()>{
     let engine: Box<dyn storage::KVStorage> = match cli.engine {
         EngineType::Kvs => Box::new(storage::KvLogStorage::open(storage_path)?),
         EngineType::Sled => Box::new(storage::SledStorage::open(storage_path)?),
+        EngineType::Memory => Box::new(storage::MemStorage::new()),
     };
 
     let mut server = server::KvsServer::new(engine);
+
+    // Stop the server gracefully on SIGINT/SIGTERM.
+    let shutdown_handle = server.shutdown_handle();
+    ctrlc::set_handler(move || shutdown_handle.shutdown())?;
+
     server.listen(cli.host, cli.port)?;
 
     return Ok(());
@@ -2,6 +2,7 @@ pub use storage::{KVStorage, KvLogStorage};
 pub use models::{Command, Result};
 pub use server::KvsServer;
 pub use client::KvsClient;
+pub use kvs_common::config;
 
 pub mod storage;
 pub mod models;
@@ -18,7 +18,16 @@ macro_rules! impl_read_from_stream {
                     const TYPE_SIZE: usize = mem::size_of::<$t>();
                     let mut buffer = [0u8; TYPE_SIZE];
 
-                    let bytes_count = stream.read(&mut buffer)?;
+                    // A single read may return a part of the value, e.g. at the end of a buffered reader's buffer.
+                    let mut bytes_count = 0;
+                    while bytes_count < TYPE_SIZE {
+                        match stream.read(&mut buffer[bytes_count..]) {
+                            Ok(0) => break,
+                            Ok(count) => bytes_count += count,
+                            Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                            Err(err) => return Err(err),
+                        }
+                    }
                     if bytes_count != TYPE_SIZE {
                         return Err(
                             io::Error::new(
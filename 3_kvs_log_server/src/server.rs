@@ -1,6 +1,8 @@
 use std::net;
 use std::io;
 use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::models;
 use crate::serialize;
@@ -9,13 +11,66 @@ use crate::storage;
 
 const SERVER_VERSION: u8 = 1u8;
 
+/// A handle to stop a running `KvsServer` from another thread, e.g. a signal handler.
+/// Shutdown stops accepting new connections and interrupts the idle keep-alive connection.
+/// The request being handled at the moment is completed.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    local_addr: Arc<Mutex<Option<net::SocketAddr>>>,
+    /// A clone of the connection being handled, to interrupt it waiting for the next request.
+    connection: Arc<Mutex<Option<net::TcpStream>>>,
+}
+
+impl ShutdownHandle {
+    fn new() -> Self {
+        ShutdownHandle {
+            requested: Arc::new(AtomicBool::new(false)),
+            local_addr: Arc::new(Mutex::new(None)),
+            connection: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Requests the server shutdown. Returns immediately, `KvsServer::listen` returns once the shutdown is completed.
+    pub fn shutdown(&self) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        log::info!("Server shutdown requested");
+
+        if let Some(connection) = self.connection.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            let _ = connection.shutdown(net::Shutdown::Read);
+        }
+
+        // Wake up the listener blocked on accepting a new connection.
+        if let Some(mut addr) = *self.local_addr.lock().unwrap_or_else(|e| e.into_inner()) {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(net::Ipv4Addr::LOCALHOST.into());
+            }
+            if let Err(err) = net::TcpStream::connect(addr) {
+                log::warn!("Cannot wake up the server listener: {}", err);
+            }
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
 pub struct KvsServer {
     engine: Box<dyn storage::KVStorage>,
+    shutdown: ShutdownHandle,
 }
 
 impl KvsServer {
     pub fn new(engine: Box<dyn storage::KVStorage>) -> KvsServer {
-        KvsServer{ engine: engine }
+        KvsServer{ engine: engine, shutdown: ShutdownHandle::new() }
+    }
+
+    /// Returns a handle to stop the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
     }
 
     fn read_header(stream: &mut dyn io::Read) -> models::Result<models::RequestHeader> {
@@ -105,6 +160,11 @@ impl KvsServer {
         log::debug!("Handling incoming connection");
 
         loop {
+            if self.shutdown.is_requested() {
+                log::debug!("Server is stopping, close connection");
+                return Ok(())
+            }
+
             let mut reader = io::BufReader::new(stream);
             let header = Self::read_header(&mut reader)?;
             if header.version > SERVER_VERSION {
@@ -162,19 +222,43 @@ impl KvsServer {
         }
     }
 
+    /// Accepts and handles connections until the shutdown is requested via the `ShutdownHandle`.
+    /// Completes the request in progress and flushes the storage before returning.
     pub fn listen(&mut self, host: String, port: u32) -> models::Result<()> {
         let addr = format!("{}:{}", host, port);
         let listener = net::TcpListener::bind(addr)?;
+        *self.shutdown.local_addr.lock().unwrap_or_else(|e| e.into_inner()) = Some(listener.local_addr()?);
 
         for connection_result in listener.incoming() {
+            if self.shutdown.is_requested() {
+                break;
+            }
+
             match connection_result {
                 Ok(mut stream) => {
+                    // Keep a stream clone to interrupt the connection on shutdown.
+                    match stream.try_clone() {
+                        Ok(stream_clone) => {
+                            *self.shutdown.connection.lock().unwrap_or_else(|e| e.into_inner()) = Some(stream_clone);
+                        },
+                        Err(err) => {
+                            log::warn!("Cannot track connection for graceful shutdown: {}", err);
+                        }
+                    }
+                    // The shutdown might be requested before the connection is tracked.
+                    if self.shutdown.is_requested() {
+                        let _ = stream.shutdown(net::Shutdown::Read);
+                    }
+
                     match self.handle_connection(&mut stream) {
                         Ok(_) => {},
+                        // The connection waiting for the next request is interrupted by the shutdown.
+                        Err(_) if self.shutdown.is_requested() => {},
                         Err(err) => {
                             log::error!("Request handling error: {}", err);
                         }
                     }
+                    *self.shutdown.connection.lock().unwrap_or_else(|e| e.into_inner()) = None;
                     match stream.shutdown(std::net::Shutdown::Both) {
                         Ok(_) => {},
                         Err(err) => {
@@ -188,6 +272,9 @@ impl KvsServer {
             }
         }
 
+        drop(listener);
+        self.engine.flush()?;
+        log::info!("Server is stopped");
         Ok(())
     }
 }
@@ -1,3 +1,5 @@
+pub use kvs_common::engine::{check_engine, ENGINE_FILE_NAME};
+
 /// Base trait for a key value storage engines.
 pub trait KVStorage {
     /// Set key `key` to value `value`.
@@ -12,4 +14,7 @@ pub trait KVStorage {
 
     /// Removes all records in the storage.
     fn reset(&mut self) -> std::result::Result<(), Box<dyn std::error::Error>>;
+
+    /// Syncs the written data to the disk.
+    fn flush(&self) -> std::result::Result<(), Box<dyn std::error::Error>>;
 }
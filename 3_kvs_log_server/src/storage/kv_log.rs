@@ -9,8 +9,24 @@ use log;
 use crate::models::{Result, Command};
 use crate::serialize::{self, get_value_offset, ReadFromStream};
 use crate::KVStorage;
+use crate::storage::base::check_engine;
 
 const MAX_SEGMENT_SIZE: u64 = 4_000_000;
+/// File locked by the storage which has the directory open.
+const LOCK_FILE_NAME: &str = "LOCK";
+
+/// Takes an advisory lock of the storage directory, held until the returned file is closed.
+/// Fails if the directory is already open by another process or by another storage of this process.
+fn lock_storage_dir(path: &Path) -> Result<File> {
+    let lock_file = OpenOptions::new().create(true).truncate(false).write(true).open(path.join(LOCK_FILE_NAME))?;
+    match lock_file.try_lock() {
+        Ok(()) => Ok(lock_file),
+        Err(std::fs::TryLockError::WouldBlock) => {
+            Err(Box::from(format!("Storage {} is already in use", path.display())))
+        },
+        Err(std::fs::TryLockError::Error(err)) => Err(Box::new(err)),
+    }
+}
 
 /// A single value position index in the log storage.
 pub struct KvStorePosition {
@@ -24,6 +40,8 @@ pub struct KvLogStorage {
     storage_dir: PathBuf,
     files: Vec<PathBuf>,
     active_file: PathBuf,
+    /// Lock of the storage directory, released on drop. `None` if the storage is not opened from a directory.
+    _lock_file: Option<File>,
 }
 
 impl KvLogStorage {
@@ -33,6 +51,7 @@ impl KvLogStorage {
             storage_dir: path.to_path_buf(),
             files: Vec::new(),
             active_file: path.join("kv_1.log"),
+            _lock_file: None,
         }
     }
 
@@ -52,7 +71,46 @@ impl KvLogStorage {
         return dir_path.join(format!("_tmp_{}", file_name));
     }
 
+    /// Checks if the file is a temporary copy of a log file being compacted.
+    /// Such files are left in the storage directory if the process is killed in the middle of the compaction.
+    fn is_tmp_file(file_path: &Path) -> bool {
+        match file_path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) => file_name.starts_with("_tmp_"),
+            None => false,
+        }
+    }
+
     /// Restore storage index by reading a sorted list of log files.
+    /// Truncates the log file at the end of its last valid record if the file ends with a record torn by a crash,
+    /// so the storage opens with the complete records and appends after them. The record is torn if it cannot be read
+    /// and only zeros follow it. An invalid record followed by other data is an error.
+    fn truncate_torn_record(file_path: &Path) -> Result<()> {
+        let file = OpenOptions::new().read(true).write(true).open(file_path)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(&file);
+        let mut valid_size = 0;
+        loop {
+            match serialize::deserialize(&mut reader) {
+                Ok(Some(_)) => valid_size = reader.stream_position()?,
+                Ok(None) => return Ok(()),
+                Err(err) => {
+                    let mut rest = Vec::new();
+                    io::Read::read_to_end(&mut reader, &mut rest)?;
+                    if rest.iter().any(|byte| *byte != 0) {
+                        return Err(err);
+                    }
+                    log::warn!(
+                        "Invalid record at offset {} of {}: {}. Truncating the file, {} bytes are dropped",
+                        valid_size, file_path.display(), err, file_size - valid_size,
+                    );
+                    file.set_len(valid_size)?;
+                    file.sync_all()?;
+                    return Ok(());
+                },
+            }
+        }
+    }
+
     fn restore_index(files: &Vec<PathBuf>) -> Result<HashMap::<String, KvStorePosition>> {
         let mut index = HashMap::<String, KvStorePosition>::new();
 
@@ -127,7 +185,7 @@ impl KvLogStorage {
                         match self.storage_index.get(&key) {
                             Some(position) => {
                                 let value_offset = file_offset + value_offset_opt.unwrap_or(0);
-                                if value_offset == position.file_offset {
+                                if position.file_idx == file_idx && value_offset == position.file_offset {
                                     log_file_commands.push(Command::Set { key, value });
                                     continue;
                                 }
@@ -185,7 +243,6 @@ impl KvLogStorage {
                     )
                 );
             }
-            file_offset += bytes_written as u64;
             let value_offset = get_value_offset(&cmd).unwrap_or(0);
             
             // Insert new positions. We expect to see only "set" commands here.
@@ -198,6 +255,7 @@ impl KvLogStorage {
                 },
                 _ => {},
             }
+            file_offset += bytes_written as u64;
         }
         tmp_file.sync_all()?;
         let compacted_file_size = File::metadata(&tmp_file)?.len();
@@ -300,18 +358,28 @@ impl KvLogStorage {
     pub fn open(path: &Path) -> Result<KvLogStorage> {
         log::info!("Reading {} to restore storage", path.display());
         let mut file_paths = Vec::new();
+        let lock_file;
 
         // If the directory exists, read the existing storage files.
         if path.exists() {
             if !path.is_dir() {
                 return Err(Box::from(format!("Path {} is not a directory", path.display())));
             }
+            // The temporary files are removed under the lock, so the writes of another process are not affected.
+            lock_file = lock_storage_dir(path)?;
 
             // Read all files in the directory and store their paths in sorted order.
             match std::fs::read_dir(path) {
                 Ok(files) => {
                     for file_result in files {
                         if let Ok(file) = file_result {
+                            if Self::is_tmp_file(&file.path()) {
+                                log::warn!(
+                                    "Removing temporary file {} left by an interrupted compaction", file.path().display(),
+                                );
+                                remove_file(file.path())?;
+                                continue;
+                            }
                             if file.path().extension() == Some(OsStr::new("log")) {
                                 file_paths.push(file.path());
                             }
@@ -333,8 +401,14 @@ impl KvLogStorage {
                     return Err(Box::from(format!("Failed to create directory {}: {}", path.display(), e)));
                 }
             }
+            lock_file = lock_storage_dir(path)?;
         }
 
+        check_engine(path, "kvs")?;
+        // A crash in the middle of a write leaves a torn record at the end of the active log file.
+        if let Some(active_file) = file_paths.last() {
+            Self::truncate_torn_record(active_file)?;
+        }
         let storage_index = Self::restore_index(&file_paths)?;
         log::info!("Storage index is restored with {} records", storage_index.len());
 
@@ -354,6 +428,7 @@ impl KvLogStorage {
                 storage_dir: path.to_path_buf(),
                 files: file_paths,
                 active_file,
+                _lock_file: Some(lock_file),
             }
         )
     }
@@ -396,12 +471,29 @@ impl KVStorage for KvLogStorage {
     fn reset(&mut self) -> Result<()> {
         for file_path in &self.files {
             log::info!("Removing log file {}", file_path.display());
-            remove_file(file_path)?;
+
+            // The active file is not created until the first write.
+            if let Err(err) = remove_file(file_path) {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    log::warn!("Cannot delete file {}. File doesn't exist", file_path.display());
+                } else {
+                    return Err(Box::new(err));
+                }
+            }
         }
-        self.files.clear();
         self.active_file = Self::get_default_log_file_path(&self.storage_dir);
+        self.files = vec![self.active_file.clone()];
         self.storage_index.clear();
 
         Ok(())
     }
+
+    /// Syncs the active log file to the disk.
+    fn flush(&self) -> Result<()> {
+        if self.active_file.exists() {
+            OpenOptions::new().append(true).open(&self.active_file)?.sync_all()?;
+        }
+        log::info!("Storage {} is flushed", self.storage_dir.display());
+        Ok(())
+    }
 }
@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+use crate::models;
+use crate::KVStorage;
+
+
+/// In-memory storage without any durability: the data is lost once the storage is dropped.
+/// Useful for tests and ephemeral caches, where the log files and syncs are pure overhead.
+#[derive(Default)]
+pub struct MemStorage {
+    map: HashMap<String, String>,
+}
+
+impl MemStorage {
+    pub fn new() -> MemStorage {
+        MemStorage::default()
+    }
+}
+
+impl KVStorage for MemStorage {
+    /// Set key `key` to value `value`.
+    fn set(&mut self, key: String, value: String) -> models::Result<()> {
+        self.map.insert(key, value);
+        Ok(())
+    }
+
+    /// Removes key `key` from the storage.
+    /// Returns `true` if the key existed.
+    fn remove(&mut self, key: String) -> models::Result<bool> {
+        Ok(self.map.remove(&key).is_some())
+    }
+
+    /// Gets value with the key `key`. Returns `None` if the key doesn't exist in the storage.
+    fn get(&self, key: String) -> models::Result<Option<String>> {
+        Ok(self.map.get(&key).cloned())
+    }
+
+    /// Removes all records in the storage.
+    fn reset(&mut self) -> models::Result<()> {
+        self.map.clear();
+        Ok(())
+    }
+
+    /// Nothing to sync, the data is kept in memory only.
+    fn flush(&self) -> models::Result<()> {
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 pub use base::KVStorage;
 pub use kv_log::KvLogStorage;
+pub use mem::MemStorage;
 pub use sled::SledStorage;
 
 pub mod base;
 pub mod kv_log;
+pub mod mem;
 pub mod sled;
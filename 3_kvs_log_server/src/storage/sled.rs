@@ -2,6 +2,7 @@ use sled;
 
 use crate::models;
 use crate::KVStorage;
+use crate::storage::base::check_engine;
 
 
 pub struct SledStorage {
@@ -10,6 +11,7 @@ pub struct SledStorage {
 
 impl SledStorage {
     pub fn open(path: &std::path::Path) -> models::Result<SledStorage> {
+        check_engine(path, "sled")?;
         let db = sled::open(path)?;
         Ok(
             SledStorage{
@@ -51,4 +53,10 @@ impl KVStorage for SledStorage {
         self.db.flush()?;
         Ok(())
     }
+
+    /// Syncs the written data to the disk.
+    fn flush(&self) -> models::Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
 }
@@ -117,6 +117,7 @@ fn kvs_set_override(#[case] engine: &str) {
 #[rstest::rstest]
 #[case("kvs")]
 #[case("sled")]
+#[case("memory")]
 #[serial_test::serial]
 fn kvs_get_missing_value(#[case] engine: &str) {
     let temp_dir = TempDir::new().unwrap();
@@ -168,6 +169,7 @@ fn kvs_remove_key(#[case] engine: &str) {
 #[rstest::rstest]
 #[case("kvs")]
 #[case("sled")]
+#[case("memory")]
 #[serial_test::serial]
 fn kvs_remove_missing_key(#[case] engine: &str) {
     let temp_dir = TempDir::new().unwrap();
@@ -213,3 +215,29 @@ fn kvs_reset(#[case] engine: &str) {
     run_client_cmd(&temp_dir, HOST, PORT, &["get", "key2"])
         .stdout(contains("GET NONE"));
 }
+
+#[serial_test::serial]
+#[test]
+fn memory_engine() {
+    let temp_dir = TempDir::new().unwrap();
+    let server_guard = run_server(&temp_dir, "memory", HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["set", "key1", "value1"])
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "key1"])
+        .stdout(contains("value1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["reset"])
+        .stdout(contains("RESET OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "key1"])
+        .stdout(contains("GET NONE"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["set", "key2", "value2"])
+        .stdout(contains("SET OK"));
+
+    // Nothing is written to the disk, so the values are lost on restart.
+    drop(server_guard);
+    assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+    let _server_guard = run_server(&temp_dir, "memory", HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "key2"])
+        .stdout(contains("GET NONE"));
+}
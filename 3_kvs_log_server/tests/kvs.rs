@@ -125,3 +125,121 @@ fn compaction() -> models::Result<()> {
 
     panic!("No compaction detected");
 }
+
+// Values compacted with their log file should be read from their new positions without reopening the storage.
+#[test]
+fn compaction_positions() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    // Overwrite the keys until the first log file is full and compacted on the rotation.
+    let mut iter = 0;
+    while !temp_dir.path().join("kv_2.log").exists() {
+        for key_id in 0..10 {
+            store.set(format!("key{}", key_id), format!("value{}-{}", key_id, iter).repeat(1000))?;
+        }
+        iter += 1;
+    }
+    for key_id in 0..10 {
+        let key = format!("key{}", key_id);
+        assert_eq!(store.get(key)?, Some(format!("value{}-{}", key_id, iter - 1).repeat(1000)));
+    }
+    Ok(())
+}
+
+// Reset should succeed before the first write, and the storage should stay writable after the reset.
+#[test]
+fn reset_storage() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    store.reset()?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.reset()?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+// A storage directory should be opened only by the engine which created it.
+#[test]
+fn engine_mismatch() -> models::Result<()> {
+    let kvs_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(kvs_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    let err = storage::SledStorage::open(kvs_dir.path()).err().expect("sled should not open a kvs storage");
+    assert!(err.to_string().contains("created by the kvs engine"));
+    assert_eq!(storage::KvLogStorage::open(kvs_dir.path())?.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    let sled_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(storage::SledStorage::open(sled_dir.path())?);
+    let err = storage::KvLogStorage::open(sled_dir.path()).err().expect("kvs should not open a sled storage");
+    assert!(err.to_string().contains("created by the sled engine"));
+
+    // A storage created before the engine file is recognized by its files.
+    std::fs::remove_file(sled_dir.path().join(storage::base::ENGINE_FILE_NAME))?;
+    let err = storage::KvLogStorage::open(sled_dir.path()).err().expect("kvs should not open a legacy sled storage");
+    assert!(err.to_string().contains("created by the sled engine"));
+    assert!(!sled_dir.path().join(storage::base::ENGINE_FILE_NAME).exists());
+    Ok(())
+}
+
+// A storage directory should be open by a single storage at a time.
+#[test]
+fn storage_lock() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    let err = storage::KvLogStorage::open(temp_dir.path()).err().expect("storage should be locked");
+    assert!(err.to_string().contains("already in use"));
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// A record torn by a crash should be dropped on open, the complete records before it should be kept.
+// The log file spans many buffers of the reader, so the records crossing the buffer boundaries are read too.
+#[test]
+fn torn_write_recovery() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log_path = temp_dir.path().join("kv_1.log");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    for idx in 0..5000 {
+        store.set(format!("key{}", idx), "v".repeat(idx % 37))?;
+    }
+    let complete_size = std::fs::metadata(&log_path)?.len();
+    store.set("torn".to_owned(), "value".repeat(10))?;
+    drop(store);
+
+    // Cut the last record in the middle and pad it with zeros, as a crash during the write may leave the file.
+    // The records have no checksums, so the padding is shorter than the record to be detected.
+    let file = std::fs::OpenOptions::new().write(true).open(&log_path)?;
+    file.set_len(complete_size + 20)?;
+    file.set_len(complete_size + 40)?;
+    drop(file);
+
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(std::fs::metadata(&log_path)?.len(), complete_size);
+    for idx in 0..5000 {
+        assert_eq!(store.get(format!("key{}", idx))?, Some("v".repeat(idx % 37)));
+    }
+    assert_eq!(store.get("torn".to_owned())?, None);
+
+    // The writes are appended after the last complete record.
+    store.set("torn".to_owned(), "value".to_owned())?;
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("torn".to_owned())?, Some("value".to_owned()));
+    assert_eq!(store.get("key4999".to_owned())?, Some("v".repeat(4999 % 37)));
+    Ok(())
+}
@@ -57,3 +57,64 @@ fn cli_invalid_path() {
         .assert()
         .failure();
 }
+
+// Options of the config file should be validated as the command line ones.
+#[test]
+fn cli_config_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("server.toml");
+    for config in ["engine = \"unknown\"\n", "unknown_option = 1\n", "port = [4000]\n"] {
+        std::fs::write(&config_path, config).unwrap();
+        let mut cmd = Command::cargo_bin("kvs_server").unwrap();
+        cmd.args(&["--config", config_path.to_str().unwrap()])
+            .current_dir(&temp_dir)
+            .assert()
+            .failure();
+    }
+
+    let mut cmd = Command::cargo_bin("kvs_server").unwrap();
+    cmd.args(&["--config", "nonexistent.toml"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Cannot read config file"));
+}
+
+// `kvs_server` should remove the leftovers of an interrupted compaction and exit successfully on SIGTERM.
+#[cfg(unix)]
+#[test]
+fn cli_sigterm() {
+    let temp_dir = TempDir::new().unwrap();
+    let tmp_file_path = temp_dir.path().join("_tmp_kv_1.log");
+    std::fs::write(&tmp_file_path, "partially compacted").unwrap();
+    let mut child = Command::cargo_bin("kvs_server")
+        .unwrap()
+        .args(&["--port", "4016"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    assert!(!tmp_file_path.exists());
+
+    Command::new("kill")
+        .args(&["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+
+    let mut status = None;
+    for _ in 0..50 {
+        status = child.try_wait().unwrap();
+        if status.is_some() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    match status {
+        Some(status) => assert!(status.success()),
+        None => {
+            child.kill().unwrap();
+            let _ = child.wait();
+            panic!("Server is not stopped on SIGTERM");
+        },
+    }
+}
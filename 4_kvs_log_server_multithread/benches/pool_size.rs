@@ -70,7 +70,7 @@ pub fn bench_set_pool_size(c: &mut Criterion) {
                             for idx in idx_range {
                                 let key = idx.to_string();
                                 let value = format!("value_00000000000{}", idx);
-                                let cmd = models::Command::Set { key: key, value: value };
+                                let cmd = models::Command::Set { key: key, value: value.into_bytes() };
                                 client.connect(HOST.to_string(), PORT, std::time::Duration::from_secs(10)).unwrap();
                                 let response = client.execute_one(cmd, false).unwrap();
 
@@ -117,7 +117,7 @@ pub fn bench_get_pool_size(c: &mut Criterion) {
                 for idx in 0..values_count {
                     let key = idx.to_string();
                     let value = format!("value_00000000000{}", idx);
-                    let cmd = models::Command::Set { key: key, value: value };
+                    let cmd = models::Command::Set { key: key, value: value.into_bytes() };
                     commands.push(cmd);
                 }
                 let response = client.execute(commands, false).unwrap();
@@ -139,7 +139,7 @@ pub fn bench_get_pool_size(c: &mut Criterion) {
                                 assert!(response.commands.len() == 1);
                                 assert!(
                                     *response.commands.first().unwrap() ==
-                                    models::ResponseCommand::Get{value: Some(expected_value)}
+                                    models::ResponseCommand::Get{value: Some(expected_value.into_bytes())}
                                 );
                             }
                         });
@@ -0,0 +1,119 @@
+use criterion::{BenchmarkId, criterion_group, criterion_main, Criterion, PlotConfiguration};
+use crossbeam::deque;
+
+use rust_kvs_server::threads::base::{Job, JobHandle, PoolCounters, ShutdownMode, ThreadPool};
+use rust_kvs_server::threads::scheduler::Scheduler;
+use rust_kvs_server::threads::shared::SharedThreadPool;
+use rust_kvs_server::models;
+
+/// The former design of `SharedThreadPool` for comparison: all the workers poll a single injector
+/// and sleep for 10ms once it is empty.
+struct InjectorThreadPool {
+    injector: std::sync::Arc<deque::Injector<Job>>,
+    stopped: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    threads: Vec<std::thread::JoinHandle<()>>,
+    counters: std::sync::Arc<PoolCounters>,
+    scheduler: Scheduler,
+}
+
+impl InjectorThreadPool {
+    fn new(size: usize) -> Self {
+        let injector = std::sync::Arc::new(deque::Injector::<Job>::new());
+        let stopped = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let threads = (0..size)
+            .map(|_| {
+                let injector = injector.clone();
+                let stopped = stopped.clone();
+                std::thread::spawn(move || {
+                    while !stopped.load(std::sync::atomic::Ordering::SeqCst) {
+                        match injector.steal() {
+                            deque::Steal::Success(job) => job(),
+                            deque::Steal::Retry => {},
+                            deque::Steal::Empty => std::thread::sleep(std::time::Duration::from_millis(10)),
+                        }
+                    }
+                })
+            })
+            .collect();
+        let scheduler_injector = injector.clone();
+        let scheduler = Scheduler::new(move |job| {
+            scheduler_injector.push(job);
+            Ok(())
+        });
+        InjectorThreadPool { injector, stopped, threads, counters: std::sync::Arc::new(PoolCounters::default()), scheduler }
+    }
+}
+
+impl ThreadPool for InjectorThreadPool {
+    fn spawn(&self, job: Job) -> models::Result<JobHandle> {
+        let (job, handle) = JobHandle::wrap(move || { job(); Ok(()) });
+        self.injector.push(job);
+        Ok(handle)
+    }
+
+    fn shutdown(&mut self, _mode: ShutdownMode) -> models::Result<()> {
+        self.stopped.store(true, std::sync::atomic::Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            thread.join().unwrap();
+        }
+        Ok(())
+    }
+
+    fn counters(&self) -> std::sync::Arc<PoolCounters> {
+        self.counters.clone()
+    }
+
+    fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+}
+
+/// Spawns bursts of short jobs with pauses in between, so the workers go idle before each burst.
+/// Waits for all the jobs of a burst to complete before the next one.
+fn run_bursts(pool: &dyn ThreadPool, bursts_count: usize, burst_size: usize) {
+    for _ in 0..bursts_count {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for idx in 0..burst_size {
+            let sender = sender.clone();
+            pool.spawn(Box::new(move || {
+                // A bit of work, so the jobs are not completed right on spawn.
+                let checksum = (0..1000u64).fold(idx as u64, |acc, x| acc.wrapping_mul(31).wrapping_add(x));
+                sender.send(checksum).unwrap();
+            })).unwrap();
+        }
+        for _ in 0..burst_size {
+            receiver.recv().unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
+
+pub fn bench_pool_bursts(c: &mut Criterion) {
+    let pool_size = 4;
+    let bursts_count = 10;
+    let burst_sizes = [1, 16, 256];
+
+    let mut group = c.benchmark_group("shared pool bursts");
+    group.sample_size(20);
+    group.plot_config(PlotConfiguration::default());
+
+    for burst_size in burst_sizes.iter() {
+        group.bench_with_input(BenchmarkId::new("injector", burst_size), burst_size, |b, &burst_size| {
+            let mut pool = InjectorThreadPool::new(pool_size);
+            b.iter(|| run_bursts(&pool, bursts_count, burst_size));
+            pool.shutdown(ShutdownMode::Graceful).unwrap();
+        });
+        group.bench_with_input(BenchmarkId::new("work-stealing", burst_size), burst_size, |b, &burst_size| {
+            let mut pool = SharedThreadPool::new(pool_size);
+            b.iter(|| run_bursts(&pool, bursts_count, burst_size));
+            pool.shutdown(ShutdownMode::Graceful).unwrap();
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_pool_bursts,
+);
+criterion_main!(benches);
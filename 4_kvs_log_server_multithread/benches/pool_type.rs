@@ -71,7 +71,7 @@ pub fn bench_set_pool_type(c: &mut Criterion) {
                             for idx in idx_range {
                                 let key = idx.to_string();
                                 let value = format!("value_00000000000{}", idx);
-                                let cmd = models::Command::Set { key: key, value: value };
+                                let cmd = models::Command::Set { key: key, value: value.into_bytes() };
                                 client.connect(
                                     HOST.to_string(), PORT, std::time::Duration::from_secs(10)
                                 ).unwrap();
@@ -124,7 +124,7 @@ pub fn bench_get_pool_type(c: &mut Criterion) {
                 for idx in 0..values_count {
                     let key = idx.to_string();
                     let value = format!("value_00000000000{}", idx);
-                    let cmd = models::Command::Set { key: key, value: value };
+                    let cmd = models::Command::Set { key: key, value: value.into_bytes() };
                     commands.push(cmd);
                 }
                 let response = client.execute(commands, false).unwrap();
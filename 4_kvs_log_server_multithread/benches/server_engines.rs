@@ -0,0 +1,148 @@
+use criterion::{BenchmarkId, criterion_group, criterion_main, Criterion, PlotConfiguration, Throughput};
+
+use rust_kvs_server::{client, models, storage, threads};
+use rust_kvs_server::server::{KvsServer, ShutdownHandle};
+use rust_kvs_server::storage::base::KvStorage;
+
+const HOST: &str = "127.0.0.1";
+const PORT: u32 = 4013;
+
+/// Storage engines run under the server.
+#[derive(Clone, Copy, Debug)]
+enum Engine {
+    Kvs,
+    Sled,
+}
+
+impl std::fmt::Display for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Engine::Kvs => write!(f, "kvs"),
+            Engine::Sled => write!(f, "sled"),
+        }
+    }
+}
+
+/// Share of the reads among the workload operations, the rest are writes.
+#[derive(Clone, Copy, Debug)]
+struct Workload {
+    name: &'static str,
+    read_percent: usize,
+}
+
+const WORKLOADS: [Workload; 3] = [
+    Workload { name: "read-heavy", read_percent: 95 },
+    Workload { name: "mixed", read_percent: 50 },
+    Workload { name: "write-heavy", read_percent: 5 },
+];
+
+/// Stops the in-process server on drop.
+struct ServerGuard {
+    shutdown_handle: ShutdownHandle,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        self.shutdown_handle.shutdown();
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+    }
+}
+
+fn serve<E: KvStorage + 'static>(engine: E, pool_size: usize) -> ServerGuard {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let handle = std::thread::spawn(move || {
+        let thread_pool = Box::new(threads::shared::SharedThreadPool::new(pool_size));
+        let mut server = KvsServer::new(engine, thread_pool);
+        sender.send(server.shutdown_handle()).unwrap();
+        server.listen(HOST.to_owned(), PORT).unwrap();
+    });
+    let shutdown_handle = receiver.recv().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    ServerGuard { shutdown_handle, handle: Some(handle) }
+}
+
+fn run_server(dir: &tempfile::TempDir, engine: Engine, pool_size: usize) -> ServerGuard {
+    match engine {
+        Engine::Kvs => serve(storage::KvLogStorage::open(dir.path()).unwrap(), pool_size),
+        Engine::Sled => serve(storage::SledStorage::open(dir.path()).unwrap(), pool_size),
+    }
+}
+
+fn connect() -> client::KvsClient {
+    let mut client = client::KvsClient::new();
+    client.connect(HOST.to_string(), PORT, std::time::Duration::from_secs(10)).unwrap();
+    client
+}
+
+/// Sets the values for all the keys, so the reads of the workloads hit.
+fn load_keys(keys_count: usize) {
+    let commands = (0..keys_count)
+        .map(|idx| models::Command::Set { key: idx.to_string(), value: format!("value_{:016}", idx).into_bytes() })
+        .collect();
+    let response = connect().execute(commands, false).unwrap();
+    assert_eq!(response.commands.len(), keys_count);
+}
+
+/// Runs `ops_per_client` operations on each of the clients over keep-alive connections.
+/// The operation kinds and keys are picked by a xorshift generator seeded with the client index,
+/// so every engine gets the same sequence of the operations.
+fn run_workload(workload: Workload, clients_count: usize, ops_per_client: usize, keys_count: usize) {
+    let client_threads: Vec<_> = (0..clients_count).map(|client_idx| {
+        std::thread::spawn(move || {
+            let mut client = connect();
+            let mut state = client_idx as u64 + 0x9E37_79B9_7F4A_7C15;
+            for _ in 0..ops_per_client {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                let key = (state as usize % keys_count).to_string();
+                let cmd = if (state >> 32) as usize % 100 < workload.read_percent {
+                    models::Command::Get { key }
+                } else {
+                    models::Command::Set { key, value: format!("value_{:016}", state).into_bytes() }
+                };
+                let response = client.execute_one(cmd, true).unwrap();
+                assert!(!matches!(response.commands.first(), None | Some(models::ResponseCommand::Error { .. })));
+            }
+            client.close().unwrap();
+        })
+    }).collect();
+
+    for client_thread in client_threads {
+        client_thread.join().unwrap();
+    }
+}
+
+pub fn bench_server_engines(c: &mut Criterion) {
+    let engines = [Engine::Kvs, Engine::Sled];
+    let pool_size = 4;
+    let clients_count = 4;
+    let ops_per_client = 250;
+    let keys_count = 1000;
+
+    let mut group = c.benchmark_group("server engines");
+    group.sample_size(20);
+    group.plot_config(PlotConfiguration::default());
+    group.throughput(Throughput::Elements((clients_count * ops_per_client) as u64));
+
+    for workload in WORKLOADS.iter() {
+        for engine in engines.iter() {
+            group.bench_with_input(BenchmarkId::new(workload.name, engine), engine, |b, &engine| {
+                let temp_dir = tempfile::TempDir::new().unwrap();
+                let _server_guard = run_server(&temp_dir, engine, pool_size);
+                load_keys(keys_count);
+                b.iter(|| run_workload(*workload, clients_count, ops_per_client, keys_count));
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_server_engines,
+);
+criterion_main!(benches);
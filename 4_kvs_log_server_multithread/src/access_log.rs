@@ -0,0 +1,133 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde_json::json;
+
+use crate::models::{Result, StatusCode};
+
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 10_000_000;
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+/// Access log record of a handled request.
+#[derive(Clone, Debug)]
+pub struct AccessLogEntry {
+    pub timestamp: SystemTime,
+    pub request_id: String,
+    pub client_addr: String,
+    pub store: String,
+    pub namespace: String,
+    /// Command type names in the request order.
+    pub commands: Vec<&'static str>,
+    /// Number of the commands working with a key.
+    pub key_count: usize,
+    /// Size of the request body in bytes.
+    pub request_bytes: usize,
+    /// Size of the serialized response in bytes.
+    pub response_bytes: usize,
+    pub duration: Duration,
+    /// Statuses of the commands in the request order.
+    pub statuses: Vec<StatusCode>,
+}
+
+impl AccessLogEntry {
+    /// Result of the whole request: `ok` if all the commands succeeded, otherwise the status of the first failed one.
+    pub fn result(&self) -> StatusCode {
+        self.statuses.iter().copied().find(|status| *status != StatusCode::Ok).unwrap_or(StatusCode::Ok)
+    }
+
+    /// Serializes the entry into a single line JSON object, without the line break.
+    pub fn to_json(&self) -> String {
+        let timestamp_ms = self.timestamp.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis();
+        let errors = self.statuses.iter().filter(|status| **status != StatusCode::Ok).count();
+        format!(
+            "{{\"timestamp_ms\":{},\"request_id\":{},\"client\":{},\"store\":{},\"namespace\":{},\"commands\":{},\
+            \"keys\":{},\"request_bytes\":{},\"response_bytes\":{},\"duration_us\":{},\"result\":{},\"errors\":{}}}",
+            timestamp_ms, json!(self.request_id), json!(self.client_addr), json!(self.store), json!(self.namespace),
+            json!(self.commands), self.key_count, self.request_bytes, self.response_bytes, self.duration.as_micros(),
+            json!(self.result().to_string()), errors,
+        )
+    }
+}
+
+struct AccessLogFile {
+    file: File,
+    size: u64,
+}
+
+/// Access log of the server requests, written as JSON lines. Once the file grows over `max_file_size`
+/// it's renamed to `<path>.1`, the older files are shifted to `<path>.2` and so on up to `max_files`,
+/// the files beyond that are removed.
+pub struct AccessLog {
+    path: PathBuf,
+    max_file_size: u64,
+    max_files: usize,
+    file: Mutex<AccessLogFile>,
+}
+
+/// Path of the rotated access log file `idx`.
+pub fn rotated_path(path: &Path, idx: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", idx));
+    PathBuf::from(name)
+}
+
+impl AccessLog {
+    /// Opens the access log file for appending, creating it if needed.
+    pub fn open(path: &Path, max_file_size: u64, max_files: usize) -> Result<AccessLog> {
+        if max_file_size == 0 {
+            return Err(Box::from("Access log max file size must be positive"));
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(AccessLog {
+            path: path.to_path_buf(),
+            max_file_size,
+            max_files,
+            file: Mutex::new(AccessLogFile { file, size }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the entry to the log, rotating the file if it's full.
+    pub fn record(&self, entry: &AccessLogEntry) -> Result<()> {
+        let mut line = entry.to_json();
+        line.push('\n');
+
+        let mut log_file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if log_file.size > 0 && log_file.size + line.len() as u64 > self.max_file_size {
+            self.rotate(&mut log_file)?;
+        }
+        log_file.file.write_all(line.as_bytes())?;
+        log_file.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&self, log_file: &mut AccessLogFile) -> Result<()> {
+        if self.max_files == 0 {
+            log_file.file.set_len(0)?;
+            log_file.size = 0;
+            return Ok(());
+        }
+
+        match fs::remove_file(rotated_path(&self.path, self.max_files)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(Box::new(err)),
+            _ => {},
+        }
+        for idx in (1..self.max_files).rev() {
+            let rotated = rotated_path(&self.path, idx);
+            if rotated.exists() {
+                fs::rename(&rotated, rotated_path(&self.path, idx + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        log_file.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        log_file.size = 0;
+        Ok(())
+    }
+}
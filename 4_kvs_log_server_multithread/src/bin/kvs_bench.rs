@@ -0,0 +1,325 @@
+use std::time;
+
+use clap::{Parser, ValueEnum};
+use rand::Rng;
+
+use rust_kvs_server::histogram::Histogram;
+use rust_kvs_server::models::{self, Result};
+use rust_kvs_server::KvsClient;
+
+#[derive(Parser)]
+#[command(version, about = "YCSB-style load generator for the KVS server", long_about = None)]
+struct Cli {
+    /// Server hostname
+    #[arg(short = 'H', long, default_value = "127.0.0.1")]
+    host: String,
+    /// Server port
+    #[arg(short = 'P', long, default_value = "4000")]
+    port: u32,
+    /// Connect to a server listening on a unix domain socket at the given path
+    #[arg(long, conflicts_with_all = ["host", "port"])]
+    socket: Option<String>,
+    /// Set log level
+    #[arg(short, long, default_value = "warning")]
+    log_level: LogLevel,
+    /// Read timeout in seconds
+    #[arg(long, default_value = "30")]
+    read_timeout: f32,
+    /// Number of the distinct keys
+    #[arg(short, long, default_value_t = 10000)]
+    key_count: usize,
+    /// Size of the written values in bytes
+    #[arg(short, long, default_value_t = 100)]
+    value_size: usize,
+    /// Share of the reads among the operations, from 0 to 1. The rest are writes.
+    #[arg(short, long, default_value_t = 0.5)]
+    read_ratio: f64,
+    /// Distribution of the operation keys
+    #[arg(short, long, default_value = "zipfian")]
+    distribution: Distribution,
+    /// Skew of the zipfian distribution, from 0 to 1 exclusive
+    #[arg(long, default_value_t = 0.99)]
+    zipf_theta: f64,
+    /// Number of the concurrent clients, each with its own connection
+    #[arg(short, long, default_value_t = 4)]
+    concurrency: usize,
+    /// Run duration in seconds
+    #[arg(short = 't', long, default_value_t = 10.0)]
+    duration: f32,
+    /// Skip setting the values of all the keys before the run
+    #[arg(long)]
+    no_load: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Distribution {
+    /// All the keys are equally likely
+    Uniform,
+    /// A few hot keys get most of the operations
+    Zipfian,
+}
+
+#[derive(Clone, ValueEnum)]
+enum LogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+/// Number of the values set in a single request by the load phase.
+const LOAD_BATCH_SIZE: usize = 1000;
+
+/// Zipfian key index generator, as described in "Quickly Generating Billion-Record Synthetic Databases"
+/// by Gray et al. and used by YCSB. Index 0 is the hottest one.
+struct Zipfian {
+    items_count: usize,
+    theta: f64,
+    alpha: f64,
+    zeta_n: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    fn new(items_count: usize, theta: f64) -> Self {
+        let zeta = |n: usize| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum::<f64>();
+        let zeta_n = zeta(items_count);
+        let zeta_2 = zeta(2.min(items_count));
+        Zipfian {
+            items_count,
+            theta,
+            alpha: 1.0 / (1.0 - theta),
+            zeta_n,
+            eta: (1.0 - (2.0 / items_count as f64).powf(1.0 - theta)) / (1.0 - zeta_2 / zeta_n),
+        }
+    }
+
+    fn next(&self, rng: &mut impl Rng) -> usize {
+        let u: f64 = rng.random();
+        let uz = u * self.zeta_n;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1.min(self.items_count - 1);
+        }
+        let idx = (self.items_count as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha)) as usize;
+        idx.min(self.items_count - 1)
+    }
+}
+
+/// Picks the keys of the operations.
+enum KeyGenerator {
+    Uniform(usize),
+    Zipfian(Zipfian),
+}
+
+impl KeyGenerator {
+    fn next(&self, rng: &mut impl Rng) -> String {
+        let idx = match self {
+            KeyGenerator::Uniform(items_count) => rng.random_range(0..*items_count),
+            KeyGenerator::Zipfian(zipfian) => zipfian.next(rng),
+        };
+        format_key(idx)
+    }
+}
+
+fn format_key(idx: usize) -> String {
+    format!("key{}", idx)
+}
+
+/// Random alphanumeric value, so the values are not trivially compressible.
+fn random_value(rng: &mut impl Rng, value_size: usize) -> Vec<u8> {
+    rng.sample_iter(rand::distr::Alphanumeric).take(value_size).collect()
+}
+
+/// Connection options shared by the clients.
+#[derive(Clone)]
+struct Target {
+    host: String,
+    port: u32,
+    socket: Option<String>,
+    read_timeout: time::Duration,
+}
+
+fn connect(target: &Target) -> Result<KvsClient> {
+    let mut client = KvsClient::new();
+    match &target.socket {
+        #[cfg(unix)]
+        Some(socket) => client.connect_unix(std::path::Path::new(socket), target.read_timeout)?,
+        #[cfg(not(unix))]
+        Some(_) => return Err(Box::from("Unix sockets are not supported on this platform")),
+        None => client.connect(target.host.clone(), target.port, target.read_timeout)?,
+    }
+    Ok(client)
+}
+
+/// Sets the values of all the keys in batches over a single keep-alive connection.
+fn load_keys(target: &Target, key_count: usize, value_size: usize) -> Result<()> {
+    let mut client = connect(target)?;
+    let mut rng = rand::rng();
+    let value = random_value(&mut rng, value_size);
+    let mut idx = 0;
+    while idx < key_count {
+        let batch_end = (idx + LOAD_BATCH_SIZE).min(key_count);
+        for key_idx in idx..batch_end {
+            client.queue(models::Command::Set { key: format_key(key_idx), value: value.clone() });
+        }
+        let responses = client.flush_queue(batch_end < key_count)?;
+        if let Some(models::ResponseCommand::Error { code, message }) = responses.iter()
+            .find(|response| matches!(response, models::ResponseCommand::Error { .. }))
+        {
+            return Err(Box::from(format!("Load failed with code {}: {}", code, message)));
+        }
+        idx = batch_end;
+    }
+    Ok(())
+}
+
+/// Operation latencies in microseconds and the failures of a client.
+#[derive(Default)]
+struct ClientReport {
+    read_latencies: Histogram,
+    write_latencies: Histogram,
+    errors_count: usize,
+}
+
+/// Runs the operations over a keep-alive connection until the deadline.
+/// A failed connection is counted as an error and reopened.
+fn run_client(
+    target: Target,
+    keys: std::sync::Arc<KeyGenerator>,
+    read_ratio: f64,
+    value_size: usize,
+    deadline: time::Instant,
+) -> Result<ClientReport> {
+    let mut rng = rand::rng();
+    let value = random_value(&mut rng, value_size);
+    let mut report = ClientReport::default();
+    let mut client = connect(&target)?;
+
+    while time::Instant::now() < deadline {
+        let key = keys.next(&mut rng);
+        let is_read = rng.random::<f64>() < read_ratio;
+        let command = match is_read {
+            true => models::Command::Get { key },
+            false => models::Command::Set { key, value: value.clone() },
+        };
+
+        let start = time::Instant::now();
+        match client.execute_one(command, true) {
+            Ok(response) => {
+                let latency = start.elapsed().as_micros() as u64;
+                match response.commands.first() {
+                    Some(models::ResponseCommand::Error { code, message }) => {
+                        log::debug!("Command failed with code {}: {}", code, message);
+                        report.errors_count += 1;
+                    },
+                    Some(_) if is_read => report.read_latencies.record(latency),
+                    Some(_) => report.write_latencies.record(latency),
+                    None => report.errors_count += 1,
+                }
+            },
+            Err(err) => {
+                log::warn!("Request failed: {}", err);
+                report.errors_count += 1;
+                client = connect(&target)?;
+            },
+        }
+    }
+    client.close()?;
+    Ok(report)
+}
+
+fn print_latencies(name: &str, latencies: &Histogram) {
+    if latencies.is_empty() {
+        println!("{}: no operations.", name);
+        return;
+    }
+    println!(
+        "{}: {} operations. Avg {:.1}us; Min {}us; p50 {}us; p95 {}us; p99 {}us; p999 {}us; Max {}us.",
+        name, latencies.len(), latencies.mean(), latencies.min(),
+        latencies.value_at_percentile(50.0), latencies.value_at_percentile(95.0),
+        latencies.value_at_percentile(99.0), latencies.value_at_percentile(99.9), latencies.max(),
+    );
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let log_level = match cli.log_level {
+        LogLevel::Debug => log::LevelFilter::Debug,
+        LogLevel::Info => log::LevelFilter::Info,
+        LogLevel::Warning => log::LevelFilter::Warn,
+        LogLevel::Error => log::LevelFilter::Error,
+    };
+    simple_logger::SimpleLogger::new().with_level(log_level).init().unwrap();
+
+    if cli.key_count == 0 || cli.concurrency == 0 {
+        eprintln!("key_count and concurrency must be positive.");
+        std::process::exit(1);
+    }
+    if !(0.0..=1.0).contains(&cli.read_ratio) {
+        eprintln!("read_ratio must be from 0 to 1.");
+        std::process::exit(1);
+    }
+    if !(cli.zipf_theta > 0.0 && cli.zipf_theta < 1.0) {
+        eprintln!("zipf_theta must be greater than 0 and less than 1.");
+        std::process::exit(1);
+    }
+
+    let target = Target {
+        host: cli.host,
+        port: cli.port,
+        socket: cli.socket,
+        read_timeout: time::Duration::from_secs_f32(cli.read_timeout),
+    };
+
+    if !cli.no_load {
+        let load_start = time::Instant::now();
+        if let Err(err) = load_keys(&target, cli.key_count, cli.value_size) {
+            eprintln!("Failed to load the keys: {}", err);
+            std::process::exit(2);
+        }
+        log::info!("Loaded {} keys in {}ms", cli.key_count, load_start.elapsed().as_millis());
+    }
+
+    let keys = std::sync::Arc::new(match cli.distribution {
+        Distribution::Uniform => KeyGenerator::Uniform(cli.key_count),
+        Distribution::Zipfian => KeyGenerator::Zipfian(Zipfian::new(cli.key_count, cli.zipf_theta)),
+    });
+    let start = time::Instant::now();
+    let deadline = start + time::Duration::from_secs_f32(cli.duration);
+    let client_threads: Vec<_> = (0..cli.concurrency).map(|_| {
+        let (target, keys) = (target.clone(), keys.clone());
+        let (read_ratio, value_size) = (cli.read_ratio, cli.value_size);
+        std::thread::spawn(move || {
+            run_client(target, keys, read_ratio, value_size, deadline).map_err(|e| e.to_string())
+        })
+    }).collect();
+
+    let mut report = ClientReport::default();
+    for client_thread in client_threads {
+        match client_thread.join().expect("client thread panicked") {
+            Ok(client_report) => {
+                report.read_latencies.merge(&client_report.read_latencies);
+                report.write_latencies.merge(&client_report.write_latencies);
+                report.errors_count += client_report.errors_count;
+            },
+            Err(err) => {
+                eprintln!("Client failed: {}", err);
+                std::process::exit(3);
+            },
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let operations_count = report.read_latencies.len() + report.write_latencies.len();
+    println!(
+        "Running {} clients for {:.1}s. Total: {} operations; {:.0} ops/s; {} errors.",
+        cli.concurrency, elapsed, operations_count, operations_count as f64 / elapsed, report.errors_count,
+    );
+    print_latencies("Read", &report.read_latencies);
+    print_latencies("Write", &report.write_latencies);
+    Ok(())
+}
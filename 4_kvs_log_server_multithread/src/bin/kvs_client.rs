@@ -7,6 +7,7 @@ use simple_logger;
 
 use rust_kvs_server::models::{self, Result};
 use rust_kvs_server::KvsClient;
+use rust_kvs_server::tls;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -20,12 +21,33 @@ struct Cli {
     /// Server port
     #[arg(short = 'P', long, default_value = "4000")]
     port: u32,
+    /// Connect to a server listening on a unix domain socket at the given path
+    #[arg(long, conflicts_with_all = ["host", "port", "tls_ca_cert", "tls_insecure"])]
+    socket: Option<String>,
     /// Set log level
     #[arg(short, long, default_value = "info")]
     log_level: LogLevel,
     /// Read timeout in seconds
     #[arg(short, long, default_value = "30")]
     read_timeout: f32,
+    /// Request deadline in seconds. The server skips the request once the deadline is exceeded.
+    #[arg(long)]
+    deadline: Option<f32>,
+    /// Execute the commands in the given namespace instead of the default one
+    #[arg(short, long)]
+    namespace: Option<String>,
+    /// Execute the commands in the given named store of the server instead of the default one
+    #[arg(long)]
+    store: Option<String>,
+    /// Connect over TLS and verify the server certificate with a PEM-encoded CA certificate
+    #[arg(long, conflicts_with = "tls_insecure")]
+    tls_ca_cert: Option<String>,
+    /// Connect over TLS without server certificate verification
+    #[arg(long)]
+    tls_insecure: bool,
+    /// Server name to verify the TLS certificate against. The hostname is used by default.
+    #[arg(long)]
+    tls_server_name: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -42,6 +64,150 @@ enum Commands {
         /// Key to get the value for
         key: String,
     },
+    /// Append `suffix` to the value of the key `key` and print the new value length
+    Append {
+        /// Key to append to, created if missing
+        key: String,
+        /// Suffix to append to the value
+        suffix: String,
+    },
+    /// Set value `value` for the key `key` and print the previous value
+    GetSet {
+        /// Key to set
+        key: String,
+        /// Value to set for the key
+        value: String,
+    },
+    /// Set value `value` for the key `key` only if the key doesn't exist
+    SetNx {
+        /// Key to set
+        key: String,
+        /// Value to set for the key
+        value: String,
+    },
+    /// Push `values` to the front of the list `key` and print the list length
+    Lpush {
+        /// Key of the list, created if missing
+        key: String,
+        /// Values to push, the last one ends up first
+        #[arg(required = true)]
+        values: Vec<String>,
+    },
+    /// Push `values` to the back of the list `key` and print the list length
+    Rpush {
+        /// Key of the list, created if missing
+        key: String,
+        /// Values to push
+        #[arg(required = true)]
+        values: Vec<String>,
+    },
+    /// Pop a value from the front of the list `key`
+    Lpop {
+        /// Key of the list
+        key: String,
+    },
+    /// Pop a value from the back of the list `key`
+    Rpop {
+        /// Key of the list
+        key: String,
+    },
+    /// Print the values of the list `key` between `start` and `stop` inclusive, negative indexes count from the end
+    #[command(allow_negative_numbers = true)]
+    Lrange {
+        /// Key of the list
+        key: String,
+        /// Index of the first value
+        start: i64,
+        /// Index of the last value
+        stop: i64,
+    },
+    /// Set the field `field` of the hash `key` to `value`
+    Hset {
+        /// Key of the hash, created if missing
+        key: String,
+        /// Field to set
+        field: String,
+        /// Value to set for the field
+        value: String,
+    },
+    /// Get the value of the field `field` of the hash `key`
+    Hget {
+        /// Key of the hash
+        key: String,
+        /// Field to get the value for
+        field: String,
+    },
+    /// Remove the `fields` of the hash `key` and print the number of the removed fields
+    Hdel {
+        /// Key of the hash
+        key: String,
+        /// Fields to remove
+        #[arg(required = true)]
+        fields: Vec<String>,
+    },
+    /// Print all the fields of the hash `key`
+    Hgetall {
+        /// Key of the hash
+        key: String,
+    },
+    /// Add the `members` to the set `key` and print the number of the added members
+    Sadd {
+        /// Key of the set, created if missing
+        key: String,
+        /// Members to add
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// Remove the `members` of the set `key` and print the number of the removed members
+    Srem {
+        /// Key of the set
+        key: String,
+        /// Members to remove
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// Check whether `member` belongs to the set `key`
+    Sismember {
+        /// Key of the set
+        key: String,
+        /// Member to check
+        member: String,
+    },
+    /// Print all the members of the set `key`
+    Smembers {
+        /// Key of the set
+        key: String,
+    },
+    /// Add `member` with the score `score` to the sorted set `key` or update its score
+    #[command(allow_negative_numbers = true)]
+    Zadd {
+        /// Key of the sorted set, created if missing
+        key: String,
+        /// Score of the member
+        score: f64,
+        /// Member to add
+        member: String,
+    },
+    /// Remove the `members` of the sorted set `key` and print the number of the removed members
+    Zrem {
+        /// Key of the sorted set
+        key: String,
+        /// Members to remove
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// Print the members of the sorted set `key` with the scores between `min` and `max` inclusive
+    #[command(allow_negative_numbers = true)]
+    Zrangebyscore {
+        /// Key of the sorted set
+        key: String,
+        /// Minimal score, `-inf` for no limit
+        #[arg(allow_hyphen_values = true)]
+        min: f64,
+        /// Maximal score, `inf` for no limit
+        #[arg(allow_hyphen_values = true)]
+        max: f64,
+    },
     /// Remove the key `key`
     Remove {
         /// Key to remove
@@ -49,6 +215,276 @@ enum Commands {
     },
     /// Reset storage by removing all of the stored values
     Reset {},
+    /// Remove all the keys starting with `prefix` and print the number of the removed keys
+    ResetPrefix {
+        /// Prefix of the keys to remove
+        prefix: String,
+    },
+    /// Stage the latest backup from the server-side directory `backup_dir` and print a restore token
+    PrepareRestore {
+        /// Backup directory on the server
+        backup_dir: String,
+    },
+    /// Switch the storage to the backup staged with `token`
+    CommitRestore {
+        /// Restore token returned by `prepare-restore`
+        token: String,
+    },
+    /// Remove the backup staged with `token`
+    AbortRestore {
+        /// Restore token returned by `prepare-restore`
+        token: String,
+    },
+    /// Compact all the sealed log files of the storage
+    Compact {},
+    /// Print the storage statistics
+    Stats {},
+    /// Print the server version, uptime, storage statistics, thread pool load and compaction status
+    Info {},
+    /// Stop the background compaction of the storage until `resume-compaction`
+    PauseCompaction {},
+    /// Resume the background compaction of the storage
+    ResumeCompaction {},
+    /// Execute the commands from a file, one command per line, in batches over a single connection
+    Exec {
+        /// Commands file. Supports `set <key> <value>`, `get <key>`, `append <key> <suffix>`, `get-set <key> <value>`,
+        /// `set-nx <key> <value>`, `lpush <key> <value>`, `rpush <key> <value>`, `lpop <key>`, `rpop <key>`,
+        /// `lrange <key> <start> <stop>`, `hset <key> <field> <value>`, `hget <key> <field>`, `hdel <key> <field>`,
+        /// `hgetall <key>`, `sadd <key> <member>`, `srem <key> <member>`, `sismember <key> <member>`,
+        /// `smembers <key>`, `zadd <key> <score> <member>`, `zrem <key> <member>`, `zrangebyscore <key> <min> <max>`,
+        /// `remove <key>`, `reset`, `reset-prefix <prefix>`, `compact`, `stats`, `info`, `pause-compaction`
+        /// and `resume-compaction` lines. Empty lines and lines starting with `#` are skipped.
+        #[arg(short, long)]
+        file: String,
+    },
+}
+
+/// Commands to execute: a single command from the command line or the commands of an `exec` file.
+enum Execution {
+    Single(models::Command),
+    /// Commands with their line numbers in the file.
+    File(Vec<(usize, models::Command)>),
+}
+
+/// Number of commands sent in a single request by `exec`.
+const EXEC_BATCH_SIZE: usize = 1000;
+
+/// Parses a line of an `exec` commands file. Returns `None` for empty and comment lines.
+/// The values of the set and push commands, the member of the set type commands and the suffix of an `append` command
+/// are the rest of the line after the key, the value of a `hset` command is the rest of the line after the field
+/// and the member of a `zadd` command is the rest of the line after the score.
+fn parse_command_line(line: &str) -> Result<Option<models::Command>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let args = args.trim_start();
+    let (key, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim_start();
+    let command = match (name, key, rest) {
+        ("set", key, value) if !key.is_empty() && !value.is_empty() => {
+            models::Command::Set { key: key.to_owned(), value: value.as_bytes().to_vec() }
+        },
+        ("get", key, "") if !key.is_empty() => models::Command::Get { key: key.to_owned() },
+        ("append", key, suffix) if !key.is_empty() && !suffix.is_empty() => {
+            models::Command::Append { key: key.to_owned(), suffix: suffix.as_bytes().to_vec() }
+        },
+        ("get-set", key, value) if !key.is_empty() && !value.is_empty() => {
+            models::Command::GetSet { key: key.to_owned(), value: value.as_bytes().to_vec() }
+        },
+        ("set-nx", key, value) if !key.is_empty() && !value.is_empty() => {
+            models::Command::SetNx { key: key.to_owned(), value: value.as_bytes().to_vec() }
+        },
+        ("lpush" | "rpush", key, value) if !key.is_empty() && !value.is_empty() => {
+            let values = vec![value.as_bytes().to_vec()];
+            models::Command::ListPush { key: key.to_owned(), values, front: name == "lpush" }
+        },
+        ("lpop" | "rpop", key, "") if !key.is_empty() => {
+            models::Command::ListPop { key: key.to_owned(), front: name == "lpop" }
+        },
+        ("lrange", key, range) if !key.is_empty() => {
+            let bounds: Vec<&str> = range.split_whitespace().collect();
+            match bounds[..] {
+                [start, stop] => {
+                    models::Command::ListRange { key: key.to_owned(), start: start.parse()?, stop: stop.parse()? }
+                },
+                _ => return Err(Box::from(format!("Invalid command '{}'", line))),
+            }
+        },
+        ("hset", key, field_value) if !key.is_empty() => {
+            let (field, value) = field_value.split_once(char::is_whitespace).unwrap_or((field_value, ""));
+            let value = value.trim_start();
+            if value.is_empty() {
+                return Err(Box::from(format!("Invalid command '{}'", line)));
+            }
+            let fields = vec![(field.to_owned(), value.as_bytes().to_vec())];
+            models::Command::HashSet { key: key.to_owned(), fields }
+        },
+        ("hget", key, field) if !key.is_empty() && !field.is_empty() && !field.contains(char::is_whitespace) => {
+            models::Command::HashGet { key: key.to_owned(), field: field.to_owned() }
+        },
+        ("hdel", key, fields) if !key.is_empty() && !fields.is_empty() => {
+            let fields = fields.split_whitespace().map(str::to_owned).collect();
+            models::Command::HashDelete { key: key.to_owned(), fields }
+        },
+        ("hgetall", key, "") if !key.is_empty() => models::Command::HashGetAll { key: key.to_owned() },
+        ("sadd", key, member) if !key.is_empty() && !member.is_empty() => {
+            models::Command::SetAdd { key: key.to_owned(), members: vec![member.as_bytes().to_vec()] }
+        },
+        ("srem", key, member) if !key.is_empty() && !member.is_empty() => {
+            models::Command::SetRemove { key: key.to_owned(), members: vec![member.as_bytes().to_vec()] }
+        },
+        ("sismember", key, member) if !key.is_empty() && !member.is_empty() => {
+            models::Command::SetIsMember { key: key.to_owned(), member: member.as_bytes().to_vec() }
+        },
+        ("smembers", key, "") if !key.is_empty() => models::Command::SetMembers { key: key.to_owned() },
+        ("zadd", key, score_member) if !key.is_empty() => {
+            let (score, member) = score_member.split_once(char::is_whitespace).unwrap_or((score_member, ""));
+            let member = member.trim_start();
+            if member.is_empty() {
+                return Err(Box::from(format!("Invalid command '{}'", line)));
+            }
+            let members = vec![(member.as_bytes().to_vec(), score.parse()?)];
+            models::Command::SortedSetAdd { key: key.to_owned(), members }
+        },
+        ("zrem", key, member) if !key.is_empty() && !member.is_empty() => {
+            models::Command::SortedSetRemove { key: key.to_owned(), members: vec![member.as_bytes().to_vec()] }
+        },
+        ("zrangebyscore", key, range) if !key.is_empty() => {
+            let bounds: Vec<&str> = range.split_whitespace().collect();
+            match bounds[..] {
+                [min, max] => {
+                    models::Command::SortedSetRangeByScore { key: key.to_owned(), min: min.parse()?, max: max.parse()? }
+                },
+                _ => return Err(Box::from(format!("Invalid command '{}'", line))),
+            }
+        },
+        ("remove", key, "") if !key.is_empty() => models::Command::Remove { key: key.to_owned() },
+        ("reset", "", "") => models::Command::Reset {},
+        ("reset-prefix", prefix, "") if !prefix.is_empty() => {
+            models::Command::ResetPrefix { prefix: prefix.to_owned() }
+        },
+        ("compact", "", "") => models::Command::Compact {},
+        ("stats", "", "") => models::Command::Stats {},
+        ("info", "", "") => models::Command::Info {},
+        ("pause-compaction", "", "") => models::Command::PauseCompaction {},
+        ("resume-compaction", "", "") => models::Command::ResumeCompaction {},
+        _ => return Err(Box::from(format!("Invalid command '{}'", line))),
+    };
+    Ok(Some(command))
+}
+
+fn read_commands_file(path: &str) -> Result<Vec<(usize, models::Command)>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut commands = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        match parse_command_line(line) {
+            Ok(Some(command)) => commands.push((idx + 1, command)),
+            Ok(None) => {},
+            Err(err) => return Err(Box::from(format!("Line {}: {}", idx + 1, err))),
+        }
+    }
+    Ok(commands)
+}
+
+/// Formats a command response. A failed command is reported as an error.
+fn format_response(response_command: &models::ResponseCommand) -> std::result::Result<String, String> {
+    match response_command {
+        models::ResponseCommand::Set {} => Ok(String::from("SET OK")),
+        models::ResponseCommand::Append { length } => Ok(format!("APPEND OK {}", length)),
+        models::ResponseCommand::GetSet { value: Some(val) } => {
+            Ok(format!("GET SET OK {}", String::from_utf8_lossy(val)))
+        },
+        models::ResponseCommand::GetSet { value: None } => Ok(String::from("GET SET NONE")),
+        models::ResponseCommand::SetNx { is_set: true } => Ok(String::from("SET NX OK")),
+        models::ResponseCommand::SetNx { is_set: false } => Ok(String::from("SET NX EXISTS")),
+        models::ResponseCommand::ListPush { length } => Ok(format!("LIST PUSH OK {}", length)),
+        models::ResponseCommand::ListPop { value: Some(val) } => {
+            Ok(format!("LIST POP OK {}", String::from_utf8_lossy(val)))
+        },
+        models::ResponseCommand::ListPop { value: None } => Ok(String::from("LIST POP NONE")),
+        models::ResponseCommand::ListRange { values } => {
+            let values: Vec<String> = values.iter().map(|value| String::from_utf8_lossy(value).into_owned()).collect();
+            Ok(format!("LIST RANGE OK {}", values.join(" ")))
+        },
+        models::ResponseCommand::HashSet { added } => Ok(format!("HASH SET OK {}", added)),
+        models::ResponseCommand::HashGet { value: Some(val) } => {
+            Ok(format!("HASH GET OK {}", String::from_utf8_lossy(val)))
+        },
+        models::ResponseCommand::HashGet { value: None } => Ok(String::from("HASH GET NONE")),
+        models::ResponseCommand::HashDelete { removed } => Ok(format!("HASH DELETE OK {}", removed)),
+        models::ResponseCommand::HashGetAll { fields } => {
+            let fields: Vec<String> = fields.iter()
+                .map(|(field, value)| format!("{}={}", field, String::from_utf8_lossy(value)))
+                .collect();
+            Ok(format!("HASH GET ALL OK {}", fields.join(" ")))
+        },
+        models::ResponseCommand::SetAdd { added } => Ok(format!("SET ADD OK {}", added)),
+        models::ResponseCommand::SetRemove { removed } => Ok(format!("SET REMOVE OK {}", removed)),
+        models::ResponseCommand::SetIsMember { is_member: true } => Ok(String::from("SET IS MEMBER TRUE")),
+        models::ResponseCommand::SetIsMember { is_member: false } => Ok(String::from("SET IS MEMBER FALSE")),
+        models::ResponseCommand::SetMembers { members } => {
+            let members: Vec<String> = members.iter()
+                .map(|member| String::from_utf8_lossy(member).into_owned())
+                .collect();
+            Ok(format!("SET MEMBERS OK {}", members.join(" ")))
+        },
+        models::ResponseCommand::SortedSetAdd { added } => Ok(format!("SORTED SET ADD OK {}", added)),
+        models::ResponseCommand::SortedSetRemove { removed } => Ok(format!("SORTED SET REMOVE OK {}", removed)),
+        models::ResponseCommand::SortedSetRangeByScore { members } => {
+            let members: Vec<String> = members.iter()
+                .map(|(member, score)| format!("{}={}", String::from_utf8_lossy(member), score))
+                .collect();
+            Ok(format!("SORTED SET RANGE OK {}", members.join(" ")))
+        },
+        models::ResponseCommand::Remove {} => Ok(String::from("REMOVE OK")),
+        models::ResponseCommand::Reset {} => Ok(String::from("RESET OK")),
+        models::ResponseCommand::ResetPrefix { removed } => Ok(format!("RESET PREFIX OK {}", removed)),
+        models::ResponseCommand::PrepareRestore { token } => Ok(format!("PREPARE RESTORE OK {}", token)),
+        models::ResponseCommand::CommitRestore {} => Ok(String::from("COMMIT RESTORE OK")),
+        models::ResponseCommand::AbortRestore {} => Ok(String::from("ABORT RESTORE OK")),
+        models::ResponseCommand::Compact {} => Ok(String::from("COMPACT OK")),
+        models::ResponseCommand::Stats { stats, pool } => Ok(format!("STATS OK {} {}", stats, pool)),
+        models::ResponseCommand::Info { info } => Ok(format!("INFO OK {}", info)),
+        models::ResponseCommand::PauseCompaction {} => Ok(String::from("PAUSE COMPACTION OK")),
+        models::ResponseCommand::ResumeCompaction {} => Ok(String::from("RESUME COMPACTION OK")),
+        models::ResponseCommand::Get { value: Some(val) } => Ok(format!("GET OK {}", String::from_utf8_lossy(val))),
+        models::ResponseCommand::Get { value: None } => Ok(String::from("GET NONE")),
+        models::ResponseCommand::GetStream { chunk: Some(chunk) } => {
+            Ok(format!("GET STREAM OK {}", String::from_utf8_lossy(chunk)))
+        },
+        models::ResponseCommand::GetStream { chunk: None } => Ok(String::from("GET STREAM NONE")),
+        models::ResponseCommand::Error { code, message } => {
+            Err(format!("Command failed with code {} ({}): {}", code, response_command.status(), message))
+        },
+    }
+}
+
+/// Executes the commands in batches over a keep-alive connection and prints the results.
+/// Returns the number of failed commands.
+fn exec_commands(client: &mut KvsClient, commands: Vec<(usize, models::Command)>) -> Result<usize> {
+    let mut failed_count = 0;
+    let mut commands = commands.into_iter().peekable();
+    while commands.peek().is_some() {
+        let batch: Vec<(usize, models::Command)> = commands.by_ref().take(EXEC_BATCH_SIZE).collect();
+        let line_numbers: Vec<usize> = batch.iter().map(|(line_number, _)| *line_number).collect();
+        for (_, command) in batch {
+            client.queue(command);
+        }
+        let keep_alive = commands.peek().is_some();
+        let responses = client.flush_queue(keep_alive)?;
+        for (line_number, response_command) in line_numbers.iter().zip(responses.iter()) {
+            match format_response(response_command) {
+                Ok(message) => log::info!("Line {}: {}", line_number, message),
+                Err(message) => {
+                    eprintln!("Line {}: {}", line_number, message);
+                    failed_count += 1;
+                },
+            }
+        }
+    }
+    Ok(failed_count)
 }
 
 #[derive(Clone, ValueEnum)]
@@ -72,18 +508,106 @@ fn main() -> Result<()>{
     let timeout = time::Duration::from_secs_f32(cli.read_timeout);
 
     let command = match cli.command {
-        Some(Commands::Set { key, value }) => models::Command::Set { key: key, value: value },
-        Some(Commands::Get { key }) => models::Command::Get { key: key },
-        Some(Commands::Remove { key }) => models::Command::Remove { key: key },
-        Some(Commands::Reset {}) => models::Command::Reset {},
+        Some(Commands::Set { key, value }) => Execution::Single(models::Command::Set { key: key, value: value.into_bytes() }),
+        Some(Commands::Get { key }) => Execution::Single(models::Command::Get { key: key }),
+        Some(Commands::Append { key, suffix }) => {
+            Execution::Single(models::Command::Append { key, suffix: suffix.into_bytes() })
+        },
+        Some(Commands::GetSet { key, value }) => {
+            Execution::Single(models::Command::GetSet { key, value: value.into_bytes() })
+        },
+        Some(Commands::SetNx { key, value }) => {
+            Execution::Single(models::Command::SetNx { key, value: value.into_bytes() })
+        },
+        Some(Commands::Lpush { key, values }) => {
+            let values = values.into_iter().map(String::into_bytes).collect();
+            Execution::Single(models::Command::ListPush { key, values, front: true })
+        },
+        Some(Commands::Rpush { key, values }) => {
+            let values = values.into_iter().map(String::into_bytes).collect();
+            Execution::Single(models::Command::ListPush { key, values, front: false })
+        },
+        Some(Commands::Lpop { key }) => Execution::Single(models::Command::ListPop { key, front: true }),
+        Some(Commands::Rpop { key }) => Execution::Single(models::Command::ListPop { key, front: false }),
+        Some(Commands::Lrange { key, start, stop }) => {
+            Execution::Single(models::Command::ListRange { key, start, stop })
+        },
+        Some(Commands::Hset { key, field, value }) => {
+            Execution::Single(models::Command::HashSet { key, fields: vec![(field, value.into_bytes())] })
+        },
+        Some(Commands::Hget { key, field }) => Execution::Single(models::Command::HashGet { key, field }),
+        Some(Commands::Hdel { key, fields }) => Execution::Single(models::Command::HashDelete { key, fields }),
+        Some(Commands::Hgetall { key }) => Execution::Single(models::Command::HashGetAll { key }),
+        Some(Commands::Sadd { key, members }) => {
+            let members = members.into_iter().map(String::into_bytes).collect();
+            Execution::Single(models::Command::SetAdd { key, members })
+        },
+        Some(Commands::Srem { key, members }) => {
+            let members = members.into_iter().map(String::into_bytes).collect();
+            Execution::Single(models::Command::SetRemove { key, members })
+        },
+        Some(Commands::Sismember { key, member }) => {
+            Execution::Single(models::Command::SetIsMember { key, member: member.into_bytes() })
+        },
+        Some(Commands::Smembers { key }) => Execution::Single(models::Command::SetMembers { key }),
+        Some(Commands::Zadd { key, score, member }) => {
+            Execution::Single(models::Command::SortedSetAdd { key, members: vec![(member.into_bytes(), score)] })
+        },
+        Some(Commands::Zrem { key, members }) => {
+            let members = members.into_iter().map(String::into_bytes).collect();
+            Execution::Single(models::Command::SortedSetRemove { key, members })
+        },
+        Some(Commands::Zrangebyscore { key, min, max }) => {
+            Execution::Single(models::Command::SortedSetRangeByScore { key, min, max })
+        },
+        Some(Commands::Remove { key }) => Execution::Single(models::Command::Remove { key: key }),
+        Some(Commands::Reset {}) => Execution::Single(models::Command::Reset {}),
+        Some(Commands::ResetPrefix { prefix }) => Execution::Single(models::Command::ResetPrefix { prefix }),
+        Some(Commands::PrepareRestore { backup_dir }) => Execution::Single(models::Command::PrepareRestore { backup_dir: backup_dir }),
+        Some(Commands::CommitRestore { token }) => Execution::Single(models::Command::CommitRestore { token: token }),
+        Some(Commands::AbortRestore { token }) => Execution::Single(models::Command::AbortRestore { token: token }),
+        Some(Commands::Compact {}) => Execution::Single(models::Command::Compact {}),
+        Some(Commands::Stats {}) => Execution::Single(models::Command::Stats {}),
+        Some(Commands::Info {}) => Execution::Single(models::Command::Info {}),
+        Some(Commands::PauseCompaction {}) => Execution::Single(models::Command::PauseCompaction {}),
+        Some(Commands::ResumeCompaction {}) => Execution::Single(models::Command::ResumeCompaction {}),
+        Some(Commands::Exec { file }) => {
+            // Parse the whole file first, so an invalid file doesn't get partially executed.
+            match read_commands_file(&file) {
+                Ok(commands) => Execution::File(commands),
+                Err(err) => {
+                    eprintln!("Cannot read commands from {}: {}", file, err);
+                    std::process::exit(1);
+                },
+            }
+        },
         None => {
             eprintln!("Use --help for usage information.");
             std::process::exit(1);
         }
     };
 
+    let tls_verification = match (cli.tls_ca_cert, cli.tls_insecure) {
+        (Some(ca_cert), _) => Some(tls::TlsVerification::CaCert(ca_cert.into())),
+        (None, true) => Some(tls::TlsVerification::Insecure),
+        (None, false) => None,
+    };
+
     let mut client = KvsClient::new();
-    match client.connect(cli.host, cli.port, timeout) {
+    client.set_namespace(cli.namespace);
+    client.set_store(cli.store);
+    let connect_result = match (cli.socket, tls_verification) {
+        #[cfg(unix)]
+        (Some(socket), _) => client.connect_unix(std::path::Path::new(&socket), timeout),
+        #[cfg(not(unix))]
+        (Some(_), _) => Err(Box::from("Unix sockets are not supported on this platform")),
+        (None, Some(verification)) => {
+            let options = tls::TlsClientOptions { verification, server_name: cli.tls_server_name };
+            client.connect_tls(cli.host, cli.port, timeout, options)
+        },
+        (None, None) => client.connect(cli.host, cli.port, timeout),
+    };
+    match connect_result {
         Ok(_) => {},
         Err(err) => {
             eprintln!("Failed to connect: {}", err);
@@ -91,7 +615,27 @@ fn main() -> Result<()>{
         },
     }
     
-    let exec_result = client.execute_one(command, false);
+    let deadline = cli.deadline.map(|seconds| time::SystemTime::now() + time::Duration::from_secs_f32(seconds));
+    let command = match command {
+        Execution::Single(command) => command,
+        Execution::File(commands) => {
+            let commands_count = commands.len();
+            match exec_commands(&mut client, commands) {
+                Ok(0) => log::info!("EXEC OK {} commands", commands_count),
+                Ok(failed_count) => {
+                    eprintln!("{} of {} commands failed", failed_count, commands_count);
+                    std::process::exit(5);
+                },
+                Err(err) => {
+                    eprintln!("Failed to handle request: {}", err);
+                    std::process::exit(3);
+                },
+            }
+            return Ok(());
+        },
+    };
+
+    let exec_result = client.execute_with_deadline(vec![command], false, deadline);
     if exec_result.is_err() {
         eprintln!("Failed to handle request: {}", exec_result.err().unwrap());
         std::process::exit(3);
@@ -100,16 +644,11 @@ fn main() -> Result<()>{
     let response = exec_result.unwrap();
     match response.commands.first() {
         Some(response_command) => {
-            match response_command {
-                models::ResponseCommand::Set {} => { log::info!("SET OK"); },
-                models::ResponseCommand::Remove {} => { log::info!("REMOVE OK"); },
-                models::ResponseCommand::Reset {} => { log::info!("RESET OK"); },
-                models::ResponseCommand::Get { value } => {
-                    match value {
-                        Some(val) => log::info!("GET OK {}", val),
-                        None => log::info!("GET NONE"),
-                    }
-                    
+            match format_response(response_command) {
+                Ok(message) => log::info!("{}", message),
+                Err(message) => {
+                    eprintln!("{}", message);
+                    std::process::exit(5);
                 },
             }
         },
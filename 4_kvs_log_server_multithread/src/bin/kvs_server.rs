@@ -4,29 +4,258 @@ use log;
 use num_cpus;
 use simple_logger;
 
-use rust_kvs_server::{models, server, storage, threads};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rust_kvs_server::{access_log, config, models, server, storage, threads, tls, trace};
+#[cfg(unix)]
+use rust_kvs_server::daemon;
+use rust_kvs_server::storage::KvStorage;
+use kvs_common::export;
+
+#[cfg(unix)]
+const RELOAD_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+/// Number of the imported pairs written with a single sync.
+const IMPORT_BATCH_SIZE: usize = 1000;
 
 #[derive(clap::Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_override_self = true)]
 struct Cli {
+    /// Read the options from a TOML file. The command line options override the file ones.
+    /// The log level and the compaction garbage ratio are reloaded from the file on SIGHUP.
+    #[arg(long)]
+    config: Option<String>,
     /// Server hostname
     #[arg(short = 'H', long, default_value = "127.0.0.1")]
     host: String,
     /// Server port
     #[arg(short = 'P', long, default_value = "4000")]
     port: u32,
+    /// Listen on a unix domain socket at the given path instead of TCP
+    #[arg(long, conflicts_with_all = ["host", "port", "tls_cert"])]
+    socket: Option<String>,
+    /// Storage engine type
+    #[arg(short, long, default_value = "kvs")]
+    engine: EngineType,
     /// Storage path
     #[arg(short, long, default_value = "./")]
     path: String,
+    /// Serve another storage directory as a named store selected by the clients, e.g. `--store users=/data/users`.
+    /// Can be repeated. The stores are opened with the same engine and options as the default storage.
+    #[arg(long = "store", value_name = "NAME=PATH", value_parser = parse_store)]
+    stores: Vec<(String, std::path::PathBuf)>,
     /// Set log level
     #[arg(short, long, default_value = "info")]
     log_level: LogLevel,
-    /// Server handlers thread pool size. Set to 0 for auto-selection.
+    /// Server handlers thread pool size, the max number of threads for the naive pool. Set to 0 for auto-selection.
     #[arg(short = 's', long, default_value_t = 0)]
     thread_pool_size: usize,
     /// Set log level
     #[arg(short = 't', long, default_value = "shared")]
     thread_pool: ThreadPoolType,
+    /// Max number of the connections queued for a free worker of the shared thread pool. Unlimited by default.
+    /// The requests of the connections beyond the limit fail with server busy errors.
+    #[arg(long)]
+    thread_pool_queue_capacity: Option<usize>,
+    /// PEM-encoded TLS certificate chain. Enables TLS for all connections.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+    /// PEM-encoded TLS private key
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+    /// Compress stored values larger than the given size in bytes
+    #[arg(long)]
+    compression_threshold: Option<usize>,
+    /// Max log file size in bytes
+    #[arg(long, default_value_t = 4_000_000)]
+    segment_size: u64,
+    /// Log files compaction thread pool size
+    #[arg(long, default_value_t = 2)]
+    compaction_pool_size: usize,
+    /// Min share of stale records in a log file to compact it, from 0 to 1
+    #[arg(long, default_value_t = 0.0)]
+    compaction_garbage_ratio: f64,
+    /// Min share of dead bytes in a sealed log file to compact it by the scheduler, from 0 to 1
+    #[arg(long, default_value_t = 0.5)]
+    compaction_dead_ratio: f64,
+    /// Max dead bytes of all the sealed log files. Beyond it the scheduler compacts the files with the most dead bytes.
+    #[arg(long)]
+    compaction_max_garbage: Option<u64>,
+    /// Seconds between the compaction scheduler checks of the sealed log files. The scheduler is disabled by default.
+    #[arg(long)]
+    compaction_interval: Option<u64>,
+    /// When to sync the writes to the disk
+    #[arg(long, default_value = "always")]
+    fsync: FsyncPolicy,
+    /// Max time in milliseconds a write waits for its group commit batch (group fsync policy)
+    #[arg(long, default_value_t = 2)]
+    group_commit_interval: u64,
+    /// Max number of writes synced in a single group commit batch (group fsync policy)
+    #[arg(long, default_value_t = 128)]
+    group_commit_max_batch: usize,
+    /// Precede each write with a sequence record, so a restore can stop at a given write
+    #[arg(long)]
+    record_sequence: bool,
+    /// Replace the storage with the latest backup from the directory before starting
+    #[arg(long)]
+    restore_from: Option<String>,
+    /// Replay the restored log up to the write with the given sequence number
+    #[arg(long, requires = "restore_from", conflicts_with = "restore_until_time")]
+    restore_until_seq: Option<u64>,
+    /// Replay the restored log up to the writes at the given time in milliseconds since the Unix epoch
+    #[arg(long, requires = "restore_from")]
+    restore_until_time: Option<u64>,
+    /// Partition the keys by hash across the given number of storages in the subdirectories of the storage path
+    #[arg(long, conflicts_with = "restore_from")]
+    shards: Option<usize>,
+    /// Max number of the namespaces opened by the clients
+    #[arg(long, default_value_t = 1024)]
+    max_namespaces: usize,
+    /// Seconds a keep-alive connection waits for the next request before it's closed. Set to 0 to disable.
+    #[arg(long, default_value_t = 300)]
+    idle_timeout: u64,
+    /// Seconds to receive a whole request once its first bytes arrive. Set to 0 to disable.
+    #[arg(long, default_value_t = 30)]
+    request_read_timeout: u64,
+    /// Max time in milliseconds to start the commands of a request, the rest are skipped. Unlimited by default.
+    #[arg(long)]
+    request_timeout: Option<u64>,
+    /// Max number of open connections. The requests of the connections beyond the limit fail with server busy errors.
+    #[arg(long)]
+    max_connections: Option<usize>,
+    /// Directory of the backups the clients can restore from. Remote restores are rejected without it.
+    #[arg(long)]
+    backup_root: Option<String>,
+    /// Write the access log of the handled requests as JSON lines to the given file
+    #[arg(long)]
+    access_log: Option<String>,
+    /// Max access log file size in bytes before it's rotated
+    #[arg(long, default_value_t = access_log::DEFAULT_MAX_FILE_SIZE)]
+    access_log_max_size: u64,
+    /// Number of the rotated access log files to keep
+    #[arg(long, default_value_t = access_log::DEFAULT_MAX_FILES)]
+    access_log_max_files: usize,
+    /// Run in the background detached from the terminal. The output is discarded unless --log-file is set.
+    #[arg(long)]
+    daemonize: bool,
+    /// Write the server process id to the given file, removed on shutdown
+    #[arg(long)]
+    pid_file: Option<String>,
+    /// Append the server log to the given file instead of the standard output
+    #[arg(long)]
+    log_file: Option<String>,
+    #[command(subcommand)]
+    command: Option<ServerCommand>,
+}
+
+// Commands run on the storage instead of starting the server.
+#[derive(clap::Subcommand)]
+enum ServerCommand {
+    /// Upload the log files changed since the previous backup to an S3-compatible object storage
+    #[cfg(feature = "s3")]
+    Backup(S3Args),
+    /// Replace the stopped server storage with the latest backup from an S3-compatible object storage
+    #[cfg(feature = "s3")]
+    Restore(S3Args),
+    /// Export the set and remove changes of the storage log as JSON lines
+    Cdc(CdcArgs),
+    /// Export all the key/value pairs of the stopped server storage to a file. The values must be UTF-8 strings.
+    Export(PairsFileArgs),
+    /// Import key/value pairs from a file into the stopped server storage. Existing keys are overwritten.
+    Import(PairsFileArgs),
+}
+
+#[derive(clap::Args)]
+struct PairsFileArgs {
+    /// Key/value pairs file
+    #[arg(long)]
+    file: std::path::PathBuf,
+    /// File format
+    #[arg(long, default_value = "json")]
+    format: PairsFormat,
+}
+
+#[derive(Clone, ValueEnum)]
+enum PairsFormat {
+    /// A JSON object mapping keys to values
+    Json,
+    /// CSV with a key,value header
+    Csv,
+}
+
+impl From<PairsFormat> for export::Format {
+    fn from(format: PairsFormat) -> Self {
+        match format {
+            PairsFormat::Json => export::Format::Json,
+            PairsFormat::Csv => export::Format::Csv,
+        }
+    }
+}
+
+#[derive(clap::Args)]
+#[command(group(clap::ArgGroup::new("sink").required(true).args(["output", "connect"])))]
+struct CdcArgs {
+    /// Export the changes written after the given sequence number, see --record-sequence
+    #[arg(long, default_value_t = 0)]
+    from_seq: u64,
+    /// Append the changes to the given file
+    #[arg(long)]
+    output: Option<String>,
+    /// Send the changes to the given TCP address
+    #[arg(long)]
+    connect: Option<String>,
+    /// Keep exporting the new changes instead of stopping at the end of the log
+    #[arg(long)]
+    follow: bool,
+    /// Milliseconds between the log reads in the follow mode
+    #[arg(long, default_value_t = 200)]
+    poll_interval: u64,
+}
+
+#[cfg(feature = "s3")]
+#[derive(clap::Args)]
+struct S3Args {
+    /// Base URL of the object storage, e.g. https://s3.eu-west-1.amazonaws.com
+    #[arg(long)]
+    s3_endpoint: String,
+    /// Bucket of the backup objects
+    #[arg(long)]
+    s3_bucket: String,
+    /// Key prefix of the backup objects
+    #[arg(long, default_value = "")]
+    s3_prefix: String,
+    /// Region of the request signatures
+    #[arg(long, default_value = "us-east-1")]
+    s3_region: String,
+    /// Access key id. Read from the AWS_ACCESS_KEY_ID environment variable by default
+    #[arg(long)]
+    s3_access_key: Option<String>,
+    /// Secret access key. Read from the AWS_SECRET_ACCESS_KEY environment variable by default
+    #[arg(long)]
+    s3_secret_key: Option<String>,
+    /// Size of the multipart upload parts in bytes. Larger log files are uploaded in parts
+    #[arg(long, default_value_t = storage::s3::DEFAULT_PART_SIZE)]
+    s3_part_size: usize,
+}
+
+#[cfg(feature = "s3")]
+impl S3Args {
+    fn target(&self) -> models::Result<storage::s3::S3Target> {
+        let credential = |value: &Option<String>, variable: &str| match value {
+            Some(value) => Ok(value.clone()),
+            None => std::env::var(variable).map_err(|_| format!("S3 credentials are not set, {} is missing", variable)),
+        };
+        let mut target = storage::s3::S3Target::new(
+            self.s3_endpoint.clone(),
+            self.s3_bucket.clone(),
+            credential(&self.s3_access_key, "AWS_ACCESS_KEY_ID")?,
+            credential(&self.s3_secret_key, "AWS_SECRET_ACCESS_KEY")?,
+        );
+        target.prefix = self.s3_prefix.clone();
+        target.region = self.s3_region.clone();
+        target.part_size = self.s3_part_size;
+        Ok(target)
+    }
 }
 
 #[derive(Clone, ValueEnum)]
@@ -37,6 +266,24 @@ enum LogLevel {
     Error,
 }
 
+#[derive(Clone, ValueEnum)]
+enum EngineType {
+    /// Custom WAL-based key-value storage
+    Kvs,
+    /// Sled storage
+    Sled,
+}
+
+#[derive(Clone, ValueEnum)]
+enum FsyncPolicy {
+    /// Sync every write before responding
+    Always,
+    /// Leave syncing to the OS
+    Never,
+    /// Sync concurrent writes together before responding
+    Group,
+}
+
 #[derive(Clone, ValueEnum)]
 enum ThreadPoolType {
     None,
@@ -45,18 +292,186 @@ enum ThreadPoolType {
     Rayon,
 }
 
-fn main() -> models::Result<()> {
-    let cli = Cli::parse();
+/// Reads the options of the config file overridden with the command line options.
+fn parse_config(config_path: &str) -> models::Result<Cli> {
+    // The file options go first, so the command line options override them.
+    let mut args: Vec<std::ffi::OsString> = std::env::args_os().take(1).collect();
+    args.extend(config::config_args(std::path::Path::new(config_path))?);
+    args.extend(std::env::args_os().skip(1));
+    Ok(Cli::try_parse_from(args)?)
+}
 
-    let log_level = match cli.log_level {
+/// Parses a `--store` option of the `NAME=PATH` form.
+fn parse_store(value: &str) -> Result<(String, std::path::PathBuf), String> {
+    match value.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok((name.to_owned(), path.into())),
+        _ => Err(format!("Expected NAME=PATH, got {}", value)),
+    }
+}
+
+fn log_level_filter(log_level: &LogLevel) -> log::LevelFilter {
+    match log_level {
         LogLevel::Debug => log::LevelFilter::Debug,
         LogLevel::Info => log::LevelFilter::Info,
         LogLevel::Warning => log::LevelFilter::Warn,
         LogLevel::Error => log::LevelFilter::Error,
-    };
-    simple_logger::SimpleLogger::new().with_level(log_level).init().unwrap();
+    }
+}
+
+/// Runs a storage command of the kvs engine storage at `storage_path`.
+fn run_command(command: &ServerCommand, storage_path: &std::path::Path) -> models::Result<()> {
+    match command {
+        ServerCommand::Cdc(args) => {
+            let mut sink: Box<dyn std::io::Write> = match (&args.output, &args.connect) {
+                (Some(output), _) => Box::new(std::fs::OpenOptions::new().create(true).append(true).open(output)?),
+                (None, Some(address)) => Box::new(std::net::TcpStream::connect(address)?),
+                (None, None) => return Err(Box::from("CDC sink is not set")),
+            };
+            let mut sink = std::io::BufWriter::new(sink.as_mut());
+            let mut reader = storage::CdcReader::open(storage_path, args.from_seq)?;
+            loop {
+                let exported = reader.export(&mut sink)?;
+                if exported == 0 {
+                    if !args.follow {
+                        break;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(args.poll_interval));
+                }
+            }
+            log::info!("Changes up to the sequence {} are exported", reader.seq());
+        },
+        ServerCommand::Export(args) => {
+            let engine = storage::KvLogStorage::open(storage_path)?;
+            let mut writer = std::io::BufWriter::new(std::fs::File::create(&args.file)?);
+            let count = export::write_records(&mut writer, args.format.clone().into(), engine.iter())?;
+            log::info!("{} records exported to {}", count, args.file.display());
+        },
+        ServerCommand::Import(args) => {
+            let mut engine = storage::KvLogStorage::open(storage_path)?;
+            let reader = std::io::BufReader::new(std::fs::File::open(&args.file)?);
+            let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+            let count = export::read_records(reader, args.format.clone().into(), |key, value| {
+                batch.push((key, value.into_bytes()));
+                if batch.len() == IMPORT_BATCH_SIZE {
+                    engine.set_batch(std::mem::take(&mut batch))?;
+                }
+                Ok(())
+            })?;
+            engine.set_batch(batch)?;
+            log::info!("{} records imported from {}", count, args.file.display());
+        },
+        #[cfg(feature = "s3")]
+        ServerCommand::Backup(args) => {
+            storage::s3::upload_backup(storage_path, &args.target()?)?;
+        },
+        #[cfg(feature = "s3")]
+        ServerCommand::Restore(args) => {
+            // The backup is downloaded next to the storage files and removed once restored.
+            std::fs::create_dir_all(storage_path)?;
+            let download_dir = tempfile::Builder::new().prefix("_s3_restore_").tempdir_in(storage_path)?;
+            storage::s3::download_backup(&args.target()?, download_dir.path())?;
+            storage::KvLogStorage::restore(download_dir.path(), storage_path)?;
+        },
+    }
+    Ok(())
+}
 
-    log::info!("Starting server at {}:{} with at {}", cli.host, cli.port, cli.path);
+/// Applies the options which can be changed on a running server.
+fn apply_runtime_options(cli: &Cli, engines: &[Box<dyn KvStorage>]) -> models::Result<()> {
+    log::set_max_level(log_level_filter(&cli.log_level));
+    for engine in engines {
+        engine.set_compaction_garbage_ratio(cli.compaction_garbage_ratio)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn request_reload(_signal: nix::libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Reloads the runtime options from the config file on SIGHUP. The signal handler only sets a flag
+/// polled by a separate thread, as reading the file is not allowed in a signal handler.
+#[cfg(unix)]
+fn watch_reload(config_path: String, engines: Vec<Box<dyn KvStorage>>) -> models::Result<()> {
+    use nix::sys::signal;
+
+    let action = signal::SigAction::new(
+        signal::SigHandler::Handler(request_reload), signal::SaFlags::SA_RESTART, signal::SigSet::empty(),
+    );
+    // Replaces the SIGHUP handler of ctrlc stopping the server.
+    unsafe { signal::sigaction(signal::Signal::SIGHUP, &action)?; }
+
+    std::thread::Builder::new().name("kvs-reload".to_owned()).spawn(move || loop {
+        std::thread::sleep(RELOAD_POLL_INTERVAL);
+        if !RELOAD_REQUESTED.swap(false, Ordering::Relaxed) {
+            continue;
+        }
+        match parse_config(&config_path).and_then(|cli| apply_runtime_options(&cli, &engines)) {
+            Ok(()) => log::info!("Config {} is reloaded", config_path),
+            Err(err) => log::error!("Cannot reload config {}: {}", config_path, err),
+        }
+    })?;
+    Ok(())
+}
+
+/// Detaches the server from the terminal and redirects its output as requested with the daemon options.
+/// Returns the pid file to be removed on shutdown.
+#[cfg(unix)]
+fn start_daemon(cli: &Cli) -> models::Result<Option<daemon::PidFile>> {
+    if cli.daemonize {
+        daemon::daemonize()?;
+    }
+    if let Some(log_file) = &cli.log_file {
+        daemon::redirect_output(std::path::Path::new(log_file))?;
+    }
+    match &cli.pid_file {
+        Some(pid_file) => Ok(Some(daemon::PidFile::create(std::path::Path::new(pid_file))?)),
+        None => Ok(None),
+    }
+}
+
+fn main() -> models::Result<()> {
+    let mut cli = Cli::parse();
+    if let Some(config_path) = &cli.config {
+        cli = match parse_config(config_path) {
+            Ok(cli) => cli,
+            Err(err) => match err.downcast::<clap::Error>() {
+                Ok(clap_err) => clap_err.exit(),
+                Err(err) => return Err(err),
+            },
+        };
+    }
+
+    // The process is forked before any thread is spawned.
+    #[cfg(unix)]
+    let pid_file = start_daemon(&cli)?;
+    #[cfg(not(unix))]
+    if cli.daemonize || cli.pid_file.is_some() || cli.log_file.is_some() {
+        return Err(Box::from("Daemon mode is not supported on this platform"));
+    }
+
+    // Log lines of the requests are prefixed with the connection and request ids.
+    // The level is filtered with the max level only, so it can be changed at runtime.
+    let logger = simple_logger::SimpleLogger::new().with_level(log::LevelFilter::Trace);
+    log::set_boxed_logger(Box::new(trace::SpanLogger::new(logger)))?;
+    log::set_max_level(log_level_filter(&cli.log_level));
+
+    if let Some(command) = &cli.command {
+        return run_command(command, std::path::Path::new(&cli.path));
+    }
+
+    match &cli.socket {
+        Some(socket) => log::info!("Starting server at {} with at {}", socket, cli.path),
+        None => log::info!("Starting server at {}:{} with at {}", cli.host, cli.port, cli.path),
+    }
+    #[cfg(unix)]
+    if let Some(pid_file) = &pid_file {
+        log::info!("Server pid {} is written to {}", std::process::id(), pid_file.path().display());
+    }
 
     let mut thread_pool_size = cli.thread_pool_size;
     if thread_pool_size == 0 {
@@ -64,16 +479,116 @@ fn main() -> models::Result<()> {
     }
     
     let storage_path = std::path::Path::new(&cli.path);
-    let engine = storage::KvLogStorage::open(storage_path)?;
+    if matches!(cli.engine, EngineType::Sled)
+        && (cli.shards.is_some() || cli.restore_from.is_some() || cli.compression_threshold.is_some()) {
+        return Err(Box::from("Shards, restore and compression are supported only by the kvs engine"));
+    }
+    if let Some(backup_dir) = &cli.restore_from {
+        let backup_dir = std::path::Path::new(backup_dir);
+        let point = match (cli.restore_until_seq, cli.restore_until_time) {
+            (Some(seq), _) => Some(storage::RecoveryPoint::Sequence(seq)),
+            (None, Some(timestamp_ms)) => Some(storage::RecoveryPoint::Timestamp(timestamp_ms)),
+            (None, None) => None,
+        };
+        match point {
+            Some(point) => storage::KvLogStorage::restore_until(backup_dir, storage_path, point)?,
+            None => storage::KvLogStorage::restore(backup_dir, storage_path)?,
+        };
+    }
+    let fsync_policy = match cli.fsync {
+        FsyncPolicy::Always => storage::FsyncPolicy::Always,
+        FsyncPolicy::Never => storage::FsyncPolicy::Never,
+        FsyncPolicy::Group => storage::FsyncPolicy::Group {
+            interval: std::time::Duration::from_millis(cli.group_commit_interval),
+            max_batch_size: cli.group_commit_max_batch,
+        },
+    };
+    let storage_builder = storage::KvLogStorage::builder()
+        .segment_size(cli.segment_size)
+        .compaction_pool_size(cli.compaction_pool_size)
+        .compaction_garbage_ratio(cli.compaction_garbage_ratio)
+        .compaction_policy(storage::CompactionPolicy {
+            dead_ratio: cli.compaction_dead_ratio,
+            max_garbage_size: cli.compaction_max_garbage,
+            interval: cli.compaction_interval.map(std::time::Duration::from_secs),
+        })
+        .fsync_policy(fsync_policy)
+        .compression_threshold(cli.compression_threshold)
+        .record_sequence(cli.record_sequence)
+        .max_namespaces(cli.max_namespaces);
     let thread_pool: Box<dyn threads::base::ThreadPool> = match cli.thread_pool {
         ThreadPoolType::None => { Box::new(threads::none::NoneThreadPool::new()) },
-        ThreadPoolType::Naive => { Box::new(threads::naive::NaiveThreadPool::new()) },
-        ThreadPoolType::Shared => { Box::new(threads::shared::SharedThreadPool::new(thread_pool_size)) },
+        ThreadPoolType::Naive => { Box::new(threads::naive::NaiveThreadPool::with_max_threads(thread_pool_size)) },
+        ThreadPoolType::Shared => match cli.thread_pool_queue_capacity {
+            Some(queue_capacity) => Box::new(threads::shared::SharedThreadPool::bounded(thread_pool_size, queue_capacity)),
+            None => Box::new(threads::shared::SharedThreadPool::new(thread_pool_size)),
+        },
         ThreadPoolType::Rayon => { Box::new(threads::rayon::RayonThreadPool::new(thread_pool_size)?) },
     };
 
-    let mut server = server::KvsServer::new(engine, thread_pool);
-    server.listen(cli.host, cli.port)?;
+    let (engine, mut server) = match (&cli.engine, cli.shards) {
+        (EngineType::Sled, _) => {
+            let engine = storage::SledStorage::open(storage_path)?;
+            (engine.clone_box(), server::KvsServer::new(engine, thread_pool))
+        },
+        (EngineType::Kvs, Some(shards_count)) => {
+            let engine = storage::ShardedKvStorage::open(storage_path, shards_count, storage_builder.clone())?;
+            (engine.clone_box(), server::KvsServer::new(engine, thread_pool))
+        },
+        (EngineType::Kvs, None) => {
+            let engine = storage_builder.clone().open(storage_path)?;
+            (engine.clone_box(), server::KvsServer::new(engine, thread_pool))
+        },
+    };
+    let mut engines = vec![engine];
+    for (name, path) in &cli.stores {
+        log::info!("Serving store {} at {}", name, path.display());
+        let engine: Box<dyn KvStorage> = match (&cli.engine, cli.shards) {
+            (EngineType::Sled, _) => Box::new(storage::SledStorage::open(path)?),
+            (EngineType::Kvs, Some(shards_count)) => {
+                Box::new(storage::ShardedKvStorage::open(path, shards_count, storage_builder.clone())?)
+            },
+            (EngineType::Kvs, None) => Box::new(storage_builder.clone().open(path)?),
+        };
+        engines.push(engine.clone_box());
+        server.add_store(name.clone(), engine)?;
+    }
+    apply_runtime_options(&cli, &engines)?;
+    if let (Some(cert_path), Some(key_path)) = (&cli.tls_cert, &cli.tls_key) {
+        log::info!("TLS is enabled with certificate {}", cert_path);
+        let tls_config = tls::load_server_config(
+            std::path::Path::new(cert_path), std::path::Path::new(key_path),
+        )?;
+        server.set_tls_config(tls_config);
+    }
+    let timeout_secs = |secs: u64| if secs == 0 { None } else { Some(std::time::Duration::from_secs(secs)) };
+    server.set_idle_timeout(timeout_secs(cli.idle_timeout));
+    server.set_request_read_timeout(timeout_secs(cli.request_read_timeout));
+    server.set_request_timeout(cli.request_timeout.map(std::time::Duration::from_millis));
+    server.set_max_connections(cli.max_connections);
+    server.set_backup_root(cli.backup_root.as_ref().map(std::path::PathBuf::from));
+    if let Some(access_log_path) = &cli.access_log {
+        let access_log = access_log::AccessLog::open(
+            std::path::Path::new(access_log_path), cli.access_log_max_size, cli.access_log_max_files,
+        )?;
+        server.set_access_log(Some(access_log));
+    }
+
+    // Stop the server gracefully on SIGINT/SIGTERM.
+    let shutdown_handle = server.shutdown_handle();
+    ctrlc::set_handler(move || shutdown_handle.shutdown())?;
+    #[cfg(unix)]
+    if let Some(config_path) = cli.config.clone() {
+        watch_reload(config_path, engines)?;
+    }
+
+    match cli.socket {
+        #[cfg(unix)]
+        Some(socket) => server.listen_unix(std::path::Path::new(&socket))?,
+        #[cfg(not(unix))]
+        Some(_) => return Err(Box::from("Unix sockets are not supported on this platform")),
+        None => server.listen(cli.host, cli.port)?,
+    }
 
     return Ok(());
 }
@@ -6,12 +6,47 @@ use std::time;
 use crate::models;
 use crate::serialize;
 use crate::serialize::{WriteToStream, ReadFromStream};
+use crate::stream::Stream;
+use crate::tls;
 
 
-const CLIENT_VERSION: u8 = 1u8;
+const CLIENT_VERSION: u8 = 5u8;
+/// Requests are sent with the lowest protocol version supporting their header fields,
+/// so the client stays compatible with the older servers, e.g. the single-threaded one.
+const NO_DEADLINE_VERSION: u8 = 1u8;
+const DEADLINE_VERSION: u8 = 2u8;
+const NAMESPACE_VERSION: u8 = 3u8;
+const STORE_VERSION: u8 = 4u8;
+/// The commands of the requests and the responses are sent in checksummed v2 frames, see `KvsClient::set_framed`.
+const FRAMED_VERSION: u8 = CLIENT_VERSION;
+/// Max number of commands in a single request, limited by the request header.
+const MAX_REQUEST_COMMANDS: usize = u16::MAX as usize;
+const DEFAULT_MAX_RECONNECTS: usize = 3;
+/// Delay before the first reconnect, increased linearly with each next attempt.
+const RECONNECT_DELAY: time::Duration = time::Duration::from_millis(100);
+
+/// Server address the client is connected to, kept to reconnect.
+#[derive(Clone)]
+enum ConnectTarget {
+    Tcp { host: String, port: u32 },
+    Tls { host: String, port: u32, options: tls::TlsClientOptions },
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
 
 pub struct KvsClient {
-    socket_opt: Option<net::TcpStream>,
+    socket_opt: Option<Box<dyn Stream>>,
+    /// Commands queued to be sent with the next `flush_queue`.
+    queue: Vec<models::Command>,
+    /// Target and read timeout of the latest connection.
+    target: Option<(ConnectTarget, time::Duration)>,
+    max_reconnects: usize,
+    /// Namespace of the sent commands, `None` for the default namespace.
+    namespace: Option<String>,
+    /// Store of the sent commands, `None` for the default store of the server.
+    store: Option<String>,
+    /// Whether the requests are sent with the framed protocol version.
+    framed: bool,
 }
 
 impl Drop for KvsClient {
@@ -24,7 +59,41 @@ impl Drop for KvsClient {
 
 impl KvsClient {
     pub fn new() -> Self {
-        KvsClient { socket_opt: None }
+        KvsClient {
+            socket_opt: None,
+            queue: Vec::new(),
+            target: None,
+            max_reconnects: DEFAULT_MAX_RECONNECTS,
+            namespace: None,
+            store: None,
+            framed: false,
+        }
+    }
+
+    /// Sets the namespace the commands of the next requests are executed in.
+    /// `None` switches back to the default namespace.
+    pub fn set_namespace(&mut self, namespace: Option<String>) {
+        self.namespace = namespace;
+    }
+
+    /// Sets the named store of the server the commands of the next requests are executed in.
+    /// `None` switches back to the default store. The namespace is selected within the store.
+    pub fn set_store(&mut self, store: Option<String>) {
+        self.store = store;
+    }
+
+    /// Sends the commands of the next requests in checksummed v2 frames and has the server reply the same way,
+    /// so a corrupted request or response fails with an `InvalidData` error instead of being misread.
+    /// Requires a server supporting the protocol version 5, the older servers reject such requests.
+    pub fn set_framed(&mut self, framed: bool) {
+        self.framed = framed;
+    }
+
+    /// Sets the max number of reconnects while sending a request over a connection closed by the server,
+    /// e.g. on a server restart. Set to 0 to disable the reconnects. Only the requests of read-only commands
+    /// are resent once written, see `send`.
+    pub fn set_max_reconnects(&mut self, max_reconnects: usize) {
+        self.max_reconnects = max_reconnects;
     }
 
     pub fn connect(&mut self, host: String, port: u32, timeout: time::Duration) -> models::Result<()> {
@@ -32,11 +101,53 @@ impl KvsClient {
         log::debug!("Connecting to {}...", addr);
         let socket = net::TcpStream::connect(addr)?;
         socket.set_read_timeout(Some(timeout))?;
-        self.socket_opt = Some(socket);
+        self.socket_opt = Some(Box::new(socket));
+        self.target = Some((ConnectTarget::Tcp { host, port }, timeout));
+        log::debug!("Connected. Read timeout {}s", timeout.as_secs_f32());
+        Ok(())
+    }
+
+    /// Connects to a server listening on a unix domain socket at `path`.
+    #[cfg(unix)]
+    pub fn connect_unix(&mut self, path: &std::path::Path, timeout: time::Duration) -> models::Result<()> {
+        log::debug!("Connecting to {}...", path.display());
+        let socket = std::os::unix::net::UnixStream::connect(path)?;
+        socket.set_read_timeout(Some(timeout))?;
+        self.socket_opt = Some(Box::new(socket));
+        self.target = Some((ConnectTarget::Unix(path.to_path_buf()), timeout));
         log::debug!("Connected. Read timeout {}s", timeout.as_secs_f32());
         Ok(())
     }
 
+    /// Connects to a TLS-enabled server. The handshake is completed before returning,
+    /// so certificate verification errors are reported here.
+    pub fn connect_tls(
+        &mut self,
+        host: String,
+        port: u32,
+        timeout: time::Duration,
+        options: tls::TlsClientOptions,
+    ) -> models::Result<()> {
+        let config = tls::client_config(&options)?;
+        let server_name = rustls::pki_types::ServerName::try_from(
+            options.server_name.clone().unwrap_or_else(|| host.clone())
+        )?;
+
+        let addr = format!("{}:{}", host, port);
+        log::debug!("Connecting to {} over TLS...", addr);
+        let mut socket = net::TcpStream::connect(&addr)?;
+        socket.set_read_timeout(Some(timeout))?;
+
+        let mut connection = rustls::ClientConnection::new(config, server_name)?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut socket)?;
+        }
+        self.socket_opt = Some(Box::new(rustls::StreamOwned::new(connection, socket)));
+        self.target = Some((ConnectTarget::Tls { host, port, options }, timeout));
+        log::debug!("Connected over TLS. Read timeout {}s", timeout.as_secs_f32());
+        Ok(())
+    }
+
     pub fn close(&mut self) -> models::Result<()> {
         if !self.is_connected() {
             return Ok(());
@@ -44,7 +155,7 @@ impl KvsClient {
 
         let socket = self.socket_opt.as_mut().unwrap();
         let _ = socket.flush();
-        let _ = socket.shutdown(net::Shutdown::Both);
+        let _ = socket.shutdown();
         self.socket_opt = None;
 
         Ok(())
@@ -54,11 +165,20 @@ impl KvsClient {
         return self.socket_opt.is_some();
     }
 
-    fn serialize_request(commands: Vec<models::Command>, keep_alive: bool) -> models::Result<Vec<u8>> {
+    fn serialize_request(
+        &self,
+        commands: Vec<models::Command>,
+        keep_alive: bool,
+        deadline: Option<time::SystemTime>,
+    ) -> models::Result<Vec<u8>> {
+        let (namespace, store) = (self.namespace.as_deref(), self.store.as_deref());
         let cmd_count = commands.len();
         let mut cmd_buffer = vec!();
         for cmd in commands {
-            let data = serialize::serialize(&cmd)?;
+            let data = match self.framed {
+                true => serialize::serialize_framed(&cmd)?,
+                false => serialize::serialize(&cmd)?,
+            };
             cmd_buffer.extend(data);
         }
 
@@ -67,12 +187,27 @@ impl KvsClient {
             keep_alive_value = 0u8;
         }
 
+        let deadline_ms = match deadline {
+            Some(deadline) => deadline.duration_since(time::UNIX_EPOCH)?.as_millis() as u64,
+            None => 0,
+        };
+
+        let version = match (self.framed, store, namespace, deadline) {
+            (true, _, _, _) => FRAMED_VERSION,
+            (false, Some(_), _, _) => STORE_VERSION,
+            (false, None, Some(_), _) => NAMESPACE_VERSION,
+            (false, None, None, Some(_)) => DEADLINE_VERSION,
+            (false, None, None, None) => NO_DEADLINE_VERSION,
+        };
         let header = models::RequestHeader{
-            version: CLIENT_VERSION,
+            version,
             keep_alive: keep_alive_value,
             command_count: cmd_count as u16,
             body_size: cmd_buffer.len() as u32,
             reserved: 0,
+            deadline: deadline_ms,
+            namespace: namespace.unwrap_or_default().to_owned(),
+            store: store.unwrap_or_default().to_owned(),
         };
 
         let mut buffer = vec!();
@@ -82,6 +217,15 @@ impl KvsClient {
         header.command_count.serialize(&mut buffer)?;
         header.body_size.serialize(&mut buffer)?;
         header.reserved.serialize(&mut buffer)?;
+        if header.version >= DEADLINE_VERSION {
+            header.deadline.serialize(&mut buffer)?;
+        }
+        if header.version >= NAMESPACE_VERSION {
+            header.namespace.serialize(&mut buffer)?;
+        }
+        if header.version >= STORE_VERSION {
+            header.store.serialize(&mut buffer)?;
+        }
         buffer.extend(cmd_buffer);
 
         Ok(buffer)
@@ -90,7 +234,7 @@ impl KvsClient {
     fn read_response(stream: &mut dyn io::Read) -> models::Result<models::Response> {
         let header =  models::ResponseHeader{
             version: serialize::ReadFromStream::deserialize(stream)?,
-            reserved_1: serialize::ReadFromStream::deserialize(stream)?,
+            flags: serialize::ReadFromStream::deserialize(stream)?,
             command_count: serialize::ReadFromStream::deserialize(stream)?,
             body_size: serialize::ReadFromStream::deserialize(stream)?,
             reserved_2: serialize::ReadFromStream::deserialize(stream)?,
@@ -103,22 +247,139 @@ impl KvsClient {
 
         let mut commands= Vec::new();
         commands.reserve(header.command_count as usize);
+        let framed = header.version >= FRAMED_VERSION;
         for _ in 0..header.command_count {
-            let cmd_type: u8 = serialize::ReadFromStream::deserialize(&mut body_reader)?;
+            let mut frame_reader;
+            let mut command_reader: &mut dyn io::Read = if framed {
+                frame_reader = io::Cursor::new(serialize::read_frame(&mut body_reader)?);
+                &mut frame_reader
+            } else {
+                &mut body_reader
+            };
+            let cmd_type: u8 = serialize::ReadFromStream::deserialize(&mut command_reader)?;
             match cmd_type {
                 b's' => {
                     commands.push(models::ResponseCommand::Set {});
                 },
+                b'n' => {
+                    let length = u64::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::Append { length });
+                },
+                b'x' => {
+                    let value = Option::<Vec<u8>>::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::GetSet { value });
+                },
+                b'y' => {
+                    let is_set = u8::deserialize(&mut command_reader)? != 0;
+                    commands.push(models::ResponseCommand::SetNx { is_set });
+                },
+                b'L' => {
+                    let length = u64::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::ListPush { length });
+                },
+                b'O' => {
+                    let value = Option::<Vec<u8>>::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::ListPop { value });
+                },
+                b'R' => {
+                    let values = Vec::<Vec<u8>>::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::ListRange { values });
+                },
+                b'H' => {
+                    let added = u64::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::HashSet { added });
+                },
+                b'G' => {
+                    let value = Option::<Vec<u8>>::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::HashGet { value });
+                },
+                b'D' => {
+                    let removed = u64::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::HashDelete { removed });
+                },
+                b'A' => {
+                    let fields = Vec::<(String, Vec<u8>)>::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::HashGetAll { fields });
+                },
+                b'S' => {
+                    let added = u64::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::SetAdd { added });
+                },
+                b'X' => {
+                    let removed = u64::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::SetRemove { removed });
+                },
+                b'I' => {
+                    let is_member = u8::deserialize(&mut command_reader)? != 0;
+                    commands.push(models::ResponseCommand::SetIsMember { is_member });
+                },
+                b'M' => {
+                    let members = Vec::<Vec<u8>>::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::SetMembers { members });
+                },
+                b'Z' => {
+                    let added = u64::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::SortedSetAdd { added });
+                },
+                b'Y' => {
+                    let removed = u64::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::SortedSetRemove { removed });
+                },
+                b'B' => {
+                    let members = Vec::<(Vec<u8>, f64)>::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::SortedSetRangeByScore { members });
+                },
                 b'r' => {
                     commands.push(models::ResponseCommand::Remove {});
                 },
                 b'g' => {
-                    let value = Option::<String>::deserialize(&mut body_reader)?;
+                    let value = Option::<Vec<u8>>::deserialize(&mut command_reader)?;
                     commands.push(models::ResponseCommand::Get { value: value });
                 },
+                b'v' => {
+                    let chunk = Option::<Vec<u8>>::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::GetStream { chunk });
+                },
                 b'z' => {
                     commands.push(models::ResponseCommand::Reset {});
                 },
+                b'd' => {
+                    let removed = u64::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::ResetPrefix { removed });
+                },
+                b'p' => {
+                    let token = String::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::PrepareRestore { token });
+                },
+                b'c' => {
+                    commands.push(models::ResponseCommand::CommitRestore {});
+                },
+                b'a' => {
+                    commands.push(models::ResponseCommand::AbortRestore {});
+                },
+                b'k' => {
+                    commands.push(models::ResponseCommand::Compact {});
+                },
+                b't' => {
+                    let stats = models::StorageStats::deserialize(&mut command_reader)?;
+                    let pool = models::ThreadPoolMetrics::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::Stats { stats, pool });
+                },
+                b'i' => {
+                    let info = models::ServerInfo::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::Info { info });
+                },
+                b'h' => {
+                    commands.push(models::ResponseCommand::PauseCompaction {});
+                },
+                b'u' => {
+                    commands.push(models::ResponseCommand::ResumeCompaction {});
+                },
+                b'e' => {
+                    let code = u16::deserialize(&mut command_reader)?;
+                    let message = String::deserialize(&mut command_reader)?;
+                    commands.push(models::ResponseCommand::Error { code, message });
+                },
                 _ => {
                     return Err(Box::new(io::Error::new(
                         io::ErrorKind::Other,
@@ -142,8 +403,20 @@ impl KvsClient {
     }
 
     pub fn execute(&mut self, commands: Vec<models::Command>, keep_alive: bool) -> models::Result<models::Response> {
-        let serialized_request = Self::serialize_request(commands, keep_alive)?;
-        let response = self.send(serialized_request)?;
+        self.execute_with_deadline(commands, keep_alive, None)
+    }
+
+    /// Executes the commands with an absolute deadline. The server skips the commands
+    /// it cannot start before the deadline and reports them with `ERROR_CODE_DEADLINE_EXCEEDED`.
+    pub fn execute_with_deadline(
+        &mut self,
+        commands: Vec<models::Command>,
+        keep_alive: bool,
+        deadline: Option<time::SystemTime>,
+    ) -> models::Result<models::Response> {
+        let read_only = commands.iter().all(models::Command::is_read_only);
+        let serialized_request = self.serialize_request(commands, keep_alive, deadline)?;
+        let response = self.send_request(&serialized_request, read_only)?;
 
         if !keep_alive {
             self.close()?;
@@ -152,20 +425,149 @@ impl KvsClient {
         Ok(response)
     }
     
+    /// Reads the value of the key `key` as a stream, `None` if the key doesn't exist. The server sends a large value
+    /// in several response frames, so the value is never read into a single response buffer.
+    /// The connection is kept alive and is busy until the stream is dropped. The request is not resent
+    /// on a lost connection.
+    pub fn get_stream(&mut self, key: String) -> models::Result<Option<ValueStream<'_>>> {
+        let commands = vec![models::Command::GetStream { key }];
+        let request_data = self.serialize_request(commands, true, None)?;
+        let mut socket = match self.socket_opt.take() {
+            Some(socket) => socket,
+            None => return Err(Box::new(io::Error::new(io::ErrorKind::NotConnected, "Client is not connected"))),
+        };
+        socket.write_all(request_data.as_slice())?;
+        socket.flush()?;
+
+        let mut stream = ValueStream {
+            client: self,
+            reader: Some(io::BufReader::new(socket)),
+            chunk: io::Cursor::new(Vec::new()),
+            continued: true,
+        };
+        match stream.read_frame()? {
+            Some(chunk) => {
+                stream.chunk = io::Cursor::new(chunk);
+                Ok(Some(stream))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Queues a command to be sent with the next `flush_queue` instead of sending it right away.
+    /// Returns the index of the command response in the flushed responses.
+    pub fn queue(&mut self, command: models::Command) -> usize {
+        self.queue.push(command);
+        self.queue.len() - 1
+    }
+
+    /// Number of the commands queued since the last `flush_queue`.
+    pub fn queued_count(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Sends all the queued commands and returns their responses in the queue order.
+    /// The commands are sent in as few requests as the protocol allows over the same connection,
+    /// so the connection is kept alive between the requests regardless of `keep_alive`.
+    /// The queue is cleared even if sending fails, the commands sent before a failure may be applied.
+    pub fn flush_queue(&mut self, keep_alive: bool) -> models::Result<Vec<models::ResponseCommand>> {
+        let mut commands = std::mem::take(&mut self.queue);
+        let mut responses = Vec::with_capacity(commands.len());
+        while !commands.is_empty() {
+            let rest = commands.split_off(commands.len().min(MAX_REQUEST_COMMANDS));
+            let commands_count = commands.len();
+            let response = self.execute(commands, keep_alive || !rest.is_empty())?;
+            if response.commands.len() != commands_count {
+                return Err(Box::from(format!(
+                    "Expected {} responses, got {}", commands_count, response.commands.len(),
+                )));
+            }
+            responses.extend(response.commands);
+            commands = rest;
+        }
+        Ok(responses)
+    }
+
+    /// Sends the request and reads the response. If the connection turns out to be closed by the server
+    /// before the request is written, reconnects and resends the request up to the max reconnects times.
+    /// A request lost after being written is not resent, as its commands may be already applied.
     pub fn send(&mut self, request_data: Vec<u8>) -> models::Result<models::Response> {
+        self.send_request(&request_data, false)
+    }
+
+    /// Same as `send`, also resending the request lost after being written if `read_only`.
+    fn send_request(&mut self, request_data: &[u8], read_only: bool) -> models::Result<models::Response> {
         if !self.is_connected() {
             // TODO autoconnect/disconnect
             return Err(Box::from(format!("Client is not ready")));
         }
 
-        let mut socket = self.socket_opt.as_mut().unwrap();
+        let mut reconnects = 0;
+        loop {
+            let result = match self.write_request(request_data) {
+                Ok(()) => match self.read_request_response() {
+                    Err(err) if !read_only => return Err(err),
+                    result => result,
+                },
+                Err(err) => Err(err),
+            };
+            match result {
+                Err(err) if reconnects < self.max_reconnects && Self::is_connection_lost(err.as_ref()) => {
+                    reconnects += 1;
+                    log::warn!("Connection is lost: {}. Reconnecting, attempt {}", err, reconnects);
+                    self.socket_opt = None;
+                    std::thread::sleep(RECONNECT_DELAY * reconnects as u32);
+                    if let Err(err) = self.reconnect() {
+                        log::warn!("Cannot reconnect: {}", err);
+                    }
+                },
+                result => return result,
+            }
+        }
+    }
+
+    /// Returns `true` for the errors of a connection closed by the server or of a server not accepting connections.
+    fn is_connection_lost(err: &(dyn std::error::Error + 'static)) -> bool {
+        match err.downcast_ref::<io::Error>() {
+            Some(io_err) => matches!(
+                io_err.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            None => false,
+        }
+    }
+
+    /// Connects again to the target of the latest connection.
+    fn reconnect(&mut self) -> models::Result<()> {
+        let (target, timeout) = self.target.clone().ok_or("Client was never connected")?;
+        match target {
+            ConnectTarget::Tcp { host, port } => self.connect(host, port, timeout),
+            ConnectTarget::Tls { host, port, options } => self.connect_tls(host, port, timeout, options),
+            #[cfg(unix)]
+            ConnectTarget::Unix(path) => self.connect_unix(&path, timeout),
+        }
+    }
+
+    fn write_request(&mut self, request_data: &[u8]) -> models::Result<()> {
+        let mut socket = match self.socket_opt.as_mut() {
+            Some(socket) => socket,
+            None => return Err(Box::new(io::Error::new(io::ErrorKind::NotConnected, "Client is not connected"))),
+        };
         
         let mut writer = io::BufWriter::new(&mut socket);
-        writer.write(request_data.as_slice())?;
+        writer.write_all(request_data)?;
         writer.flush()?;
-        drop(writer);
+        Ok(())
+    }
 
-        let mut reader = io::BufReader::new(&mut socket);
+    fn read_request_response(&mut self) -> models::Result<models::Response> {
+        let socket = self.socket_opt.as_mut().ok_or("Client is not connected")?;
+        let mut reader = io::BufReader::new(socket);
         let response = Self::read_response(&mut reader)?;
         drop(reader);
 
@@ -174,3 +576,64 @@ impl KvsClient {
         Ok(response)
     }
 }
+
+/// Reader of a value streamed by `KvsClient::get_stream`. The next response frames are read from the connection
+/// as the value is read. Dropping the stream before the end of the value closes the connection,
+/// the client reconnects for the next requests.
+pub struct ValueStream<'a> {
+    client: &'a mut KvsClient,
+    /// Connection taken from the client until the stream is dropped.
+    reader: Option<io::BufReader<Box<dyn Stream>>>,
+    /// Chunk of the latest frame.
+    chunk: io::Cursor<Vec<u8>>,
+    /// The server is to send another frame.
+    continued: bool,
+}
+
+impl ValueStream<'_> {
+    /// Reads the next response frame and returns its chunk of the value.
+    fn read_frame(&mut self) -> models::Result<Option<Vec<u8>>> {
+        let reader = self.reader.as_mut().ok_or("Value stream is closed")?;
+        let response = KvsClient::read_response(reader)?;
+        self.continued = response.header.flags & models::RESPONSE_FLAG_CONTINUED != 0;
+        match response.commands.into_iter().next().map(models::ResponseCommand::into_result) {
+            Some(Ok(models::ResponseCommand::GetStream { chunk })) => Ok(chunk),
+            Some(Err(err)) => Err(err),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+}
+
+impl io::Read for ValueStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.chunk.read(buf)?;
+            if read > 0 || buf.is_empty() || !self.continued {
+                return Ok(read);
+            }
+            let chunk = self.read_frame().map_err(|err| io::Error::other(err.to_string()))?;
+            self.chunk = io::Cursor::new(chunk.unwrap_or_default());
+        }
+    }
+}
+
+impl Drop for ValueStream<'_> {
+    fn drop(&mut self) {
+        let reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => return,
+        };
+        let mut socket = reader.into_inner();
+        if !self.continued {
+            self.client.socket_opt = Some(socket);
+            return;
+        }
+
+        // The rest of the frames are still coming, so the connection cannot be used for the next requests.
+        log::debug!("Value stream is dropped before its end, reconnecting");
+        let _ = socket.shutdown();
+        if let Err(err) = self.client.reconnect() {
+            log::warn!("Cannot reconnect: {}", err);
+        }
+    }
+}
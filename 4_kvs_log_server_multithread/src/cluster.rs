@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+use std::time;
+
+use crate::client::KvsClient;
+use crate::models;
+use crate::storage::bloom::fnv1a;
+
+const DEFAULT_VIRTUAL_NODES: usize = 100;
+/// Reconnects to a node before it is considered removed from the cluster.
+const NODE_MAX_RECONNECTS: usize = 1;
+
+struct ClusterNode {
+    host: String,
+    port: u32,
+    /// Keep-alive connection, opened on the first command routed to the node.
+    client: Option<KvsClient>,
+}
+
+/// Position on the hash ring. FNV-1a hashes of similar strings, like the virtual node names, differ
+/// mostly in the low bits, so they are mixed with the MurmurHash3 finalizer to spread over the ring.
+fn ring_hash(data: &[u8]) -> u64 {
+    let mut hash = fnv1a(data, 0);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+impl ClusterNode {
+    fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Client of a cluster of independent servers. The keys are mapped to the servers by consistent hashing
+/// with virtual nodes, so adding or removing a server moves only the keys of its ring segments.
+/// A server that cannot be reached is removed from the ring and the command is retried on the server
+/// owning the key after the rebalancing. The data of a removed server is not migrated.
+pub struct ClusterKvsClient {
+    nodes: Vec<ClusterNode>,
+    /// Hash ring of the virtual nodes, mapping their hashes to the node indexes.
+    ring: BTreeMap<u64, usize>,
+    virtual_nodes: usize,
+    timeout: time::Duration,
+}
+
+impl ClusterKvsClient {
+    /// Creates a client of the servers at `addrs`. The connections are opened lazily.
+    pub fn new(addrs: Vec<(String, u32)>, timeout: time::Duration) -> models::Result<ClusterKvsClient> {
+        Self::with_virtual_nodes(addrs, timeout, DEFAULT_VIRTUAL_NODES)
+    }
+
+    /// Same as `new` with `virtual_nodes` ring points per server. More points spread the keys more evenly.
+    pub fn with_virtual_nodes(
+        addrs: Vec<(String, u32)>,
+        timeout: time::Duration,
+        virtual_nodes: usize,
+    ) -> models::Result<ClusterKvsClient> {
+        if addrs.is_empty() {
+            return Err(Box::from("Cluster must have at least one server"));
+        }
+        if virtual_nodes == 0 {
+            return Err(Box::from("Virtual nodes count must be positive"));
+        }
+        let mut cluster = ClusterKvsClient { nodes: Vec::new(), ring: BTreeMap::new(), virtual_nodes, timeout };
+        for (host, port) in addrs {
+            cluster.add_node(host, port);
+        }
+        Ok(cluster)
+    }
+
+    /// Adds a server to the ring. The keys of its ring segments are routed to it from now on.
+    pub fn add_node(&mut self, host: String, port: u32) {
+        let node_idx = self.nodes.len();
+        self.nodes.push(ClusterNode { host, port, client: None });
+        let addr = self.nodes[node_idx].addr();
+        for virtual_idx in 0..self.virtual_nodes {
+            let hash = ring_hash(format!("{}#{}", addr, virtual_idx).as_bytes());
+            self.ring.insert(hash, node_idx);
+        }
+    }
+
+    /// Removes a server from the ring. Its keys are routed to the next servers on the ring.
+    /// Returns `false` if there is no such server in the ring.
+    pub fn remove_node(&mut self, host: &str, port: u32) -> bool {
+        match self.nodes.iter().position(|node| node.host == host && node.port == port) {
+            Some(node_idx) => {
+                self.remove_node_idx(node_idx);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn remove_node_idx(&mut self, node_idx: usize) {
+        log::warn!("Removing server {} from the cluster", self.nodes[node_idx].addr());
+        self.ring.retain(|_, idx| *idx != node_idx);
+        self.nodes[node_idx].client = None;
+    }
+
+    /// Addresses of the servers in the ring.
+    pub fn nodes(&self) -> Vec<String> {
+        let mut node_idxs: Vec<usize> = self.ring.values().copied().collect();
+        node_idxs.sort();
+        node_idxs.dedup();
+        node_idxs.iter().map(|idx| self.nodes[*idx].addr()).collect()
+    }
+
+    fn node_idx(&self, key: &str) -> Option<usize> {
+        let hash = ring_hash(key.as_bytes());
+        self.ring.range(hash..).next().or_else(|| self.ring.iter().next()).map(|(_, idx)| *idx)
+    }
+
+    /// Address of the server owning the key.
+    pub fn node_for(&self, key: &str) -> Option<String> {
+        self.node_idx(key).map(|idx| self.nodes[idx].addr())
+    }
+
+    /// Sends the command to the node over its keep-alive connection.
+    fn execute_on(&mut self, node_idx: usize, command: models::Command) -> models::Result<models::Response> {
+        let timeout = self.timeout;
+        let node = &mut self.nodes[node_idx];
+        if node.client.is_none() {
+            let mut client = KvsClient::new();
+            client.set_max_reconnects(NODE_MAX_RECONNECTS);
+            client.connect(node.host.clone(), node.port, timeout)?;
+            node.client = Some(client);
+        }
+        node.client.as_mut().unwrap().execute_one(command, true)
+    }
+
+    /// Executes the command on the server owning `key`. The servers failing to handle the request
+    /// are removed from the ring one by one until the command is handled or no servers are left.
+    /// Errors reported by a server for the command itself are returned as is.
+    fn execute(&mut self, key: &str, command: models::Command) -> models::Result<models::ResponseCommand> {
+        loop {
+            let node_idx = self.node_idx(key).ok_or("No servers left in the cluster")?;
+            match self.execute_on(node_idx, command.clone()) {
+                Ok(mut response) => {
+                    return match response.commands.pop() {
+                        Some(response_command) => response_command.into_result(),
+                        None => Err(Box::from("Unable to get the server response")),
+                    };
+                },
+                Err(err) => {
+                    log::warn!("Server {} failed to handle the request: {}", self.nodes[node_idx].addr(), err);
+                    self.remove_node_idx(node_idx);
+                },
+            }
+        }
+    }
+
+    /// Set key `key` to a binary value `value`.
+    pub fn set(&mut self, key: String, value: Vec<u8>) -> models::Result<()> {
+        let command = models::Command::Set { key: key.clone(), value };
+        self.execute(&key, command)?;
+        Ok(())
+    }
+
+    /// Gets value with the key `key`. Returns `None` if the key doesn't exist on the owning server.
+    pub fn get(&mut self, key: String) -> models::Result<Option<Vec<u8>>> {
+        let command = models::Command::Get { key: key.clone() };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::Get { value } => Ok(value),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Appends `suffix` to the value with the key `key` on the owning server. Returns the length of the new value.
+    pub fn append(&mut self, key: String, suffix: Vec<u8>) -> models::Result<u64> {
+        let command = models::Command::Append { key: key.clone(), suffix };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::Append { length } => Ok(length),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Sets key `key` to a binary value `value` on the owning server and returns the previous value.
+    pub fn get_set(&mut self, key: String, value: Vec<u8>) -> models::Result<Option<Vec<u8>>> {
+        let command = models::Command::GetSet { key: key.clone(), value };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::GetSet { value } => Ok(value),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Sets key `key` to a binary value `value` on the owning server only if the key doesn't exist.
+    /// Returns `true` if the value was set.
+    pub fn set_nx(&mut self, key: String, value: Vec<u8>) -> models::Result<bool> {
+        let command = models::Command::SetNx { key: key.clone(), value };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::SetNx { is_set } => Ok(is_set),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Pushes `values` to the front or to the back of the list with the key `key` on the owning server.
+    /// Returns the length of the list.
+    pub fn list_push(&mut self, key: String, values: Vec<Vec<u8>>, front: bool) -> models::Result<u64> {
+        let command = models::Command::ListPush { key: key.clone(), values, front };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::ListPush { length } => Ok(length),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Pops a value from the front or from the back of the list with the key `key` on the owning server.
+    pub fn list_pop(&mut self, key: String, front: bool) -> models::Result<Option<Vec<u8>>> {
+        let command = models::Command::ListPop { key: key.clone(), front };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::ListPop { value } => Ok(value),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Reads the values of the list with the key `key` between `start` and `stop` inclusive from the owning server.
+    pub fn list_range(&mut self, key: String, start: i64, stop: i64) -> models::Result<Vec<Vec<u8>>> {
+        let command = models::Command::ListRange { key: key.clone(), start, stop };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::ListRange { values } => Ok(values),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Sets the `fields` of the hash with the key `key` on the owning server. Returns the number of the added fields.
+    pub fn hash_set(&mut self, key: String, fields: Vec<(String, Vec<u8>)>) -> models::Result<u64> {
+        let command = models::Command::HashSet { key: key.clone(), fields };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::HashSet { added } => Ok(added),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Gets the value of the field `field` of the hash with the key `key` from the owning server.
+    pub fn hash_get(&mut self, key: String, field: String) -> models::Result<Option<Vec<u8>>> {
+        let command = models::Command::HashGet { key: key.clone(), field };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::HashGet { value } => Ok(value),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Removes the `fields` of the hash with the key `key` on the owning server.
+    /// Returns the number of the removed fields.
+    pub fn hash_delete(&mut self, key: String, fields: Vec<String>) -> models::Result<u64> {
+        let command = models::Command::HashDelete { key: key.clone(), fields };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::HashDelete { removed } => Ok(removed),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Gets all the fields of the hash with the key `key` from the owning server.
+    pub fn hash_get_all(&mut self, key: String) -> models::Result<Vec<(String, Vec<u8>)>> {
+        let command = models::Command::HashGetAll { key: key.clone() };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::HashGetAll { fields } => Ok(fields),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Adds the `members` to the set with the key `key` on the owning server.
+    /// Returns the number of the added members.
+    pub fn set_add(&mut self, key: String, members: Vec<Vec<u8>>) -> models::Result<u64> {
+        let command = models::Command::SetAdd { key: key.clone(), members };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::SetAdd { added } => Ok(added),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Removes the `members` of the set with the key `key` on the owning server.
+    /// Returns the number of the removed members.
+    pub fn set_remove(&mut self, key: String, members: Vec<Vec<u8>>) -> models::Result<u64> {
+        let command = models::Command::SetRemove { key: key.clone(), members };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::SetRemove { removed } => Ok(removed),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Checks whether `member` belongs to the set with the key `key` on the owning server.
+    pub fn set_is_member(&mut self, key: String, member: Vec<u8>) -> models::Result<bool> {
+        let command = models::Command::SetIsMember { key: key.clone(), member };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::SetIsMember { is_member } => Ok(is_member),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Gets the members of the set with the key `key` from the owning server.
+    pub fn set_members(&mut self, key: String) -> models::Result<Vec<Vec<u8>>> {
+        let command = models::Command::SetMembers { key: key.clone() };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::SetMembers { members } => Ok(members),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Adds the `members` with their scores to the sorted set with the key `key` on the owning server.
+    /// Returns the number of the added members.
+    pub fn sorted_set_add(&mut self, key: String, members: Vec<(Vec<u8>, f64)>) -> models::Result<u64> {
+        let command = models::Command::SortedSetAdd { key: key.clone(), members };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::SortedSetAdd { added } => Ok(added),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Removes the `members` of the sorted set with the key `key` on the owning server.
+    /// Returns the number of the removed members.
+    pub fn sorted_set_remove(&mut self, key: String, members: Vec<Vec<u8>>) -> models::Result<u64> {
+        let command = models::Command::SortedSetRemove { key: key.clone(), members };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::SortedSetRemove { removed } => Ok(removed),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Gets the members of the sorted set with the key `key` with the scores between `min` and `max` inclusive
+    /// from the owning server.
+    pub fn sorted_set_range_by_score(
+        &mut self,
+        key: String,
+        min: f64,
+        max: f64,
+    ) -> models::Result<Vec<(Vec<u8>, f64)>> {
+        let command = models::Command::SortedSetRangeByScore { key: key.clone(), min, max };
+        match self.execute(&key, command)? {
+            models::ResponseCommand::SortedSetRangeByScore { members } => Ok(members),
+            response => Err(Box::from(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Removes key `key` from the owning server.
+    pub fn remove(&mut self, key: String) -> models::Result<()> {
+        let command = models::Command::Remove { key: key.clone() };
+        self.execute(&key, command)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use nix::sys::signal;
+use nix::unistd;
+
+use crate::models::Result;
+
+/// Detaches the process from the terminal to run it in the background under the classic init systems.
+/// The process forks twice with a new session in between, so the daemon is not a session leader and cannot
+/// acquire a controlling terminal. The parents exit right away, the standard streams are reopened to `/dev/null`.
+/// The working directory is kept, so the relative paths of the options stay valid.
+/// Must be called before any thread is spawned, as only the calling thread survives a fork.
+pub fn daemonize() -> Result<()> {
+    // The first child is not a process group leader, so it can start a new session.
+    if let unistd::ForkResult::Parent { .. } = unsafe { unistd::fork()? } {
+        std::process::exit(0);
+    }
+    unistd::setsid()?;
+    if let unistd::ForkResult::Parent { .. } = unsafe { unistd::fork()? } {
+        std::process::exit(0);
+    }
+
+    let dev_null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    unistd::dup2_stdin(&dev_null)?;
+    unistd::dup2_stdout(&dev_null)?;
+    unistd::dup2_stderr(&dev_null)?;
+    Ok(())
+}
+
+/// Redirects stdout and stderr, and so the server log, to the end of the file at `path`.
+pub fn redirect_output(path: &Path) -> Result<()> {
+    let file = OpenOptions::new().append(true).create(true).open(path)
+        .map_err(|err| format!("Cannot open log file {}: {}", path.display(), err))?;
+    unistd::dup2_stdout(&file)?;
+    unistd::dup2_stderr(&file)?;
+    Ok(())
+}
+
+/// A file with the id of the server process. The file is removed once dropped, i.e. on a clean shutdown.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the id of the current process to `path`. Fails if the file belongs to a running process,
+    /// a file left by a killed server is overwritten.
+    pub fn create(path: &Path) -> Result<PidFile> {
+        if let Ok(content) = std::fs::read_to_string(path) {
+            let running_pid = content.trim().parse::<i32>().ok()
+                .filter(|pid| signal::kill(unistd::Pid::from_raw(*pid), None).is_ok());
+            if let Some(pid) = running_pid {
+                return Err(Box::from(format!("Server is already running with pid {} from {}", pid, path.display())));
+            }
+            log::warn!("Replacing stale pid file {}", path.display());
+        }
+
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(path)
+            .map_err(|err| format!("Cannot create pid file {}: {}", path.display(), err))?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(PidFile { path: path.to_path_buf() })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            log::warn!("Cannot remove pid file {}: {}", self.path.display(), err);
+        }
+    }
+}
@@ -2,10 +2,19 @@ pub use storage::KvLogStorage;
 pub use models::{Command, Result};
 pub use server::KvsServer;
 pub use client::KvsClient;
+pub use cluster::ClusterKvsClient;
+pub use kvs_common::{config, histogram};
 
 pub mod storage;
 pub mod models;
 pub mod server;
 pub mod client;
+pub mod cluster;
 pub mod threads;
+pub mod tls;
+pub mod stream;
+pub mod access_log;
+pub mod trace;
+#[cfg(unix)]
+pub mod daemon;
 mod serialize;
@@ -3,12 +3,261 @@ use std::fmt;
 
 pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// Error code of a command failed on the server side.
+pub const ERROR_CODE_INTERNAL: u16 = 1;
+/// Error code of a command skipped because the request deadline is exceeded.
+pub const ERROR_CODE_DEADLINE_EXCEEDED: u16 = 2;
+/// Error code of a command referring to an entity which doesn't exist, e.g. an unknown restore token.
+pub const ERROR_CODE_NOT_FOUND: u16 = 3;
+/// Error code of a command failed on damaged data, e.g. a malformed log record.
+pub const ERROR_CODE_CORRUPTION: u16 = 4;
+/// Error code of a command rejected because the server is out of capacity. Worth retrying later.
+pub const ERROR_CODE_SERVER_BUSY: u16 = 5;
+/// Error code of a command the client is not allowed to execute.
+pub const ERROR_CODE_UNAUTHORIZED: u16 = 6;
+/// Error code of a command working with a value of another type, e.g. a list command on a plain value.
+pub const ERROR_CODE_WRONG_TYPE: u16 = 7;
+/// Error code of a write command rejected by a storage opened read-only.
+pub const ERROR_CODE_READ_ONLY: u16 = 8;
+
+/// Status of a handled command. Successful commands get their regular responses,
+/// the failed ones get an error response with the numeric code of the status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusCode {
+    Ok,
+    Internal,
+    DeadlineExceeded,
+    NotFound,
+    Corruption,
+    ServerBusy,
+    Unauthorized,
+    WrongType,
+    ReadOnly,
+    /// An error code unknown to this client, e.g. sent by a newer server.
+    Unknown(u16),
+}
+
+impl StatusCode {
+    /// Converts an error response code into a status.
+    pub fn from_error_code(code: u16) -> StatusCode {
+        match code {
+            ERROR_CODE_INTERNAL => StatusCode::Internal,
+            ERROR_CODE_DEADLINE_EXCEEDED => StatusCode::DeadlineExceeded,
+            ERROR_CODE_NOT_FOUND => StatusCode::NotFound,
+            ERROR_CODE_CORRUPTION => StatusCode::Corruption,
+            ERROR_CODE_SERVER_BUSY => StatusCode::ServerBusy,
+            ERROR_CODE_UNAUTHORIZED => StatusCode::Unauthorized,
+            ERROR_CODE_WRONG_TYPE => StatusCode::WrongType,
+            ERROR_CODE_READ_ONLY => StatusCode::ReadOnly,
+            code => StatusCode::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusCode::Ok => write!(f, "ok"),
+            StatusCode::Internal => write!(f, "internal error"),
+            StatusCode::DeadlineExceeded => write!(f, "deadline exceeded"),
+            StatusCode::NotFound => write!(f, "not found"),
+            StatusCode::Corruption => write!(f, "corruption"),
+            StatusCode::ServerBusy => write!(f, "server busy"),
+            StatusCode::Unauthorized => write!(f, "unauthorized"),
+            StatusCode::WrongType => write!(f, "wrong type"),
+            StatusCode::ReadOnly => write!(f, "read only"),
+            StatusCode::Unknown(code) => write!(f, "unknown error {}", code),
+        }
+    }
+}
+
+/// The value of a `Command::SetFlagged` record is LZ4-compressed.
+pub const VALUE_FLAG_COMPRESSED: u8 = 1;
+
+/// Type of a collection value. The storages keep it apart from the value bytes, so a plain value
+/// is never mistaken for a collection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollectionType {
+    List,
+    Hash,
+    Set,
+    SortedSet,
+}
+
+impl CollectionType {
+    /// Code of the type in the `Command::SetCollection` records.
+    pub fn code(&self) -> u8 {
+        match self {
+            CollectionType::List => b'l',
+            CollectionType::Hash => b'h',
+            CollectionType::Set => b's',
+            CollectionType::SortedSet => b'z',
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<CollectionType> {
+        match code {
+            b'l' => Some(CollectionType::List),
+            b'h' => Some(CollectionType::Hash),
+            b's' => Some(CollectionType::Set),
+            b'z' => Some(CollectionType::SortedSet),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollectionType::List => "list",
+            CollectionType::Hash => "hash",
+            CollectionType::Set => "set",
+            CollectionType::SortedSet => "sorted set",
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum Command {
-    Set { key: String, value: String },
+    Set { key: String, value: Vec<u8> },
+    /// Log storage record of a set command with value flags. Not accepted by the server.
+    SetFlagged { key: String, flags: u8, value: Vec<u8> },
+    /// Log storage record of the sequence number and the time in milliseconds since the Unix epoch
+    /// of the records written after it. Not accepted by the server.
+    Sequence { seq: u64, timestamp_ms: u64 },
+    /// Log storage record setting the key to a collection of the type `collection_type`, `value` holds
+    /// the items encoded by `storage::collections::Collection::encode`. Written instead of the mutation records
+    /// of a collection to keep the log short. Not accepted by the server.
+    SetCollection { key: String, collection_type: CollectionType, value: Vec<u8> },
     Get { key: String },
+    /// Reads the value of the key like `Get`. The value of a single streamed get in a request is sent
+    /// in several response frames, see `RESPONSE_FLAG_CONTINUED`.
+    GetStream { key: String },
+    /// Appends `suffix` to the value of the key, the missing key is set to `suffix`.
+    Append { key: String, suffix: Vec<u8> },
+    /// Sets the value of the key and returns the previous value.
+    GetSet { key: String, value: Vec<u8> },
+    /// Sets the value of the key only if the key doesn't exist.
+    SetNx { key: String, value: Vec<u8> },
+    /// Pushes `values` one by one to the front or to the back of the list, the missing list is created.
+    ListPush { key: String, values: Vec<Vec<u8>>, front: bool },
+    /// Pops a value from the front or from the back of the list, the emptied list is removed.
+    ListPop { key: String, front: bool },
+    /// Reads the values between `start` and `stop` inclusive. Negative indexes count from the end of the list.
+    ListRange { key: String, start: i64, stop: i64 },
+    /// Sets the fields of the hash, the missing hash is created.
+    HashSet { key: String, fields: Vec<(String, Vec<u8>)> },
+    HashGet { key: String, field: String },
+    /// Removes the fields of the hash, the emptied hash is removed.
+    HashDelete { key: String, fields: Vec<String> },
+    HashGetAll { key: String },
+    /// Adds the members to the set, the missing set is created.
+    SetAdd { key: String, members: Vec<Vec<u8>> },
+    /// Removes the members of the set, the emptied set is removed.
+    SetRemove { key: String, members: Vec<Vec<u8>> },
+    SetIsMember { key: String, member: Vec<u8> },
+    SetMembers { key: String },
+    /// Adds the members with their scores to the sorted set, the missing sorted set is created.
+    SortedSetAdd { key: String, members: Vec<(Vec<u8>, f64)> },
+    /// Removes the members of the sorted set, the emptied sorted set is removed.
+    SortedSetRemove { key: String, members: Vec<Vec<u8>> },
+    /// Reads the members with the scores between `min` and `max` inclusive.
+    SortedSetRangeByScore { key: String, min: f64, max: f64 },
     Remove { key: String },
     Reset {},
+    /// Removes all the keys starting with the prefix.
+    ResetPrefix { prefix: String },
+    PrepareRestore { backup_dir: String },
+    CommitRestore { token: String },
+    AbortRestore { token: String },
+    Compact {},
+    Stats {},
+    Info {},
+    PauseCompaction {},
+    ResumeCompaction {},
+}
+
+/// Log storage statistics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageStats {
+    pub keys_count: u64,
+    pub segments_count: u64,
+    /// Total size of the log files in bytes.
+    pub disk_size: u64,
+    /// Size of the latest set records of the stored keys in bytes.
+    pub live_size: u64,
+    /// Estimated size of the stale records in bytes, reclaimable by compaction.
+    pub garbage_size: u64,
+    pub last_compaction: Option<std::time::SystemTime>,
+    /// Number of the log files rewritten or removed by compaction since the storage is opened.
+    pub compacted_segments: u64,
+    /// Size of the log files reclaimed by compaction in bytes.
+    pub reclaimed_size: u64,
+    pub compaction_failures: u64,
+    /// Total time spent compacting the log files, including the skipped and failed compactions.
+    pub compaction_time: std::time::Duration,
+}
+
+impl fmt::Display for StorageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let last_compaction = match self.last_compaction.map(|time| time.duration_since(std::time::UNIX_EPOCH)) {
+            Some(Ok(since_epoch)) => since_epoch.as_secs().to_string(),
+            _ => String::from("never"),
+        };
+        write!(
+            f,
+            "keys={} segments={} disk_size={} live_size={} garbage_size={} last_compaction={} \
+            compacted_segments={} reclaimed_size={} compaction_failures={} compaction_time_ms={}",
+            self.keys_count, self.segments_count, self.disk_size, self.live_size, self.garbage_size, last_compaction,
+            self.compacted_segments, self.reclaimed_size, self.compaction_failures, self.compaction_time.as_millis(),
+        )
+    }
+}
+
+/// Server thread pool load statistics.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ThreadPoolMetrics {
+    /// Number of the spawned jobs waiting for a free worker.
+    pub queue_depth: u64,
+    /// Number of the workers running a job at the moment.
+    pub busy_workers: u64,
+    /// Number of the jobs completed without a panic.
+    pub completed_jobs: u64,
+    pub panicked_jobs: u64,
+    /// Average time the started jobs waited for a free worker.
+    pub avg_wait_time: std::time::Duration,
+}
+
+impl fmt::Display for ThreadPoolMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pool_queue_depth={} pool_busy_workers={} pool_completed_jobs={} pool_panicked_jobs={} pool_avg_wait_us={}",
+            self.queue_depth, self.busy_workers, self.completed_jobs, self.panicked_jobs,
+            self.avg_wait_time.as_micros(),
+        )
+    }
+}
+
+/// Server status returned by the `Info` command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// Version of the server package.
+    pub version: String,
+    /// Time since the server is created.
+    pub uptime: std::time::Duration,
+    pub compaction_paused: bool,
+    /// Storage statistics with the number of the keys and the disk usage.
+    pub stats: StorageStats,
+    pub pool: ThreadPoolMetrics,
+}
+
+impl fmt::Display for ServerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "version={} uptime_s={} compaction_paused={} {} {}",
+            self.version, self.uptime.as_secs(), self.compaction_paused, self.stats, self.pool,
+        )
+    }
 }
 
 #[derive(Clone)]
@@ -26,13 +275,148 @@ impl std::fmt::Display for EngineType {
     }
 }
 
+impl Command {
+    /// Short lowercase name of the command type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Set { .. } => "set",
+            Command::SetFlagged { .. } => "set_flagged",
+            Command::Sequence { .. } => "sequence",
+            Command::SetCollection { .. } => "set_collection",
+            Command::Get { .. } => "get",
+            Command::GetStream { .. } => "get_stream",
+            Command::Append { .. } => "append",
+            Command::GetSet { .. } => "get_set",
+            Command::SetNx { .. } => "set_nx",
+            Command::ListPush { .. } => "list_push",
+            Command::ListPop { .. } => "list_pop",
+            Command::ListRange { .. } => "list_range",
+            Command::HashSet { .. } => "hash_set",
+            Command::HashGet { .. } => "hash_get",
+            Command::HashDelete { .. } => "hash_delete",
+            Command::HashGetAll { .. } => "hash_get_all",
+            Command::SetAdd { .. } => "set_add",
+            Command::SetRemove { .. } => "set_remove",
+            Command::SetIsMember { .. } => "set_is_member",
+            Command::SetMembers { .. } => "set_members",
+            Command::SortedSetAdd { .. } => "sorted_set_add",
+            Command::SortedSetRemove { .. } => "sorted_set_remove",
+            Command::SortedSetRangeByScore { .. } => "sorted_set_range_by_score",
+            Command::Remove { .. } => "remove",
+            Command::Reset {} => "reset",
+            Command::ResetPrefix { .. } => "reset_prefix",
+            Command::PrepareRestore { .. } => "prepare_restore",
+            Command::CommitRestore { .. } => "commit_restore",
+            Command::AbortRestore { .. } => "abort_restore",
+            Command::Compact {} => "compact",
+            Command::Stats {} => "stats",
+            Command::Info {} => "info",
+            Command::PauseCompaction {} => "pause_compaction",
+            Command::ResumeCompaction {} => "resume_compaction",
+        }
+    }
+
+    /// Whether the command doesn't change the stored data, so it's safe to execute it again.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Command::Get { .. }
+                | Command::GetStream { .. }
+                | Command::ListRange { .. }
+                | Command::HashGet { .. }
+                | Command::HashGetAll { .. }
+                | Command::SetIsMember { .. }
+                | Command::SetMembers { .. }
+                | Command::SortedSetRangeByScore { .. }
+                | Command::Stats {}
+                | Command::Info {}
+        )
+    }
+
+    /// Key the command works with, `None` for the commands of the whole storage.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Command::Set { key, .. }
+            | Command::SetFlagged { key, .. }
+            | Command::SetCollection { key, .. }
+            | Command::Get { key }
+            | Command::GetStream { key }
+            | Command::Append { key, .. }
+            | Command::GetSet { key, .. }
+            | Command::SetNx { key, .. }
+            | Command::ListPush { key, .. }
+            | Command::ListPop { key, .. }
+            | Command::ListRange { key, .. }
+            | Command::HashSet { key, .. }
+            | Command::HashGet { key, .. }
+            | Command::HashDelete { key, .. }
+            | Command::HashGetAll { key }
+            | Command::SetAdd { key, .. }
+            | Command::SetRemove { key, .. }
+            | Command::SetIsMember { key, .. }
+            | Command::SetMembers { key }
+            | Command::SortedSetAdd { key, .. }
+            | Command::SortedSetRemove { key, .. }
+            | Command::SortedSetRangeByScore { key, .. }
+            | Command::Remove { key } => Some(key),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Command::Set {key, value} => write!(f, "Set<key={}, value={}>", key, value),
+            Command::Set {key, value} => write!(f, "Set<key={}, value={}>", key, String::from_utf8_lossy(value)),
+            Command::SetFlagged {key, flags, value} => {
+                write!(f, "SetFlagged<key={}, flags={}, value_size={}>", key, flags, value.len())
+            },
+            Command::Sequence {seq, timestamp_ms} => write!(f, "Sequence<seq={}, timestamp_ms={}>", seq, timestamp_ms),
+            Command::SetCollection {key, collection_type, value} => {
+                write!(f, "SetCollection<key={}, type={}, value_size={}>", key, collection_type.name(), value.len())
+            },
             Command::Get {key} => write!(f, "Get<key={}>", key),
+            Command::GetStream {key} => write!(f, "GetStream<key={}>", key),
+            Command::Append {key, suffix} => {
+                write!(f, "Append<key={}, suffix={}>", key, String::from_utf8_lossy(suffix))
+            },
+            Command::GetSet {key, value} => write!(f, "GetSet<key={}, value={}>", key, String::from_utf8_lossy(value)),
+            Command::SetNx {key, value} => write!(f, "SetNx<key={}, value={}>", key, String::from_utf8_lossy(value)),
+            Command::ListPush {key, values, front} => {
+                write!(f, "ListPush<key={}, values_count={}, front={}>", key, values.len(), front)
+            },
+            Command::ListPop {key, front} => write!(f, "ListPop<key={}, front={}>", key, front),
+            Command::ListRange {key, start, stop} => write!(f, "ListRange<key={}, start={}, stop={}>", key, start, stop),
+            Command::HashSet {key, fields} => write!(f, "HashSet<key={}, fields_count={}>", key, fields.len()),
+            Command::HashGet {key, field} => write!(f, "HashGet<key={}, field={}>", key, field),
+            Command::HashDelete {key, fields} => write!(f, "HashDelete<key={}, fields={}>", key, fields.join(",")),
+            Command::HashGetAll {key} => write!(f, "HashGetAll<key={}>", key),
+            Command::SetAdd {key, members} => write!(f, "SetAdd<key={}, members_count={}>", key, members.len()),
+            Command::SetRemove {key, members} => write!(f, "SetRemove<key={}, members_count={}>", key, members.len()),
+            Command::SetIsMember {key, member} => {
+                write!(f, "SetIsMember<key={}, member={}>", key, String::from_utf8_lossy(member))
+            },
+            Command::SetMembers {key} => write!(f, "SetMembers<key={}>", key),
+            Command::SortedSetAdd {key, members} => {
+                write!(f, "SortedSetAdd<key={}, members_count={}>", key, members.len())
+            },
+            Command::SortedSetRemove {key, members} => {
+                write!(f, "SortedSetRemove<key={}, members_count={}>", key, members.len())
+            },
+            Command::SortedSetRangeByScore {key, min, max} => {
+                write!(f, "SortedSetRangeByScore<key={}, min={}, max={}>", key, min, max)
+            },
             Command::Remove {key} => write!(f, "Remove<key={}>", key),
             Command::Reset {} => write!(f, "Reset"),
+            Command::ResetPrefix {prefix} => write!(f, "ResetPrefix<prefix={}>", prefix),
+            Command::PrepareRestore {backup_dir} => write!(f, "PrepareRestore<backup_dir={}>", backup_dir),
+            Command::CommitRestore {token} => write!(f, "CommitRestore<token={}>", token),
+            Command::AbortRestore {token} => write!(f, "AbortRestore<token={}>", token),
+            Command::Compact {} => write!(f, "Compact"),
+            Command::Stats {} => write!(f, "Stats"),
+            Command::Info {} => write!(f, "Info"),
+            Command::PauseCompaction {} => write!(f, "PauseCompaction"),
+            Command::ResumeCompaction {} => write!(f, "ResumeCompaction"),
         }
     }
 }
@@ -43,6 +427,25 @@ pub struct RequestHeader {
     pub command_count: u16,
     pub body_size: u32,
     pub reserved: u32,
+    /// Absolute request deadline in milliseconds since the Unix epoch, 0 if not set.
+    /// Sent only since the protocol version 2.
+    pub deadline: u64,
+    /// Namespace of the request commands, empty for the default namespace.
+    /// Sent only since the protocol version 3.
+    pub namespace: String,
+    /// Named store of the request commands, empty for the default store of the server.
+    /// Sent only since the protocol version 4.
+    pub store: String,
+}
+
+impl RequestHeader {
+    /// Returns the request deadline if set.
+    pub fn deadline(&self) -> Option<std::time::SystemTime> {
+        if self.deadline == 0 {
+            return None;
+        }
+        Some(std::time::UNIX_EPOCH + std::time::Duration::from_millis(self.deadline))
+    }
 }
 
 pub struct Request {
@@ -54,29 +457,125 @@ impl fmt::Display for Request {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "<version={}; keep_alive={}; command_count={}, body_size={}>",
+            "<version={}; keep_alive={}; command_count={}, body_size={}, deadline={}, store={}, namespace={}>",
             self.header.version,
             self.header.keep_alive,
             self.header.command_count,
             self.header.body_size,
+            self.header.deadline,
+            self.header.store,
+            self.header.namespace,
         )
     }
 }
 
+/// Another response frame follows the frame with this flag. The frames are sent for a request of a single
+/// `Command::GetStream`, each of them with a `ResponseCommand::GetStream` of the next chunk of the value.
+pub const RESPONSE_FLAG_CONTINUED: u8 = 1;
+
 pub struct ResponseHeader {
     pub version: u8,
-    pub reserved_1: u8,
+    /// `RESPONSE_FLAG_*` bits.
+    pub flags: u8,
     pub command_count: u16,
     pub body_size: u32,
     pub reserved_2: u32,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq)]
 pub enum ResponseCommand {
     Set {},
-    Get { value: Option<String> },
+    Get { value: Option<Vec<u8>> },
+    /// A chunk of the value, `None` if the key doesn't exist.
+    GetStream { chunk: Option<Vec<u8>> },
+    /// Length of the value after the append.
+    Append { length: u64 },
+    /// Previous value of the key, `None` if the key didn't exist.
+    GetSet { value: Option<Vec<u8>> },
+    /// `is_set` is `false` if the key already existed and kept its value.
+    SetNx { is_set: bool },
+    /// Length of the list after the push.
+    ListPush { length: u64 },
+    /// Popped value, `None` if the list doesn't exist.
+    ListPop { value: Option<Vec<u8>> },
+    ListRange { values: Vec<Vec<u8>> },
+    /// Number of the fields added to the hash, the rest of the set fields are overwritten.
+    HashSet { added: u64 },
+    HashGet { value: Option<Vec<u8>> },
+    /// Number of the removed fields.
+    HashDelete { removed: u64 },
+    /// Fields of the hash sorted by the field names.
+    HashGetAll { fields: Vec<(String, Vec<u8>)> },
+    /// Number of the members added to the set.
+    SetAdd { added: u64 },
+    /// Number of the removed members.
+    SetRemove { removed: u64 },
+    SetIsMember { is_member: bool },
+    /// Members of the set sorted in the byte order.
+    SetMembers { members: Vec<Vec<u8>> },
+    /// Number of the members added to the sorted set, the scores of the rest are updated.
+    SortedSetAdd { added: u64 },
+    /// Number of the removed members.
+    SortedSetRemove { removed: u64 },
+    /// Members with their scores ordered by the scores.
+    SortedSetRangeByScore { members: Vec<(Vec<u8>, f64)> },
     Remove {},
     Reset {},
+    /// Number of the removed keys.
+    ResetPrefix { removed: u64 },
+    PrepareRestore { token: String },
+    CommitRestore {},
+    AbortRestore {},
+    Compact {},
+    Stats { stats: StorageStats, pool: ThreadPoolMetrics },
+    Info { info: ServerInfo },
+    PauseCompaction {},
+    ResumeCompaction {},
+    Error { code: u16, message: String },
+}
+
+/// A command failed on the server side. The storage also returns it for the failures with a specific error code,
+/// the rest of the errors are reported with `ERROR_CODE_INTERNAL`.
+#[derive(Debug)]
+pub struct CommandError {
+    pub code: u16,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: u16, message: String) -> CommandError {
+        CommandError { code, message }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        StatusCode::from_error_code(self.code)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Command failed with code {} ({}): {}", self.code, self.status(), self.message)
+    }
+}
+
+impl Error for CommandError {}
+
+impl ResponseCommand {
+    /// Status of the command, `StatusCode::Ok` for all the responses except errors.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ResponseCommand::Error { code, .. } => StatusCode::from_error_code(*code),
+            _ => StatusCode::Ok,
+        }
+    }
+
+    /// Converts an error response into `CommandError`.
+    pub fn into_result(self) -> Result<ResponseCommand> {
+        match self {
+            ResponseCommand::Error { code, message } => Err(Box::new(CommandError { code, message })),
+            response => Ok(response),
+        }
+    }
 }
 
 pub struct Response {
@@ -2,7 +2,32 @@ use std::io;
 use std::result;
 use std::mem;
 
-use crate::models::{Command, Result};
+use crate::models::{CollectionType, Command, Result, ServerInfo, StorageStats, ThreadPoolMetrics};
+use crate::storage::bloom::fnv1a;
+
+/// First bytes of a v2 record frame. v1 records start with an ASCII command code, so the formats never clash.
+const FRAME_MAGIC: [u8; 2] = [0xC5, 0x4B];
+/// Frame body size limit, the same as the size limit of a v1 value.
+const FRAME_MAX_BODY_SIZE: u64 = u32::MAX as u64;
+/// First bytes of a segment file header. The first byte differs from the first bytes of the records of any format,
+/// so a segment written before the header was introduced is told apart by its first record.
+const SEGMENT_MAGIC: [u8; 2] = [0xC6, 0x4B];
+/// Format version written to the header of the new segment files.
+pub const SEGMENT_FORMAT_VERSION: u32 = 1;
+/// Format version of the segments without a header, which may hold the records of any format.
+pub const LEGACY_SEGMENT_FORMAT_VERSION: u32 = 0;
+/// Size of the segment file header: the magic bytes and a 4 bytes format version.
+pub const SEGMENT_HEADER_SIZE: u64 = (SEGMENT_MAGIC.len() + size_of::<u32>()) as u64;
+
+/// Binary format of a command record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// A command code followed by the fields with 4 bytes length prefixes.
+    V1,
+    /// A frame of the magic bytes, a varint body size, the body and a 4 bytes checksum of the body.
+    /// The body is a command code followed by the fields with varint length prefixes.
+    V2,
+}
 
 
 pub trait ReadFromStream {
@@ -18,11 +43,22 @@ macro_rules! impl_read_from_stream {
                     const TYPE_SIZE: usize = mem::size_of::<$t>();
                     let mut buffer = [0u8; TYPE_SIZE];
 
-                    let bytes_count = stream.read(&mut buffer)?;
+                    // A single read may return a part of the value, e.g. at the end of a buffered reader's buffer.
+                    let mut bytes_count = 0;
+                    while bytes_count < TYPE_SIZE {
+                        match stream.read(&mut buffer[bytes_count..]) {
+                            Ok(0) => break,
+                            Ok(count) => bytes_count += count,
+                            Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    // Nothing to read at all means the stream is closed, e.g. by the other side of a connection.
+                    let kind = if bytes_count == 0 { io::ErrorKind::UnexpectedEof } else { io::ErrorKind::InvalidData };
                     if bytes_count != TYPE_SIZE {
                         return Err(
                             io::Error::new(
-                                io::ErrorKind::InvalidData,
+                                kind,
                                 format!("Not enough bytes to read {}", std::any::type_name::<$t>()),
                             ),
                         );
@@ -35,7 +71,7 @@ macro_rules! impl_read_from_stream {
     };
 }
 
-impl_read_from_stream!(u8, u16, u32, u64);
+impl_read_from_stream!(u8, u16, u32, u64, i64, f64);
 
 
 impl<T: ReadFromStream> ReadFromStream for Option<T> {
@@ -62,16 +98,55 @@ impl<T: ReadFromStream> ReadFromStream for Option<T> {
 }
 
 
-impl ReadFromStream for String {
-    fn deserialize(stream: &mut dyn io::Read) -> result::Result<String, io::Error> {
+impl ReadFromStream for Vec<u8> {
+    fn deserialize(stream: &mut dyn io::Read) -> result::Result<Vec<u8>, io::Error> {
         let mut size_buffer = [0u8; 4];
         stream.read_exact(&mut size_buffer)?;
         let size = u32::from_be_bytes(size_buffer) as usize;
 
-        let mut str_buffer = vec![0u8; size];
-        str_buffer.reserve(size);
-        stream.read_exact(&mut str_buffer[..])?;
+        let mut buffer = vec![0u8; size];
+        stream.read_exact(&mut buffer[..])?;
+        Ok(buffer)
+    }
+}
+
+
+impl ReadFromStream for Vec<Vec<u8>> {
+    fn deserialize(stream: &mut dyn io::Read) -> result::Result<Vec<Vec<u8>>, io::Error> {
+        let count = u32::deserialize(stream)?;
+        (0..count).map(|_| Vec::<u8>::deserialize(stream)).collect()
+    }
+}
+
+
+impl ReadFromStream for Vec<String> {
+    fn deserialize(stream: &mut dyn io::Read) -> result::Result<Vec<String>, io::Error> {
+        let count = u32::deserialize(stream)?;
+        (0..count).map(|_| String::deserialize(stream)).collect()
+    }
+}
+
+
+impl ReadFromStream for Vec<(String, Vec<u8>)> {
+    fn deserialize(stream: &mut dyn io::Read) -> result::Result<Vec<(String, Vec<u8>)>, io::Error> {
+        let count = u32::deserialize(stream)?;
+        (0..count).map(|_| Ok((String::deserialize(stream)?, Vec::<u8>::deserialize(stream)?))).collect()
+    }
+}
+
+
+impl ReadFromStream for Vec<(Vec<u8>, f64)> {
+    fn deserialize(stream: &mut dyn io::Read) -> result::Result<Vec<(Vec<u8>, f64)>, io::Error> {
+        let count = u32::deserialize(stream)?;
+        (0..count).map(|_| Ok((Vec::<u8>::deserialize(stream)?, f64::deserialize(stream)?))).collect()
+    }
+}
+
 
+impl ReadFromStream for String {
+    fn deserialize(stream: &mut dyn io::Read) -> result::Result<String, io::Error> {
+        // Strings share the length-prefixed layout of the byte arrays.
+        let str_buffer = Vec::<u8>::deserialize(stream)?;
         match String::from_utf8(str_buffer) {
             Ok(result) => Ok(result),
             Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
@@ -80,6 +155,77 @@ impl ReadFromStream for String {
 }
 
 
+/// Size of the LEB128 varint encoding of `value` in bytes.
+pub fn varint_size(mut value: u64) -> usize {
+    let mut size = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        size += 1;
+    }
+    size
+}
+
+
+/// Writes `value` as a LEB128 varint: 7 bits per byte starting from the lowest ones,
+/// the high bit of a byte is set if more bytes follow.
+pub fn write_varint(mut value: u64, buffer: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+
+pub fn read_varint(stream: &mut dyn io::Read) -> result::Result<u64, io::Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = u8::deserialize(stream)?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "Varint is too long"))
+}
+
+
+pub(crate) fn write_varint_bytes(bytes: &[u8], buffer: &mut Vec<u8>) {
+    write_varint(bytes.len() as u64, buffer);
+    buffer.extend(bytes);
+}
+
+
+pub(crate) fn read_varint_bytes(stream: &mut dyn io::Read) -> result::Result<Vec<u8>, io::Error> {
+    let size = read_varint(stream)?;
+    if size > FRAME_MAX_BODY_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Field size {} is too large", size)));
+    }
+    let mut buffer = vec![0u8; size as usize];
+    stream.read_exact(&mut buffer[..])?;
+    Ok(buffer)
+}
+
+
+fn read_varint_string(stream: &mut dyn io::Read) -> result::Result<String, io::Error> {
+    String::from_utf8(read_varint_bytes(stream)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+
+fn read_collection_type(stream: &mut dyn io::Read) -> result::Result<CollectionType, io::Error> {
+    let code = u8::deserialize(stream)?;
+    CollectionType::from_code(code)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Unknown collection type {}", code)))
+}
+
+
+/// Checksum of a frame body, the lower half of its FNV-1a hash.
+fn frame_checksum(body: &[u8]) -> u32 {
+    fnv1a(body, 0) as u32
+}
+
+
 pub trait WriteToStream {
     fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error>;
 }
@@ -97,7 +243,7 @@ macro_rules! impl_write_to_stream {
     };
 }
 
-impl_write_to_stream!(u8, u16, u32, u64);
+impl_write_to_stream!(u8, u16, u32, u64, i64, f64);
 
 
 impl<T: WriteToStream> WriteToStream for Option<T> {
@@ -114,16 +260,166 @@ impl<T: WriteToStream> WriteToStream for Option<T> {
 }
 
 
-impl WriteToStream for String {
+impl WriteToStream for [u8] {
     fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
         let len = self.len() as u32;
         buffer.extend(len.to_be_bytes());
-        buffer.extend(self.as_bytes());
+        buffer.extend(self);
+        Ok(())
+    }
+}
+
+
+impl WriteToStream for Vec<u8> {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+        self.as_slice().serialize(buffer)
+    }
+}
+
+
+impl WriteToStream for Vec<Vec<u8>> {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+        (self.len() as u32).serialize(buffer)?;
+        for value in self {
+            value.serialize(buffer)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl WriteToStream for Vec<String> {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+        (self.len() as u32).serialize(buffer)?;
+        for value in self {
+            value.serialize(buffer)?;
+        }
         Ok(())
     }
 }
 
 
+impl WriteToStream for Vec<(String, Vec<u8>)> {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+        (self.len() as u32).serialize(buffer)?;
+        for (field, value) in self {
+            field.serialize(buffer)?;
+            value.serialize(buffer)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl WriteToStream for Vec<(Vec<u8>, f64)> {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+        (self.len() as u32).serialize(buffer)?;
+        for (member, score) in self {
+            member.serialize(buffer)?;
+            score.serialize(buffer)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl WriteToStream for String {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+        self.as_bytes().serialize(buffer)
+    }
+}
+
+
+impl ReadFromStream for StorageStats {
+    fn deserialize(stream: &mut dyn io::Read) -> result::Result<StorageStats, io::Error> {
+        Ok(StorageStats {
+            keys_count: u64::deserialize(stream)?,
+            segments_count: u64::deserialize(stream)?,
+            disk_size: u64::deserialize(stream)?,
+            live_size: u64::deserialize(stream)?,
+            garbage_size: u64::deserialize(stream)?,
+            // Milliseconds since the Unix epoch.
+            last_compaction: Option::<u64>::deserialize(stream)?
+                .map(|millis| std::time::UNIX_EPOCH + std::time::Duration::from_millis(millis)),
+            compacted_segments: u64::deserialize(stream)?,
+            reclaimed_size: u64::deserialize(stream)?,
+            compaction_failures: u64::deserialize(stream)?,
+            // Microseconds.
+            compaction_time: std::time::Duration::from_micros(u64::deserialize(stream)?),
+        })
+    }
+}
+
+
+impl WriteToStream for StorageStats {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+        self.keys_count.serialize(buffer)?;
+        self.segments_count.serialize(buffer)?;
+        self.disk_size.serialize(buffer)?;
+        self.live_size.serialize(buffer)?;
+        self.garbage_size.serialize(buffer)?;
+        let last_compaction = self.last_compaction
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_millis() as u64);
+        last_compaction.serialize(buffer)?;
+        self.compacted_segments.serialize(buffer)?;
+        self.reclaimed_size.serialize(buffer)?;
+        self.compaction_failures.serialize(buffer)?;
+        (self.compaction_time.as_micros() as u64).serialize(buffer)
+    }
+}
+
+
+impl ReadFromStream for ThreadPoolMetrics {
+    fn deserialize(stream: &mut dyn io::Read) -> result::Result<ThreadPoolMetrics, io::Error> {
+        Ok(ThreadPoolMetrics {
+            queue_depth: u64::deserialize(stream)?,
+            busy_workers: u64::deserialize(stream)?,
+            completed_jobs: u64::deserialize(stream)?,
+            panicked_jobs: u64::deserialize(stream)?,
+            // Microseconds.
+            avg_wait_time: std::time::Duration::from_micros(u64::deserialize(stream)?),
+        })
+    }
+}
+
+
+impl WriteToStream for ThreadPoolMetrics {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+        self.queue_depth.serialize(buffer)?;
+        self.busy_workers.serialize(buffer)?;
+        self.completed_jobs.serialize(buffer)?;
+        self.panicked_jobs.serialize(buffer)?;
+        (self.avg_wait_time.as_micros() as u64).serialize(buffer)
+    }
+}
+
+
+impl ReadFromStream for ServerInfo {
+    fn deserialize(stream: &mut dyn io::Read) -> result::Result<ServerInfo, io::Error> {
+        Ok(ServerInfo {
+            version: String::deserialize(stream)?,
+            // Seconds.
+            uptime: std::time::Duration::from_secs(u64::deserialize(stream)?),
+            compaction_paused: u8::deserialize(stream)? != 0,
+            stats: StorageStats::deserialize(stream)?,
+            pool: ThreadPoolMetrics::deserialize(stream)?,
+        })
+    }
+}
+
+
+impl WriteToStream for ServerInfo {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+        self.version.serialize(buffer)?;
+        self.uptime.as_secs().serialize(buffer)?;
+        (self.compaction_paused as u8).serialize(buffer)?;
+        self.stats.serialize(buffer)?;
+        self.pool.serialize(buffer)
+    }
+}
+
+
 pub fn serialize(command: &Command) -> result::Result<Vec<u8>, io::Error> {
     match command {
         Command::Set { key, value } => {
@@ -133,12 +429,161 @@ pub fn serialize(command: &Command) -> result::Result<Vec<u8>, io::Error> {
             value.serialize(&mut buffer)?;
             return Ok(buffer);
         },
+        Command::SetFlagged { key, flags, value } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"f");
+            key.serialize(&mut buffer)?;
+            flags.serialize(&mut buffer)?;
+            value.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::Sequence { seq, timestamp_ms } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"q");
+            seq.serialize(&mut buffer)?;
+            timestamp_ms.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::SetCollection { key, collection_type, value } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"C");
+            key.serialize(&mut buffer)?;
+            collection_type.code().serialize(&mut buffer)?;
+            value.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
         Command::Get { key } => {
             let mut buffer: Vec<u8> = Vec::new();
             buffer.extend(b"g");
             key.serialize(&mut buffer)?;
             return Ok(buffer);
         },
+        Command::GetStream { key } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"v");
+            key.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::Append { key, suffix } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"n");
+            key.serialize(&mut buffer)?;
+            suffix.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::GetSet { key, value } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"x");
+            key.serialize(&mut buffer)?;
+            value.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::SetNx { key, value } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"y");
+            key.serialize(&mut buffer)?;
+            value.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::ListPush { key, values, front } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"L");
+            key.serialize(&mut buffer)?;
+            (*front as u8).serialize(&mut buffer)?;
+            values.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::ListPop { key, front } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"O");
+            key.serialize(&mut buffer)?;
+            (*front as u8).serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::ListRange { key, start, stop } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"R");
+            key.serialize(&mut buffer)?;
+            start.serialize(&mut buffer)?;
+            stop.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::HashSet { key, fields } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"H");
+            key.serialize(&mut buffer)?;
+            fields.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::HashGet { key, field } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"G");
+            key.serialize(&mut buffer)?;
+            field.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::HashDelete { key, fields } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"D");
+            key.serialize(&mut buffer)?;
+            fields.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::HashGetAll { key } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"A");
+            key.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::SetAdd { key, members } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"S");
+            key.serialize(&mut buffer)?;
+            members.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::SetRemove { key, members } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"X");
+            key.serialize(&mut buffer)?;
+            members.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::SetIsMember { key, member } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"I");
+            key.serialize(&mut buffer)?;
+            member.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::SetMembers { key } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"M");
+            key.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::SortedSetAdd { key, members } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"Z");
+            key.serialize(&mut buffer)?;
+            members.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::SortedSetRemove { key, members } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"Y");
+            key.serialize(&mut buffer)?;
+            members.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::SortedSetRangeByScore { key, min, max } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"B");
+            key.serialize(&mut buffer)?;
+            min.serialize(&mut buffer)?;
+            max.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
         Command::Remove { key } => {
             let mut buffer: Vec<u8> = Vec::new();
             buffer.extend(b"r");
@@ -150,20 +595,506 @@ pub fn serialize(command: &Command) -> result::Result<Vec<u8>, io::Error> {
             buffer.extend(b"z");
             return Ok(buffer);
         },
+        Command::ResetPrefix { prefix } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"d");
+            prefix.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::PrepareRestore { backup_dir } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"p");
+            backup_dir.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::CommitRestore { token } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"c");
+            token.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::AbortRestore { token } => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"a");
+            token.serialize(&mut buffer)?;
+            return Ok(buffer);
+        },
+        Command::Compact {} => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"k");
+            return Ok(buffer);
+        },
+        Command::Stats {} => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"t");
+            return Ok(buffer);
+        },
+        Command::Info {} => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"i");
+            return Ok(buffer);
+        },
+        Command::PauseCompaction {} => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"h");
+            return Ok(buffer);
+        },
+        Command::ResumeCompaction {} => {
+            let mut buffer: Vec<u8> = Vec::new();
+            buffer.extend(b"u");
+            return Ok(buffer);
+        },
     }
 }
 
 
-pub fn get_value_offset(command: &Command) -> Option<u64> {
-    // Get offset in bytes from the serialized command start till it's stored value if some.
+/// Serializes a command into a v2 record frame.
+pub fn serialize_framed(command: &Command) -> result::Result<Vec<u8>, io::Error> {
+    let mut body: Vec<u8> = Vec::new();
     match command {
-        Command::Set { key, value: _ } => Some((b"s".len() + size_of::<u32>() + key.len()) as u64),
-        _ => None,
+        Command::Set { key, value } => {
+            body.extend(b"s");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint_bytes(value, &mut body);
+        },
+        Command::SetFlagged { key, flags, value } => {
+            body.extend(b"f");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            flags.serialize(&mut body)?;
+            write_varint_bytes(value, &mut body);
+        },
+        Command::Sequence { seq, timestamp_ms } => {
+            body.extend(b"q");
+            write_varint(*seq, &mut body);
+            write_varint(*timestamp_ms, &mut body);
+        },
+        Command::SetCollection { key, collection_type, value } => {
+            body.extend(b"C");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            collection_type.code().serialize(&mut body)?;
+            write_varint_bytes(value, &mut body);
+        },
+        Command::Get { key } => {
+            body.extend(b"g");
+            write_varint_bytes(key.as_bytes(), &mut body);
+        },
+        Command::GetStream { key } => {
+            body.extend(b"v");
+            write_varint_bytes(key.as_bytes(), &mut body);
+        },
+        Command::Append { key, suffix } => {
+            body.extend(b"n");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint_bytes(suffix, &mut body);
+        },
+        Command::GetSet { key, value } => {
+            body.extend(b"x");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint_bytes(value, &mut body);
+        },
+        Command::SetNx { key, value } => {
+            body.extend(b"y");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint_bytes(value, &mut body);
+        },
+        Command::ListPush { key, values, front } => {
+            body.extend(b"L");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            (*front as u8).serialize(&mut body)?;
+            write_varint(values.len() as u64, &mut body);
+            for value in values {
+                write_varint_bytes(value, &mut body);
+            }
+        },
+        Command::ListPop { key, front } => {
+            body.extend(b"O");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            (*front as u8).serialize(&mut body)?;
+        },
+        Command::ListRange { key, start, stop } => {
+            body.extend(b"R");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            start.serialize(&mut body)?;
+            stop.serialize(&mut body)?;
+        },
+        Command::HashSet { key, fields } => {
+            body.extend(b"H");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint(fields.len() as u64, &mut body);
+            for (field, value) in fields {
+                write_varint_bytes(field.as_bytes(), &mut body);
+                write_varint_bytes(value, &mut body);
+            }
+        },
+        Command::HashGet { key, field } => {
+            body.extend(b"G");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint_bytes(field.as_bytes(), &mut body);
+        },
+        Command::HashDelete { key, fields } => {
+            body.extend(b"D");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint(fields.len() as u64, &mut body);
+            for field in fields {
+                write_varint_bytes(field.as_bytes(), &mut body);
+            }
+        },
+        Command::HashGetAll { key } => {
+            body.extend(b"A");
+            write_varint_bytes(key.as_bytes(), &mut body);
+        },
+        Command::SetAdd { key, members } => {
+            body.extend(b"S");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint(members.len() as u64, &mut body);
+            for member in members {
+                write_varint_bytes(member, &mut body);
+            }
+        },
+        Command::SetRemove { key, members } => {
+            body.extend(b"X");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint(members.len() as u64, &mut body);
+            for member in members {
+                write_varint_bytes(member, &mut body);
+            }
+        },
+        Command::SetIsMember { key, member } => {
+            body.extend(b"I");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint_bytes(member, &mut body);
+        },
+        Command::SetMembers { key } => {
+            body.extend(b"M");
+            write_varint_bytes(key.as_bytes(), &mut body);
+        },
+        Command::SortedSetAdd { key, members } => {
+            body.extend(b"Z");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint(members.len() as u64, &mut body);
+            for (member, score) in members {
+                write_varint_bytes(member, &mut body);
+                score.serialize(&mut body)?;
+            }
+        },
+        Command::SortedSetRemove { key, members } => {
+            body.extend(b"Y");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            write_varint(members.len() as u64, &mut body);
+            for member in members {
+                write_varint_bytes(member, &mut body);
+            }
+        },
+        Command::SortedSetRangeByScore { key, min, max } => {
+            body.extend(b"B");
+            write_varint_bytes(key.as_bytes(), &mut body);
+            min.serialize(&mut body)?;
+            max.serialize(&mut body)?;
+        },
+        Command::Remove { key } => {
+            body.extend(b"r");
+            write_varint_bytes(key.as_bytes(), &mut body);
+        },
+        Command::Reset {} => body.extend(b"z"),
+        Command::ResetPrefix { prefix } => {
+            body.extend(b"d");
+            write_varint_bytes(prefix.as_bytes(), &mut body);
+        },
+        Command::PrepareRestore { backup_dir } => {
+            body.extend(b"p");
+            write_varint_bytes(backup_dir.as_bytes(), &mut body);
+        },
+        Command::CommitRestore { token } => {
+            body.extend(b"c");
+            write_varint_bytes(token.as_bytes(), &mut body);
+        },
+        Command::AbortRestore { token } => {
+            body.extend(b"a");
+            write_varint_bytes(token.as_bytes(), &mut body);
+        },
+        Command::Compact {} => body.extend(b"k"),
+        Command::Stats {} => body.extend(b"t"),
+        Command::Info {} => body.extend(b"i"),
+        Command::PauseCompaction {} => body.extend(b"h"),
+        Command::ResumeCompaction {} => body.extend(b"u"),
     }
+
+    let mut buffer = Vec::with_capacity(FRAME_MAGIC.len() + varint_size(body.len() as u64) + body.len() + 4);
+    write_frame(&body, &mut buffer)?;
+    Ok(buffer)
 }
 
 
+/// Appends a v2 frame of `body` to the buffer: the magic bytes, the varint body size, the body and its checksum.
+/// The storage records and the commands of the framed protocol requests and responses share the frame.
+pub fn write_frame(body: &[u8], buffer: &mut Vec<u8>) -> result::Result<(), io::Error> {
+    buffer.extend(FRAME_MAGIC);
+    write_varint(body.len() as u64, buffer);
+    buffer.extend(body);
+    frame_checksum(body).serialize(buffer)
+}
+
+
+/// Reads a v2 frame written by `write_frame` and returns its body. A checksum mismatch is an `InvalidData` error.
+pub fn read_frame<T: io::Read>(reader: &mut T) -> Result<Vec<u8>> {
+    if u8::deserialize(reader)? != FRAME_MAGIC[0] {
+        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, "Invalid frame magic")));
+    }
+    read_frame_body(reader)
+}
+
+
+/// Size of the v2 frame body of a set record with a key of `key_size` bytes and a value of `value_size` bytes.
+fn framed_set_body_size(key_size: usize, flagged: bool, value_size: usize) -> usize {
+    let flags_size = if flagged { size_of::<u8>() } else { 0 };
+    1 + varint_size(key_size as u64) + key_size + flags_size + varint_size(value_size as u64) + value_size
+}
+
+
+/// Size of a v2 set record. `flagged` is `true` for the `Command::SetFlagged` records.
+pub fn framed_set_record_size(key_size: usize, flagged: bool, value_size: usize) -> u64 {
+    let body_size = framed_set_body_size(key_size, flagged, value_size);
+    (FRAME_MAGIC.len() + varint_size(body_size as u64) + body_size + size_of::<u32>()) as u64
+}
+
+
+pub fn get_value_offset(command: &Command, format: RecordFormat) -> Option<u64> {
+    // Get offset in bytes from the serialized command start till the bytes of it's stored value if some.
+    let (key, flagged, value) = match command {
+        Command::Set { key, value } => (key, false, value),
+        Command::SetFlagged { key, value, .. } => (key, true, value),
+        _ => return None,
+    };
+    let flags_size = if flagged { size_of::<u8>() } else { 0 };
+    match format {
+        RecordFormat::V1 => Some((1 + size_of::<u32>() + key.len() + flags_size + size_of::<u32>()) as u64),
+        RecordFormat::V2 => {
+            let body_size = framed_set_body_size(key.len(), flagged, value.len());
+            let header_size = FRAME_MAGIC.len() + varint_size(body_size as u64);
+            let value_offset = 1 + varint_size(key.len() as u64) + key.len() + flags_size + varint_size(value.len() as u64);
+            Some((header_size + value_offset) as u64)
+        },
+    }
+}
+
+
+/// Builds the header written at the start of a new segment file.
+pub fn segment_header() -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(SEGMENT_HEADER_SIZE as usize);
+    buffer.extend(SEGMENT_MAGIC);
+    buffer.extend(SEGMENT_FORMAT_VERSION.to_be_bytes());
+    buffer
+}
+
+
+/// Reads the header at the start of a segment file and returns the segment format version.
+/// Nothing is read from a legacy segment without a header. The segments of the newer versions are rejected.
+pub fn read_segment_header<T: io::BufRead>(reader: &mut T) -> Result<u32> {
+    if reader.fill_buf()?.first() != Some(&SEGMENT_MAGIC[0]) {
+        return Ok(LEGACY_SEGMENT_FORMAT_VERSION);
+    }
+    reader.consume(1);
+    let magic_tail = u8::deserialize(reader)?;
+    if magic_tail != SEGMENT_MAGIC[1] {
+        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, "Invalid segment header magic")));
+    }
+    let version = u32::deserialize(reader)?;
+    if version > SEGMENT_FORMAT_VERSION {
+        return Err(
+            Box::new(
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported segment format version {}, expected up to {}", version, SEGMENT_FORMAT_VERSION),
+                )
+            )
+        );
+    }
+    Ok(version)
+}
+
+
+/// Reads the rest of a v2 frame after its first magic byte and returns the body.
+fn read_frame_body<T: io::Read>(reader: &mut T) -> Result<Vec<u8>> {
+    let magic_tail = u8::deserialize(reader)?;
+    if magic_tail != FRAME_MAGIC[1] {
+        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, "Invalid record frame magic")));
+    }
+    let body_size = read_varint(reader)?;
+    if body_size > FRAME_MAX_BODY_SIZE {
+        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, format!("Frame size {} is too large", body_size))));
+    }
+    let mut body = vec![0u8; body_size as usize];
+    reader.read_exact(&mut body[..])?;
+    let checksum = u32::deserialize(reader)?;
+    if checksum != frame_checksum(&body) {
+        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, "Record frame checksum mismatch")));
+    }
+    Ok(body)
+}
+
+
+/// Reads the body of a v2 frame, the first magic byte is already read.
+fn deserialize_frame<T: io::Read>(reader: &mut T) -> Result<Command> {
+    let mut body_reader = io::Cursor::new(read_frame_body(reader)?);
+    let command_code = u8::deserialize(&mut body_reader)?;
+    let command = match command_code {
+        b's' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let value = read_varint_bytes(&mut body_reader)?;
+            Command::Set { key, value }
+        },
+        b'f' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let flags = u8::deserialize(&mut body_reader)?;
+            let value = read_varint_bytes(&mut body_reader)?;
+            Command::SetFlagged { key, flags, value }
+        },
+        b'q' => {
+            let seq = read_varint(&mut body_reader)?;
+            let timestamp_ms = read_varint(&mut body_reader)?;
+            Command::Sequence { seq, timestamp_ms }
+        },
+        b'C' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let collection_type = read_collection_type(&mut body_reader)?;
+            let value = read_varint_bytes(&mut body_reader)?;
+            Command::SetCollection { key, collection_type, value }
+        },
+        b'r' => Command::Remove { key: read_varint_string(&mut body_reader)? },
+        b'g' => Command::Get { key: read_varint_string(&mut body_reader)? },
+        b'v' => Command::GetStream { key: read_varint_string(&mut body_reader)? },
+        b'n' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let suffix = read_varint_bytes(&mut body_reader)?;
+            Command::Append { key, suffix }
+        },
+        b'x' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let value = read_varint_bytes(&mut body_reader)?;
+            Command::GetSet { key, value }
+        },
+        b'y' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let value = read_varint_bytes(&mut body_reader)?;
+            Command::SetNx { key, value }
+        },
+        b'L' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let front = u8::deserialize(&mut body_reader)? != 0;
+            let count = read_varint(&mut body_reader)?;
+            let values = (0..count)
+                .map(|_| read_varint_bytes(&mut body_reader))
+                .collect::<result::Result<Vec<_>, io::Error>>()?;
+            Command::ListPush { key, values, front }
+        },
+        b'O' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let front = u8::deserialize(&mut body_reader)? != 0;
+            Command::ListPop { key, front }
+        },
+        b'R' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let start = i64::deserialize(&mut body_reader)?;
+            let stop = i64::deserialize(&mut body_reader)?;
+            Command::ListRange { key, start, stop }
+        },
+        b'H' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let count = read_varint(&mut body_reader)?;
+            let fields = (0..count)
+                .map(|_| Ok((read_varint_string(&mut body_reader)?, read_varint_bytes(&mut body_reader)?)))
+                .collect::<result::Result<Vec<_>, io::Error>>()?;
+            Command::HashSet { key, fields }
+        },
+        b'G' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let field = read_varint_string(&mut body_reader)?;
+            Command::HashGet { key, field }
+        },
+        b'D' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let count = read_varint(&mut body_reader)?;
+            let fields = (0..count)
+                .map(|_| read_varint_string(&mut body_reader))
+                .collect::<result::Result<Vec<_>, io::Error>>()?;
+            Command::HashDelete { key, fields }
+        },
+        b'A' => Command::HashGetAll { key: read_varint_string(&mut body_reader)? },
+        b'S' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let count = read_varint(&mut body_reader)?;
+            let members = (0..count)
+                .map(|_| read_varint_bytes(&mut body_reader))
+                .collect::<result::Result<Vec<_>, io::Error>>()?;
+            Command::SetAdd { key, members }
+        },
+        b'X' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let count = read_varint(&mut body_reader)?;
+            let members = (0..count)
+                .map(|_| read_varint_bytes(&mut body_reader))
+                .collect::<result::Result<Vec<_>, io::Error>>()?;
+            Command::SetRemove { key, members }
+        },
+        b'I' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let member = read_varint_bytes(&mut body_reader)?;
+            Command::SetIsMember { key, member }
+        },
+        b'M' => Command::SetMembers { key: read_varint_string(&mut body_reader)? },
+        b'Z' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let count = read_varint(&mut body_reader)?;
+            let members = (0..count)
+                .map(|_| Ok((read_varint_bytes(&mut body_reader)?, f64::deserialize(&mut body_reader)?)))
+                .collect::<result::Result<Vec<_>, io::Error>>()?;
+            Command::SortedSetAdd { key, members }
+        },
+        b'Y' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let count = read_varint(&mut body_reader)?;
+            let members = (0..count)
+                .map(|_| read_varint_bytes(&mut body_reader))
+                .collect::<result::Result<Vec<_>, io::Error>>()?;
+            Command::SortedSetRemove { key, members }
+        },
+        b'B' => {
+            let key = read_varint_string(&mut body_reader)?;
+            let min = f64::deserialize(&mut body_reader)?;
+            let max = f64::deserialize(&mut body_reader)?;
+            Command::SortedSetRangeByScore { key, min, max }
+        },
+        b'z' => Command::Reset {},
+        b'd' => Command::ResetPrefix { prefix: read_varint_string(&mut body_reader)? },
+        b'p' => Command::PrepareRestore { backup_dir: read_varint_string(&mut body_reader)? },
+        b'c' => Command::CommitRestore { token: read_varint_string(&mut body_reader)? },
+        b'a' => Command::AbortRestore { token: read_varint_string(&mut body_reader)? },
+        b'k' => Command::Compact {},
+        b't' => Command::Stats {},
+        b'i' => Command::Info {},
+        b'h' => Command::PauseCompaction {},
+        b'u' => Command::ResumeCompaction {},
+        _ => {
+            return Err(
+                Box::new(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown command {}", command_code)))
+            );
+        },
+    };
+    if body_reader.position() != body_reader.get_ref().len() as u64 {
+        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, "Unexpected data in the record frame")));
+    }
+    Ok(command)
+}
+
+
+/// Reads a command record of any format. Returns `None` at the end of the stream.
 pub fn deserialize<T: io::Read>(reader: &mut T) -> Result<Option<Command>> {
+    Ok(deserialize_record(reader)?.map(|(command, _)| command))
+}
+
+
+/// Reads a command record of any format along with the format. Returns `None` at the end of the stream.
+pub fn deserialize_record<T: io::Read>(reader: &mut T) -> Result<Option<(Command, RecordFormat)>> {
     let mut command_buffer = [0u8; 1];
     let bytes_count = reader.read(&mut command_buffer)?;
     if bytes_count == 0 {
@@ -171,12 +1102,38 @@ pub fn deserialize<T: io::Read>(reader: &mut T) -> Result<Option<Command>> {
     }
 
     let command_code = u8::from_be_bytes(command_buffer);
+    if command_code == FRAME_MAGIC[0] {
+        return Ok(Some((deserialize_frame(reader)?, RecordFormat::V2)));
+    }
+    Ok(deserialize_v1(command_code, reader)?.map(|command| (command, RecordFormat::V1)))
+}
+
+
+/// Reads the fields of a v1 record, the command code is already read.
+fn deserialize_v1<T: io::Read>(command_code: u8, reader: &mut T) -> Result<Option<Command>> {
     match command_code {
         b's' => {
             let key = String::deserialize(reader)?;
-            let value = String::deserialize(reader)?;
+            let value = Vec::<u8>::deserialize(reader)?;
             return Ok(Some(Command::Set { key: key, value: value }))
         },
+        b'f' => {
+            let key = String::deserialize(reader)?;
+            let flags = u8::deserialize(reader)?;
+            let value = Vec::<u8>::deserialize(reader)?;
+            return Ok(Some(Command::SetFlagged { key, flags, value }))
+        },
+        b'q' => {
+            let seq = u64::deserialize(reader)?;
+            let timestamp_ms = u64::deserialize(reader)?;
+            return Ok(Some(Command::Sequence { seq, timestamp_ms }))
+        },
+        b'C' => {
+            let key = String::deserialize(reader)?;
+            let collection_type = read_collection_type(reader)?;
+            let value = Vec::<u8>::deserialize(reader)?;
+            return Ok(Some(Command::SetCollection { key, collection_type, value }))
+        },
         b'r' => {
             let key = String::deserialize(reader)?;
             return Ok(Some(Command::Remove { key: key }))
@@ -185,9 +1142,130 @@ pub fn deserialize<T: io::Read>(reader: &mut T) -> Result<Option<Command>> {
             let key = String::deserialize(reader)?;
             return Ok(Some(Command::Get { key: key }))
         },
+        b'v' => {
+            let key = String::deserialize(reader)?;
+            return Ok(Some(Command::GetStream { key }))
+        },
+        b'n' => {
+            let key = String::deserialize(reader)?;
+            let suffix = Vec::<u8>::deserialize(reader)?;
+            return Ok(Some(Command::Append { key, suffix }))
+        },
+        b'x' => {
+            let key = String::deserialize(reader)?;
+            let value = Vec::<u8>::deserialize(reader)?;
+            return Ok(Some(Command::GetSet { key, value }))
+        },
+        b'y' => {
+            let key = String::deserialize(reader)?;
+            let value = Vec::<u8>::deserialize(reader)?;
+            return Ok(Some(Command::SetNx { key, value }))
+        },
+        b'L' => {
+            let key = String::deserialize(reader)?;
+            let front = u8::deserialize(reader)? != 0;
+            let values = Vec::<Vec<u8>>::deserialize(reader)?;
+            return Ok(Some(Command::ListPush { key, values, front }))
+        },
+        b'O' => {
+            let key = String::deserialize(reader)?;
+            let front = u8::deserialize(reader)? != 0;
+            return Ok(Some(Command::ListPop { key, front }))
+        },
+        b'R' => {
+            let key = String::deserialize(reader)?;
+            let start = i64::deserialize(reader)?;
+            let stop = i64::deserialize(reader)?;
+            return Ok(Some(Command::ListRange { key, start, stop }))
+        },
+        b'H' => {
+            let key = String::deserialize(reader)?;
+            let fields = Vec::<(String, Vec<u8>)>::deserialize(reader)?;
+            return Ok(Some(Command::HashSet { key, fields }))
+        },
+        b'G' => {
+            let key = String::deserialize(reader)?;
+            let field = String::deserialize(reader)?;
+            return Ok(Some(Command::HashGet { key, field }))
+        },
+        b'D' => {
+            let key = String::deserialize(reader)?;
+            let fields = Vec::<String>::deserialize(reader)?;
+            return Ok(Some(Command::HashDelete { key, fields }))
+        },
+        b'A' => {
+            let key = String::deserialize(reader)?;
+            return Ok(Some(Command::HashGetAll { key }))
+        },
+        b'S' => {
+            let key = String::deserialize(reader)?;
+            let members = Vec::<Vec<u8>>::deserialize(reader)?;
+            return Ok(Some(Command::SetAdd { key, members }))
+        },
+        b'X' => {
+            let key = String::deserialize(reader)?;
+            let members = Vec::<Vec<u8>>::deserialize(reader)?;
+            return Ok(Some(Command::SetRemove { key, members }))
+        },
+        b'I' => {
+            let key = String::deserialize(reader)?;
+            let member = Vec::<u8>::deserialize(reader)?;
+            return Ok(Some(Command::SetIsMember { key, member }))
+        },
+        b'M' => {
+            let key = String::deserialize(reader)?;
+            return Ok(Some(Command::SetMembers { key }))
+        },
+        b'Z' => {
+            let key = String::deserialize(reader)?;
+            let members = Vec::<(Vec<u8>, f64)>::deserialize(reader)?;
+            return Ok(Some(Command::SortedSetAdd { key, members }))
+        },
+        b'Y' => {
+            let key = String::deserialize(reader)?;
+            let members = Vec::<Vec<u8>>::deserialize(reader)?;
+            return Ok(Some(Command::SortedSetRemove { key, members }))
+        },
+        b'B' => {
+            let key = String::deserialize(reader)?;
+            let min = f64::deserialize(reader)?;
+            let max = f64::deserialize(reader)?;
+            return Ok(Some(Command::SortedSetRangeByScore { key, min, max }))
+        },
         b'z' => {
             return Ok(Some(Command::Reset {}))
         },
+        b'd' => {
+            let prefix = String::deserialize(reader)?;
+            return Ok(Some(Command::ResetPrefix { prefix }))
+        },
+        b'p' => {
+            let backup_dir = String::deserialize(reader)?;
+            return Ok(Some(Command::PrepareRestore { backup_dir: backup_dir }))
+        },
+        b'c' => {
+            let token = String::deserialize(reader)?;
+            return Ok(Some(Command::CommitRestore { token: token }))
+        },
+        b'a' => {
+            let token = String::deserialize(reader)?;
+            return Ok(Some(Command::AbortRestore { token: token }))
+        },
+        b'k' => {
+            return Ok(Some(Command::Compact {}))
+        },
+        b't' => {
+            return Ok(Some(Command::Stats {}))
+        },
+        b'i' => {
+            return Ok(Some(Command::Info {}))
+        },
+        b'h' => {
+            return Ok(Some(Command::PauseCompaction {}))
+        },
+        b'u' => {
+            return Ok(Some(Command::ResumeCompaction {}))
+        },
         _ => {
             return Err(
                 Box::new(io::Error::new(io::ErrorKind::Other, format!("Unknown command {}", command_code)))
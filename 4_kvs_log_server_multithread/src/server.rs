@@ -1,52 +1,359 @@
+use std::collections::HashMap;
 use std::net;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{BufRead, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
+use crate::access_log::{AccessLog, AccessLogEntry};
 use crate::models;
 use crate::serialize;
 use crate::serialize::WriteToStream;
-use crate::storage;
-use crate::storage::kv_log;
+use crate::storage::{KvStorage, ValueReader};
+use crate::stream::Stream;
 use crate::threads;
+use crate::threads::base::ThreadPoolExt;
+use crate::trace;
 
-const SERVER_VERSION: u8 = 1u8;
+const SERVER_VERSION: u8 = 5u8;
+/// The first protocol version with the request deadline in the header.
+const DEADLINE_VERSION: u8 = 2u8;
+/// The first protocol version with the request namespace in the header.
+const NAMESPACE_VERSION: u8 = 3u8;
+/// The first protocol version with the request store in the header.
+const STORE_VERSION: u8 = 4u8;
+/// The first protocol version with the commands of the requests and the responses in checksummed v2 frames.
+const FRAMED_VERSION: u8 = 5u8;
+/// Version of the responses to the requests of the protocol versions before `FRAMED_VERSION`.
+const UNFRAMED_RESPONSE_VERSION: u8 = 4u8;
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const DEFAULT_REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// Max time to read the request of a rejected connection. The rejected connections are replied one by one
+/// by a single thread, so they are not allowed to hold it for long.
+const BUSY_REPLY_TIMEOUT: Duration = Duration::from_millis(200);
+/// Max number of the rejected connections waiting for the busy reply, the connections beyond it are closed.
+const MAX_PENDING_REJECTS: usize = 64;
+/// Max size of the value chunk in a frame of a streamed get response.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Limits of the accepted connections.
+#[derive(Clone)]
+struct ConnectionOptions {
+    /// Max time to wait for the next request on a keep-alive connection.
+    idle_timeout: Option<Duration>,
+    /// Max time to read a whole request once its first bytes are received.
+    request_read_timeout: Option<Duration>,
+    /// Max time to handle a request once it's read, including the storage I/O.
+    request_timeout: Option<Duration>,
+    max_connections: Option<usize>,
+    /// Reported as the start of the server uptime.
+    server_created: Instant,
+    /// Directory the backups of the restore commands are resolved within, restores are rejected without it.
+    backup_root: Option<PathBuf>,
+}
+
+/// Returns `true` for the errors of a read interrupted by a socket read timeout.
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Reader of a connection stream failing with `TimedOut` once its deadline passes.
+/// The socket read timeout is lowered to the time left before each read, so the deadline limits
+/// the whole read rather than a single read call, e.g. of a client trickling the request bytes.
+struct DeadlineReader<'a> {
+    stream: &'a mut Box<dyn Stream>,
+    deadline: Option<Instant>,
+}
+
+impl DeadlineReader<'_> {
+    /// Sets the deadline `timeout` from now, `None` to read without a deadline.
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.deadline = timeout.map(|timeout| Instant::now() + timeout);
+        if timeout.is_none() {
+            self.stream.set_read_timeout(None)?;
+        }
+        Ok(())
+    }
+}
+
+impl io::Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let time_left = deadline.saturating_duration_since(Instant::now());
+            if time_left.is_zero() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Read deadline exceeded"));
+            }
+            self.stream.set_read_timeout(Some(time_left))?;
+        }
+        self.stream.read(buf).map_err(|err| {
+            if is_timeout(&err) { io::Error::new(io::ErrorKind::TimedOut, "Read deadline exceeded") } else { err }
+        })
+    }
+}
 
 fn read_header(stream: &mut dyn io::Read) -> models::Result<models::RequestHeader> {
-    Ok(
-        models::RequestHeader{
-            version: serialize::ReadFromStream::deserialize(stream)?,
-            keep_alive: serialize::ReadFromStream::deserialize(stream)?,
-            command_count: serialize::ReadFromStream::deserialize(stream)?,
-            body_size: serialize::ReadFromStream::deserialize(stream)?,
-            reserved: serialize::ReadFromStream::deserialize(stream)?,
+    let mut header = models::RequestHeader{
+        version: serialize::ReadFromStream::deserialize(stream)?,
+        keep_alive: serialize::ReadFromStream::deserialize(stream)?,
+        command_count: serialize::ReadFromStream::deserialize(stream)?,
+        body_size: serialize::ReadFromStream::deserialize(stream)?,
+        reserved: serialize::ReadFromStream::deserialize(stream)?,
+        deadline: 0,
+        namespace: String::new(),
+        store: String::new(),
+    };
+    if header.version >= DEADLINE_VERSION {
+        header.deadline = serialize::ReadFromStream::deserialize(stream)?;
+    }
+    if header.version >= NAMESPACE_VERSION {
+        header.namespace = serialize::ReadFromStream::deserialize(stream)?;
+    }
+    if header.version >= STORE_VERSION {
+        header.store = serialize::ReadFromStream::deserialize(stream)?;
+    }
+    Ok(header)
+}
+
+/// Storages served by the server: the default one and the named stores selected by the request header.
+struct Stores {
+    default: Box<dyn KvStorage>,
+    named: HashMap<String, Box<dyn KvStorage>>,
+}
+
+impl Clone for Stores {
+    fn clone(&self) -> Stores {
+        Stores {
+            default: self.default.clone_box(),
+            named: self.named.iter().map(|(name, storage)| (name.clone(), storage.clone_box())).collect(),
+        }
+    }
+}
+
+impl Stores {
+    /// Returns the storage of the store `name`, the default storage for an empty name.
+    /// Fails with `ERROR_CODE_NOT_FOUND` for an unknown store.
+    fn get(&self, name: &str) -> models::Result<Box<dyn KvStorage>> {
+        if name.is_empty() {
+            return Ok(self.default.clone_box());
         }
-    )
+        match self.named.get(name) {
+            Some(storage) => Ok(storage.clone_box()),
+            None => Err(Box::new(models::CommandError::new(
+                models::ERROR_CODE_NOT_FOUND,
+                format!("Unknown store {}", name),
+            ))),
+        }
+    }
+}
+
+/// Returns `true` if the deadline is set and already passed.
+fn deadline_exceeded(deadline: Option<std::time::SystemTime>) -> bool {
+    match deadline {
+        Some(deadline) => std::time::SystemTime::now() >= deadline,
+        None => false,
+    }
+}
+
+/// Error code of a failed command: the code of a `CommandError`, `ERROR_CODE_CORRUPTION` for malformed data
+/// and `ERROR_CODE_INTERNAL` for the rest of the errors.
+fn error_code(err: &(dyn std::error::Error + 'static)) -> u16 {
+    if let Some(command_err) = err.downcast_ref::<models::CommandError>() {
+        return command_err.code;
+    }
+    match err.downcast_ref::<io::Error>().map(|io_err| io_err.kind()) {
+        Some(io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof) => models::ERROR_CODE_CORRUPTION,
+        _ => models::ERROR_CODE_INTERNAL,
+    }
 }
 
-fn serialize_response(responses: Vec<models::ResponseCommand>) -> models::Result<Vec<u8>> {
+fn error_response(err: &(dyn std::error::Error + 'static)) -> models::ResponseCommand {
+    let message = match err.downcast_ref::<models::CommandError>() {
+        Some(command_err) => command_err.message.clone(),
+        None => err.to_string(),
+    };
+    models::ResponseCommand::Error { code: error_code(err), message }
+}
+
+/// The earliest of the deadlines, `None` if there are none.
+fn earliest_deadline(
+    deadline: Option<std::time::SystemTime>,
+    other: Option<std::time::SystemTime>,
+) -> Option<std::time::SystemTime> {
+    match (deadline, other) {
+        (Some(deadline), Some(other)) => Some(deadline.min(other)),
+        (deadline, other) => deadline.or(other),
+    }
+}
+
+fn deadline_exceeded_response() -> models::ResponseCommand {
+    models::ResponseCommand::Error {
+        code: models::ERROR_CODE_DEADLINE_EXCEEDED,
+        message: String::from("Request deadline exceeded"),
+    }
+}
+
+fn server_busy_response(message: &str) -> models::ResponseCommand {
+    models::ResponseCommand::Error {
+        code: models::ERROR_CODE_SERVER_BUSY,
+        message: message.to_owned(),
+    }
+}
+
+/// Serializes the responses, each command in a checksummed v2 frame if `framed`.
+fn serialize_response(responses: Vec<models::ResponseCommand>, framed: bool) -> models::Result<Vec<u8>> {
+    serialize_response_frame(responses, 0u8, framed)
+}
+
+/// Serializes the responses as a single response frame with the `RESPONSE_FLAG_*` `flags`.
+/// The commands of the framed protocol version are written in checksummed v2 frames.
+fn serialize_response_frame(
+    responses: Vec<models::ResponseCommand>,
+    flags: u8,
+    framed: bool,
+) -> models::Result<Vec<u8>> {
     let command_count = responses.len();
     let mut body_buffer = Vec::new();
     for response in responses {
+        let mut command_buffer = Vec::new();
         match response {
             models::ResponseCommand::Get { value } => {
-                body_buffer.write(&[b'g'])?;
-                value.serialize(&mut body_buffer)?;
+                command_buffer.write(&[b'g'])?;
+                value.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::GetStream { chunk } => {
+                command_buffer.write_all(b"v")?;
+                chunk.serialize(&mut command_buffer)?;
             },
             models::ResponseCommand::Set {} => {
-                body_buffer.write(&[b's'])?;
+                command_buffer.write(&[b's'])?;
+            },
+            models::ResponseCommand::Append { length } => {
+                command_buffer.write_all(b"n")?;
+                length.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::GetSet { value } => {
+                command_buffer.write_all(b"x")?;
+                value.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::SetNx { is_set } => {
+                command_buffer.write_all(b"y")?;
+                (is_set as u8).serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::ListPush { length } => {
+                command_buffer.write_all(b"L")?;
+                length.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::ListPop { value } => {
+                command_buffer.write_all(b"O")?;
+                value.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::ListRange { values } => {
+                command_buffer.write_all(b"R")?;
+                values.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::HashSet { added } => {
+                command_buffer.write_all(b"H")?;
+                added.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::HashGet { value } => {
+                command_buffer.write_all(b"G")?;
+                value.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::HashDelete { removed } => {
+                command_buffer.write_all(b"D")?;
+                removed.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::HashGetAll { fields } => {
+                command_buffer.write_all(b"A")?;
+                fields.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::SetAdd { added } => {
+                command_buffer.write_all(b"S")?;
+                added.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::SetRemove { removed } => {
+                command_buffer.write_all(b"X")?;
+                removed.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::SetIsMember { is_member } => {
+                command_buffer.write_all(b"I")?;
+                (is_member as u8).serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::SetMembers { members } => {
+                command_buffer.write_all(b"M")?;
+                members.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::SortedSetAdd { added } => {
+                command_buffer.write_all(b"Z")?;
+                added.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::SortedSetRemove { removed } => {
+                command_buffer.write_all(b"Y")?;
+                removed.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::SortedSetRangeByScore { members } => {
+                command_buffer.write_all(b"B")?;
+                members.serialize(&mut command_buffer)?;
             },
             models::ResponseCommand::Remove {} => {
-                body_buffer.write(&[b'r'])?;
+                command_buffer.write(&[b'r'])?;
             },
             models::ResponseCommand::Reset {} => {
-                body_buffer.write(&[b'z'])?;
-            }
+                command_buffer.write(&[b'z'])?;
+            },
+            models::ResponseCommand::ResetPrefix { removed } => {
+                command_buffer.write_all(b"d")?;
+                removed.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::PrepareRestore { token } => {
+                command_buffer.write_all(b"p")?;
+                token.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::CommitRestore {} => {
+                command_buffer.write_all(b"c")?;
+            },
+            models::ResponseCommand::AbortRestore {} => {
+                command_buffer.write_all(b"a")?;
+            },
+            models::ResponseCommand::Compact {} => {
+                command_buffer.write_all(b"k")?;
+            },
+            models::ResponseCommand::Stats { stats, pool } => {
+                command_buffer.write_all(b"t")?;
+                stats.serialize(&mut command_buffer)?;
+                pool.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::Info { info } => {
+                command_buffer.write_all(b"i")?;
+                info.serialize(&mut command_buffer)?;
+            },
+            models::ResponseCommand::PauseCompaction {} => {
+                command_buffer.write_all(b"h")?;
+            },
+            models::ResponseCommand::ResumeCompaction {} => {
+                command_buffer.write_all(b"u")?;
+            },
+            models::ResponseCommand::Error { code, message } => {
+                command_buffer.write_all(b"e")?;
+                code.serialize(&mut command_buffer)?;
+                message.serialize(&mut command_buffer)?;
+            },
         };
+        if framed {
+            serialize::write_frame(&command_buffer, &mut body_buffer)?;
+        } else {
+            body_buffer.extend(command_buffer);
+        }
     }
 
     let header =  models::ResponseHeader{
-        version: SERVER_VERSION,
-        reserved_1: 0u8,
+        version: if framed { FRAMED_VERSION } else { UNFRAMED_RESPONSE_VERSION },
+        flags,
         command_count: command_count as u16,
         body_size: body_buffer.len() as u32,
         reserved_2: 0u32,
@@ -55,7 +362,7 @@ fn serialize_response(responses: Vec<models::ResponseCommand>) -> models::Result
     let mut response_buffer = Vec::new();
     response_buffer.reserve(size_of::<models::ResponseHeader>() + body_buffer.len());
     header.version.serialize(&mut response_buffer)?;
-    header.reserved_1.serialize(&mut response_buffer)?;
+    header.flags.serialize(&mut response_buffer)?;
     header.command_count.serialize(&mut response_buffer)?;
     header.body_size.serialize(&mut response_buffer)?;
     header.reserved_2.serialize(&mut response_buffer)?;
@@ -64,40 +371,461 @@ fn serialize_response(responses: Vec<models::ResponseCommand>) -> models::Result
     Ok(response_buffer)
 }
 
-fn handle_request(storage: &mut kv_log::KvLogStorage, request: models::Request) -> models::Result<Vec<models::ResponseCommand>> {
+/// Writes the responses and returns the number of the written bytes. The `value` of a single streamed get
+/// is written instead, in frames of up to `STREAM_CHUNK_SIZE` bytes each read from the storage right before
+/// it's written, so the value is never held in memory as a whole. If reading the value fails midway,
+/// the error is returned and the connection is closed.
+fn write_response(
+    writer: &mut dyn io::Write,
+    responses: Vec<models::ResponseCommand>,
+    value: Option<ValueReader>,
+    framed: bool,
+) -> models::Result<usize> {
+    let Some(mut value) = value else {
+        let response_data = serialize_response(responses, framed)?;
+        log::debug!("{}", String::from_utf8_lossy(&response_data));
+        writer.write_all(response_data.as_slice())?;
+        return Ok(response_data.len());
+    };
+
+    // An empty value is still sent in a single frame.
+    let frame_count = value.size.div_ceil(STREAM_CHUNK_SIZE as u64).max(1);
+    log::debug!("Streaming a value of {} bytes in {} frames", value.size, frame_count);
+    let mut written = 0;
+    let mut remaining = value.size;
+    for idx in 0..frame_count {
+        let mut chunk = vec![0u8; remaining.min(STREAM_CHUNK_SIZE as u64) as usize];
+        value.reader.read_exact(&mut chunk)?;
+        remaining -= chunk.len() as u64;
+        let flags = if idx + 1 < frame_count { models::RESPONSE_FLAG_CONTINUED } else { 0u8 };
+        let responses = vec![models::ResponseCommand::GetStream { chunk: Some(chunk) }];
+        let frame = serialize_response_frame(responses, flags, framed)?;
+        writer.write_all(frame.as_slice())?;
+        written += frame.len();
+    }
+    Ok(written)
+}
+
+fn handle_command(
+    storage: &mut dyn KvStorage,
+    pool_counters: &threads::base::PoolCounters,
+    options: &ConnectionOptions,
+    command: models::Command,
+) -> models::Result<models::ResponseCommand> {
+    log::info!("Handling command {}", command);
+    let response_command = match command {
+        models::Command::Get { key } => {
+            let value = storage.get_bytes(key)?;
+            models::ResponseCommand::Get{value: value}
+        },
+        models::Command::GetStream { key } => {
+            let chunk = storage.get_bytes(key)?;
+            models::ResponseCommand::GetStream{chunk}
+        },
+        models::Command::Set { key, value } => {
+            storage.set_bytes(key, value)?;
+            models::ResponseCommand::Set{}
+        },
+        models::Command::SetFlagged { .. } => {
+            return Err(Box::from("Flagged set records are internal to the log storage"));
+        },
+        models::Command::Sequence { .. } => {
+            return Err(Box::from("Sequence records are internal to the log storage"));
+        },
+        models::Command::SetCollection { .. } => {
+            return Err(Box::from("Collection records are internal to the log storage"));
+        },
+        models::Command::Append { key, suffix } => {
+            let length = storage.append(key, suffix)?;
+            models::ResponseCommand::Append{length}
+        },
+        models::Command::GetSet { key, value } => {
+            let value = storage.get_set(key, value)?;
+            models::ResponseCommand::GetSet{value}
+        },
+        models::Command::SetNx { key, value } => {
+            let is_set = storage.set_nx(key, value)?;
+            models::ResponseCommand::SetNx{is_set}
+        },
+        models::Command::ListPush { key, values, front } => {
+            let length = storage.list_push(key, values, front)?;
+            models::ResponseCommand::ListPush{length}
+        },
+        models::Command::ListPop { key, front } => {
+            let value = storage.list_pop(key, front)?;
+            models::ResponseCommand::ListPop{value}
+        },
+        models::Command::ListRange { key, start, stop } => {
+            let values = storage.list_range(key, start, stop)?;
+            models::ResponseCommand::ListRange{values}
+        },
+        models::Command::HashSet { key, fields } => {
+            let added = storage.hash_set(key, fields)?;
+            models::ResponseCommand::HashSet{added}
+        },
+        models::Command::HashGet { key, field } => {
+            let value = storage.hash_get(key, field)?;
+            models::ResponseCommand::HashGet{value}
+        },
+        models::Command::HashDelete { key, fields } => {
+            let removed = storage.hash_delete(key, fields)?;
+            models::ResponseCommand::HashDelete{removed}
+        },
+        models::Command::HashGetAll { key } => {
+            let fields = storage.hash_get_all(key)?;
+            models::ResponseCommand::HashGetAll{fields}
+        },
+        models::Command::SetAdd { key, members } => {
+            let added = storage.set_add(key, members)?;
+            models::ResponseCommand::SetAdd{added}
+        },
+        models::Command::SetRemove { key, members } => {
+            let removed = storage.set_remove(key, members)?;
+            models::ResponseCommand::SetRemove{removed}
+        },
+        models::Command::SetIsMember { key, member } => {
+            let is_member = storage.set_is_member(key, member)?;
+            models::ResponseCommand::SetIsMember{is_member}
+        },
+        models::Command::SetMembers { key } => {
+            let members = storage.set_members(key)?;
+            models::ResponseCommand::SetMembers{members}
+        },
+        models::Command::SortedSetAdd { key, members } => {
+            let added = storage.sorted_set_add(key, members)?;
+            models::ResponseCommand::SortedSetAdd{added}
+        },
+        models::Command::SortedSetRemove { key, members } => {
+            let removed = storage.sorted_set_remove(key, members)?;
+            models::ResponseCommand::SortedSetRemove{removed}
+        },
+        models::Command::SortedSetRangeByScore { key, min, max } => {
+            let members = storage.sorted_set_range_by_score(key, min, max)?;
+            models::ResponseCommand::SortedSetRangeByScore{members}
+        },
+        models::Command::Remove { key } => {
+            storage.remove(key)?;
+            models::ResponseCommand::Remove{}
+        },
+        models::Command::Reset { } => {
+            storage.reset()?;
+            models::ResponseCommand::Reset{}
+        },
+        models::Command::ResetPrefix { prefix } => {
+            let removed = storage.reset_prefix(prefix)?;
+            models::ResponseCommand::ResetPrefix{removed}
+        },
+        models::Command::PrepareRestore { backup_dir } => {
+            let backup_dir = resolve_backup_dir(options.backup_root.as_deref(), &backup_dir)?;
+            let token = storage.prepare_restore(&backup_dir)?;
+            models::ResponseCommand::PrepareRestore{ token }
+        },
+        models::Command::CommitRestore { token } => {
+            storage.commit_restore(&token)?;
+            models::ResponseCommand::CommitRestore{}
+        },
+        models::Command::AbortRestore { token } => {
+            storage.abort_restore(&token)?;
+            models::ResponseCommand::AbortRestore{}
+        },
+        models::Command::Compact {} => {
+            storage.compact()?;
+            models::ResponseCommand::Compact{}
+        },
+        models::Command::Stats {} => {
+            models::ResponseCommand::Stats{ stats: storage.stats()?, pool: pool_counters.snapshot() }
+        },
+        models::Command::Info {} => {
+            let info = models::ServerInfo {
+                version: env!("CARGO_PKG_VERSION").to_owned(),
+                uptime: options.server_created.elapsed(),
+                compaction_paused: storage.is_compaction_paused(),
+                stats: storage.stats()?,
+                pool: pool_counters.snapshot(),
+            };
+            models::ResponseCommand::Info{info}
+        },
+        models::Command::PauseCompaction {} => {
+            storage.pause_compaction()?;
+            models::ResponseCommand::PauseCompaction{}
+        },
+        models::Command::ResumeCompaction {} => {
+            storage.resume_compaction()?;
+            models::ResponseCommand::ResumeCompaction{}
+        },
+    };
+    Ok(response_command)
+}
+
+/// Resolves the backup directory of a restore command within the backup root, relative to it.
+/// Fails with `ERROR_CODE_UNAUTHORIZED` without a backup root or for a directory outside of it,
+/// so the clients cannot restore the storage from an arbitrary server path.
+fn resolve_backup_dir(backup_root: Option<&Path>, backup_dir: &str) -> models::Result<PathBuf> {
+    let unauthorized = |message: String| -> models::Result<PathBuf> {
+        Err(Box::new(models::CommandError::new(models::ERROR_CODE_UNAUTHORIZED, message)))
+    };
+    let Some(backup_root) = backup_root else {
+        return unauthorized("Restores are disabled, the server has no backup root".to_owned());
+    };
+    let path = backup_root.join(backup_dir);
+    let is_within_root = path.starts_with(backup_root)
+        && !path.components().any(|component| component == std::path::Component::ParentDir);
+    // The symlinks are followed by the restore, so the directory they resolve to must be within the root too.
+    let is_resolved_within_root = match (path.canonicalize(), backup_root.canonicalize()) {
+        (Ok(resolved_path), Ok(resolved_root)) => resolved_path.starts_with(resolved_root),
+        (Err(err), _) if err.kind() == io::ErrorKind::NotFound => true,
+        (Err(err), _) | (_, Err(err)) => return Err(Box::new(err)),
+    };
+    if !is_within_root || !is_resolved_within_root {
+        return unauthorized(format!("Backup directory {} is outside of the backup root", backup_dir));
+    }
+    Ok(path)
+}
+
+/// Handles all the request commands. A failed command is reported with an error response
+/// and doesn't prevent the rest of the commands from being handled.
+/// The consecutive set and remove commands are written in batches fitting `KvStorage::max_batch_size`.
+/// If a batch fails, its commands are handled one by one, so each of them gets its own status.
+/// The consecutive get commands are read at once and fail as a whole too, except for the gets of the collections.
+/// Commands are not handled once the `deadline` is exceeded.
+/// The commands of a request with a store are handled by the storage of the store,
+/// and the commands of a request with a namespace by the storage of the namespace within it.
+/// The value of a request with a single streamed get is opened instead of being read, see `write_response`.
+fn handle_request(
+    stores: &Stores,
+    pool_counters: &threads::base::PoolCounters,
+    options: &ConnectionOptions,
+    request: models::Request,
+    deadline: Option<std::time::SystemTime>,
+) -> (Vec<models::ResponseCommand>, Option<ValueReader>) {
     let mut responses = Vec::new();
 
-    for command in request.commands {
-        log::info!("Handling command {}", command);
-        let response_command = match command {
-            models::Command::Get { key } => {
-                let value = storage.get(key)?;
-                models::ResponseCommand::Get{value: value}
-            },
-            models::Command::Set { key, value } => {
-                storage.set(key, value)?;
-                models::ResponseCommand::Set{}
-            },
-            models::Command::Remove { key } => {
-                storage.remove(key)?;
-                models::ResponseCommand::Remove{}
+    let mut storage = match stores.get(&request.header.store) {
+        Ok(storage) => storage,
+        Err(err) => {
+            log::error!("Cannot select store {}: {}", request.header.store, err);
+            return (request.commands.iter().map(|_| error_response(err.as_ref())).collect(), None);
+        },
+    };
+    let storage = storage.as_mut();
+    let mut namespace_storage;
+    let storage = if request.header.namespace.is_empty() {
+        storage
+    } else {
+        match storage.namespace(&request.header.namespace) {
+            Ok(opened_storage) => {
+                namespace_storage = opened_storage;
+                namespace_storage.as_mut()
             },
-            models::Command::Reset { } => {
-                storage.reset()?;
-                models::ResponseCommand::Reset{}
+            Err(err) => {
+                log::error!("Cannot open namespace {}: {}", request.header.namespace, err);
+                return (request.commands.iter().map(|_| error_response(err.as_ref())).collect(), None);
             },
-        };
-        responses.push(response_command);
+        }
+    };
+
+    match request.commands.as_slice() {
+        [command @ models::Command::GetStream { key }] if !deadline_exceeded(deadline) => {
+            log::info!("Handling command {}", command);
+            return match storage.open_value(key.clone()) {
+                Ok(Some(value)) => (Vec::new(), Some(value)),
+                Ok(None) => (vec![models::ResponseCommand::GetStream { chunk: None }], None),
+                Err(err) => {
+                    log::error!("Command handling error: {}", err);
+                    (vec![error_response(err.as_ref())], None)
+                },
+            };
+        },
+        _ => {},
     }
 
-    Ok(responses)
+    let mut commands = request.commands.into_iter().peekable();
+    while let Some(command) = commands.next() {
+        if deadline_exceeded(deadline) {
+            log::warn!("Request deadline exceeded, skipping command {}", command);
+            responses.push(deadline_exceeded_response());
+            continue;
+        }
+
+        // The consecutive writes are written in batches with a single sync each.
+        if is_batch_write(&command) && commands.peek().is_some_and(is_batch_write) {
+            let mut writes = vec![command];
+            while let Some(command) = commands.next_if(is_batch_write) {
+                writes.push(command);
+            }
+            for batch in split_write_batches(writes, storage.max_batch_size()) {
+                responses.extend(handle_write_batch(storage, pool_counters, options, batch));
+            }
+            continue;
+        }
+
+        // The consecutive reads are read at once, opening each log file once.
+        if is_batch_read(&command) && commands.peek().is_some_and(is_batch_read) {
+            let mut batch = vec![command];
+            while let Some(command) = commands.next_if(is_batch_read) {
+                batch.push(command);
+            }
+            responses.extend(handle_read_batch(storage, batch));
+            continue;
+        }
+
+        responses.push(handle_single_command(storage, pool_counters, options, command));
+    }
+
+    (responses, None)
+}
+
+/// Handles a command on its own, a failure is reported with an error response.
+fn handle_single_command(
+    storage: &mut dyn KvStorage,
+    pool_counters: &threads::base::PoolCounters,
+    options: &ConnectionOptions,
+    command: models::Command,
+) -> models::ResponseCommand {
+    match handle_command(storage, pool_counters, options, command) {
+        Ok(response_command) => response_command,
+        Err(err) => {
+            log::error!("Command handling error: {}", err);
+            error_response(err.as_ref())
+        },
+    }
+}
+
+/// Commands written by `KvStorage::write_batch` when several of them come in a row.
+fn is_batch_write(command: &models::Command) -> bool {
+    matches!(command, models::Command::Set { .. } | models::Command::Remove { .. })
+}
+
+/// Size of the log record of a batch write. The values may be written compressed, so it's an upper bound.
+fn batch_record_size(command: &models::Command) -> u64 {
+    match command {
+        models::Command::Set { key, value } => serialize::framed_set_record_size(key.len(), false, value.len()),
+        command => serialize::serialize_framed(command).map_or(0, |record| record.len() as u64),
+    }
+}
+
+/// Splits the consecutive writes into batches with at most `max_batch_size` bytes of the records each.
+/// A write larger than the limit is a batch on its own.
+fn split_write_batches(writes: Vec<models::Command>, max_batch_size: Option<u64>) -> Vec<Vec<models::Command>> {
+    let Some(max_batch_size) = max_batch_size else {
+        return vec![writes];
+    };
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_size = 0;
+    for command in writes {
+        let record_size = batch_record_size(&command);
+        if !batch.is_empty() && batch_size + record_size > max_batch_size {
+            batches.push(std::mem::take(&mut batch));
+            batch_size = 0;
+        }
+        batch_size += record_size;
+        batch.push(command);
+    }
+    batches.push(batch);
+    batches
+}
+
+/// Writes the set and remove commands as a batch. If the batch fails, e.g. with a value exceeding a log file,
+/// its commands are handled one by one, so only the failing ones get an error response.
+fn handle_write_batch(
+    storage: &mut dyn KvStorage,
+    pool_counters: &threads::base::PoolCounters,
+    options: &ConnectionOptions,
+    batch: Vec<models::Command>,
+) -> Vec<models::ResponseCommand> {
+    if batch.len() == 1 {
+        let command = batch.into_iter().next().expect("the batch has a command");
+        return vec![handle_single_command(storage, pool_counters, options, command)];
+    }
+    log::info!("Handling a batch of {} writes", batch.len());
+    match storage.write_batch(batch.clone()) {
+        Ok(()) => batch.iter()
+            .map(|command| match command {
+                models::Command::Remove { .. } => models::ResponseCommand::Remove {},
+                _ => models::ResponseCommand::Set {},
+            })
+            .collect(),
+        Err(err) => {
+            log::warn!("Write batch handling error, handling its commands one by one: {}", err);
+            batch.into_iter().map(|command| handle_single_command(storage, pool_counters, options, command)).collect()
+        },
+    }
+}
+
+/// Commands read by `KvStorage::get_many` when several of them come in a row.
+fn is_batch_read(command: &models::Command) -> bool {
+    matches!(command, models::Command::Get { .. })
 }
 
-fn handle_connection(mut storage: kv_log::KvLogStorage, mut stream: net::TcpStream) -> models::Result<()> {
+/// Reads the values of the get commands at once. If the read fails, all of its commands fail.
+/// A get of a collection fails on its own: the values are read one by one then.
+fn handle_read_batch(storage: &dyn KvStorage, batch: Vec<models::Command>) -> Vec<models::ResponseCommand> {
+    log::info!("Handling a batch of {} reads", batch.len());
+    let batch_size = batch.len();
+    let keys: Vec<String> = batch.into_iter()
+        .filter_map(|command| match command {
+            models::Command::Get { key } => Some(key),
+            _ => None,
+        })
+        .collect();
+    match storage.get_many(keys.clone()) {
+        Ok(values) => values.into_iter().map(|value| models::ResponseCommand::Get { value }).collect(),
+        Err(err) if error_code(err.as_ref()) == models::ERROR_CODE_WRONG_TYPE => {
+            keys.into_iter()
+                .map(|key| match storage.get_bytes(key) {
+                    Ok(value) => models::ResponseCommand::Get { value },
+                    Err(err) => error_response(err.as_ref()),
+                })
+                .collect()
+        },
+        Err(err) => {
+            log::error!("Read batch handling error: {}", err);
+            (0..batch_size).map(|_| error_response(err.as_ref())).collect()
+        },
+    }
+}
+
+fn handle_connection(
+    stores: Stores,
+    mut stream: Box<dyn Stream>,
+    shutdown: ShutdownHandle,
+    options: ConnectionOptions,
+    client_addr: String,
+    access_log: Option<Arc<AccessLog>>,
+    pool_counters: Arc<threads::base::PoolCounters>,
+) -> models::Result<()> {
     log::debug!("Handling incoming connection");
 
     loop {
-        let mut reader = io::BufReader::new(&stream);
+        // Do not wait for new requests on keep-alive connections once the server is stopping.
+        if shutdown.is_requested() {
+            log::debug!("Server is shutting down, close connection");
+            break;
+        }
+
+        // Wait for the next request up to the idle timeout.
+        let mut reader = io::BufReader::new(DeadlineReader { stream: &mut stream, deadline: None });
+        reader.get_mut().set_timeout(options.idle_timeout)?;
+        match reader.fill_buf() {
+            // The connection is closed by the client or its idle read is interrupted by the server shutdown.
+            Ok([]) => break,
+            Ok(_) => {},
+            Err(err) if is_timeout(&err) => {
+                log::info!("Connection is idle for {:?}, closing", options.idle_timeout.unwrap_or_default());
+                break;
+            },
+            Err(_) if shutdown.is_requested() => break,
+            Err(err) => return Err(Box::new(err)),
+        }
+
+        let request_id = trace::new_request_id();
+        let _span = trace::Span::enter(format!("req={}", request_id));
+
+        // The rest of the request is expected to arrive within the request read timeout.
+        let started_at = std::time::SystemTime::now();
+        let started = Instant::now();
+        reader.get_mut().set_timeout(options.request_read_timeout)?;
         let header = read_header(&mut reader)?;
         if header.version > SERVER_VERSION {
             return Err(
@@ -107,6 +835,7 @@ fn handle_connection(mut storage: kv_log::KvLogStorage, mut stream: net::TcpStre
             )
         }
         let keep_alive = header.keep_alive != 0;
+        let framed = header.version >= FRAMED_VERSION;
 
         log::debug!("Body size {}", header.body_size);
         
@@ -130,20 +859,53 @@ fn handle_connection(mut storage: kv_log::KvLogStorage, mut stream: net::TcpStre
         }
         drop(body_reader);
 
+        // The request is handled until the client deadline or the server request timeout, whichever comes first.
+        let server_deadline = options.request_timeout.map(|timeout| std::time::SystemTime::now() + timeout);
+        let deadline = earliest_deadline(header.deadline(), server_deadline);
+        let mut access_log_entry = access_log.as_ref().map(|_| AccessLogEntry {
+            timestamp: started_at,
+            request_id: request_id.clone(),
+            client_addr: client_addr.clone(),
+            store: header.store.clone(),
+            namespace: header.namespace.clone(),
+            commands: commands.iter().map(|command| command.name()).collect(),
+            key_count: commands.iter().filter(|command| command.key().is_some()).count(),
+            request_bytes: header.body_size as usize,
+            response_bytes: 0,
+            duration: Duration::ZERO,
+            statuses: Vec::new(),
+        });
         let request = models::Request{
             header: header,
             commands: commands,
         };
         log::debug!("Handling request {}", request);
-        let responses = handle_request(&mut storage, request)?;
+        let (responses, value) = handle_request(&stores, &pool_counters, &options, request, deadline);
 
-        let response_data = serialize_response(responses)?;
-        log::debug!("{}", String::from_utf8_lossy(&response_data));
+        // Only the skipped commands are reported as exceeded, the handled ones are applied and keep their results.
+        if deadline_exceeded(deadline) {
+            log::warn!("Request deadline exceeded before sending the response");
+        }
+
+        if let Some(entry) = access_log_entry.as_mut() {
+            entry.statuses = match value {
+                Some(_) => vec![models::StatusCode::Ok],
+                None => responses.iter().map(|response| response.status()).collect(),
+            };
+        }
         let mut writer = io::BufWriter::new(&mut stream);
-        writer.write(response_data.as_slice())?;
+        let response_bytes = write_response(&mut writer, responses, value, framed)?;
         writer.flush()?;
         drop(writer);
 
+        if let (Some(access_log), Some(mut entry)) = (&access_log, access_log_entry) {
+            entry.response_bytes = response_bytes;
+            entry.duration = started.elapsed();
+            if let Err(err) = access_log.record(&entry) {
+                log::warn!("Cannot write access log {}: {}", access_log.path().display(), err);
+            }
+        }
+
         if keep_alive {
             log::debug!("Request handled, keep connection alive");
             continue;
@@ -153,51 +915,505 @@ fn handle_connection(mut storage: kv_log::KvLogStorage, mut stream: net::TcpStre
     }
 
     log::debug!("Request handled, close connection");
-    match stream.shutdown(std::net::Shutdown::Both) {
+    match stream.shutdown() {
         Ok(_) => {},
         Err(err) => { log::warn!("Cannot close socket gracefully: {}", err); }
     }
     Ok(())
 }
 
+/// Replies to the first request of a connection the server has no capacity for with a server busy error
+/// for each command and closes the connection. The request body is skipped without parsing the commands.
+fn reject_connection(mut stream: Box<dyn Stream>, message: &str) -> models::Result<()> {
+    let mut reader = io::BufReader::new(DeadlineReader { stream: &mut stream, deadline: None });
+    reader.get_mut().set_timeout(Some(BUSY_REPLY_TIMEOUT))?;
+    let header = read_header(&mut reader)?;
+    io::copy(&mut (&mut reader).take(header.body_size as u64), &mut io::sink())?;
+    drop(reader);
+
+    let responses = (0..header.command_count).map(|_| server_busy_response(message)).collect();
+    stream.write_all(serialize_response(responses, header.version >= FRAMED_VERSION)?.as_slice())?;
+    stream.flush()?;
+    stream.shutdown()?;
+    Ok(())
+}
+
+/// Thread replying to the rejected connections with `reject_connection`, so reading their requests
+/// never holds the accepting thread. Stops once the rejecter is dropped and the pending connections are replied.
+struct ConnectionRejecter {
+    sender: Option<std::sync::mpsc::SyncSender<(Box<dyn Stream>, &'static str)>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl ConnectionRejecter {
+    fn start() -> io::Result<ConnectionRejecter> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<(Box<dyn Stream>, &'static str)>(MAX_PENDING_REJECTS);
+        let thread = std::thread::Builder::new()
+            .name("kvs-rejecter".to_owned())
+            .spawn(move || {
+                for (stream, message) in receiver {
+                    if let Err(err) = reject_connection(stream, message) {
+                        log::warn!("Cannot reply to the rejected connection: {}", err);
+                    }
+                }
+            })?;
+        Ok(ConnectionRejecter { sender: Some(sender), thread: Some(thread) })
+    }
+
+    /// Queues the connection to be replied with the server busy errors. The connection is closed right away
+    /// if `MAX_PENDING_REJECTS` connections are waiting for the reply already.
+    fn reject(&self, stream: Box<dyn Stream>, message: &'static str) {
+        let Some(sender) = self.sender.as_ref() else {
+            return;
+        };
+        if let Err(std::sync::mpsc::TrySendError::Full(_)) = sender.try_send((stream, message)) {
+            log::warn!("Too many rejected connections waiting for the reply, closing the new one");
+        }
+    }
+}
+
+impl Drop for ConnectionRejecter {
+    fn drop(&mut self) {
+        self.sender.take();
+        if self.thread.take().is_some_and(|thread| thread.join().is_err()) {
+            log::error!("Connection rejecter thread panicked");
+        }
+    }
+}
+
+/// A socket of an accepted connection.
+trait AcceptedSocket: Sized {
+    /// Clones the socket to interrupt the connection on shutdown.
+    fn try_clone_socket(&self) -> io::Result<ConnectionSocket>;
+
+    /// Address of the client for the access log.
+    fn client_addr(&self) -> String;
+
+    /// Wraps the socket into a connection stream.
+    fn into_stream(self, tls_config: &Option<std::sync::Arc<rustls::ServerConfig>>) -> models::Result<Box<dyn Stream>>;
+}
+
+impl AcceptedSocket for net::TcpStream {
+    fn try_clone_socket(&self) -> io::Result<ConnectionSocket> {
+        Ok(ConnectionSocket::Tcp(self.try_clone()?))
+    }
+
+    fn client_addr(&self) -> String {
+        self.peer_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| String::from("unknown"))
+    }
+
+    /// Wraps an accepted TCP connection into a TLS session if the server is configured with TLS.
+    /// The handshake itself is performed lazily on the first read in the connection handler thread.
+    fn into_stream(self, tls_config: &Option<std::sync::Arc<rustls::ServerConfig>>) -> models::Result<Box<dyn Stream>> {
+        match tls_config {
+            Some(config) => {
+                let connection = rustls::ServerConnection::new(config.clone())?;
+                Ok(Box::new(rustls::StreamOwned::new(connection, self)))
+            },
+            None => Ok(Box::new(self)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AcceptedSocket for UnixStream {
+    fn try_clone_socket(&self) -> io::Result<ConnectionSocket> {
+        Ok(ConnectionSocket::Unix(self.try_clone()?))
+    }
+
+    /// The clients of a unix socket are usually unnamed, so they are not told apart.
+    fn client_addr(&self) -> String {
+        String::from("unix")
+    }
+
+    /// TLS is rejected on listen, a local socket connection is always plain.
+    fn into_stream(self, _tls_config: &Option<std::sync::Arc<rustls::ServerConfig>>) -> models::Result<Box<dyn Stream>> {
+        Ok(Box::new(self))
+    }
+}
+
+/// A clone of a connection socket kept to interrupt the connection on shutdown.
+enum ConnectionSocket {
+    Tcp(net::TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl ConnectionSocket {
+    fn shutdown_read(&self) -> io::Result<()> {
+        match self {
+            ConnectionSocket::Tcp(socket) => socket.shutdown(net::Shutdown::Read),
+            #[cfg(unix)]
+            ConnectionSocket::Unix(socket) => socket.shutdown(net::Shutdown::Read),
+        }
+    }
+}
+
+/// Address the server listens on.
+#[derive(Clone)]
+enum ListenAddr {
+    Tcp(net::SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Removes a socket file left by a server which is not running anymore.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> models::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            Err(Box::from(format!("Cannot listen on {}: the file exists and is not a socket", path.display())))
+        },
+        Ok(_) => match UnixStream::connect(path) {
+            Ok(_) => Err(Box::from(format!("Socket {} is in use by a running server", path.display()))),
+            Err(_) => {
+                log::warn!("Removing stale socket {}", path.display());
+                std::fs::remove_file(path)?;
+                Ok(())
+            },
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Open connections of the server. Keeps the socket clones to interrupt the connections on shutdown
+/// and caps the number of the open connections.
+struct ConnectionRegistry {
+    next_id: AtomicUsize,
+    /// Socket clones by connection ids. `None` for the connections which socket cannot be cloned,
+    /// they are counted but cannot be interrupted.
+    connections: Mutex<HashMap<usize, Option<ConnectionSocket>>>,
+}
+
+impl ConnectionRegistry {
+    fn new() -> Self {
+        ConnectionRegistry { next_id: AtomicUsize::new(0), connections: Mutex::new(HashMap::new()) }
+    }
+
+    /// Registers a new connection. Returns `None` if `max_connections` connections are open already.
+    fn register(&self, socket: Option<ConnectionSocket>, max_connections: Option<usize>) -> Option<usize> {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        if max_connections.is_some_and(|max_connections| connections.len() >= max_connections) {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        connections.insert(id, socket);
+        Some(id)
+    }
+
+    fn unregister(&self, id: usize) {
+        self.connections.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    }
+
+    fn count(&self) -> usize {
+        self.connections.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Closes the read half of all the connections, so the responses being handled are still delivered.
+    fn shutdown_read_all(&self) {
+        let connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        for socket in connections.values().flatten() {
+            let _ = socket.shutdown_read();
+        }
+    }
+}
+
+/// A handle to stop a running `KvsServer` from another thread.
+/// Shutdown stops accepting new connections and interrupts idle keep-alive connections.
+/// Requests being handled at the moment are completed.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    local_addr: Arc<Mutex<Option<ListenAddr>>>,
+    connections: Arc<ConnectionRegistry>,
+}
+
+impl ShutdownHandle {
+    fn new() -> Self {
+        ShutdownHandle {
+            requested: Arc::new(AtomicBool::new(false)),
+            local_addr: Arc::new(Mutex::new(None)),
+            connections: Arc::new(ConnectionRegistry::new()),
+        }
+    }
+
+    /// Requests the server shutdown. Returns immediately, `KvsServer::listen` returns once the shutdown is completed.
+    pub fn shutdown(&self) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        log::info!("Server shutdown requested");
+
+        // Interrupt connections waiting for the next request.
+        self.connections.shutdown_read_all();
+
+        // Wake up the listener blocked on accepting a new connection.
+        let local_addr = self.local_addr.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let wake_up_result = match local_addr {
+            Some(ListenAddr::Tcp(mut addr)) => {
+                if addr.ip().is_unspecified() {
+                    addr.set_ip(net::Ipv4Addr::LOCALHOST.into());
+                }
+                net::TcpStream::connect(addr).map(|_| ())
+            },
+            #[cfg(unix)]
+            Some(ListenAddr::Unix(path)) => UnixStream::connect(path).map(|_| ()),
+            None => Ok(()),
+        };
+        if let Err(err) = wake_up_result {
+            log::warn!("Cannot wake up the server listener: {}", err);
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Number of the open connections of the server.
+    pub fn connections_count(&self) -> usize {
+        self.connections.count()
+    }
+}
+
 pub struct KvsServer {
     thread_pool: Box<dyn threads::base::ThreadPool>,
-    engine: storage::KvLogStorage,
+    engine: Box<dyn KvStorage>,
+    /// Named stores by names, see `add_store`.
+    stores: HashMap<String, Box<dyn KvStorage>>,
+    tls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
+    shutdown: ShutdownHandle,
+    connection_options: ConnectionOptions,
+    access_log: Option<Arc<AccessLog>>,
 }
 
 impl KvsServer {
-    pub fn new(engine: storage::KvLogStorage, thread_pool: Box<dyn threads::base::ThreadPool>) -> KvsServer {
+    pub fn new(engine: impl KvStorage + 'static, thread_pool: Box<dyn threads::base::ThreadPool>) -> KvsServer {
         KvsServer{
             thread_pool: thread_pool,
-            engine: engine,
+            engine: Box::new(engine),
+            stores: HashMap::new(),
+            tls_config: None,
+            shutdown: ShutdownHandle::new(),
+            connection_options: ConnectionOptions {
+                idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+                request_read_timeout: Some(DEFAULT_REQUEST_READ_TIMEOUT),
+                request_timeout: None,
+                max_connections: None,
+                server_created: Instant::now(),
+                backup_root: None,
+            },
+            access_log: None,
+        }
+    }
+
+    /// Returns a handle to stop the server from another thread.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Serves `engine` as the store `name` in addition to the default storage. The requests with the store name
+    /// in the header are handled by its storage, the requests with an unknown store fail with `ERROR_CODE_NOT_FOUND`.
+    pub fn add_store(&mut self, name: String, engine: Box<dyn KvStorage>) -> models::Result<()> {
+        if name.is_empty() {
+            return Err(Box::from("Store name must not be empty"));
+        }
+        if self.stores.contains_key(&name) {
+            return Err(Box::from(format!("Store {} is already added", name)));
         }
+        self.stores.insert(name, engine);
+        Ok(())
     }
 
+    /// Enables TLS for all the accepted connections.
+    pub fn set_tls_config(&mut self, tls_config: std::sync::Arc<rustls::ServerConfig>) {
+        self.tls_config = Some(tls_config);
+    }
+
+    /// Sets the max time a keep-alive connection waits for the next request before it's closed, 5 minutes by default.
+    /// `None` keeps the idle connections open until the client closes them.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.connection_options.idle_timeout = idle_timeout;
+    }
+
+    /// Sets the max time to read a whole request once its first bytes are received, 30 seconds by default.
+    /// A connection failing to send the request in time is closed.
+    pub fn set_request_read_timeout(&mut self, request_read_timeout: Option<Duration>) {
+        self.connection_options.request_read_timeout = request_read_timeout;
+    }
+
+    /// Sets the max time to handle a request once it's read, unlimited by default. The deadline is checked
+    /// before each command, so the commands not started in time fail with `ERROR_CODE_DEADLINE_EXCEEDED`,
+    /// while the started ones complete and keep their results.
+    pub fn set_request_timeout(&mut self, request_timeout: Option<Duration>) {
+        self.connection_options.request_timeout = request_timeout;
+    }
+
+    /// Records the handled requests to the access log, disabled by default.
+    pub fn set_access_log(&mut self, access_log: Option<AccessLog>) {
+        self.access_log = access_log.map(Arc::new);
+    }
+
+    /// Sets the max number of the open connections, unlimited by default.
+    /// The connections accepted beyond the limit are not handed to the thread pool: the commands of their
+    /// first request are replied with `ERROR_CODE_SERVER_BUSY` and the connections are closed.
+    pub fn set_max_connections(&mut self, max_connections: Option<usize>) {
+        self.connection_options.max_connections = max_connections;
+    }
+
+    /// Sets the directory the backups of the remote restores are read from, `None` by default.
+    /// The backup directory of a restore command is resolved relative to it and must not leave it.
+    /// Without the backup root the restore commands fail with `ERROR_CODE_UNAUTHORIZED`.
+    pub fn set_backup_root(&mut self, backup_root: Option<PathBuf>) {
+        self.connection_options.backup_root = backup_root;
+    }
+
+    /// Accepts and handles connections until the shutdown is requested via the `ShutdownHandle`.
+    /// Waits for the connections in progress and the storage background jobs to complete
+    /// and flushes the storage before returning.
     pub fn listen(&mut self, host: String, port: u32) -> models::Result<()> {
         let addr = format!("{}:{}", host, port);
         let listener = net::TcpListener::bind(addr)?;
+        *self.shutdown.local_addr.lock().unwrap_or_else(|e| e.into_inner()) = Some(ListenAddr::Tcp(listener.local_addr()?));
+        self.accept_connections(listener.incoming());
 
-        for connection_result in listener.incoming() {
-            match connection_result {
-                Ok(stream) => {
-                    let storage = self.engine.clone();
-                    if let Err(err) = self.thread_pool.spawn(
-                        Box::new(move || {
-                            match handle_connection(storage, stream) {
-                                Ok(_) => {},
-                                Err(err) => { log::error!("Request handling error: {}", err) }
+        // Stop accepting connections and wait for the in-flight ones to complete.
+        drop(listener);
+        self.drain_connections()
+    }
+
+    /// Same as `listen`, but accepts connections on a unix domain socket at `path`.
+    /// A socket file left by a stopped server is replaced, the socket file is removed on shutdown.
+    /// TLS is not supported for local connections.
+    #[cfg(unix)]
+    pub fn listen_unix(&mut self, path: &Path) -> models::Result<()> {
+        if self.tls_config.is_some() {
+            return Err(Box::from("TLS is not supported on unix sockets"));
+        }
+        remove_stale_socket(path)?;
+        let listener = UnixListener::bind(path)?;
+        *self.shutdown.local_addr.lock().unwrap_or_else(|e| e.into_inner()) = Some(ListenAddr::Unix(path.to_path_buf()));
+        self.accept_connections(listener.incoming());
+
+        drop(listener);
+        if let Err(err) = std::fs::remove_file(path) {
+            log::warn!("Cannot remove socket {}: {}", path.display(), err);
+        }
+        self.drain_connections()
+    }
+
+    fn accept_connections<S: AcceptedSocket>(&mut self, incoming: impl Iterator<Item = io::Result<S>>) {
+        let stores = Stores {
+            default: self.engine.clone_box(),
+            named: self.stores.iter().map(|(name, engine)| (name.clone(), engine.clone_box())).collect(),
+        };
+        let rejecter = match ConnectionRejecter::start() {
+            Ok(rejecter) => Some(rejecter),
+            Err(err) => {
+                log::error!("Cannot start the connection rejecter thread: {}", err);
+                None
+            },
+        };
+        for connection_result in incoming {
+            if self.shutdown.is_requested() {
+                break;
+            }
+
+            let socket = match connection_result {
+                Ok(socket) => socket,
+                Err(err) => {
+                    log::error!("Cannot handle incoming connection: {}", err);
+                    continue;
+                }
+            };
+
+            // Keep a socket clone to interrupt the connection on shutdown.
+            let socket_clone = match socket.try_clone_socket() {
+                Ok(socket_clone) => Some(socket_clone),
+                Err(err) => {
+                    log::warn!("Cannot track connection for graceful shutdown: {}", err);
+                    None
+                },
+            };
+            let id = match self.shutdown.connections.register(socket_clone, self.connection_options.max_connections) {
+                Some(id) => id,
+                None => {
+                    log::warn!("Too many open connections, rejecting the new one");
+                    match socket.into_stream(&self.tls_config) {
+                        Ok(stream) => {
+                            if let Some(rejecter) = rejecter.as_ref() {
+                                rejecter.reject(stream, "Too many open connections");
                             }
-                        })
-                    ) {
-                        log::error!("Cannot spawn a new thread to handle connection: {}", err);    
+                        },
+                        Err(err) => log::warn!("Cannot reply to the rejected connection: {}", err),
+                    }
+                    continue;
+                },
+            };
+
+            let client_addr = socket.client_addr();
+            match socket.into_stream(&self.tls_config) {
+                Ok(stream) => {
+                    let stores = stores.clone();
+                    let shutdown = self.shutdown.clone();
+                    let options = self.connection_options.clone();
+                    let access_log = self.access_log.clone();
+                    let pool_counters = self.thread_pool.counters();
+                    // The stream is taken back to reject the connection if the thread pool queue is full.
+                    let stream = Arc::new(Mutex::new(Some(stream)));
+                    let job_stream = stream.clone();
+                    if let Err(err) = self.thread_pool.execute(move || {
+                        let _span = trace::Span::enter(format!("conn={}", id));
+                        let connections = shutdown.connections.clone();
+                        scopeguard::defer! {
+                            connections.unregister(id);
+                        }
+                        let Some(stream) = job_stream.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+                            return;
+                        };
+                        match handle_connection(
+                            stores, stream, shutdown, options, client_addr, access_log, pool_counters,
+                        ) {
+                            Ok(_) => {},
+                            Err(err) => { log::error!("Request handling error: {}", err) }
+                        }
+                    }) {
+                        self.shutdown.connections.unregister(id);
+                        let stream = stream.lock().unwrap_or_else(|e| e.into_inner()).take();
+                        match stream {
+                            Some(stream) if err.is::<threads::base::QueueFullError>() => {
+                                log::warn!("{}, rejecting the new connection", err);
+                                if let Some(rejecter) = rejecter.as_ref() {
+                                    rejecter.reject(stream, "Server is overloaded");
+                                }
+                            },
+                            _ => log::error!("Cannot spawn a new thread to handle connection: {}", err),
+                        }
                     }
                 },
                 Err(err) => {
                     log::error!("Cannot handle incoming connection: {}", err);
+                    self.shutdown.connections.unregister(id);
                 }
             }
         }
+    }
+
+    /// Waits for the in-flight connections to complete, stops the thread pool and closes the storages.
+    fn drain_connections(&mut self) -> models::Result<()> {
+        log::info!("Waiting for {} connections to complete", self.shutdown.connections_count());
+        while self.shutdown.connections_count() > 0 {
+            std::thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+        self.thread_pool.shutdown(threads::base::ShutdownMode::Graceful)?;
 
+        self.engine.close()?;
+        for engine in self.stores.values() {
+            engine.close()?;
+        }
+        log::info!("Server is stopped");
         Ok(())
     }
 }
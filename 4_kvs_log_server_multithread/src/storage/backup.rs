@@ -0,0 +1,292 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log;
+
+use crate::models::Result;
+use crate::storage::kv_log::{file_idx_to_path, path_to_idx};
+
+pub(crate) const MANIFEST_FILE_NAME: &str = "MANIFEST";
+
+/// Initial value of the segment checksums.
+pub(crate) const CHECKSUM_SEED: u64 = 0xcbf29ce484222325;
+
+/// Get name of a single backup generation directory.
+pub(crate) fn generation_name(generation: u64) -> String {
+    format!("gen_{}", generation)
+}
+
+/// Get directory of a single backup generation.
+fn generation_dir(backup_dir: &Path, generation: u64) -> PathBuf {
+    backup_dir.join(generation_name(generation))
+}
+
+/// Updates a 64-bit FNV-1a checksum with the next bytes of the content.
+pub(crate) fn update_checksum(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Computes a 64-bit FNV-1a checksum of a file content.
+fn file_checksum(path: &Path) -> Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buffer = [0u8; 64 * 1024];
+    let mut hash = CHECKSUM_SEED;
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hash = update_checksum(hash, &buffer[..bytes_read]);
+    }
+    Ok(hash)
+}
+
+/// A single segment record in the backup manifest.
+/// `size` and `modified` identify the segment content: compaction and appends
+/// change at least one of them, so a segment with the same fingerprint is not copied again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentRecord {
+    pub file_idx: usize,
+    /// Backup generation holding the copy of the segment.
+    pub generation: u64,
+    pub size: u64,
+    pub modified: u128,
+    /// Checksum of the segment copy. Missing in the manifests written before the checksums were introduced.
+    pub checksum: Option<u64>,
+}
+
+impl SegmentRecord {
+    pub(crate) fn has_same_content(&self, other: &SegmentRecord) -> bool {
+        self.file_idx == other.file_idx && self.size == other.size && self.modified == other.modified
+    }
+}
+
+/// Backup manifest describing the full list of segments of the latest backup.
+/// Each segment points to the generation directory its copy was taken in,
+/// so restore layers the base backup with all the following increments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupManifest {
+    pub generation: u64,
+    pub segments: Vec<SegmentRecord>,
+}
+
+impl BackupManifest {
+    /// Segments copied by the backup of this manifest generation.
+    pub fn copied_segments(&self) -> Vec<&SegmentRecord> {
+        self.segments.iter().filter(|s| s.generation == self.generation).collect()
+    }
+
+    /// Reads the manifest from the backup directory. Returns `None` if there are no backups yet.
+    pub fn read(backup_dir: &Path) -> Result<Option<BackupManifest>> {
+        let manifest_path = backup_dir.join(MANIFEST_FILE_NAME);
+        if !manifest_path.exists() {
+            return Ok(None);
+        }
+
+        let reader = BufReader::new(File::open(&manifest_path)?);
+        let lines = reader.lines().collect::<std::io::Result<Vec<String>>>()?;
+        Ok(Some(Self::parse(&lines, &manifest_path.display().to_string())?))
+    }
+
+    /// Parses the manifest lines read from `source`.
+    pub(crate) fn parse(lines: &[String], source: &str) -> Result<BackupManifest> {
+        let generation = match lines.first() {
+            Some(line) => parse_generation_line(line)?,
+            None => return Err(Box::from(format!("Backup manifest {} is empty", source))),
+        };
+
+        let mut segments = Vec::new();
+        for line in &lines[1..] {
+            if line.is_empty() {
+                continue;
+            }
+            segments.push(parse_segment_line(line)?);
+        }
+
+        Ok(BackupManifest { generation, segments })
+    }
+
+    /// Formats the manifest as it is written to the manifest file.
+    pub(crate) fn format(&self) -> String {
+        let mut content = format!("generation {}\n", self.generation);
+        for segment in &self.segments {
+            content.push_str(&format!(
+                "{} {} {} {}", segment.file_idx, segment.generation, segment.size, segment.modified,
+            ));
+            match segment.checksum {
+                Some(checksum) => content.push_str(&format!(" {:016x}\n", checksum)),
+                None => content.push('\n'),
+            }
+        }
+        content
+    }
+
+    /// Writes the manifest to the backup directory.
+    /// The manifest is written to a temporary file first and then renamed,
+    /// so an interrupted backup never leaves a partially written manifest.
+    pub(crate) fn write(&self, backup_dir: &Path) -> Result<()> {
+        let manifest_path = backup_dir.join(MANIFEST_FILE_NAME);
+        let tmp_manifest_path = backup_dir.join(format!("_tmp_{}", MANIFEST_FILE_NAME));
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_manifest_path)?;
+        file.write_all(self.format().as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(tmp_manifest_path, manifest_path)?;
+        Ok(())
+    }
+}
+
+fn parse_generation_line(line: &str) -> Result<u64> {
+    match line.split_once(' ') {
+        Some(("generation", value)) => Ok(value.parse::<u64>()?),
+        _ => Err(Box::from(format!("Invalid backup manifest header: {}", line))),
+    }
+}
+
+fn parse_segment_line(line: &str) -> Result<SegmentRecord> {
+    let parts: Vec<&str> = line.split(' ').collect();
+    if parts.len() != 4 && parts.len() != 5 {
+        return Err(Box::from(format!("Invalid backup manifest record: {}", line)));
+    }
+
+    Ok(
+        SegmentRecord {
+            file_idx: parts[0].parse::<usize>()?,
+            generation: parts[1].parse::<u64>()?,
+            size: parts[2].parse::<u64>()?,
+            modified: parts[3].parse::<u128>()?,
+            checksum: match parts.get(4) {
+                Some(checksum) => Some(u64::from_str_radix(checksum, 16)?),
+                None => None,
+            },
+        }
+    )
+}
+
+/// List the current storage segments sorted by file index.
+fn list_segments(storage_dir: &Path) -> Result<Vec<SegmentRecord>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(storage_dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(std::ffi::OsStr::new("log")) {
+            continue;
+        }
+        if let Some(file_idx) = path_to_idx(&path) {
+            // Skip temporary compaction files sharing the segment index.
+            if path != file_idx_to_path(storage_dir, file_idx) {
+                continue;
+            }
+            let metadata = fs::metadata(&path)?;
+            let modified = metadata
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_nanos();
+            segments.push(SegmentRecord { file_idx, generation: 0, size: metadata.len(), modified, checksum: None });
+        }
+    }
+    segments.sort_by_key(|s| s.file_idx);
+    Ok(segments)
+}
+
+/// Backs up the storage segments from `storage_dir` to `backup_dir`.
+/// Only segments created or rewritten since the previous backup are copied to a new generation directory,
+/// the rest are referenced from the previous generations.
+/// The caller is responsible for blocking the storage writes while the backup is running.
+pub(crate) fn create_backup(storage_dir: &Path, backup_dir: &Path) -> Result<BackupManifest> {
+    fs::create_dir_all(backup_dir)?;
+
+    let previous = BackupManifest::read(backup_dir)?;
+    let generation = previous.as_ref().map(|m| m.generation + 1).unwrap_or(1);
+    let target_dir = generation_dir(backup_dir, generation);
+    if target_dir.exists() {
+        log::warn!(
+            "Backup directory {} already exists. It might be a result of a previous failed backup.",
+            target_dir.display(),
+        );
+        fs::remove_dir_all(&target_dir)?;
+    }
+    fs::create_dir_all(&target_dir)?;
+
+    let mut segments = Vec::new();
+    for mut segment in list_segments(storage_dir)? {
+        let backed_up = previous.as_ref().and_then(|m| m.segments.iter().find(|s| s.has_same_content(&segment)));
+        match backed_up {
+            Some(backed_up_segment) => {
+                segment.generation = backed_up_segment.generation;
+                segment.checksum = backed_up_segment.checksum;
+            },
+            None => {
+                let source_path = file_idx_to_path(storage_dir, segment.file_idx);
+                let target_path = file_idx_to_path(&target_dir, segment.file_idx);
+                log::info!("Copying segment {} to {}", source_path.display(), target_path.display());
+                fs::copy(&source_path, &target_path)?;
+                segment.generation = generation;
+                segment.checksum = Some(file_checksum(&target_path)?);
+            },
+        }
+        segments.push(segment);
+    }
+
+    let manifest = BackupManifest { generation, segments };
+    manifest.write(backup_dir)?;
+    log::info!(
+        "Backup generation {} completed: {}/{} segments copied",
+        generation, manifest.copied_segments().len(), manifest.segments.len(),
+    );
+    Ok(manifest)
+}
+
+/// Checks that a segment copy matches its manifest record.
+fn validate_segment(segment: &SegmentRecord, path: &Path) -> Result<()> {
+    let size = fs::metadata(path)?.len();
+    if size != segment.size {
+        return Err(Box::from(format!(
+            "Segment {} size mismatch: expected {} bytes, got {}", path.display(), segment.size, size,
+        )));
+    }
+    match segment.checksum {
+        Some(checksum) if file_checksum(path)? != checksum => {
+            Err(Box::from(format!("Segment {} checksum mismatch", path.display())))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Restores the latest backup from `backup_dir` into `target_dir`.
+/// The base backup and all the increments are layered using the manifest.
+/// The restored segments are validated against the manifest sizes and checksums.
+/// The target directory must be empty or must not exist.
+pub fn restore_backup(backup_dir: &Path, target_dir: &Path) -> Result<BackupManifest> {
+    let manifest = BackupManifest::read(backup_dir)?
+        .ok_or_else(|| format!("No backups found in {}", backup_dir.display()))?;
+
+    if target_dir.exists() && fs::read_dir(target_dir)?.next().is_some() {
+        return Err(Box::from(format!("Restore target {} is not empty", target_dir.display())));
+    }
+    fs::create_dir_all(target_dir)?;
+
+    for segment in &manifest.segments {
+        let source_path = file_idx_to_path(&generation_dir(backup_dir, segment.generation), segment.file_idx);
+        let target_path = file_idx_to_path(target_dir, segment.file_idx);
+        log::info!("Restoring segment {} to {}", source_path.display(), target_path.display());
+        fs::copy(&source_path, &target_path)?;
+        validate_segment(segment, &target_path)?;
+    }
+
+    log::info!(
+        "Backup generation {} restored to {}: {} segments",
+        manifest.generation, target_dir.display(), manifest.segments.len(),
+    );
+    Ok(manifest)
+}
@@ -0,0 +1,237 @@
+use std::io;
+use std::path::Path;
+
+use crate::models::{CollectionType, Command, Result, StorageStats};
+use crate::storage::collections::{self, Collection, MutationResult};
+pub use kvs_common::engine::{check_engine, ENGINE_FILE_NAME};
+
+/// Reads the collection of a key, see `KvStorage::read_collection`.
+pub type CollectionRead<'a> = dyn FnMut(Option<&Collection>) -> Result<()> + 'a;
+
+/// Reader of a stored binary value of `size` bytes, see `KvStorage::open_value`.
+pub struct ValueReader {
+    pub size: u64,
+    pub reader: Box<dyn io::Read + Send>,
+}
+
+impl ValueReader {
+    /// Reader of a value held in memory.
+    pub fn from_bytes(value: Vec<u8>) -> ValueReader {
+        ValueReader { size: value.len() as u64, reader: Box::new(io::Cursor::new(value)) }
+    }
+}
+
+/// Storage operations used by the server. Each connection handler works with its own handle to the storage.
+pub trait KvStorage: Send {
+    /// Set key `key` to a binary value `value`.
+    fn set_bytes(&mut self, key: String, value: Vec<u8>) -> Result<()>;
+
+    /// Gets the binary value with the key `key`. Returns `None` if the key doesn't exist in the storage.
+    fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>>;
+
+    /// Gets the binary values with the keys `keys` in the same order, `None` for the missing keys.
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>>;
+
+    /// Opens the binary value with the key `key` to be read in parts. Returns `None` if the key doesn't exist.
+    /// The whole value is read with `get_bytes` unless the storage can read it from its files.
+    fn open_value(&self, key: String) -> Result<Option<ValueReader>> {
+        Ok(self.get_bytes(key)?.map(ValueReader::from_bytes))
+    }
+
+    /// Appends `suffix` to the binary value with the key `key`, the missing key is set to `suffix`.
+    /// Returns the length of the new value.
+    fn append(&mut self, key: String, suffix: Vec<u8>) -> Result<u64>;
+
+    /// Sets key `key` to a binary value `value` and returns the previous value, `None` if the key didn't exist.
+    fn get_set(&mut self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>>;
+
+    /// Sets key `key` to a binary value `value` only if the key doesn't exist. Returns `true` if the value was set.
+    fn set_nx(&mut self, key: String, value: Vec<u8>) -> Result<bool>;
+
+    /// Applies the collection mutation command `mutation` (`Command::ListPush`, `Command::HashSet`, ...)
+    /// to the collection with the key of the command atomically. The missing collection is created
+    /// and the emptied one is removed. Fails with `ERROR_CODE_WRONG_TYPE` if the key holds a plain value
+    /// or a collection of another type.
+    fn update_collection(&mut self, mutation: Command) -> Result<MutationResult>;
+
+    /// Calls `read` with the collection of the type `collection_type` with the key `key`, `None` for a missing key.
+    /// Fails with `ERROR_CODE_WRONG_TYPE` if the key holds a plain value or a collection of another type.
+    fn read_collection(&self, key: String, collection_type: CollectionType, read: &mut CollectionRead) -> Result<()>;
+
+    /// Pushes `values` one by one to the front or to the back of the list with the key `key`,
+    /// the missing list is created. Returns the length of the list.
+    fn list_push(&mut self, key: String, values: Vec<Vec<u8>>, front: bool) -> Result<u64> {
+        Ok(self.update_collection(Command::ListPush { key, values, front })?.count)
+    }
+
+    /// Pops a value from the front or from the back of the list with the key `key`, the emptied list is removed.
+    /// Returns `None` if the list doesn't exist.
+    fn list_pop(&mut self, key: String, front: bool) -> Result<Option<Vec<u8>>> {
+        Ok(self.update_collection(Command::ListPop { key, front })?.popped)
+    }
+
+    /// Reads the values of the list with the key `key` between `start` and `stop` inclusive.
+    /// Negative indexes count from the end of the list, a missing list is empty.
+    fn list_range(&self, key: String, start: i64, stop: i64) -> Result<Vec<Vec<u8>>> {
+        let mut values = Vec::new();
+        self.read_collection(key, CollectionType::List, &mut |collection| {
+            if let Some(Collection::List(list)) = collection {
+                let bounds = collections::list_range_bounds(list.len(), start, stop);
+                values = bounds.map_or_else(Vec::new, |(start, stop)| list.range(start..=stop).cloned().collect());
+            }
+            Ok(())
+        })?;
+        Ok(values)
+    }
+
+    /// Sets the `fields` of the hash with the key `key`, the missing hash is created.
+    /// Returns the number of the added fields, the rest are overwritten.
+    fn hash_set(&mut self, key: String, fields: Vec<(String, Vec<u8>)>) -> Result<u64> {
+        Ok(self.update_collection(Command::HashSet { key, fields })?.count)
+    }
+
+    /// Gets the value of the field `field` of the hash with the key `key`.
+    /// Returns `None` if the hash or the field doesn't exist.
+    fn hash_get(&self, key: String, field: String) -> Result<Option<Vec<u8>>> {
+        let mut value = None;
+        self.read_collection(key, CollectionType::Hash, &mut |collection| {
+            if let Some(Collection::Hash(hash)) = collection {
+                value = hash.get(&field).cloned();
+            }
+            Ok(())
+        })?;
+        Ok(value)
+    }
+
+    /// Removes the `fields` of the hash with the key `key`, the emptied hash is removed.
+    /// Returns the number of the removed fields.
+    fn hash_delete(&mut self, key: String, fields: Vec<String>) -> Result<u64> {
+        Ok(self.update_collection(Command::HashDelete { key, fields })?.count)
+    }
+
+    /// Gets all the fields of the hash with the key `key` sorted by the field names, none for a missing hash.
+    fn hash_get_all(&self, key: String) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut fields = Vec::new();
+        self.read_collection(key, CollectionType::Hash, &mut |collection| {
+            if let Some(Collection::Hash(hash)) = collection {
+                fields = hash.iter().map(|(field, value)| (field.clone(), value.clone())).collect();
+            }
+            Ok(())
+        })?;
+        Ok(fields)
+    }
+
+    /// Adds the `members` to the set with the key `key`, the missing set is created.
+    /// Returns the number of the added members, the existing ones are ignored.
+    fn set_add(&mut self, key: String, members: Vec<Vec<u8>>) -> Result<u64> {
+        Ok(self.update_collection(Command::SetAdd { key, members })?.count)
+    }
+
+    /// Removes the `members` of the set with the key `key`, the emptied set is removed.
+    /// Returns the number of the removed members.
+    fn set_remove(&mut self, key: String, members: Vec<Vec<u8>>) -> Result<u64> {
+        Ok(self.update_collection(Command::SetRemove { key, members })?.count)
+    }
+
+    /// Checks whether `member` belongs to the set with the key `key`, a missing set has no members.
+    fn set_is_member(&self, key: String, member: Vec<u8>) -> Result<bool> {
+        let mut is_member = false;
+        self.read_collection(key, CollectionType::Set, &mut |collection| {
+            is_member = matches!(collection, Some(Collection::Set(set)) if set.contains(&member));
+            Ok(())
+        })?;
+        Ok(is_member)
+    }
+
+    /// Gets the sorted members of the set with the key `key`, none for a missing set.
+    fn set_members(&self, key: String) -> Result<Vec<Vec<u8>>> {
+        let mut members = Vec::new();
+        self.read_collection(key, CollectionType::Set, &mut |collection| {
+            if let Some(Collection::Set(set)) = collection {
+                members = set.iter().cloned().collect();
+            }
+            Ok(())
+        })?;
+        Ok(members)
+    }
+
+    /// Adds the `members` with their scores to the sorted set with the key `key`, the missing sorted set is created.
+    /// Returns the number of the added members, the scores of the existing ones are updated.
+    fn sorted_set_add(&mut self, key: String, members: Vec<(Vec<u8>, f64)>) -> Result<u64> {
+        Ok(self.update_collection(Command::SortedSetAdd { key, members })?.count)
+    }
+
+    /// Removes the `members` of the sorted set with the key `key`, the emptied sorted set is removed.
+    /// Returns the number of the removed members.
+    fn sorted_set_remove(&mut self, key: String, members: Vec<Vec<u8>>) -> Result<u64> {
+        Ok(self.update_collection(Command::SortedSetRemove { key, members })?.count)
+    }
+
+    /// Gets the members of the sorted set with the key `key` with the scores between `min` and `max` inclusive,
+    /// ordered by the scores.
+    fn sorted_set_range_by_score(&self, key: String, min: f64, max: f64) -> Result<Vec<(Vec<u8>, f64)>> {
+        let mut members = Vec::new();
+        self.read_collection(key, CollectionType::SortedSet, &mut |collection| {
+            if let Some(Collection::SortedSet(sorted_set)) = collection {
+                members = sorted_set.range_by_score(min, max);
+            }
+            Ok(())
+        })?;
+        Ok(members)
+    }
+
+    /// Removes key `key` from the storage.
+    /// Returns `true` if the key existed.
+    fn remove(&mut self, key: String) -> Result<bool>;
+
+    /// Removes all records in the storage.
+    fn reset(&mut self) -> Result<()>;
+
+    /// Removes all the keys starting with `prefix`. Returns the number of the removed keys.
+    fn reset_prefix(&mut self, prefix: String) -> Result<u64>;
+
+    /// Writes the set and remove commands as a single batch, applied atomically where the storage supports it.
+    fn write_batch(&mut self, commands: Vec<Command>) -> Result<()>;
+
+    /// Max total size of the log records of a `write_batch` in bytes, unlimited if `None`.
+    fn max_batch_size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Compacts all the sealed log files.
+    fn compact(&self) -> Result<()>;
+
+    fn stats(&self) -> Result<StorageStats>;
+
+    /// Syncs the written data to the disk.
+    fn flush(&self) -> Result<()>;
+
+    /// Waits for the background jobs to complete and syncs the written data to the disk. Called on shutdown.
+    fn close(&self) -> Result<()>;
+
+    /// Stages the latest backup from `backup_dir`. Returns a token to commit or abort the restore.
+    fn prepare_restore(&self, backup_dir: &Path) -> Result<String>;
+
+    fn commit_restore(&mut self, token: &str) -> Result<()>;
+
+    fn abort_restore(&self, token: &str) -> Result<()>;
+
+    /// Returns a handle to the storage of the namespace `name`, created on the first access.
+    fn namespace(&self, name: &str) -> Result<Box<dyn KvStorage>>;
+
+    /// Changes the min share of the stale records in a log file to compact it, for all the storage handles.
+    fn set_compaction_garbage_ratio(&self, ratio: f64) -> Result<()>;
+
+    /// Stops the background compaction, waiting for the running compactions to complete.
+    fn pause_compaction(&self) -> Result<()>;
+
+    fn resume_compaction(&self) -> Result<()>;
+
+    /// Returns `true` while the background compaction is paused by `pause_compaction`.
+    fn is_compaction_paused(&self) -> bool {
+        false
+    }
+
+    /// Returns another handle to the same storage.
+    fn clone_box(&self) -> Box<dyn KvStorage>;
+}
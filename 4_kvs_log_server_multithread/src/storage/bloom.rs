@@ -0,0 +1,114 @@
+use std::fs::{rename, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::models::Result;
+use crate::serialize::{ReadFromStream, WriteToStream};
+
+/// Expected false positive rate of the segment filters.
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+const MIN_BITS_COUNT: usize = 64;
+
+/// Convert log file index to the path of its bloom filter file.
+pub(crate) fn filter_path(storage_path: &Path, file_idx: usize) -> PathBuf {
+    storage_path.join(format!("kv_{}.bloom", file_idx))
+}
+
+/// 64-bit FNV-1a hash. Unlike the std hashers it is stable across builds, so the filters can be persisted.
+pub(crate) fn fnv1a(data: &[u8], seed: u64) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64 ^ seed;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Bloom filter of the keys stored in a sealed log file.
+/// `log_file_size` is the size of the log file the filter was built for,
+/// a filter of a file changed since then is stale and is rebuilt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BloomFilter {
+    pub log_file_size: u64,
+    hashes_count: u32,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `items_count` keys.
+    pub fn new(items_count: usize, log_file_size: u64) -> BloomFilter {
+        // Optimal size and number of hashes for the expected false positive rate.
+        let ln2 = std::f64::consts::LN_2;
+        let bits_count = (-(items_count.max(1) as f64) * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil() as usize;
+        let bits_count = bits_count.max(MIN_BITS_COUNT);
+        let hashes_count = ((bits_count as f64 / items_count.max(1) as f64) * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            log_file_size,
+            hashes_count,
+            bits: vec![0u64; bits_count.div_ceil(64)],
+        }
+    }
+
+    /// Bit positions of a key, derived from two hashes with double hashing.
+    fn bit_positions(&self, key: &[u8]) -> impl Iterator<Item = usize> + '_ {
+        let h1 = fnv1a(key, 0);
+        let h2 = fnv1a(key, 0x9e3779b97f4a7c15) | 1;
+        let bits_count = (self.bits.len() * 64) as u64;
+        (0..self.hashes_count as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits_count) as usize)
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        let positions: Vec<usize> = self.bit_positions(key).collect();
+        for position in positions {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    /// Returns `false` if the key is definitely not in the filter.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_positions(key).all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+
+    /// Reads a filter file. Returns `None` if the file doesn't exist or is damaged, so the filter is rebuilt.
+    pub fn read(path: &Path) -> Option<BloomFilter> {
+        let file = File::open(path).ok()?;
+        let mut reader = BufReader::new(file);
+        let mut read = || -> io::Result<BloomFilter> {
+            let log_file_size = u64::deserialize(&mut reader)?;
+            let hashes_count = u32::deserialize(&mut reader)?;
+            let words_count = u64::deserialize(&mut reader)? as usize;
+            let mut bits = Vec::with_capacity(words_count);
+            for _ in 0..words_count {
+                bits.push(u64::deserialize(&mut reader)?);
+            }
+            Ok(BloomFilter { log_file_size, hashes_count, bits })
+        };
+        match read() {
+            Ok(filter) if filter.hashes_count > 0 && !filter.bits.is_empty() => Some(filter),
+            Ok(_) => None,
+            Err(err) => {
+                log::warn!("Cannot read bloom filter {}: {}", path.display(), err);
+                None
+            },
+        }
+    }
+
+    /// Writes the filter to a temporary file and renames it, so a filter file is never partially written.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut buffer = Vec::with_capacity(20 + self.bits.len() * 8);
+        self.log_file_size.serialize(&mut buffer)?;
+        self.hashes_count.serialize(&mut buffer)?;
+        (self.bits.len() as u64).serialize(&mut buffer)?;
+        for word in &self.bits {
+            word.serialize(&mut buffer)?;
+        }
+
+        let tmp_path = path.with_extension("bloom.tmp");
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        file.write_all(&buffer)?;
+        file.sync_data()?;
+        drop(file);
+        rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
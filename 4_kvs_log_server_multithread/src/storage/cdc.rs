@@ -0,0 +1,276 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::models::{Command, Result};
+use crate::serialize;
+use crate::storage::kv_log::{decode_value, file_idx_to_path, path_to_idx};
+use crate::storage::watch::ChangeEvent;
+
+/// Change of the stored data read from the log files, see `CdcReader`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CdcEvent {
+    /// Sequence number of the write, shared by the changes of a batch.
+    /// 0 for the changes read before the first sequence record.
+    pub seq: u64,
+    /// Time of the write in milliseconds since the Unix epoch, 0 if unknown.
+    pub timestamp_ms: u64,
+    pub change: ChangeEvent,
+}
+
+impl CdcEvent {
+    /// Serializes the event into a single line JSON object, without the line break.
+    /// Values which are not valid UTF-8 are written hex-encoded as `value_hex`.
+    pub fn to_json(&self) -> String {
+        let change = match &self.change {
+            ChangeEvent::Set { key, value } => match std::str::from_utf8(value) {
+                Ok(value) => format!("\"set\",\"key\":{},\"value\":{}", json!(key), json!(value)),
+                Err(_) => {
+                    let value_hex: String = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    format!("\"set\",\"key\":{},\"value_hex\":\"{}\"", json!(key), value_hex)
+                },
+            },
+            ChangeEvent::Remove { key } => format!("\"remove\",\"key\":{}", json!(key)),
+            ChangeEvent::Reset => "\"reset\"".to_owned(),
+        };
+        format!("{{\"seq\":{},\"timestamp_ms\":{},\"op\":{}}}", self.seq, self.timestamp_ms, change)
+    }
+}
+
+/// Log file being read by a `CdcReader`.
+struct TailedFile {
+    file_idx: usize,
+    file: File,
+    /// Offset of the next record to read, 0 until the segment header is read.
+    offset: u64,
+    has_records: bool,
+    has_sequence: bool,
+}
+
+/// Change-data-capture reader. Tails the log files of a storage directory and returns the set and remove changes
+/// in the order of the writes, so a downstream system can mirror the keys. The storage may be served meanwhile.
+/// Reading can be resumed after a sequence number if the storage is written with
+/// `KvLogStorageBuilder::record_sequence`.
+/// A reset or a restore of the storage is returned as `ChangeEvent::Reset` followed by the restored keys.
+/// It's detected by the replaced active log file, so the reader must be at the end of the log meanwhile.
+/// Compaction drops the sequence records and the overwritten changes of the rewritten files,
+/// so the reader returns the latest values of their keys instead.
+/// The collection records are skipped, only the removal of a collection is returned.
+pub struct CdcReader {
+    storage_dir: PathBuf,
+    from_seq: u64,
+    /// Whether the changes up to `from_seq` are still skipped.
+    resuming: bool,
+    /// Whether a log file without sequence records is read since the last sequence record within `from_seq`.
+    unsequenced_file: bool,
+    has_sequence_within: bool,
+    seq: u64,
+    timestamp_ms: u64,
+    /// Index of the last log file read, 0 before the first one.
+    file_idx: usize,
+    file: Option<TailedFile>,
+}
+
+impl CdcReader {
+    /// Starts reading the log of the storage in `storage_dir` after the write `from_seq`,
+    /// from the beginning of the log if it's 0.
+    pub fn open(storage_dir: &Path, from_seq: u64) -> Result<CdcReader> {
+        if !storage_dir.is_dir() {
+            return Err(Box::from(format!("Storage {} doesn't exist", storage_dir.display())));
+        }
+        Ok(
+            CdcReader {
+                storage_dir: storage_dir.to_path_buf(),
+                from_seq,
+                resuming: from_seq > 0,
+                unsequenced_file: false,
+                has_sequence_within: false,
+                seq: 0,
+                timestamp_ms: 0,
+                file_idx: 0,
+                file: None,
+            }
+        )
+    }
+
+    /// Sequence number of the last write read, to resume the reading after it.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Reads the changes written since the previous call. Returns an empty list at the end of the log.
+    pub fn poll(&mut self) -> Result<Vec<CdcEvent>> {
+        let mut events = Vec::new();
+        loop {
+            let file_idxs = list_log_files(&self.storage_dir)?;
+            let mut tailed = match self.file.take() {
+                Some(tailed) => tailed,
+                None => match file_idxs.iter().find(|file_idx| **file_idx > self.file_idx) {
+                    Some(file_idx) => match self.open_file(*file_idx)? {
+                        Some(tailed) => tailed,
+                        // Compaction removes the files without live records.
+                        None => {
+                            self.file_idx = *file_idx;
+                            continue;
+                        },
+                    },
+                    None => return Ok(events),
+                },
+            };
+
+            // Listed before reading, so a file with a newer one is sealed and is read to its end.
+            let next_file_idx = file_idxs.iter().copied().find(|file_idx| *file_idx > tailed.file_idx);
+            self.read_records(&mut tailed, next_file_idx.is_some(), &mut events)?;
+            match next_file_idx {
+                Some(_) => {
+                    if tailed.has_records && !tailed.has_sequence {
+                        self.unsequenced_file = true;
+                    }
+                    self.file_idx = tailed.file_idx;
+                },
+                None if is_replaced(&tailed, &self.storage_dir)? => {
+                    log::info!("Log file {} is replaced, reading the log from the start", tailed.file_idx);
+                    self.file_idx = 0;
+                    self.resuming = false;
+                    let reset = CdcEvent { seq: self.seq, timestamp_ms: self.timestamp_ms, change: ChangeEvent::Reset };
+                    events.push(reset);
+                },
+                None => {
+                    self.file = Some(tailed);
+                    return Ok(events);
+                },
+            }
+        }
+    }
+
+    /// Writes the changes written since the previous call to `sink` as JSON lines.
+    /// Returns the number of the written changes.
+    pub fn export(&mut self, sink: &mut dyn Write) -> Result<usize> {
+        let events = self.poll()?;
+        for event in &events {
+            let mut line = event.to_json();
+            line.push('\n');
+            sink.write_all(line.as_bytes())?;
+        }
+        sink.flush()?;
+        Ok(events.len())
+    }
+
+    /// Opens a log file to read. Returns `None` if the file is removed meanwhile.
+    fn open_file(&self, file_idx: usize) -> Result<Option<TailedFile>> {
+        match File::open(file_idx_to_path(&self.storage_dir, file_idx)) {
+            Ok(file) => Ok(Some(TailedFile { file_idx, file, offset: 0, has_records: false, has_sequence: false })),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+
+    /// Reads the complete records of the file after the last read one.
+    /// A record being written is read on the next poll, unless the file is `sealed`.
+    fn read_records(&mut self, tailed: &mut TailedFile, sealed: bool, events: &mut Vec<CdcEvent>) -> Result<()> {
+        let mut buffer = Vec::new();
+        tailed.file.seek(io::SeekFrom::Start(tailed.offset))?;
+        tailed.file.read_to_end(&mut buffer)?;
+        let mut reader = io::Cursor::new(buffer.as_slice());
+
+        let read_offset = tailed.offset;
+        if tailed.offset == 0 {
+            // The header is written right before the first records.
+            if buffer.is_empty() {
+                return Ok(());
+            }
+            serialize::read_segment_header(&mut reader)?;
+            tailed.offset = reader.position();
+        }
+        loop {
+            let command = match serialize::deserialize(&mut reader) {
+                Ok(Some(command)) => command,
+                Ok(None) => break,
+                Err(_) if !sealed => break,
+                Err(err) => {
+                    let file_path = file_idx_to_path(&self.storage_dir, tailed.file_idx);
+                    return Err(Box::from(format!("Cannot read log file {}: {}", file_path.display(), err)));
+                },
+            };
+            tailed.offset = read_offset + reader.position();
+            match command {
+                Command::Sequence { seq, timestamp_ms } => {
+                    tailed.has_sequence = true;
+                    self.read_sequence(seq, timestamp_ms)?;
+                },
+                Command::Set { key, value } => {
+                    tailed.has_records = true;
+                    self.push_event(events, ChangeEvent::Set { key, value });
+                },
+                Command::SetFlagged { key, flags, value } => {
+                    tailed.has_records = true;
+                    self.push_event(events, ChangeEvent::Set { key, value: decode_value(flags, value)? });
+                },
+                Command::Remove { key } => {
+                    tailed.has_records = true;
+                    self.push_event(events, ChangeEvent::Remove { key });
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn read_sequence(&mut self, seq: u64, timestamp_ms: u64) -> Result<()> {
+        self.seq = seq;
+        self.timestamp_ms = timestamp_ms;
+        if !self.resuming {
+            return Ok(());
+        }
+        if seq <= self.from_seq {
+            self.unsequenced_file = false;
+            self.has_sequence_within = true;
+            return Ok(());
+        }
+        // The writes before the first sequence record read are compacted or reset.
+        if self.unsequenced_file || (!self.has_sequence_within && seq > 1) {
+            return Err(Box::from(format!("Sequence {} is within the compacted log files", self.from_seq)));
+        }
+        self.resuming = false;
+        Ok(())
+    }
+
+    fn push_event(&self, events: &mut Vec<CdcEvent>, change: ChangeEvent) {
+        if !self.resuming {
+            events.push(CdcEvent { seq: self.seq, timestamp_ms: self.timestamp_ms, change });
+        }
+    }
+}
+
+/// Lists the indexes of the log files in the storage directory, sorted.
+fn list_log_files(storage_dir: &Path) -> Result<Vec<usize>> {
+    let mut file_idxs = Vec::new();
+    for entry in fs::read_dir(storage_dir)? {
+        let path = entry?.path();
+        // Skip temporary compaction files sharing the segment index.
+        if let Some(file_idx) = path_to_idx(&path).filter(|file_idx| path == file_idx_to_path(storage_dir, *file_idx)) {
+            file_idxs.push(file_idx);
+        }
+    }
+    file_idxs.sort();
+    Ok(file_idxs)
+}
+
+/// Checks if the active log file is removed or replaced by a reset or a restore.
+fn is_replaced(tailed: &TailedFile, storage_dir: &Path) -> Result<bool> {
+    let metadata = match fs::metadata(file_idx_to_path(storage_dir, tailed.file_idx)) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(err) => return Err(Box::new(err)),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let tailed_metadata = tailed.file.metadata()?;
+        Ok(metadata.ino() != tailed_metadata.ino() || metadata.dev() != tailed_metadata.dev())
+    }
+    #[cfg(not(unix))]
+    Ok(metadata.len() < tailed.offset)
+}
@@ -0,0 +1,370 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io;
+
+use crate::models::{self, CollectionType, Command, CommandError, Result};
+use crate::serialize::{read_varint, read_varint_bytes, varint_size, write_varint, write_varint_bytes, ReadFromStream};
+
+/// Error of a collection command on a plain value or a collection of another type.
+pub fn wrong_type_error(collection_type: CollectionType) -> Box<dyn std::error::Error> {
+    Box::new(CommandError::new(
+        models::ERROR_CODE_WRONG_TYPE,
+        format!("The stored value is not a {}", collection_type.name()),
+    ))
+}
+
+/// Error of a plain value command on a collection.
+pub fn collection_value_error(collection_type: CollectionType) -> Box<dyn std::error::Error> {
+    Box::new(CommandError::new(
+        models::ERROR_CODE_WRONG_TYPE,
+        format!("The stored value is a {}, not a plain value", collection_type.name()),
+    ))
+}
+
+/// Type of the collection changed by a mutation command, `None` for the other commands.
+pub fn mutation_type(command: &Command) -> Option<CollectionType> {
+    match command {
+        Command::ListPush { .. } | Command::ListPop { .. } => Some(CollectionType::List),
+        Command::HashSet { .. } | Command::HashDelete { .. } => Some(CollectionType::Hash),
+        Command::SetAdd { .. } | Command::SetRemove { .. } => Some(CollectionType::Set),
+        Command::SortedSetAdd { .. } | Command::SortedSetRemove { .. } => Some(CollectionType::SortedSet),
+        _ => None,
+    }
+}
+
+/// Result of a collection mutation, see `Collection::apply`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MutationResult {
+    /// Length of the list after a push, otherwise the number of the added or removed items.
+    pub count: u64,
+    /// Value popped from the list.
+    pub popped: Option<Vec<u8>>,
+    /// Number of the items left in the collection. The emptied collection is removed.
+    pub length: u64,
+    /// Whether the collection is changed, the unchanged collection is not written.
+    pub changed: bool,
+}
+
+/// Score of a sorted set member ordered by `f64::total_cmp`.
+#[derive(Clone, Copy, Debug)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Score) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Score) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Score) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members of a sorted set with their scores, indexed by the scores.
+/// The members with equal scores are ordered by their bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SortedSet {
+    scores: BTreeMap<Vec<u8>, Score>,
+    by_score: BTreeSet<(Score, Vec<u8>)>,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).map(|score| score.0)
+    }
+
+    /// Sets the score of the member and returns the previous one.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> Option<f64> {
+        let previous_score = self.scores.insert(member.clone(), Score(score));
+        if let Some(previous_score) = previous_score {
+            self.by_score.remove(&(previous_score, member.clone()));
+        }
+        self.by_score.insert((Score(score), member));
+        previous_score.map(|score| score.0)
+    }
+
+    /// Removes the member and returns its score.
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.by_score.remove(&(score, member.to_vec()));
+        Some(score.0)
+    }
+
+    /// Returns the members ordered by the scores.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], f64)> {
+        self.by_score.iter().map(|(score, member)| (member.as_slice(), score.0))
+    }
+
+    /// Returns the members with the scores between `min` and `max` inclusive, ordered by the scores.
+    pub fn range_by_score(&self, min: f64, max: f64) -> Vec<(Vec<u8>, f64)> {
+        if min.is_nan() || max.is_nan() {
+            return Vec::new();
+        }
+        // -0.0 is ordered before 0.0, while the bounds compare them as equal.
+        let start = if min == 0.0 { -0.0 } else { min };
+        self.by_score.range((Score(start), Vec::new())..)
+            .take_while(|(score, _)| score.0 <= max)
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+}
+
+/// Collection value held in memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Collection {
+    List(VecDeque<Vec<u8>>),
+    Hash(BTreeMap<String, Vec<u8>>),
+    Set(BTreeSet<Vec<u8>>),
+    SortedSet(SortedSet),
+}
+
+impl Collection {
+    /// Creates an empty collection of the type `collection_type`.
+    pub fn new(collection_type: CollectionType) -> Collection {
+        match collection_type {
+            CollectionType::List => Collection::List(VecDeque::new()),
+            CollectionType::Hash => Collection::Hash(BTreeMap::new()),
+            CollectionType::Set => Collection::Set(BTreeSet::new()),
+            CollectionType::SortedSet => Collection::SortedSet(SortedSet::default()),
+        }
+    }
+
+    pub fn collection_type(&self) -> CollectionType {
+        match self {
+            Collection::List(_) => CollectionType::List,
+            Collection::Hash(_) => CollectionType::Hash,
+            Collection::Set(_) => CollectionType::Set,
+            Collection::SortedSet(_) => CollectionType::SortedSet,
+        }
+    }
+
+    /// Number of the items in the collection.
+    pub fn len(&self) -> usize {
+        match self {
+            Collection::List(list) => list.len(),
+            Collection::Hash(hash) => hash.len(),
+            Collection::Set(set) => set.len(),
+            Collection::SortedSet(sorted_set) => sorted_set.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the result of applying the mutation command `mutation` without changing the collection,
+    /// so the mutation can be written to the log before it's applied.
+    /// Fails with `ERROR_CODE_WRONG_TYPE` if the mutation is of another collection type.
+    pub fn mutation_result(&self, mutation: &Command) -> Result<MutationResult> {
+        let length = self.len() as u64;
+        let result = match (self, mutation) {
+            (Collection::List(_), Command::ListPush { values, .. }) => {
+                let length = length + values.len() as u64;
+                MutationResult { count: length, popped: None, length, changed: !values.is_empty() }
+            },
+            (Collection::List(list), Command::ListPop { front, .. }) => {
+                let popped = if *front { list.front() } else { list.back() }.cloned();
+                let length = length - popped.is_some() as u64;
+                MutationResult { count: 0, changed: popped.is_some(), popped, length }
+            },
+            (Collection::Hash(hash), Command::HashSet { fields, .. }) => {
+                let added: BTreeSet<&String> = fields.iter()
+                    .map(|(field, _)| field)
+                    .filter(|field| !hash.contains_key(*field))
+                    .collect();
+                let changed = fields.iter().any(|(field, value)| hash.get(field) != Some(value));
+                MutationResult { count: added.len() as u64, popped: None, length: length + added.len() as u64, changed }
+            },
+            (Collection::Hash(hash), Command::HashDelete { fields, .. }) => {
+                let removed: BTreeSet<&String> = fields.iter().filter(|field| hash.contains_key(*field)).collect();
+                removed_result(length, removed.len())
+            },
+            (Collection::Set(set), Command::SetAdd { members, .. }) => {
+                let added: BTreeSet<&Vec<u8>> = members.iter().filter(|member| !set.contains(*member)).collect();
+                let count = added.len() as u64;
+                MutationResult { count, popped: None, length: length + count, changed: count > 0 }
+            },
+            (Collection::Set(set), Command::SetRemove { members, .. }) => {
+                let removed: BTreeSet<&Vec<u8>> = members.iter().filter(|member| set.contains(*member)).collect();
+                removed_result(length, removed.len())
+            },
+            (Collection::SortedSet(sorted_set), Command::SortedSetAdd { members, .. }) => {
+                if members.iter().any(|(_, score)| score.is_nan()) {
+                    return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "A score cannot be NaN")));
+                }
+                let added: BTreeSet<&Vec<u8>> = members.iter()
+                    .map(|(member, _)| member)
+                    .filter(|member| sorted_set.score(member).is_none())
+                    .collect();
+                let changed = members.iter()
+                    .any(|(member, score)| sorted_set.score(member).map(f64::to_bits) != Some(score.to_bits()));
+                MutationResult { count: added.len() as u64, popped: None, length: length + added.len() as u64, changed }
+            },
+            (Collection::SortedSet(sorted_set), Command::SortedSetRemove { members, .. }) => {
+                let removed: BTreeSet<&Vec<u8>> = members.iter()
+                    .filter(|member| sorted_set.score(member).is_some())
+                    .collect();
+                removed_result(length, removed.len())
+            },
+            (_, mutation) => match mutation_type(mutation) {
+                Some(collection_type) => return Err(wrong_type_error(collection_type)),
+                None => return Err(Box::from(format!("Command {} is not a collection mutation", mutation.name()))),
+            },
+        };
+        Ok(result)
+    }
+
+    /// Applies the mutation command `mutation` to the collection and returns its result, see `mutation_result`.
+    pub fn apply(&mut self, mutation: &Command) -> Result<MutationResult> {
+        let result = self.mutation_result(mutation)?;
+        match (self, mutation) {
+            (Collection::List(list), Command::ListPush { values, front, .. }) => {
+                for value in values {
+                    if *front { list.push_front(value.clone()) } else { list.push_back(value.clone()) }
+                }
+            },
+            (Collection::List(list), Command::ListPop { front, .. }) => {
+                if *front { list.pop_front(); } else { list.pop_back(); }
+            },
+            (Collection::Hash(hash), Command::HashSet { fields, .. }) => {
+                hash.extend(fields.iter().cloned());
+            },
+            (Collection::Hash(hash), Command::HashDelete { fields, .. }) => {
+                for field in fields {
+                    hash.remove(field);
+                }
+            },
+            (Collection::Set(set), Command::SetAdd { members, .. }) => {
+                set.extend(members.iter().cloned());
+            },
+            (Collection::Set(set), Command::SetRemove { members, .. }) => {
+                for member in members {
+                    set.remove(member);
+                }
+            },
+            (Collection::SortedSet(sorted_set), Command::SortedSetAdd { members, .. }) => {
+                for (member, score) in members {
+                    sorted_set.insert(member.clone(), *score);
+                }
+            },
+            (Collection::SortedSet(sorted_set), Command::SortedSetRemove { members, .. }) => {
+                for member in members {
+                    sorted_set.remove(member);
+                }
+            },
+            _ => {},
+        }
+        Ok(result)
+    }
+
+    /// Encodes the items of the collection, the type is kept apart. The items are encoded as a varint number
+    /// of the items followed by:
+    /// - the list values with varint length prefixes;
+    /// - the hash field names and values with varint length prefixes, sorted by the field names;
+    /// - the set members with varint length prefixes, sorted;
+    /// - the sorted set members ordered by the scores, each as an 8 bytes score followed by the member
+    ///   with a varint length prefix.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.encoded_size());
+        write_varint(self.len() as u64, &mut buffer);
+        match self {
+            Collection::List(list) => list.iter().for_each(|value| write_varint_bytes(value, &mut buffer)),
+            Collection::Hash(hash) => {
+                for (field, value) in hash {
+                    write_varint_bytes(field.as_bytes(), &mut buffer);
+                    write_varint_bytes(value, &mut buffer);
+                }
+            },
+            Collection::Set(set) => set.iter().for_each(|member| write_varint_bytes(member, &mut buffer)),
+            Collection::SortedSet(sorted_set) => {
+                for (member, score) in sorted_set.iter() {
+                    buffer.extend(score.to_be_bytes());
+                    write_varint_bytes(member, &mut buffer);
+                }
+            },
+        }
+        buffer
+    }
+
+    /// Size of the encoded items in bytes, see `encode`.
+    pub fn encoded_size(&self) -> usize {
+        let bytes_size = |bytes: &[u8]| varint_size(bytes.len() as u64) + bytes.len();
+        let items_size: usize = match self {
+            Collection::List(list) => list.iter().map(|value| bytes_size(value)).sum(),
+            Collection::Hash(hash) => {
+                hash.iter().map(|(field, value)| bytes_size(field.as_bytes()) + bytes_size(value)).sum()
+            },
+            Collection::Set(set) => set.iter().map(|member| bytes_size(member)).sum(),
+            Collection::SortedSet(sorted_set) => {
+                sorted_set.iter().map(|(member, _)| size_of::<f64>() + bytes_size(member)).sum()
+            },
+        };
+        varint_size(self.len() as u64) + items_size
+    }
+
+    /// Decodes the items of a collection of the type `collection_type` encoded by `encode`.
+    pub fn decode(collection_type: CollectionType, value: &[u8]) -> Result<Collection> {
+        let mut reader = io::Cursor::new(value);
+        let count = read_varint(&mut reader)?;
+        let mut collection = Collection::new(collection_type);
+        for _ in 0..count {
+            match &mut collection {
+                Collection::List(list) => list.push_back(read_varint_bytes(&mut reader)?),
+                Collection::Hash(hash) => {
+                    let field = String::from_utf8(read_varint_bytes(&mut reader)?)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+                    hash.insert(field, read_varint_bytes(&mut reader)?);
+                },
+                Collection::Set(set) => { set.insert(read_varint_bytes(&mut reader)?); },
+                Collection::SortedSet(sorted_set) => {
+                    let score = f64::deserialize(&mut reader)?;
+                    sorted_set.insert(read_varint_bytes(&mut reader)?, score);
+                },
+            }
+        }
+        if reader.position() != value.len() as u64 {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected data in the {} value", collection_type.name()),
+            )));
+        }
+        Ok(collection)
+    }
+}
+
+/// Result of a removal of `removed_count` distinct items from a collection of `length` items.
+fn removed_result(length: u64, removed_count: usize) -> MutationResult {
+    let count = removed_count as u64;
+    MutationResult { count, popped: None, length: length - count, changed: count > 0 }
+}
+
+/// Resolves the inclusive range `start..=stop` of a list of `length` items to the item indexes.
+/// Negative indexes count from the end of the list, the range is clamped to the list.
+/// Returns `None` for an empty range.
+pub fn list_range_bounds(length: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let length = length as i64;
+    let resolve = |idx: i64| if idx < 0 { length + idx } else { idx };
+    let start = resolve(start).max(0);
+    let stop = resolve(stop).min(length - 1);
+    if start > stop {
+        return None;
+    }
+    Some((start as usize, stop as usize))
+}
@@ -0,0 +1,59 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log;
+
+use crate::models::Result;
+
+struct SchedulerShared {
+    stopped: Mutex<bool>,
+    /// Wakes the scheduler thread up to stop.
+    stop: Condvar,
+}
+
+/// Background thread running the compaction checks of a storage once per interval.
+/// The thread is stopped and joined when the last storage handle is dropped,
+/// so the storage resources held by the checks are released by then.
+pub(crate) struct CompactionScheduler {
+    shared: Arc<SchedulerShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CompactionScheduler {
+    pub(crate) fn start<F>(interval: Duration, mut check: F) -> Result<CompactionScheduler>
+    where
+        F: FnMut() -> Result<()> + Send + 'static,
+    {
+        let shared = Arc::new(SchedulerShared { stopped: Mutex::new(false), stop: Condvar::new() });
+        let thread_shared = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name("kvs-compaction-scheduler".to_owned())
+            .spawn(move || {
+                let mut stopped = thread_shared.stopped.lock().unwrap_or_else(|e| e.into_inner());
+                loop {
+                    stopped = thread_shared.stop.wait_timeout(stopped, interval)
+                        .unwrap_or_else(|e| e.into_inner()).0;
+                    if *stopped {
+                        return;
+                    }
+                    drop(stopped);
+                    if let Err(err) = check() {
+                        log::error!("Scheduled compaction check failed: {}", err);
+                    }
+                    stopped = thread_shared.stopped.lock().unwrap_or_else(|e| e.into_inner());
+                }
+            })?;
+        Ok(CompactionScheduler { shared, thread: Some(thread) })
+    }
+}
+
+impl Drop for CompactionScheduler {
+    fn drop(&mut self) {
+        *self.shared.stopped.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        self.shared.stop.notify_one();
+        if self.thread.take().is_some_and(|thread| thread.join().is_err()) {
+            log::error!("Compaction scheduler thread panicked");
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::models::{CollectionType, Command, Result, StorageStats};
+use crate::storage::base::{CollectionRead, KvStorage};
+use crate::storage::collections::MutationResult;
+
+/// Faults to inject, set up before the storage is used.
+#[derive(Clone, Default)]
+struct FaultConfig {
+    /// Numbers of the writes to fail, starting from 1.
+    failing_writes: HashSet<u64>,
+    /// Numbers of the reads to corrupt, starting from 1.
+    corrupted_reads: HashSet<u64>,
+    /// Delay before each read and write.
+    latency: Option<Duration>,
+}
+
+/// Numbers of the reads and writes made through all the handles of the storage.
+#[derive(Default)]
+struct FaultCounters {
+    writes: AtomicU64,
+    reads: AtomicU64,
+}
+
+/// Storage decorator injecting faults into the operations of the wrapped storage, so the error paths of the server
+/// and the clients can be tested deterministically. The writes are `set_bytes`, `append`, `get_set`, `set_nx`,
+/// `update_collection`, `remove`, `reset` and `write_batch`, the reads are `get_bytes`, each key of `get_many`
+/// and `read_collection`. They are numbered from 1 across all the handles and namespaces of the storage.
+/// The collection reads are never corrupted.
+pub struct FaultyStorage {
+    storage: Box<dyn KvStorage>,
+    config: Arc<FaultConfig>,
+    counters: Arc<FaultCounters>,
+}
+
+impl FaultyStorage {
+    /// Wraps the storage without any faults.
+    pub fn new(storage: impl KvStorage + 'static) -> Self {
+        FaultyStorage {
+            storage: Box::new(storage),
+            config: Arc::new(FaultConfig::default()),
+            counters: Arc::new(FaultCounters::default()),
+        }
+    }
+
+    /// Fails the write number `n` without passing it to the wrapped storage.
+    pub fn fail_write(mut self, n: u64) -> Self {
+        Arc::make_mut(&mut self.config).failing_writes.insert(n);
+        self
+    }
+
+    /// Returns the value of the read number `n` with all its bits flipped.
+    pub fn corrupt_read(mut self, n: u64) -> Self {
+        Arc::make_mut(&mut self.config).corrupted_reads.insert(n);
+        self
+    }
+
+    /// Delays each read and write by `latency`.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        Arc::make_mut(&mut self.config).latency = Some(latency);
+        self
+    }
+
+    /// Number of the writes made so far, including the failed ones.
+    pub fn writes_count(&self) -> u64 {
+        self.counters.writes.load(Ordering::SeqCst)
+    }
+
+    /// Number of the reads made so far, including the corrupted ones.
+    pub fn reads_count(&self) -> u64 {
+        self.counters.reads.load(Ordering::SeqCst)
+    }
+
+    fn wrap(&self, storage: Box<dyn KvStorage>) -> FaultyStorage {
+        FaultyStorage { storage, config: self.config.clone(), counters: self.counters.clone() }
+    }
+
+    fn delay(&self) {
+        if let Some(latency) = self.config.latency {
+            std::thread::sleep(latency);
+        }
+    }
+
+    /// Counts a write and fails it if configured.
+    fn write(&self) -> Result<()> {
+        self.delay();
+        let n = self.counters.writes.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.config.failing_writes.contains(&n) {
+            return Err(Box::from(format!("Injected failure of write {}", n)));
+        }
+        Ok(())
+    }
+}
+
+/// The clones share the faults and the operation numbers.
+impl Clone for FaultyStorage {
+    fn clone(&self) -> FaultyStorage {
+        self.wrap(self.storage.clone_box())
+    }
+}
+
+impl KvStorage for FaultyStorage {
+    fn set_bytes(&mut self, key: String, value: Vec<u8>) -> Result<()> {
+        self.write()?;
+        self.storage.set_bytes(key, value)
+    }
+
+    fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>> {
+        self.delay();
+        let n = self.counters.reads.fetch_add(1, Ordering::SeqCst) + 1;
+        let value = self.storage.get_bytes(key)?;
+        if self.config.corrupted_reads.contains(&n) {
+            return Ok(value.map(|value| value.into_iter().map(|byte| !byte).collect()));
+        }
+        Ok(value)
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        keys.into_iter().map(|key| self.get_bytes(key)).collect()
+    }
+
+    fn append(&mut self, key: String, suffix: Vec<u8>) -> Result<u64> {
+        self.write()?;
+        self.storage.append(key, suffix)
+    }
+
+    fn get_set(&mut self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.write()?;
+        self.storage.get_set(key, value)
+    }
+
+    fn set_nx(&mut self, key: String, value: Vec<u8>) -> Result<bool> {
+        self.write()?;
+        self.storage.set_nx(key, value)
+    }
+
+    fn update_collection(&mut self, mutation: Command) -> Result<MutationResult> {
+        self.write()?;
+        self.storage.update_collection(mutation)
+    }
+
+    fn read_collection(&self, key: String, collection_type: CollectionType, read: &mut CollectionRead) -> Result<()> {
+        self.delay();
+        self.counters.reads.fetch_add(1, Ordering::SeqCst);
+        self.storage.read_collection(key, collection_type, read)
+    }
+
+    fn remove(&mut self, key: String) -> Result<bool> {
+        self.write()?;
+        self.storage.remove(key)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.write()?;
+        self.storage.reset()
+    }
+
+    fn reset_prefix(&mut self, prefix: String) -> Result<u64> {
+        self.write()?;
+        self.storage.reset_prefix(prefix)
+    }
+
+    fn write_batch(&mut self, commands: Vec<Command>) -> Result<()> {
+        self.write()?;
+        self.storage.write_batch(commands)
+    }
+
+    fn max_batch_size(&self) -> Option<u64> {
+        self.storage.max_batch_size()
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.storage.compact()
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        self.storage.stats()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.storage.flush()
+    }
+
+    fn close(&self) -> Result<()> {
+        self.storage.close()
+    }
+
+    fn prepare_restore(&self, backup_dir: &Path) -> Result<String> {
+        self.storage.prepare_restore(backup_dir)
+    }
+
+    fn commit_restore(&mut self, token: &str) -> Result<()> {
+        self.storage.commit_restore(token)
+    }
+
+    fn abort_restore(&self, token: &str) -> Result<()> {
+        self.storage.abort_restore(token)
+    }
+
+    /// The namespace handles share the faults and the operation numbers with the storage.
+    fn namespace(&self, name: &str) -> Result<Box<dyn KvStorage>> {
+        Ok(Box::new(self.wrap(self.storage.namespace(name)?)))
+    }
+
+    fn set_compaction_garbage_ratio(&self, ratio: f64) -> Result<()> {
+        self.storage.set_compaction_garbage_ratio(ratio)
+    }
+
+    fn pause_compaction(&self) -> Result<()> {
+        self.storage.pause_compaction()
+    }
+
+    fn resume_compaction(&self) -> Result<()> {
+        self.storage.resume_compaction()
+    }
+
+    fn is_compaction_paused(&self) -> bool {
+        self.storage.is_compaction_paused()
+    }
+
+    fn clone_box(&self) -> Box<dyn KvStorage> {
+        Box::new(self.clone())
+    }
+}
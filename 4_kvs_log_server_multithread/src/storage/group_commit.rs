@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use log;
+
+use crate::models::Result;
+
+const SYNC_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Writes state shared between the writers and the commit thread.
+/// Writes are numbered in the order they are appended to the log files.
+struct GroupCommitState {
+    written_seq: u64,
+    synced_seq: u64,
+    /// Log files with the writes not synced yet.
+    pending_files: HashSet<PathBuf>,
+    /// Error of the latest failed sync. The writes not synced yet are reported as failed.
+    error: Option<String>,
+    stopped: bool,
+}
+
+struct GroupCommitShared {
+    state: Mutex<GroupCommitState>,
+    /// Notifies the commit thread about new writes.
+    written: Condvar,
+    /// Notifies the writers about completed syncs.
+    synced: Condvar,
+}
+
+/// Group commit of the log writes. Writers append their records to the log files without syncing
+/// and wait while a commit thread syncs the files once per batch of writes.
+/// A batch is committed once it reaches `max_batch_size` writes or `interval` passes since its first write.
+/// The commit thread is shared by a storage and its namespaces, so it syncs the files of several storage directories.
+/// It completes the pending writes and stops when the last storage handle is dropped.
+pub(crate) struct GroupCommit {
+    shared: Arc<GroupCommitShared>,
+}
+
+impl GroupCommit {
+    pub(crate) fn start(interval: Duration, max_batch_size: usize) -> Result<GroupCommit> {
+        let shared = Arc::new(GroupCommitShared {
+            state: Mutex::new(GroupCommitState {
+                written_seq: 0,
+                synced_seq: 0,
+                pending_files: HashSet::new(),
+                error: None,
+                stopped: false,
+            }),
+            written: Condvar::new(),
+            synced: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        std::thread::Builder::new()
+            .name("kvs-group-commit".to_owned())
+            .spawn(move || Self::run(thread_shared, interval, max_batch_size))?;
+        Ok(GroupCommit { shared })
+    }
+
+    /// Registers a write appended to the log file `file_path`. Returns the write sequence number to wait for.
+    /// Should be called under the storage write lock, so the numbers follow the order of the writes.
+    pub(crate) fn register(&self, file_path: PathBuf) -> u64 {
+        let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.written_seq += 1;
+        state.pending_files.insert(file_path);
+        self.shared.written.notify_one();
+        state.written_seq
+    }
+
+    /// Blocks until the write `seq` is synced to the disk.
+    pub(crate) fn wait(&self, seq: u64) -> Result<()> {
+        let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        while state.synced_seq < seq {
+            if let Some(err) = &state.error {
+                return Err(Box::from(format!("Cannot sync the log files: {}", err)));
+            }
+            state = self.shared.synced.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        Ok(())
+    }
+
+    fn run(shared: Arc<GroupCommitShared>, interval: Duration, max_batch_size: usize) {
+        loop {
+            let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+            while state.written_seq == state.synced_seq && !state.stopped {
+                state = shared.written.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+            if state.written_seq == state.synced_seq {
+                log::debug!("Group commit thread is stopped");
+                return;
+            }
+
+            // Collect the batch until it's full or the commit interval passes.
+            let deadline = Instant::now() + interval;
+            while state.written_seq - state.synced_seq < max_batch_size as u64 && !state.stopped {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                state = shared.written.wait_timeout(state, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+            }
+
+            let batch_seq = state.written_seq;
+            let files = std::mem::take(&mut state.pending_files);
+            state.error = None;
+            drop(state);
+
+            let result = Self::sync_files(&files);
+            let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+            match result {
+                Ok(()) => state.synced_seq = batch_seq,
+                Err(err) => {
+                    log::error!("Group commit failed: {}", err);
+                    // Retry the failed files with the next batch.
+                    state.pending_files.extend(files);
+                    state.error = Some(err.to_string());
+                    shared.synced.notify_all();
+                    if state.stopped {
+                        return;
+                    }
+                    drop(state);
+                    std::thread::sleep(interval.max(SYNC_RETRY_DELAY));
+                    continue;
+                },
+            }
+            shared.synced.notify_all();
+        }
+    }
+
+    /// Syncs the log files. The files removed by compaction or reset meanwhile are skipped.
+    fn sync_files(files: &HashSet<PathBuf>) -> Result<()> {
+        for file_path in files {
+            match OpenOptions::new().append(true).open(file_path) {
+                Ok(file) => file.sync_data()?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+                Err(err) => return Err(Box::new(err)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GroupCommit {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.stopped = true;
+        self.shared.written.notify_one();
+    }
+}
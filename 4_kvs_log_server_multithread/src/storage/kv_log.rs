@@ -1,27 +1,98 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::{self, Seek};
 use std::path::{Path, PathBuf};
 use std::fs::{remove_file, rename, File, OpenOptions};
 use std::io::BufReader;
 use log;
 use dashmap;
+use smallvec::SmallVec;
 
-use crate::models::{Result, Command};
-use crate::serialize::{self, get_value_offset, ReadFromStream};
+use crate::models::{self, CollectionType, Command, Result};
+use crate::serialize::{self, get_value_offset, RecordFormat};
+use crate::storage::backup;
+use crate::storage::base::{check_engine, CollectionRead, KvStorage, ValueReader, ENGINE_FILE_NAME};
+use crate::storage::bloom::{self, BloomFilter};
+use crate::storage::collections::{self, Collection, MutationResult};
+use crate::storage::compaction_scheduler::CompactionScheduler;
+use crate::storage::group_commit::GroupCommit;
+use crate::storage::manifest::{SegmentManifest, SegmentState, MANIFEST_FILE_NAME};
+use crate::storage::sharded::SHARDS_FILE_NAME;
+use crate::storage::watch::{ChangeEvent, Subscription, WatchFilter, WatchRegistry};
 use crate::threads;
-use crate::threads::base::ThreadPool;
+use crate::threads::base::{JobHandle, ThreadPoolExt};
 
-const MAX_SEGMENT_SIZE: u64 = 4_000_000;
+const DEFAULT_SEGMENT_SIZE: u64 = 4_000_000;
 const DEFAULT_FILE_IDX: usize = 1;
-const COMPACTION_POOL_SIZE: usize = 2;
+const DEFAULT_COMPACTION_POOL_SIZE: usize = 2;
+const DEFAULT_COMPACTION_DEAD_RATIO: f64 = 0.5;
+const DEFAULT_MAX_NAMESPACES: usize = 1024;
+/// Values up to this size in bytes are kept in the index, so reading them never touches the disk.
+const INLINE_VALUE_MAX_SIZE: usize = 64;
+/// A collection is rewritten as a single record once it has more mutation records in the log than items,
+/// but not before this number of the mutation records.
+const COLLECTION_SNAPSHOT_MIN_MUTATIONS: u64 = 64;
+const NAMESPACE_MAX_LENGTH: usize = 64;
+/// File locked by the storage which has the directory open.
+const LOCK_FILE_NAME: &str = "LOCK";
 
 /// Convert file index to the actual file path.
-fn file_idx_to_path(storage_path: &Path, file_idx: usize) -> PathBuf {
+pub(crate) fn file_idx_to_path(storage_path: &Path, file_idx: usize) -> PathBuf {
     storage_path.join(format!("kv_{}.log", file_idx))
 }
 
+/// Checks if the directory `path` holds the log files or the manifest of a storage. A missing directory holds none.
+pub(crate) fn has_log_files(path: &Path) -> Result<bool> {
+    if !path.is_dir() {
+        return Ok(false);
+    }
+    for entry in std::fs::read_dir(path)? {
+        let file_path = entry?.path();
+        if !file_path.is_file() {
+            continue;
+        }
+        if file_path.file_name() == Some(std::ffi::OsStr::new(MANIFEST_FILE_NAME)) {
+            return Ok(true);
+        }
+        if file_path.extension() == Some(std::ffi::OsStr::new("log")) && path_to_idx(&file_path).is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Checks the namespace name. Names are limited to ASCII letters, digits, `-` and `_`,
+/// so a namespace never escapes the storage directory.
+pub(crate) fn validate_namespace(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > NAMESPACE_MAX_LENGTH {
+        return Err(Box::from(format!("Namespace name must be from 1 to {} characters long", NAMESPACE_MAX_LENGTH)));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(Box::from(format!("Invalid namespace name {}", name)));
+    }
+    Ok(())
+}
+
+/// Takes an advisory lock of the storage directory, held until the returned file is closed.
+/// Fails if the directory is already open by another process or by another storage of this process.
+fn lock_storage_dir(path: &Path) -> Result<File> {
+    let lock_file = OpenOptions::new().create(true).truncate(false).write(true).open(path.join(LOCK_FILE_NAME))?;
+    match lock_file.try_lock() {
+        Ok(()) => Ok(lock_file),
+        Err(std::fs::TryLockError::WouldBlock) => {
+            Err(Box::from(format!("Storage {} is already in use", path.display())))
+        },
+        Err(std::fs::TryLockError::Error(err)) => Err(Box::new(err)),
+    }
+}
+
+/// Convert namespace name to the path of its storage directory.
+fn namespace_path(storage_path: &Path, name: &str) -> Result<PathBuf> {
+    validate_namespace(name)?;
+    Ok(storage_path.join(format!("ns_{}", name)))
+}
+
 /// Convert file path to file index if some.
-fn path_to_idx(file_path: &Path) -> Option<usize> {
+pub(crate) fn path_to_idx(file_path: &Path) -> Option<usize> {
     if let Some(file_stem) = file_path.file_stem() {
         if let Some(stem_str) = file_stem.to_str() {
             let parts = stem_str.split('_');
@@ -51,21 +122,135 @@ fn get_tmp_file_path(storage_path: &Path, file_path: &Path) -> Result<PathBuf> {
     Ok(storage_path.join(format!("_tmp_{}", file_name)))
 }
 
+/// Checks if the file is a temporary file of a compaction or a filter write.
+/// Such files are left in the storage directory if the process is killed in the middle of the write.
+fn is_tmp_file(file_path: &Path) -> bool {
+    let is_compacted_copy = file_path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("_tmp_"));
+    is_compacted_copy || file_path.extension() == Some(std::ffi::OsStr::new("tmp"))
+}
+
+/// Decodes a value stored in a log record with `flags`.
+pub(crate) fn decode_value(flags: u8, value: Vec<u8>) -> Result<Vec<u8>> {
+    if flags & models::VALUE_FLAG_COMPRESSED != 0 {
+        Ok(lz4_flex::decompress_size_prepended(&value)?)
+    } else {
+        Ok(value)
+    }
+}
+
 /// A single value position index in the log storage.
-struct KvStorePosition {
-    file_idx: usize,
-    file_offset: u64,
+/// Small values are inlined into the index entry, the rest are read from the log files.
+/// Inlined values are still written to the log files to be restored on startup.
+enum KvStorePosition {
+    /// An inlined value and the index of the log file holding its record.
+    Inline { value: SmallVec<[u8; INLINE_VALUE_MAX_SIZE]>, file_idx: usize },
+    /// Position of a value in a log file. `flags` describe the stored value encoding,
+    /// `size` is the size of the stored (possibly compressed) value.
+    OnDisk { file_idx: usize, file_offset: u64, flags: u8, size: u32 },
+    /// A collection kept in memory. It's restored from the record which created it in the log file `file_idx`,
+    /// a mutation record or a `Command::SetCollection` one, and the `mutations_count` mutation records
+    /// written after it to the same and the newer log files.
+    Collection { value: Box<Collection>, file_idx: usize, mutations_count: u64 },
+}
+
+impl KvStorePosition {
+    /// Returns an inlined position of a record in the log file `file_idx` if the value is small enough.
+    fn inline(value: &[u8], file_idx: usize) -> Option<KvStorePosition> {
+        if value.len() <= INLINE_VALUE_MAX_SIZE {
+            Some(KvStorePosition::Inline { value: SmallVec::from_slice(value), file_idx })
+        } else {
+            None
+        }
+    }
+
+    /// Index of the log file holding the record of the value.
+    fn file_idx(&self) -> usize {
+        match self {
+            KvStorePosition::Inline { file_idx, .. }
+            | KvStorePosition::OnDisk { file_idx, .. }
+            | KvStorePosition::Collection { file_idx, .. } => *file_idx,
+        }
+    }
+
+    /// Size of the v2 log record holding the value of the key `key`.
+    /// The records written in the older format before an upgrade are estimated as the v2 ones,
+    /// the records of a collection are estimated as a single `Command::SetCollection` one.
+    fn record_size(&self, key: &str) -> u64 {
+        match self {
+            KvStorePosition::Inline { value, .. } => serialize::framed_set_record_size(key.len(), false, value.len()),
+            KvStorePosition::OnDisk { flags, size, .. } => {
+                serialize::framed_set_record_size(key.len(), *flags != 0, *size as usize)
+            },
+            KvStorePosition::Collection { value, .. } => {
+                serialize::framed_set_record_size(key.len(), true, value.encoded_size())
+            },
+        }
+    }
+}
+
+/// Records of a key kept by the compaction of a log file.
+enum CompactedRecords {
+    /// The last set record of the key.
+    Value(Command),
+    /// Collection set or created in the file, written as a single `Command::SetCollection` record.
+    Collection(Collection),
+    /// Mutations of a collection set in an older file.
+    Mutations(Vec<Command>),
+}
+
+/// Size of the value stored in a set log record.
+fn stored_value_size(cmd: &Command) -> u32 {
+    match cmd {
+        Command::Set { value, .. } | Command::SetFlagged { value, .. } => value.len() as u32,
+        _ => 0,
+    }
+}
+
+/// Position of the value of a set record written at `record_offset` of the log file, `None` for the other records.
+fn record_position(cmd: &Command, file_idx: usize, record_offset: u64) -> Option<KvStorePosition> {
+    let flags = match cmd {
+        Command::SetFlagged { flags, .. } => *flags,
+        _ => 0,
+    };
+    get_value_offset(cmd, RecordFormat::V2).map(|value_offset| KvStorePosition::OnDisk {
+        file_idx,
+        file_offset: record_offset + value_offset,
+        flags,
+        size: stored_value_size(cmd),
+    })
 }
 
 /// Internal storage data structure to be exclusively locked during writes.
 struct KvLogStorageInternal {
     active_file_idx: usize,
+    /// Storage data generation. Incremented each time the whole set of log files is replaced (reset or restore),
+    /// so background jobs started for the previous files do not touch the new ones.
+    generation: u64,
+    /// Group commit sequence number of the last write to wait for.
+    pending_commit: Option<u64>,
+    /// Time of the latest log file rewrite or removal by compaction.
+    last_compaction: Option<std::time::SystemTime>,
+    /// States of the log files persisted to recover the compactions and resets interrupted by a crash.
+    manifest: SegmentManifest,
+    compaction_metrics: CompactionMetrics,
+    /// Sequence number and time of the last sequence record, see `KvLogStorageBuilder::record_sequence`.
+    sequence: u64,
+    sequence_time_ms: u64,
 }
 
 impl Clone for KvLogStorageInternal {
     fn clone(&self) -> KvLogStorageInternal {
         KvLogStorageInternal {
             active_file_idx: self.active_file_idx,
+            generation: self.generation,
+            pending_commit: self.pending_commit,
+            last_compaction: self.last_compaction,
+            manifest: self.manifest.clone(),
+            compaction_metrics: self.compaction_metrics,
+            sequence: self.sequence,
+            sequence_time_ms: self.sequence_time_ms,
         }
     }
 
@@ -74,12 +259,295 @@ impl Clone for KvLogStorageInternal {
     }
 }
 
+/// Counters of the compaction jobs since the storage is opened.
+#[derive(Clone, Copy, Default)]
+struct CompactionMetrics {
+    compacted_segments: u64,
+    reclaimed_size: u64,
+    failures: u64,
+    time: std::time::Duration,
+}
+
+/// When the log files are synced to the disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Sync every write before acknowledging it.
+    Always,
+    /// Leave syncing to the OS. The active log file is still synced on `flush`.
+    Never,
+    /// Sync the writes in batches, each write is acknowledged once its batch is synced.
+    /// A batch is synced once it has `max_batch_size` writes or `interval` passes since its first write.
+    Group { interval: std::time::Duration, max_batch_size: usize },
+}
+
+/// When the sealed log files are compacted by the background scheduler. The dead bytes of a log file are
+/// estimated as its size not taken by the latest records of the stored keys, so they include the tombstones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactionPolicy {
+    /// A sealed log file is compacted once the share of its dead bytes exceeds the ratio, from 0 to 1.
+    pub dead_ratio: f64,
+    /// Once the dead bytes of all the sealed log files exceed the size, the files with the most dead bytes
+    /// are compacted regardless of the ratio until the rest fit the size.
+    pub max_garbage_size: Option<u64>,
+    /// Interval of the log files checks. Without it the scheduler is not started,
+    /// and only the rotated log files are compacted.
+    pub interval: Option<std::time::Duration>,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        CompactionPolicy {
+            dead_ratio: DEFAULT_COMPACTION_DEAD_RATIO,
+            max_garbage_size: None,
+            interval: None,
+        }
+    }
+}
+
+impl CompactionPolicy {
+    /// Selects the log files to compact from `(file_idx, file_size, dead_size)` of the sealed log files.
+    fn select(&self, segments: &[(usize, u64, u64)]) -> Vec<usize> {
+        let mut selected = Vec::new();
+        let mut rest = Vec::new();
+        let mut rest_dead_size = 0;
+        for (file_idx, file_size, dead_size) in segments {
+            if *dead_size > 0 && *dead_size as f64 > self.dead_ratio * *file_size as f64 {
+                selected.push(*file_idx);
+            } else {
+                rest.push((*file_idx, *dead_size));
+                rest_dead_size += *dead_size;
+            }
+        }
+
+        if let Some(max_garbage_size) = self.max_garbage_size {
+            rest.sort_by_key(|(_, dead_size)| std::cmp::Reverse(*dead_size));
+            for (file_idx, dead_size) in rest {
+                if rest_dead_size <= max_garbage_size || dead_size == 0 {
+                    break;
+                }
+                selected.push(file_idx);
+                rest_dead_size -= dead_size;
+            }
+        }
+        selected
+    }
+}
+
+/// Point of the log a restore replays the records up to, see `KvLogStorage::restore_until`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryPoint {
+    /// Sequence number of the last write to keep.
+    Sequence(u64),
+    /// Time of the last write to keep in milliseconds since the Unix epoch.
+    Timestamp(u64),
+}
+
+impl RecoveryPoint {
+    /// Whether the writes of a sequence record are within the point.
+    fn includes(&self, seq: u64, timestamp_ms: u64) -> bool {
+        match self {
+            RecoveryPoint::Sequence(last_seq) => seq <= *last_seq,
+            RecoveryPoint::Timestamp(last_timestamp_ms) => timestamp_ms <= *last_timestamp_ms,
+        }
+    }
+}
+
+/// Tunable storage options, see `KvLogStorageBuilder`.
+#[derive(Clone)]
+struct KvLogStorageOptions {
+    segment_size: u64,
+    compaction_pool_size: usize,
+    fsync_policy: FsyncPolicy,
+    compaction_garbage_ratio: f64,
+    compaction_policy: CompactionPolicy,
+    compression_threshold: Option<usize>,
+    record_sequence: bool,
+    max_namespaces: usize,
+    /// Opened by `KvLogStorage::open_read_only`.
+    read_only: bool,
+}
+
+impl Default for KvLogStorageOptions {
+    fn default() -> Self {
+        KvLogStorageOptions {
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            compaction_pool_size: DEFAULT_COMPACTION_POOL_SIZE,
+            fsync_policy: FsyncPolicy::Always,
+            compaction_garbage_ratio: 0.0,
+            compaction_policy: CompactionPolicy::default(),
+            compression_threshold: None,
+            record_sequence: false,
+            max_namespaces: DEFAULT_MAX_NAMESPACES,
+            read_only: false,
+        }
+    }
+}
+
+/// Builds a `KvLogStorage` with non-default options.
+#[derive(Clone, Default)]
+pub struct KvLogStorageBuilder {
+    options: KvLogStorageOptions,
+}
+
+impl KvLogStorageBuilder {
+    /// Max size of a log file in bytes. Writes are rotated to the next file once the active one is full.
+    pub fn segment_size(mut self, segment_size: u64) -> Self {
+        self.options.segment_size = segment_size;
+        self
+    }
+
+    /// Number of threads compacting the rotated log files.
+    pub fn compaction_pool_size(mut self, compaction_pool_size: usize) -> Self {
+        self.options.compaction_pool_size = compaction_pool_size;
+        self
+    }
+
+    pub fn fsync_policy(mut self, fsync_policy: FsyncPolicy) -> Self {
+        self.options.fsync_policy = fsync_policy;
+        self
+    }
+
+    /// Min share of the stale records in a rotated log file to rewrite it, from 0 to 1.
+    /// With 0 a file is compacted whenever it has at least one stale record, with 1 the rotated files are not compacted.
+    pub fn compaction_garbage_ratio(mut self, compaction_garbage_ratio: f64) -> Self {
+        self.options.compaction_garbage_ratio = compaction_garbage_ratio;
+        self
+    }
+
+    /// Compact the sealed log files in the background according to the policy,
+    /// in addition to the compaction of the rotated log files.
+    pub fn compaction_policy(mut self, compaction_policy: CompactionPolicy) -> Self {
+        self.options.compaction_policy = compaction_policy;
+        self
+    }
+
+    /// Compress the values larger than `threshold` bytes, see `KvLogStorage::set_compression_threshold`.
+    pub fn compression_threshold(mut self, compression_threshold: Option<usize>) -> Self {
+        self.options.compression_threshold = compression_threshold;
+        self
+    }
+
+    /// Precede each write with a sequence record of the next sequence number and the write time,
+    /// so a restore can replay the log up to a given write, see `KvLogStorage::restore_until`.
+    /// The numbers keep increasing across resets. Compaction drops the sequence records of the rewritten files.
+    pub fn record_sequence(mut self, record_sequence: bool) -> Self {
+        self.options.record_sequence = record_sequence;
+        self
+    }
+
+    /// Max number of the namespaces opened by `KvLogStorage::namespace`, 1024 by default.
+    /// The opened namespaces are kept until the storage is closed, so the limit bounds the memory
+    /// and the open files taken by the names the clients send.
+    pub fn max_namespaces(mut self, max_namespaces: usize) -> Self {
+        self.options.max_namespaces = max_namespaces;
+        self
+    }
+
+    /// Opens a directory as a log-base key-value storage with the configured options.
+    pub fn open(self, path: &Path) -> Result<KvLogStorage> {
+        let options = self.options;
+        if options.segment_size == 0 {
+            return Err(Box::from("Segment size must be positive"));
+        }
+        if options.compaction_pool_size == 0 {
+            return Err(Box::from("Compaction pool size must be positive"));
+        }
+        if let FsyncPolicy::Group { max_batch_size: 0, .. } = options.fsync_policy {
+            return Err(Box::from("Group commit batch size must be positive"));
+        }
+        if !(0.0..=1.0).contains(&options.compaction_garbage_ratio) {
+            return Err(Box::from(format!(
+                "Compaction garbage ratio must be from 0 to 1, got {}", options.compaction_garbage_ratio,
+            )));
+        }
+        if !(0.0..=1.0).contains(&options.compaction_policy.dead_ratio) {
+            return Err(Box::from(format!(
+                "Compaction dead ratio must be from 0 to 1, got {}", options.compaction_policy.dead_ratio,
+            )));
+        }
+        if options.compaction_policy.interval == Some(std::time::Duration::ZERO) {
+            return Err(Box::from("Compaction interval must be positive"));
+        }
+        KvLogStorage::open_with_options(path, options, None)
+    }
+}
+
+/// A backup restored to a staging directory and waiting to be committed.
+struct PreparedRestore {
+    staging_dir: PathBuf,
+    file_idxs: Vec<usize>,
+    index: dashmap::DashMap<String, KvStorePosition>,
+}
+
+/// Positions of the stored keys, shared by the storage handles. A restore swaps the whole map under the lock,
+/// so the lock-free readers see either the previous keys or the restored ones, never a partially filled index.
+type SharedIndex = std::sync::Arc<std::sync::RwLock<std::sync::Arc<dashmap::DashMap<String, KvStorePosition>>>>;
+
+/// Returns the current map of a shared index.
+fn load_index(index: &SharedIndex) -> std::sync::Arc<dashmap::DashMap<String, KvStorePosition>> {
+    index.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Bloom filters of the sealed log files by file indexes.
+type SegmentFilters = std::sync::Arc<std::sync::RwLock<HashMap<usize, BloomFilter>>>;
+
+/// Iterator over the key/value pairs of a `KvLogStorage`, see `KvLogStorage::iter_bytes`.
+pub struct KvLogStorageIter {
+    storage: KvLogStorage,
+    keys: std::vec::IntoIter<String>,
+}
+
+impl Iterator for KvLogStorageIter {
+    type Item = Result<(String, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for key in self.keys.by_ref() {
+            // The collections are not key/value pairs.
+            let is_collection = self.storage.index().get(&key)
+                .is_some_and(|position| matches!(*position, KvStorePosition::Collection { .. }));
+            if is_collection {
+                continue;
+            }
+            match self.storage.get_bytes(key.clone()) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        None
+    }
+}
+
 /// Key-value log-based storage.
 pub struct KvLogStorage {
     internal: std::sync::Arc<std::sync::Mutex<KvLogStorageInternal>>,
-    index: std::sync::Arc<dashmap::DashMap<String, KvStorePosition>>,
+    index: SharedIndex,
     storage_dir: PathBuf,
-    compaction_thread_pool: std::sync::Arc<std::sync::Mutex::<threads::shared::SharedThreadPool>>,
+    compaction_thread_pool: std::sync::Arc<threads::shared::SharedThreadPool>,
+    prepared_restores: std::sync::Arc<std::sync::Mutex<HashMap<String, PreparedRestore>>>,
+    /// Indexes of the log files being compacted at the moment.
+    compacting_files: std::sync::Arc<std::sync::Mutex<HashSet<usize>>>,
+    /// Filters of the keys set in the sealed log files. Compaction consults them
+    /// instead of reading the older log files to find out if a tombstone is still needed.
+    filters: SegmentFilters,
+    /// Commit thread of the `FsyncPolicy::Group` policy.
+    group_commit: Option<std::sync::Arc<GroupCommit>>,
+    /// Subscriptions to the changes of the keys.
+    watchers: std::sync::Arc<WatchRegistry>,
+    /// Storages of the namespaces opened so far by names.
+    namespaces: std::sync::Arc<std::sync::Mutex<HashMap<String, KvLogStorage>>>,
+    /// Bits of the `f64` compaction garbage ratio, shared by the handles so it can be changed at runtime.
+    compaction_garbage_ratio: std::sync::Arc<std::sync::atomic::AtomicU64>,
+    /// Handles of the compaction jobs not joined yet with the indexes of their log files.
+    pending_compactions: std::sync::Arc<std::sync::Mutex<Vec<(usize, JobHandle)>>>,
+    /// Log files rotated while the compaction is paused with their garbage ratios, `None` unless paused.
+    paused_compactions: std::sync::Arc<std::sync::Mutex<Option<HashMap<usize, f64>>>>,
+    /// Lock of the storage directory, released when the last handle is dropped. `None` for a read-only storage.
+    lock_file: Option<std::sync::Arc<File>>,
+    /// Background compaction checks of the `CompactionPolicy` with an interval.
+    /// The scheduler's own handle has none, so the scheduler stops with the last handle of the users.
+    compaction_scheduler: Option<std::sync::Arc<CompactionScheduler>>,
+    options: KvLogStorageOptions,
 }
 
 impl Clone for KvLogStorage {
@@ -89,6 +557,18 @@ impl Clone for KvLogStorage {
             internal: self.internal.clone(),
             storage_dir: self.storage_dir.clone(),
             compaction_thread_pool: self.compaction_thread_pool.clone(),
+            prepared_restores: self.prepared_restores.clone(),
+            compacting_files: self.compacting_files.clone(),
+            filters: self.filters.clone(),
+            group_commit: self.group_commit.clone(),
+            watchers: self.watchers.clone(),
+            namespaces: self.namespaces.clone(),
+            compaction_garbage_ratio: self.compaction_garbage_ratio.clone(),
+            pending_compactions: self.pending_compactions.clone(),
+            paused_compactions: self.paused_compactions.clone(),
+            lock_file: self.lock_file.clone(),
+            compaction_scheduler: self.compaction_scheduler.clone(),
+            options: self.options.clone(),
         }
     }
 
@@ -98,22 +578,72 @@ impl Clone for KvLogStorage {
 }
 
 impl KvLogStorage {
-    /// Opens a directory as a log-base key-value storage.
+    /// Opens a directory as a log-base key-value storage with the default options.
     pub fn open(path: &Path) -> Result<KvLogStorage> {
+        Self::builder().open(path)
+    }
+
+    /// Opens a storage directory for reads only, e.g. for an analytics job or a backup verifier.
+    /// The directory is not locked and its files are never changed: the storage doesn't rotate nor compact
+    /// the log files and the writes fail with `ERROR_CODE_READ_ONLY`. A record torn at the end of the active
+    /// log file is skipped. The writes of another process to the directory are not seen after the open.
+    pub fn open_read_only(path: &Path) -> Result<KvLogStorage> {
+        if !path.is_dir() {
+            return Err(Box::from(format!("Path {} is not a directory", path.display())));
+        }
+        Self::open_with_options(path, KvLogStorageOptions { read_only: true, ..KvLogStorageOptions::default() }, None)
+    }
+
+    /// Returns a builder to open a storage with non-default options.
+    pub fn builder() -> KvLogStorageBuilder {
+        KvLogStorageBuilder::default()
+    }
+
+    /// Opens the storage in `path`. The storage of a namespace shares the compaction thread pool,
+    /// the group commit thread and the compaction scheduler of its `parent` storage.
+    fn open_with_options(
+        path: &Path,
+        options: KvLogStorageOptions,
+        parent: Option<&KvLogStorage>,
+    ) -> Result<KvLogStorage> {
         log::info!("Reading {} to restore storage", path.display());
         let mut file_idxs = Vec::new();
+        let read_only = options.read_only;
+        let lock_file;
 
         // If the directory exists, read the existing storage files.
         if path.exists() {
             if !path.is_dir() {
                 return Err(Box::from(format!("Path {} is not a directory", path.display())));
             }
+            // The log files of a sharded storage are in the shard directories, its root has none to open.
+            if path.join(SHARDS_FILE_NAME).exists() {
+                return Err(Box::from(format!(
+                    "Storage {} has a sharded layout, cannot open it as an unsharded storage", path.display(),
+                )));
+            }
+            // The temporary files are removed under the lock, so the writes of another process are not affected.
+            // A read-only storage leaves the files of the interrupted writes to the next writer.
+            lock_file = if read_only { None } else { Some(lock_storage_dir(path)?) };
+            if !read_only {
+                Self::recover_segments(path)?;
+            }
 
             // Read all files in the directory and store their paths in sorted order.
             match std::fs::read_dir(path) {
                 Ok(files) => {
                     for file_result in files {
                         if let Ok(file) = file_result {
+                            if file.path().is_file() && is_tmp_file(&file.path()) {
+                                if !read_only {
+                                    log::warn!(
+                                        "Removing temporary file {} left by an interrupted write",
+                                        file.path().display(),
+                                    );
+                                    remove_file(file.path())?;
+                                }
+                                continue;
+                            }
                             if file.path().extension() == Some(std::ffi::OsStr::new("log")) {
                                 if let Some(file_idx) = path_to_idx(&file.path()) {
                                     file_idxs.push(file_idx);
@@ -137,6 +667,17 @@ impl KvLogStorage {
                     return Err(Box::from(format!("Failed to create directory {}: {}", path.display(), e)));
                 }
             }
+            lock_file = Some(lock_storage_dir(path)?);
+        }
+
+        // The engine file of a legacy directory is not created by a read-only storage.
+        if !read_only || path.join(ENGINE_FILE_NAME).exists() {
+            check_engine(path, "kvs")?;
+        }
+        let mut manifest = SegmentManifest::read(path)?;
+        if read_only {
+            // The obsolete log files are left by an interrupted reset and are not restored.
+            file_idxs.retain(|file_idx| manifest.state(*file_idx) != Some(SegmentState::Obsolete));
         }
 
         // Use the latest known file as active. If no files found - use default first file.
@@ -145,30 +686,264 @@ impl KvLogStorage {
         let file_path = file_idx_to_path(&path.to_path_buf(), active_file_idx);
         log::info!("{} files found, active record at {}", file_idxs.len(), file_path.display());
 
-        let storage_index = Self::restore_index(path, &file_idxs)?;
+        // A crash in the middle of a write leaves a torn record at the end of the active log file.
+        // A read-only storage reads the active log file up to the torn record instead of truncating it.
+        let mut file_ends = HashMap::new();
+        if !file_idxs.is_empty() && read_only {
+            if let Some(valid_size) = Self::torn_record_offset(path, active_file_idx)? {
+                file_ends.insert(active_file_idx, valid_size);
+            }
+        } else if !file_idxs.is_empty() {
+            Self::truncate_torn_record(path, active_file_idx)?;
+        }
+        let storage_index = Self::restore_index(path, &file_idxs, &file_ends)?;
+        let (sequence, sequence_time_ms) = match options.record_sequence {
+            true => Self::last_sequence(path, &file_idxs, &file_ends)?,
+            false => (0, 0),
+        };
+        // The filters are used by the compaction only, which never runs on a read-only storage.
+        let filters = if read_only {
+            HashMap::new()
+        } else {
+            Self::restore_filters(path, &file_idxs, active_file_idx)?
+        };
+        if !read_only {
+            manifest.replace(&file_idxs)?;
+        }
+        let group_commit = match (options.fsync_policy, parent) {
+            (FsyncPolicy::Group { .. }, Some(parent)) => parent.group_commit.clone(),
+            (FsyncPolicy::Group { interval, max_batch_size }, None) => {
+                Some(std::sync::Arc::new(GroupCommit::start(interval, max_batch_size)?))
+            },
+            _ => None,
+        };
+        let compaction_thread_pool = match parent {
+            Some(parent) => parent.compaction_thread_pool.clone(),
+            None => std::sync::Arc::new(threads::shared::SharedThreadPool::new(options.compaction_pool_size)),
+        };
 
-        Ok(
-            KvLogStorage {
-                index: std::sync::Arc::new(storage_index),
-                storage_dir: path.to_path_buf(),
-                internal: std::sync::Arc::new(
-                    std::sync::Mutex::new(
-                        KvLogStorageInternal {
-                            active_file_idx: active_file_idx,
-                        },
-                    )
-                ),
-                compaction_thread_pool: std::sync::Arc::new(
-                    std::sync::Mutex::new(
-                        threads::shared::SharedThreadPool::new(COMPACTION_POOL_SIZE)
-                    )
-                ),
+        let mut storage = KvLogStorage {
+            index: std::sync::Arc::new(std::sync::RwLock::new(std::sync::Arc::new(storage_index))),
+            storage_dir: path.to_path_buf(),
+            internal: std::sync::Arc::new(
+                std::sync::Mutex::new(
+                    KvLogStorageInternal {
+                        active_file_idx: active_file_idx,
+                        generation: 0,
+                        pending_commit: None,
+                        last_compaction: None,
+                        manifest,
+                        compaction_metrics: CompactionMetrics::default(),
+                        sequence,
+                        sequence_time_ms,
+                    },
+                )
+            ),
+            compaction_thread_pool,
+            prepared_restores: std::sync::Arc::new(std::sync::Mutex::new(HashMap::new())),
+            compacting_files: std::sync::Arc::new(std::sync::Mutex::new(HashSet::new())),
+            filters: std::sync::Arc::new(std::sync::RwLock::new(filters)),
+            group_commit,
+            watchers: std::sync::Arc::new(WatchRegistry::new()),
+            namespaces: std::sync::Arc::new(std::sync::Mutex::new(HashMap::new())),
+            compaction_garbage_ratio: std::sync::Arc::new(
+                std::sync::atomic::AtomicU64::new(options.compaction_garbage_ratio.to_bits())
+            ),
+            pending_compactions: std::sync::Arc::new(std::sync::Mutex::new(Vec::new())),
+            paused_compactions: std::sync::Arc::new(std::sync::Mutex::new(None)),
+            lock_file: lock_file.map(std::sync::Arc::new),
+            compaction_scheduler: None,
+            options,
+        };
+        // The scheduler of the storage checks its namespaces too.
+        if let (Some(interval), None) = (storage.options.compaction_policy.interval, parent) {
+            let scheduled_storage = storage.clone();
+            let mut checked_segments = HashMap::new();
+            let mut namespaces_checked_segments: HashMap<String, HashMap<usize, (u64, u64)>> = HashMap::new();
+            let scheduler = CompactionScheduler::start(interval, move || {
+                scheduled_storage.schedule_compactions(&mut checked_segments)?;
+                let namespaces: Vec<(String, KvLogStorage)> = scheduled_storage.namespaces.lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .iter()
+                    .map(|(name, storage)| (name.clone(), storage.clone()))
+                    .collect();
+                for (name, storage) in namespaces {
+                    storage.schedule_compactions(namespaces_checked_segments.entry(name).or_default())?;
+                }
+                Ok(())
+            })?;
+            storage.compaction_scheduler = Some(std::sync::Arc::new(scheduler));
+        }
+        Ok(storage)
+    }
+
+    /// Rewrites the legacy log files of the storage at `path` and of its namespaces into the current segment format,
+    /// so a storage directory written by an older version is not stranded when the format evolves.
+    /// The storage must not be opened meanwhile. Returns the number of rewritten log files.
+    pub fn migrate(path: &Path) -> Result<usize> {
+        if !path.is_dir() {
+            return Err(Box::from(format!("Storage {} doesn't exist", path.display())));
+        }
+        let _lock_file = lock_storage_dir(path)?;
+        check_engine(path, "kvs")?;
+
+        let mut file_idxs = Vec::new();
+        let mut namespace_dirs = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry_path = entry?.path();
+            let is_namespace = entry_path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("ns_"));
+            if entry_path.is_dir() && is_namespace {
+                namespace_dirs.push(entry_path);
+            } else if entry_path.extension() == Some(std::ffi::OsStr::new("log")) {
+                file_idxs.extend(path_to_idx(&entry_path));
+            }
+        }
+        file_idxs.sort();
+
+        if let Some(active_file_idx) = file_idxs.last() {
+            Self::truncate_torn_record(path, *active_file_idx)?;
+        }
+        let mut migrated_count = 0;
+        for file_idx in file_idxs {
+            if Self::migrate_log_file(path, file_idx)? {
+                migrated_count += 1;
+            }
+        }
+        for namespace_dir in namespace_dirs {
+            migrated_count += Self::migrate(&namespace_dir)?;
+        }
+        log::info!("{} log files of {} are migrated", migrated_count, path.display());
+        Ok(migrated_count)
+    }
+
+    /// Rewrites a legacy log file into the current format keeping all its records in order.
+    /// Returns `false` if the file is already in the current format.
+    fn migrate_log_file(storage_dir: &Path, file_idx: usize) -> Result<bool> {
+        let log_file_path = file_idx_to_path(storage_dir, file_idx);
+        let mut reader = BufReader::new(File::open(&log_file_path)?);
+        let version = serialize::read_segment_header(&mut reader)?;
+        if version == serialize::SEGMENT_FORMAT_VERSION {
+            return Ok(false)
+        }
+
+        let tmp_file_path = get_tmp_file_path(storage_dir, &log_file_path)?;
+        log::info!(
+            "Migrating {} from format version {} to {}",
+            log_file_path.display(), version, serialize::SEGMENT_FORMAT_VERSION,
+        );
+        let tmp_file = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_file_path)?;
+        let mut writer = io::BufWriter::new(tmp_file);
+        io::Write::write_all(&mut writer, &serialize::segment_header())?;
+        while let Some(command) = serialize::deserialize(&mut reader)? {
+            io::Write::write_all(&mut writer, &serialize::serialize_framed(&command)?)?;
+        }
+        let tmp_file = writer.into_inner().map_err(|err| err.into_error())?;
+        tmp_file.sync_all()?;
+        drop(tmp_file);
+        drop(reader);
+        rename(&tmp_file_path, &log_file_path)?;
+
+        // The filter of the rewritten file is rebuilt on the next start.
+        match remove_file(bloom::filter_path(storage_dir, file_idx)) {
+            Ok(()) => {},
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => return Err(Box::new(err)),
+        }
+        Ok(true)
+    }
+
+    /// Completes or rolls back the changes of the log files interrupted by a crash, as recorded in the manifest.
+    /// The obsolete log files are removed, as all their records are stale. The temporary file of an interrupted
+    /// compaction is removed and the original log file is kept. A compaction interrupted after the temporary file
+    /// is renamed has already replaced the log file with the complete compacted one.
+    fn recover_segments(storage_dir: &Path) -> Result<()> {
+        let manifest = SegmentManifest::read(storage_dir)?;
+        for (file_idx, state) in manifest.segments() {
+            let file_path = file_idx_to_path(storage_dir, *file_idx);
+            match state {
+                SegmentState::Obsolete => {
+                    log::warn!("Removing obsolete log file {} left by an interrupted write", file_path.display());
+                    for path in [file_path, bloom::filter_path(storage_dir, *file_idx)] {
+                        match remove_file(&path) {
+                            Ok(()) => {},
+                            Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+                            Err(err) => return Err(Box::new(err)),
+                        }
+                    }
+                },
+                SegmentState::Compacting => {
+                    let tmp_file_path = get_tmp_file_path(storage_dir, &file_path)?;
+                    if tmp_file_path.exists() {
+                        log::warn!("Rolling back the interrupted compaction of {}", file_path.display());
+                        remove_file(&tmp_file_path)?;
+                    } else {
+                        log::info!("Compaction of {} was completed before the restart", file_path.display());
+                    }
+                },
+                SegmentState::Active | SegmentState::Sealed => {},
+            }
+        }
+        Ok(())
+    }
+
+    /// Truncates the log file at the end of its last valid record if the file ends with a record torn by a crash,
+    /// so the storage opens with the complete records and appends after them. The record is torn if it cannot be read
+    /// and only zeros follow it, the zeros of a file extended without the data. An invalid record followed by
+    /// other data is an error, as dropping the records after it would lose the complete writes.
+    fn truncate_torn_record(storage_dir: &Path, file_idx: usize) -> Result<()> {
+        let file_path = file_idx_to_path(storage_dir, file_idx);
+        if let Some(valid_size) = Self::torn_record_offset(storage_dir, file_idx)? {
+            let file = OpenOptions::new().write(true).open(&file_path)?;
+            log::warn!(
+                "Truncating {}, {} bytes are dropped", file_path.display(), file.metadata()?.len() - valid_size,
+            );
+            file.set_len(valid_size)?;
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the log file without the record torn at its end, `None` if the file has no torn record.
+    /// See `truncate_torn_record`.
+    fn torn_record_offset(storage_dir: &Path, file_idx: usize) -> Result<Option<u64>> {
+        let file_path = file_idx_to_path(storage_dir, file_idx);
+        let file = OpenOptions::new().read(true).open(&file_path)?;
+        let mut reader = BufReader::new(&file);
+        let mut valid_size = 0;
+        // The header of a new file is torn the same way as a record.
+        let mut header_is_read = false;
+        loop {
+            let record = if header_is_read {
+                serialize::deserialize_record(&mut reader).map(|record| record.is_some())
+            } else {
+                header_is_read = true;
+                serialize::read_segment_header(&mut reader).map(|_| true)
+            };
+            match record {
+                Ok(true) => valid_size = reader.stream_position()?,
+                Ok(false) => return Ok(None),
+                Err(err) => {
+                    let mut rest = Vec::new();
+                    io::Read::read_to_end(&mut reader, &mut rest)?;
+                    if rest.iter().any(|byte| *byte != 0) {
+                        return Err(err);
+                    }
+                    log::warn!("Torn record at offset {} of {}: {}", valid_size, file_path.display(), err);
+                    return Ok(Some(valid_size));
+                },
             }
-        )
+        }
     }
 
     /// Restore storage index by reading a sorted list of log files (by file indexes).
-    fn restore_index(storage_dir: &Path, files_idxs: &Vec<usize>) -> Result<dashmap::DashMap::<String, KvStorePosition>> {
+    /// The files with an offset in `file_ends` are read up to the offset.
+    fn restore_index(
+        storage_dir: &Path,
+        files_idxs: &Vec<usize>,
+        file_ends: &HashMap<usize, u64>,
+    ) -> Result<dashmap::DashMap::<String, KvStorePosition>> {
         // We build a regular hashmap first as we know this method should be called
         // in a single thread on a startup. Later we will transform this map to a thread-safe
         // dashmap implementation.
@@ -176,31 +951,63 @@ impl KvLogStorage {
 
         // Iterate through known storage files (expected to be sorted).
         for file_idx in files_idxs {
+            // A file torn within its header has no records.
+            let file_end = file_ends.get(file_idx).copied();
+            if file_end == Some(0) {
+                continue;
+            }
             // Read each file using a buffered reader.
             let file_path = &file_idx_to_path(storage_dir, *file_idx);
             let file = OpenOptions::new()
                 .read(true)
                 .open(file_path)?;
             let mut reader = BufReader::new(file);
+            serialize::read_segment_header(&mut reader)?;
             let file_idx = path_to_idx(file_path)
                 .ok_or_else(|| format!("Invalid file path: {}", file_path.display()))?;
 
             // Read commands one by one until the end. Restore the index on fly.
             loop {
                 let mut file_offset = reader.stream_position()?;
-                let command = serialize::deserialize(&mut reader)?;
-                match command {
-                    Some(cmd) => {
-                        let value_offset_opt = serialize::get_value_offset(&cmd);
+                if file_end.is_some_and(|file_end| file_offset >= file_end) {
+                    break;
+                }
+                let record = serialize::deserialize_record(&mut reader)?;
+                match record {
+                    Some((cmd, format)) => {
+                        let value_offset_opt = serialize::get_value_offset(&cmd, format);
                         match cmd {
-                            Command::Set { key, value: _} => {
+                            Command::Set { key, value} => {
                                 file_offset += value_offset_opt.unwrap_or(0);
-                                index.insert(key, KvStorePosition{ file_idx: file_idx, file_offset: file_offset });
+                                let position = KvStorePosition::inline(&value, file_idx).unwrap_or(
+                                    KvStorePosition::OnDisk {
+                                        file_idx: file_idx, file_offset: file_offset, flags: 0, size: value.len() as u32,
+                                    }
+                                );
+                                index.insert(key, position);
+                            },
+                            Command::SetFlagged { key, flags, value } => {
+                                // Compressed values are never small enough to be inlined.
+                                file_offset += value_offset_opt.unwrap_or(0);
+                                let size = value.len() as u32;
+                                let value = decode_value(flags, value)?;
+                                let position = KvStorePosition::inline(&value, file_idx).unwrap_or(
+                                    KvStorePosition::OnDisk { file_idx, file_offset, flags, size }
+                                );
+                                index.insert(key, position);
                             },
                             Command::Remove { key } => {
                                 index.remove(&key);
                             },
-                            _ => {},
+                            Command::SetCollection { key, collection_type, value } => {
+                                let value = Box::new(Collection::decode(collection_type, &value)?);
+                                index.insert(key, KvStorePosition::Collection { value, file_idx, mutations_count: 0 });
+                            },
+                            cmd => {
+                                if let Some(collection_type) = collections::mutation_type(&cmd) {
+                                    Self::replay_mutation(&mut index, &cmd, collection_type, file_idx)?;
+                                }
+                            },
                         }
                     },
                     None => break
@@ -212,61 +1019,352 @@ impl KvLogStorage {
         Ok(dashmap::DashMap::from_iter(index))
     }
 
-    fn compact_log_file(
-        storage_dir: PathBuf,
-        write_mutex: std::sync::Arc::<std::sync::Mutex::<KvLogStorageInternal>>,
-        index: std::sync::Arc::<dashmap::DashMap<String, KvStorePosition>>,
-        log_file_idx: usize,
+    /// Applies a collection mutation record of the log file `file_idx` to the index being restored.
+    fn replay_mutation(
+        index: &mut HashMap<String, KvStorePosition>,
+        mutation: &Command,
+        collection_type: CollectionType,
+        file_idx: usize,
     ) -> Result<()> {
-        let log_file_path = file_idx_to_path(&storage_dir, log_file_idx);
-        log::info!("Compacting log file {}", log_file_path.display());
+        let key = mutation.key().unwrap_or_default();
+        let position = index.entry(key.to_owned()).or_insert_with(|| KvStorePosition::Collection {
+            value: Box::new(Collection::new(collection_type)),
+            file_idx,
+            mutations_count: 0,
+        });
+        let emptied = match position {
+            KvStorePosition::Collection { value, mutations_count, .. } => {
+                value.apply(mutation)?;
+                *mutations_count += 1;
+                value.is_empty()
+            },
+            _ => return Err(collections::wrong_type_error(collection_type)),
+        };
+        // The storage writes a remove record instead of emptying a collection.
+        if emptied {
+            index.remove(key);
+        }
+        Ok(())
+    }
 
-        let file = OpenOptions::new()
-                .read(true)
-                .open(&log_file_path)?;
-        let initial_file_size = File::metadata(&file)?.len();
-        let mut reader = BufReader::new(&file);
+    /// Finds the last sequence record in the log files, `(0, 0)` if there are none.
+    fn last_sequence(storage_dir: &Path, files_idxs: &[usize], file_ends: &HashMap<usize, u64>) -> Result<(u64, u64)> {
+        // The active log file has the last sequence record unless it's empty after a rotation,
+        // the compacted files have none.
+        for file_idx in files_idxs.iter().rev() {
+            let file_end = file_ends.get(file_idx).copied().unwrap_or(u64::MAX);
+            if file_end == 0 {
+                continue;
+            }
+            let mut reader = BufReader::new(File::open(file_idx_to_path(storage_dir, *file_idx))?);
+            serialize::read_segment_header(&mut reader)?;
+            let mut last_sequence = None;
+            while reader.stream_position()? < file_end {
+                match serialize::deserialize(&mut reader)? {
+                    Some(Command::Sequence { seq, timestamp_ms }) => last_sequence = Some((seq, timestamp_ms)),
+                    Some(_) => {},
+                    None => break,
+                }
+            }
+            if let Some(last_sequence) = last_sequence {
+                return Ok(last_sequence);
+            }
+        }
+        Ok((0, 0))
+    }
 
-        // Read commands one by one until the end of the file.
-        // The actual values stored in this file after compaction go to a hashmap.
-        // The tombstones for keys from previous files go to a set of tombstones to keep in the file.
-        let mut file_key_values = HashMap::<String, String>::new();
-        let mut keys_to_remove = HashSet::<String>::new();
-        let mut commands_count = 0;
-        loop {
-            if let Some(command) = serialize::deserialize(&mut reader)? {
-                match command {
-                    Command::Set { key, value} => {
-                        keys_to_remove.remove(&key);
-                        file_key_values.insert(key, value);
-                        commands_count += 1;
+    /// Cuts the log files restored to `storage_dir` at the first sequence record beyond the recovery point,
+    /// so only the writes up to the point are replayed. Returns the indexes of the kept log files.
+    /// Compaction drops the sequence records of the rewritten files, so the point cannot be located
+    /// if a log file without them is written between the last sequence record within the point and the cut.
+    fn truncate_log(storage_dir: &Path, files_idxs: &[usize], point: RecoveryPoint) -> Result<Vec<usize>> {
+        let mut has_sequence = false;
+        // Whether a log file without sequence records follows the last sequence record within the point.
+        let mut unsequenced_file = false;
+        let mut has_sequence_within = false;
+        for (pos, file_idx) in files_idxs.iter().enumerate() {
+            let file_path = file_idx_to_path(storage_dir, *file_idx);
+            let mut reader = BufReader::new(File::open(&file_path)?);
+            serialize::read_segment_header(&mut reader)?;
+            let (mut file_has_records, mut file_has_sequence) = (false, false);
+            loop {
+                let file_offset = reader.stream_position()?;
+                match serialize::deserialize(&mut reader)? {
+                    Some(Command::Sequence { seq, timestamp_ms }) => {
+                        has_sequence = true;
+                        file_has_sequence = true;
+                        if point.includes(seq, timestamp_ms) {
+                            unsequenced_file = false;
+                            has_sequence_within = true;
+                            continue;
+                        }
+                        // The writes before the first kept sequence record are compacted or reset.
+                        if unsequenced_file || (!has_sequence_within && seq > 1) {
+                            return Err(Box::from(format!(
+                                "Recovery point {:?} is within the compacted log files", point,
+                            )));
+                        }
+                        drop(reader);
+                        log::info!("Cutting {} at {} before the sequence {}", file_path.display(), file_offset, seq);
+                        let file = OpenOptions::new().write(true).open(&file_path)?;
+                        file.set_len(file_offset)?;
+                        file.sync_all()?;
+                        return Ok(files_idxs[..pos + 1].to_vec());
                     },
-                    Command::Remove { key } => {
-                        file_key_values.remove(&key);
-                        keys_to_remove.insert(key);
-                        commands_count += 1;
-                    }
-                    _ => {},
+                    Some(_) => file_has_records = true,
+                    None => break,
                 }
-            } else {
-                break
+            }
+            if file_has_records && !file_has_sequence {
+                unsequenced_file = true;
             }
         }
-        drop(reader);
-        drop(file);
-
-        // If the amount of commands matches the expected number of compacted set/remove commands,
-        // we can skip compaction.
-        if commands_count == file_key_values.len() + keys_to_remove.len() {
-            log::info!("No records to compact found in {}", log_file_path.display());
-            return Ok(())
+        if !has_sequence {
+            return Err(Box::from("The log has no sequence records, the storage is written without record_sequence"));
+        }
+        if unsequenced_file {
+            return Err(Box::from(format!("Recovery point {:?} is within the compacted log files", point)));
+        }
+        Ok(files_idxs.to_vec())
+    }
+
+    /// Reads the keys set in a log file, skipping the keys removed later in the same file.
+    fn read_file_keys(storage_dir: &Path, file_idx: usize) -> Result<HashSet<String>> {
+        let file = OpenOptions::new().read(true).open(file_idx_to_path(storage_dir, file_idx))?;
+        let mut reader = BufReader::new(file);
+        serialize::read_segment_header(&mut reader)?;
+        let mut keys = HashSet::new();
+        while let Some(command) = serialize::deserialize(&mut reader)? {
+            match command {
+                Command::Set { key, .. } | Command::SetFlagged { key, .. } | Command::SetCollection { key, .. } => {
+                    keys.insert(key);
+                },
+                Command::Remove { key } => { keys.remove(&key); },
+                command => {
+                    if let Some(key) = command.key().filter(|_| collections::mutation_type(&command).is_some()) {
+                        keys.insert(key.to_owned());
+                    }
+                },
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Builds a bloom filter of `keys` for a log file of `log_file_size` bytes and persists it next to the file.
+    fn build_filter<'a>(
+        storage_dir: &Path,
+        file_idx: usize,
+        log_file_size: u64,
+        keys: impl ExactSizeIterator<Item = &'a String>,
+    ) -> Result<BloomFilter> {
+        let mut filter = BloomFilter::new(keys.len(), log_file_size);
+        for key in keys {
+            filter.insert(key.as_bytes());
+        }
+        filter.write(&bloom::filter_path(storage_dir, file_idx))?;
+        Ok(filter)
+    }
+
+    /// Loads the filters of the sealed log files. The missing and stale filters are rebuilt.
+    fn restore_filters(storage_dir: &Path, files_idxs: &[usize], active_file_idx: usize) -> Result<HashMap<usize, BloomFilter>> {
+        let mut filters = HashMap::new();
+        for file_idx in files_idxs.iter().filter(|idx| **idx != active_file_idx) {
+            let log_file_size = std::fs::metadata(file_idx_to_path(storage_dir, *file_idx))?.len();
+            let filter = match BloomFilter::read(&bloom::filter_path(storage_dir, *file_idx)) {
+                Some(filter) if filter.log_file_size == log_file_size => filter,
+                _ => {
+                    log::info!("Building bloom filter for the log file with idx={}", file_idx);
+                    let keys = Self::read_file_keys(storage_dir, *file_idx)?;
+                    Self::build_filter(storage_dir, *file_idx, log_file_size, keys.iter())?
+                },
+            };
+            filters.insert(*file_idx, filter);
+        }
+        Ok(filters)
+    }
+
+    /// Checks if a key may be set in the log files older than `file_idx`.
+    /// Files without a filter yet are assumed to contain the key.
+    fn older_files_may_contain(storage_dir: &Path, filters: &SegmentFilters, file_idx: usize, key: &str) -> bool {
+        let filters = filters.read().unwrap_or_else(|e| e.into_inner());
+        (DEFAULT_FILE_IDX..file_idx).any(|idx| {
+            match filters.get(&idx) {
+                Some(filter) => filter.may_contain(key.as_bytes()),
+                None => file_idx_to_path(storage_dir, idx).exists(),
+            }
+        })
+    }
+
+    /// Removes the filter of a log file from the memory and the disk.
+    fn remove_filter(storage_dir: &Path, filters: &SegmentFilters, file_idx: usize) -> Result<()> {
+        filters.write().unwrap_or_else(|e| e.into_inner()).remove(&file_idx);
+        match remove_file(bloom::filter_path(storage_dir, file_idx)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(Box::new(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds a collection mutation record read by the compaction of a log file to the kept records of its key.
+    fn compact_mutation(
+        file_key_values: &mut HashMap<String, CompactedRecords>,
+        keys_to_remove: &mut HashSet<String>,
+        mutation: Command,
+        collection_type: CollectionType,
+    ) -> Result<()> {
+        let key = mutation.key().unwrap_or_default().to_owned();
+        match file_key_values.get_mut(&key) {
+            Some(CompactedRecords::Collection(collection)) => { collection.apply(&mutation)?; },
+            Some(CompactedRecords::Mutations(mutations)) => mutations.push(mutation),
+            Some(CompactedRecords::Value(_)) => return Err(collections::wrong_type_error(collection_type)),
+            // The collection removed earlier in the file is created again.
+            None if keys_to_remove.remove(&key) => {
+                let mut collection = Collection::new(collection_type);
+                collection.apply(&mutation)?;
+                file_key_values.insert(key, CompactedRecords::Collection(collection));
+            },
+            None => { file_key_values.insert(key, CompactedRecords::Mutations(vec![mutation])); },
+        }
+        Ok(())
+    }
+
+    /// Compacts a sealed log file. Returns the number of the reclaimed bytes,
+    /// or `None` if the file is not rewritten nor removed.
+    fn compact_log_file(
+        storage_dir: PathBuf,
+        write_mutex: std::sync::Arc::<std::sync::Mutex::<KvLogStorageInternal>>,
+        index: SharedIndex,
+        filters: SegmentFilters,
+        log_file_idx: usize,
+        garbage_ratio: f64,
+    ) -> Result<Option<u64>> {
+        let log_file_path = file_idx_to_path(&storage_dir, log_file_idx);
+        log::info!("Compacting log file {}", log_file_path.display());
+        let generation = write_mutex.lock().unwrap_or_else(|e| e.into_inner()).generation;
+
+        let file = OpenOptions::new()
+                .read(true)
+                .open(&log_file_path)?;
+        let initial_file_size = File::metadata(&file)?.len();
+        let mut reader = BufReader::new(&file);
+        serialize::read_segment_header(&mut reader)?;
+
+        // Read commands one by one until the end of the file.
+        // The actual values stored in this file after compaction go to a hashmap.
+        // The tombstones for keys from previous files go to a set of tombstones to keep in the file.
+        // Set records are kept as is, so the compressed values are not recompressed.
+        // The collections set or created in the file are folded, the mutations of the older collections are kept.
+        let mut file_key_values = HashMap::<String, CompactedRecords>::new();
+        let mut keys_to_remove = HashSet::<String>::new();
+        let mut commands_count = 0;
+        loop {
+            if let Some(command) = serialize::deserialize(&mut reader)? {
+                match command {
+                    Command::Set { ref key, .. } | Command::SetFlagged { ref key, .. } => {
+                        let key = key.clone();
+                        keys_to_remove.remove(&key);
+                        file_key_values.insert(key, CompactedRecords::Value(command));
+                        commands_count += 1;
+                    },
+                    Command::Remove { key } => {
+                        file_key_values.remove(&key);
+                        keys_to_remove.insert(key);
+                        commands_count += 1;
+                    },
+                    Command::SetCollection { key, collection_type, value } => {
+                        keys_to_remove.remove(&key);
+                        let collection = Collection::decode(collection_type, &value)?;
+                        file_key_values.insert(key, CompactedRecords::Collection(collection));
+                        commands_count += 1;
+                    },
+                    command => {
+                        if let Some(collection_type) = collections::mutation_type(&command) {
+                            Self::compact_mutation(
+                                &mut file_key_values, &mut keys_to_remove, command, collection_type,
+                            )?;
+                            commands_count += 1;
+                        }
+                    },
+                }
+            } else {
+                break
+            }
+        }
+        drop(reader);
+        drop(file);
+
+        // The values of the keys set again or removed in the newer files are stale too.
+        // A key found in this file by the index may be set again meanwhile, then its record is just kept.
+        // A collection is based on this file if it's set or created here, its later mutations are applied on top.
+        let current_index = load_index(&index);
+        file_key_values.retain(|key, records| {
+            current_index.get(key).is_some_and(|position| match records {
+                CompactedRecords::Mutations(_) => position.file_idx() <= log_file_idx,
+                _ => position.file_idx() == log_file_idx,
+            })
+        });
+        // The collections created by a mutation in this file are folded too.
+        for (key, records) in file_key_values.iter_mut() {
+            let is_created = current_index.get(key).is_some_and(|position| position.file_idx() == log_file_idx);
+            let mutations = match records {
+                CompactedRecords::Mutations(mutations) if is_created => mutations,
+                _ => continue,
+            };
+            if let Some(collection_type) = mutations.first().and_then(collections::mutation_type) {
+                let mut collection = Collection::new(collection_type);
+                for mutation in mutations.iter() {
+                    collection.apply(mutation)?;
+                }
+                *records = CompactedRecords::Collection(collection);
+            }
+        }
+
+        // Tombstones are needed only for the keys which may be set in the older files.
+        keys_to_remove.retain(|key| Self::older_files_may_contain(&storage_dir, &filters, log_file_idx, key));
+
+        // If the amount of commands matches the expected number of compacted set/remove commands,
+        // we can skip compaction.
+        let records_count: usize = file_key_values.values()
+            .map(|records| match records {
+                CompactedRecords::Mutations(mutations) => mutations.len(),
+                _ => 1,
+            })
+            .sum();
+        let live_count = records_count + keys_to_remove.len();
+        if commands_count == live_count {
+            log::info!("No records to compact found in {}", log_file_path.display());
+            Self::publish_filter(
+                &storage_dir, &write_mutex, &filters, generation, log_file_idx, initial_file_size, file_key_values.keys(),
+            )?;
+            return Ok(None);
+        }
+
+        // Skip the files with too few stale records to be worth rewriting, the ratio of 1 skips all the files.
+        let stale_count = commands_count - live_count;
+        if garbage_ratio >= 1.0 || (stale_count as f64) < garbage_ratio * commands_count as f64 {
+            log::info!(
+                "Only {}/{} records in {} are stale, skipping compaction",
+                stale_count, commands_count, log_file_path.display(),
+            );
+            Self::publish_filter(
+                &storage_dir, &write_mutex, &filters, generation, log_file_idx, initial_file_size, file_key_values.keys(),
+            )?;
+            return Ok(None);
         }
 
         // If all records are compacted - just remove the file.
         if file_key_values.is_empty() && keys_to_remove.is_empty() {
+            let mut internal = write_mutex.lock().unwrap_or_else(|e| e.into_inner());
+            if internal.generation != generation {
+                log::info!("Storage files are replaced, skipping compaction of {}", log_file_path.display());
+                return Ok(None)
+            }
             log::info!("All records in {} are compacted. Deleting the log file.", log_file_path.display());
+            internal.manifest.set_state(log_file_idx, SegmentState::Obsolete)?;
             remove_file(log_file_path)?;
-            return Ok(())
+            internal.manifest.remove(log_file_idx)?;
+            internal.last_compaction = Some(std::time::SystemTime::now());
+            Self::remove_filter(&storage_dir, &filters, log_file_idx)?;
+            return Ok(Some(initial_file_size));
         }
 
         // Write the compacted commands to a temporary file.
@@ -283,47 +1381,82 @@ impl KvLogStorage {
             );
             remove_file(&tmp_file_path)?;
         }
+        let mut internal = write_mutex.lock().unwrap_or_else(|e| e.into_inner());
+        if internal.generation != generation {
+            log::info!("Storage files are replaced, skipping compaction of {}", log_file_path.display());
+            return Ok(None)
+        }
+        internal.manifest.set_state(log_file_idx, SegmentState::Compacting)?;
+        drop(internal);
         let mut tmp_file = OpenOptions::new()
             .append(true)
             .create(true)
             .open(&tmp_file_path)?;
+        io::Write::write_all(&mut tmp_file, &serialize::segment_header())?;
 
         // Rebuild the index subset for the compacted file to update the value positions.
         // Later we can merge the updated index with the actual storage index.
         let mut file_index = HashMap::<String, KvStorePosition>::new();
+        let file_keys: Vec<String> = file_key_values.keys().cloned().collect();
         
         // Insert SET commands and update the index positions.
-        let mut file_offset = 0u64;
-        for (key, value) in file_key_values {
-            let cmd = Command::Set{ key: key.clone(), value: value };
-            let serialized_command = serialize::serialize(&cmd)?;
-            let bytes_written = io::Write::write(&mut tmp_file, &serialized_command)?;
-            if bytes_written != serialized_command.len() {
-                return Err(
-                    Box::from(
-                        std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!(
-                                "Unable to flush entire command, got {}/{} bytes written",
-                                bytes_written,
-                                serialized_command.len(),
-                            ),
+        let mut file_offset = serialize::SEGMENT_HEADER_SIZE;
+        for (key, records) in file_key_values {
+            let cmds = match records {
+                CompactedRecords::Value(cmd) => vec![cmd],
+                CompactedRecords::Collection(collection) => vec![Command::SetCollection {
+                    key: key.clone(),
+                    collection_type: collection.collection_type(),
+                    value: collection.encode(),
+                }],
+                CompactedRecords::Mutations(mutations) => mutations,
+            };
+            for cmd in cmds {
+                // Inlined values are not moved by compaction, only the on-disk positions are updated.
+                let (is_on_disk, flags) = match &cmd {
+                    Command::SetFlagged { flags, value, .. } => {
+                        (*flags & models::VALUE_FLAG_COMPRESSED != 0 || value.len() > INLINE_VALUE_MAX_SIZE, *flags)
+                    },
+                    Command::Set { value, .. } => (value.len() > INLINE_VALUE_MAX_SIZE, 0),
+                    _ => (false, 0),
+                };
+                let serialized_command = serialize::serialize_framed(&cmd)?;
+                let bytes_written = io::Write::write(&mut tmp_file, &serialized_command)?;
+                if bytes_written != serialized_command.len() {
+                    return Err(
+                        Box::from(
+                            std::io::Error::new(
+                                std::io::ErrorKind::Other,
+                                format!(
+                                    "Unable to flush entire command, got {}/{} bytes written",
+                                    bytes_written,
+                                    serialized_command.len(),
+                                ),
+                            )
                         )
-                    )
-                );
-            }
+                    );
+                }
 
-            let value_offset = get_value_offset(&cmd).unwrap_or(0);
-            file_index.insert(
-                key, KvStorePosition { file_idx: log_file_idx, file_offset: file_offset + value_offset }
-            );
-            file_offset += bytes_written as u64;
+                if is_on_disk {
+                    let value_offset = get_value_offset(&cmd, RecordFormat::V2).unwrap_or(0);
+                    file_index.insert(
+                        key.clone(),
+                        KvStorePosition::OnDisk {
+                            file_idx: log_file_idx,
+                            file_offset: file_offset + value_offset,
+                            flags,
+                            size: stored_value_size(&cmd),
+                        },
+                    );
+                }
+                file_offset += bytes_written as u64;
+            }
         }
 
         // Insert tombstones for keys from previous files.
         for key in keys_to_remove {
             let cmd = Command::Remove { key: key };
-            let serialized_command = serialize::serialize(&cmd)?;
+            let serialized_command = serialize::serialize_framed(&cmd)?;
             let bytes_written = io::Write::write(&mut tmp_file, &serialized_command)?;
             if bytes_written != serialized_command.len() {
                 return Err(
@@ -347,18 +1480,30 @@ impl KvLogStorage {
         drop(tmp_file);
 
         // Acquire the storage write mutex to make actual changes in the storage files and index.
-        let _mutex_guard = write_mutex.lock().unwrap_or_else(|e| e.into_inner());
+        let mut mutex_guard = write_mutex.lock().unwrap_or_else(|e| e.into_inner());
+        if mutex_guard.generation != generation {
+            log::info!("Storage files are replaced, skipping compaction of {}", log_file_path.display());
+            remove_file(tmp_file_path)?;
+            return Ok(None)
+        }
         
         // Replace the original file with the compacted temp file.
         log::info!("Replacing {} with compacted {}", log_file_path.display(), tmp_file_path.display());
         rename(tmp_file_path, &log_file_path)?;
+        mutex_guard.manifest.set_state(log_file_idx, SegmentState::Sealed)?;
+        mutex_guard.last_compaction = Some(std::time::SystemTime::now());
+        let filter = Self::build_filter(&storage_dir, log_file_idx, compacted_file_size, file_keys.iter())?;
+        filters.write().unwrap_or_else(|e| e.into_inner()).insert(log_file_idx, filter);
 
         // Update the storage index. If a key has a newer value, or doesn't exists, skip the key position update.
+        let index = load_index(&index);
         for (key, new_position) in file_index {
-            if let Some(existing_pos) = index.get(&key) {
-                if existing_pos.file_idx == log_file_idx {
-                    index.insert(key, new_position);
-                }
+            let is_in_compacted_file = match index.get(&key).as_deref() {
+                Some(KvStorePosition::OnDisk { file_idx, .. }) => *file_idx == log_file_idx,
+                _ => false,
+            };
+            if is_in_compacted_file {
+                index.insert(key, new_position);
             }
         }
 
@@ -366,21 +1511,180 @@ impl KvLogStorage {
             "Log file {} compaction completed: {} -> {} bytes",
             log_file_path.display(), initial_file_size, compacted_file_size
         );
+        Ok(Some(initial_file_size.saturating_sub(compacted_file_size)))
+    }
+
+    /// Builds and stores the filter of a sealed log file which is not rewritten by compaction.
+    fn publish_filter<'a>(
+        storage_dir: &Path,
+        write_mutex: &std::sync::Mutex<KvLogStorageInternal>,
+        filters: &SegmentFilters,
+        generation: u64,
+        log_file_idx: usize,
+        log_file_size: u64,
+        keys: impl ExactSizeIterator<Item = &'a String>,
+    ) -> Result<()> {
+        let internal = write_mutex.lock().unwrap_or_else(|e| e.into_inner());
+        if internal.generation != generation {
+            return Ok(())
+        }
+        let filter = Self::build_filter(storage_dir, log_file_idx, log_file_size, keys)?;
+        filters.write().unwrap_or_else(|e| e.into_inner()).insert(log_file_idx, filter);
+        Ok(())
+    }
+
+    /// Compacts a log file unless it is already being compacted by another job.
+    fn compact_log_file_exclusive(
+        storage_dir: PathBuf,
+        write_mutex: std::sync::Arc::<std::sync::Mutex::<KvLogStorageInternal>>,
+        index: SharedIndex,
+        compacting_files: std::sync::Arc<std::sync::Mutex<HashSet<usize>>>,
+        filters: SegmentFilters,
+        log_file_idx: usize,
+        garbage_ratio: f64,
+    ) -> Result<()> {
+        if !compacting_files.lock().unwrap_or_else(|e| e.into_inner()).insert(log_file_idx) {
+            log::info!("Log file with idx={} is already being compacted", log_file_idx);
+            return Ok(())
+        }
+        let started_at = std::time::Instant::now();
+        let result = Self::compact_log_file(
+            storage_dir, write_mutex.clone(), index, filters, log_file_idx, garbage_ratio,
+        );
+        let duration = started_at.elapsed();
+        let (outcome, reclaimed_size) = match &result {
+            Ok(Some(reclaimed_size)) => ("compacted", *reclaimed_size),
+            Ok(None) => ("skipped", 0),
+            Err(_) => ("failed", 0),
+        };
+        log::info!(
+            "Compaction summary: file_idx={} outcome={} reclaimed_size={} duration_ms={}",
+            log_file_idx, outcome, reclaimed_size, duration.as_millis(),
+        );
+
+        // A failed or skipped compaction leaves the original log file in place.
+        let mut internal = write_mutex.lock().unwrap_or_else(|e| e.into_inner());
+        let metrics = &mut internal.compaction_metrics;
+        match &result {
+            Ok(Some(_)) => metrics.compacted_segments += 1,
+            Ok(None) => {},
+            Err(_) => metrics.failures += 1,
+        }
+        metrics.reclaimed_size += reclaimed_size;
+        metrics.time += duration;
+        let manifest_result = match internal.manifest.state(log_file_idx) {
+            Some(SegmentState::Compacting) => internal.manifest.set_state(log_file_idx, SegmentState::Sealed),
+            _ => Ok(()),
+        };
+        drop(internal);
+        if let Err(err) = manifest_result {
+            log::error!("Cannot update the manifest of the log file with idx={}: {}", log_file_idx, err);
+        }
+        compacting_files.lock().unwrap_or_else(|e| e.into_inner()).remove(&log_file_idx);
+        result.map(|_| ())
+    }
+
+    /// Logs the result of a completed compaction job.
+    fn log_compaction_result(log_file_idx: usize, result: Result<()>) {
+        match result {
+            Ok(()) => log::debug!("Compaction of the log file with idx={} is completed", log_file_idx),
+            Err(err) => log::error!("Compaction of the log file with idx={} failed: {}", log_file_idx, err),
+        }
+    }
+
+    /// Estimates the dead bytes of the sealed log files, except the ones being compacted.
+    /// Returns `(file_idx, file_size, dead_size)` of each file.
+    fn segments_garbage(&self) -> Result<Vec<(usize, u64, u64)>> {
+        let active_file_idx = self.internal.lock().unwrap_or_else(|e| e.into_inner()).active_file_idx;
+        let mut live_sizes = HashMap::<usize, u64>::new();
+        for entry in self.index().iter() {
+            *live_sizes.entry(entry.value().file_idx()).or_default() += entry.value().record_size(entry.key());
+        }
+
+        let compacting_files = self.compacting_files.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut segments = Vec::new();
+        for file_idx in DEFAULT_FILE_IDX..active_file_idx {
+            if compacting_files.contains(&file_idx) {
+                continue;
+            }
+            let file_size = match std::fs::metadata(file_idx_to_path(&self.storage_dir, file_idx)) {
+                Ok(metadata) => metadata.len(),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(Box::new(err)),
+            };
+            let live_size = live_sizes.get(&file_idx).copied().unwrap_or(0) + serialize::SEGMENT_HEADER_SIZE;
+            segments.push((file_idx, file_size, file_size.saturating_sub(live_size)));
+        }
+        Ok(segments)
+    }
+
+    /// Starts the compactions of the sealed log files selected by the compaction policy.
+    /// `checked_segments` keeps the size and the dead bytes of each file when its compaction was started.
+    /// A file is not selected again until it is rewritten or gets more dead bytes, so the files with the dead
+    /// bytes compaction cannot drop, e.g. the tombstones of the older files, are not reread on each check.
+    fn schedule_compactions(&self, checked_segments: &mut HashMap<usize, (u64, u64)>) -> Result<()> {
+        if self.is_compaction_paused() {
+            return Ok(())
+        }
+        let segments: Vec<(usize, u64, u64)> = self.segments_garbage()?
+            .into_iter()
+            .map(|(file_idx, file_size, dead_size)| {
+                let is_checked = checked_segments.get(&file_idx).is_some_and(|(checked_size, checked_dead_size)| {
+                    *checked_size == file_size && *checked_dead_size >= dead_size
+                });
+                (file_idx, file_size, if is_checked { 0 } else { dead_size })
+            })
+            .collect();
+        let sizes: HashMap<usize, (u64, u64)> = segments.iter()
+            .map(|(file_idx, file_size, dead_size)| (*file_idx, (*file_size, *dead_size)))
+            .collect();
+        checked_segments.retain(|file_idx, _| sizes.contains_key(file_idx));
+
+        for file_idx in self.options.compaction_policy.select(&segments) {
+            log::info!("Scheduling compaction of the log file with idx={}", file_idx);
+            checked_segments.insert(file_idx, sizes[&file_idx]);
+            self.run_compaction(file_idx, 0.0);
+        }
         Ok(())
     }
 
     /// Runs the compaction process in a new thread.
-    /// The compaction threads are taken from a separate thread pool guarded with a mutex.
-    /// As compaction process is relatively rare, it is not expected to cause mutex contention.
-    fn run_compaction(&self, log_file_idx: usize) {
+    /// The compaction threads are taken from a separate thread pool shared by the storage handles.
+    /// The results of the completed compactions are logged on the next compaction or on close.
+    /// While the compaction is paused the log file is only remembered to compact it on resume.
+    fn run_compaction(&self, log_file_idx: usize, garbage_ratio: f64) {
+        // The lock is held until the job is registered, so `pause_compaction` cannot miss it.
+        let mut paused_compactions = self.paused_compactions.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(deferred) = paused_compactions.as_mut() {
+            log::info!("Compaction is paused, deferring the compaction of the log file with idx={}", log_file_idx);
+            let deferred_ratio = deferred.entry(log_file_idx).or_insert(garbage_ratio);
+            *deferred_ratio = deferred_ratio.min(garbage_ratio);
+            return;
+        }
+
         let storage_dir = self.storage_dir.clone();
         let internal = self.internal.clone();
         let index = self.index.clone();
-        let mut pool = self.compaction_thread_pool.lock().unwrap_or_else(|e| e.into_inner());
-        if let Err(err) = pool.spawn(Box::new(move || {
-            Self::compact_log_file(storage_dir, internal, index, log_file_idx).ok();
-        })) {
-            log::error!("Cannot queue the compaction job for the log file with idx={}: {}", log_file_idx, err);
+        let compacting_files = self.compacting_files.clone();
+        let filters = self.filters.clone();
+
+        // The handle is registered under the same lock it is spawned, so `close` cannot miss it.
+        let mut pending_compactions = self.pending_compactions.lock().unwrap_or_else(|e| e.into_inner());
+        pending_compactions.retain(|(file_idx, handle)| match handle.join_timeout(std::time::Duration::ZERO) {
+            Some(result) => {
+                Self::log_compaction_result(*file_idx, result);
+                false
+            },
+            None => true,
+        });
+        let spawned = self.compaction_thread_pool.spawn_task(move || {
+            Self::compact_log_file_exclusive(
+                storage_dir, internal, index, compacting_files, filters, log_file_idx, garbage_ratio,
+            )
+        });
+        match spawned {
+            Ok(handle) => pending_compactions.push((log_file_idx, handle)),
+            Err(err) => log::error!("Cannot queue the compaction job for the log file with idx={}: {}", log_file_idx, err),
         }
     }
 
@@ -392,108 +1696,454 @@ impl KvLogStorage {
         let next_file_path = file_idx_to_path(&self.storage_dir, internal.active_file_idx);
         
         log::info!("Rotating log file {} to {}", prev_file_path.display(), next_file_path.display());
-        
-        self.run_compaction(prev_idx);
+        let next_idx = internal.active_file_idx;
+        internal.manifest.update(|segments| {
+            segments.insert(prev_idx, SegmentState::Sealed);
+            segments.insert(next_idx, SegmentState::Active);
+        })?;
+
+        self.run_compaction(prev_idx, self.compaction_garbage_ratio());
 
         Ok(())
     }
 
-    /// Writes a command to the log storage.
+    /// Writes a command to the log storage and syncs it according to the fsync policy.
     /// If the command contains a value, it's position is returned.
-    fn write(&self, internal: &mut KvLogStorageInternal, cmd: Command) -> Result<Option<KvStorePosition>> {
-        let serialized_command = serialize::serialize(&cmd)?;
+    /// With the group commit the write is not synced yet, see `commit`.
+    fn write(&self, internal: &mut KvLogStorageInternal, cmd: &Command) -> Result<Option<KvStorePosition>> {
+        let position = self.write_unsynced(internal, cmd)?;
+        self.sync_files(internal, &HashSet::from([internal.active_file_idx]))?;
+        Ok(position)
+    }
+
+    /// Syncs the written log files according to the fsync policy.
+    fn sync_files(&self, internal: &mut KvLogStorageInternal, file_idxs: &HashSet<usize>) -> Result<()> {
+        match &self.group_commit {
+            Some(group_commit) => {
+                for file_idx in file_idxs {
+                    let file_path = file_idx_to_path(&self.storage_dir, *file_idx);
+                    internal.pending_commit = Some(group_commit.register(file_path));
+                }
+            },
+            None if self.options.fsync_policy == FsyncPolicy::Always => {
+                for file_idx in file_idxs {
+                    // The rotated files may be already compacted, the compacted files are synced.
+                    match OpenOptions::new().append(true).open(file_idx_to_path(&self.storage_dir, *file_idx)) {
+                        Ok(file) => file.sync_data()?,
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+                        Err(err) => return Err(Box::new(err)),
+                    }
+                }
+            },
+            None => {},
+        }
+        Ok(())
+    }
+
+    /// Writes a command to the active log file without syncing it.
+    fn write_unsynced(&self, internal: &mut KvLogStorageInternal, cmd: &Command) -> Result<Option<KvStorePosition>> {
+        let serialized_command = serialize::serialize_framed(cmd)?;
         let command_size = serialized_command.len() as u64;
-        if command_size > MAX_SEGMENT_SIZE {
-            return Err(Box::from(format!("A single log entry size cannot exceed {}", MAX_SEGMENT_SIZE)));
+        // A new log file starts with the header, so an entry must fit the file along with it.
+        let max_command_size = self.options.segment_size.saturating_sub(serialize::SEGMENT_HEADER_SIZE);
+        if command_size > max_command_size {
+            return Err(Box::from(format!("A single log entry size cannot exceed {}", max_command_size)));
+        }
+
+        let file_offset = self.append_records(internal, &serialized_command)?;
+        Ok(record_position(cmd, internal.active_file_idx, file_offset))
+    }
+
+    /// Appends the serialized records to the active log file, rotating it if the records don't fit.
+    /// Returns the offset of the records in the file. A failed write is truncated, so no partial record is left.
+    fn append_records(&self, internal: &mut KvLogStorageInternal, data: &[u8]) -> Result<u64> {
+        // The sequence record is written together with the records, so a rotation never separates them.
+        let (sequence_record, sequence_time_ms) = match self.options.record_sequence {
+            true => {
+                let now_ms = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis() as u64;
+                let timestamp_ms = now_ms.max(internal.sequence_time_ms);
+                let command = Command::Sequence { seq: internal.sequence + 1, timestamp_ms };
+                (serialize::serialize_framed(&command)?, timestamp_ms)
+            },
+            false => (Vec::new(), internal.sequence_time_ms),
+        };
+        let data = &[sequence_record.as_slice(), data].concat();
+        if serialize::SEGMENT_HEADER_SIZE + data.len() as u64 > self.options.segment_size {
+            return Err(Box::from(format!("Unable to write {} bytes: exceeds the segment size", data.len())));
         }
 
-        let mut file_offset = 0u64;
-        let mut data_is_written = false;
-        while !data_is_written {
+        loop {
             let active_file_path = file_idx_to_path(&self.storage_dir, internal.active_file_idx);
             let mut file = OpenOptions::new()
                 .append(true)
                 .create(true)
                 .open(&active_file_path)?;
 
+            let mut file_size = File::metadata(&file)?.len();
+            if file_size == 0 {
+                io::Write::write_all(&mut file, &serialize::segment_header())?;
+                file_size = serialize::SEGMENT_HEADER_SIZE;
+            }
+
             // If the current active file exceeds max allowed size - try writing to the next file.
-            let file_size = File::metadata(&file)?.len();
-            if file_size + command_size > MAX_SEGMENT_SIZE {
+            if file_size + data.len() as u64 > self.options.segment_size {
                 self.rotate_file(internal)?;
                 continue;
             }
 
-            file_offset = file.seek(io::SeekFrom::End(0))?;
-            let bytes_written = io::Write::write(&mut file, &serialized_command)?;
-            if bytes_written != serialized_command.len() {
-                return Err(
-                    Box::from(
-                        std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!(
-                                "Unable to flush entire command, got {}/{} bytes written",
-                                bytes_written,
-                                serialized_command.len(),
-                            ),
-                        )
-                    )
-                );
+            let file_offset = file.seek(io::SeekFrom::End(0))?;
+            if let Err(err) = io::Write::write_all(&mut file, data) {
+                if let Err(truncate_err) = file.set_len(file_offset) {
+                    log::error!("Cannot truncate the failed write in {}: {}", active_file_path.display(), truncate_err);
+                }
+                return Err(Box::from(format!("Unable to write {} bytes: {}", data.len(), err)));
+            }
+            if self.options.record_sequence {
+                internal.sequence += 1;
+                internal.sequence_time_ms = sequence_time_ms;
             }
-            file.sync_data()?;
-            data_is_written = true;
+            return Ok(file_offset + sequence_record.len() as u64);
         }
+    }
 
-        match serialize::get_value_offset(&cmd) {
-            Some(value_offset) => {
-                Ok(
-                    Some(
-                        KvStorePosition {
-                            file_idx: internal.active_file_idx,
-                            file_offset: file_offset + value_offset
-                        }
-                    )
-                )
-            },
-            None => Ok(None),
+    /// Waits for the group commit of the last write, if any. Called after the write lock is released,
+    /// so the concurrent writers are synced together.
+    fn commit(&self, mut internal: std::sync::MutexGuard<KvLogStorageInternal>) -> Result<()> {
+        let pending_commit = internal.pending_commit.take();
+        drop(internal);
+        match (&self.group_commit, pending_commit) {
+            (Some(group_commit), Some(seq)) => group_commit.wait(seq),
+            _ => Ok(()),
         }
     }
 
-    /// Reads a value from the log files using the position.
-    fn read_value(storage_path: &Path, position: &KvStorePosition) -> Result<String> {
-        let file_path = file_idx_to_path(&storage_path, position.file_idx);
-        let file = OpenOptions::new().read(true).open(file_path)?;
+    /// Reads a value from the log files using the position. The position points to the value bytes,
+    /// so the values are read the same way from the records of any format.
+    fn read_value(storage_path: &Path, file_idx: usize, file_offset: u64, flags: u8, size: u32) -> Result<Vec<u8>> {
+        let file_path = file_idx_to_path(&storage_path, file_idx);
+        let mut file = OpenOptions::new().read(true).open(file_path)?;
 
-        let mut reader = BufReader::new(file);
-        reader.seek(io::SeekFrom::Start(position.file_offset))?;
-        
-        match String::deserialize(&mut reader) {
-            Ok(result) => Ok(result),
-            Err(err) => Err(Box::new(err)),
+        file.seek(io::SeekFrom::Start(file_offset))?;
+        let mut value = vec![0u8; size as usize];
+        io::Read::read_exact(&mut file, &mut value)?;
+        decode_value(flags, value)
+    }
+
+    /// Builds a set log record. Values above the compression threshold are compressed
+    /// if it makes the record smaller. Values small enough to be inlined are never compressed.
+    fn set_record(&self, key: String, value: Vec<u8>) -> Command {
+        let threshold = match self.options.compression_threshold {
+            Some(threshold) => threshold.max(INLINE_VALUE_MAX_SIZE),
+            None => return Command::Set { key, value },
+        };
+        if value.len() > threshold {
+            let compressed = lz4_flex::compress_prepend_size(&value);
+            if compressed.len() < value.len() {
+                return Command::SetFlagged { key, flags: models::VALUE_FLAG_COMPRESSED, value: compressed };
+            }
         }
+        Command::Set { key, value }
+    }
+
+    /// Enables compression of the values larger than `threshold` bytes, or disables it if `None`.
+    /// The log files written before are read regardless of the setting.
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.options.compression_threshold = threshold;
     }
 
     /// Set key `key` to value `value`.
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.set_bytes(key, value.into_bytes())
+    }
+
+    /// Set key `key` to a binary value `value`.
+    pub fn set_bytes(&mut self, key: String, value: Vec<u8>) -> Result<()> {
+        self.check_writable()?;
         let mut internal = match self.internal.lock() {
             Ok(guard) => guard,
             Err(poisoned) => poisoned.into_inner(),
         };
-        let cmd = Command::Set { key: key.clone(), value: value };
-        let pos = self.write(&mut internal, cmd)?.unwrap();
-        self.index.insert(key, pos);
+        self.write_value(&mut internal, key, value)?;
+        self.commit(internal)
+    }
+
+    /// Appends `suffix` to the binary value with the key `key`, the missing key is set to `suffix`.
+    /// The value is read and written back under the write lock, so concurrent appends are never lost.
+    /// Returns the length of the new value.
+    pub fn append(&mut self, key: String, suffix: Vec<u8>) -> Result<u64> {
+        self.check_writable()?;
+        let mut internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+        let mut value = self.get_bytes(key.clone())?.unwrap_or_default();
+        value.extend(suffix);
+        let length = value.len() as u64;
+        self.write_value(&mut internal, key, value)?;
+        self.commit(internal)?;
+        Ok(length)
+    }
+
+    /// Sets key `key` to a binary value `value` and returns the previous value, `None` if the key didn't exist.
+    pub fn get_set(&mut self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.check_writable()?;
+        let mut internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+        let previous_value = self.get_bytes(key.clone())?;
+        self.write_value(&mut internal, key, value)?;
+        self.commit(internal)?;
+        Ok(previous_value)
+    }
+
+    /// Sets key `key` to a binary value `value` only if the key doesn't exist. Returns `true` if the value was set.
+    pub fn set_nx(&mut self, key: String, value: Vec<u8>) -> Result<bool> {
+        self.check_writable()?;
+        let mut internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+        if self.index().contains_key(&key) {
+            return Ok(false);
+        }
+        self.write_value(&mut internal, key, value)?;
+        self.commit(internal)?;
+        Ok(true)
+    }
+
+    /// Applies the collection mutation command `mutation` to the collection with the key of the command.
+    /// The mutation is written as a single log record and applied to the collection held in the index,
+    /// the emptied collection is removed by a remove record. Nothing is written if the collection is unchanged.
+    /// The collection is rewritten as a single record once it has too many mutation records,
+    /// so they can be compacted.
+    pub fn update_collection(&mut self, mutation: Command) -> Result<MutationResult> {
+        let collection_type = collections::mutation_type(&mutation)
+            .ok_or_else(|| format!("Command {} is not a collection mutation", mutation.name()))?;
+        let key = mutation.key().unwrap_or_default().to_owned();
+        self.check_writable()?;
+        let mut internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+        let index = self.index();
+        let result = match index.get(&key).as_deref() {
+            Some(KvStorePosition::Collection { value, .. }) => value.mutation_result(&mutation)?,
+            Some(_) => return Err(collections::wrong_type_error(collection_type)),
+            None => Collection::new(collection_type).mutation_result(&mutation)?,
+        };
+        if !result.changed {
+            return Ok(result);
+        }
+        if result.length == 0 {
+            self.remove_value(&mut internal, key)?;
+            self.commit(internal)?;
+            return Ok(result);
+        }
+
+        self.write(&mut internal, &mutation)?;
+        let file_idx = internal.active_file_idx;
+        let mut position = index.entry(key.clone()).or_insert_with(|| KvStorePosition::Collection {
+            value: Box::new(Collection::new(collection_type)),
+            file_idx,
+            mutations_count: 0,
+        });
+        let snapshot = match position.value_mut() {
+            KvStorePosition::Collection { value, mutations_count, .. } => {
+                value.apply(&mutation)?;
+                *mutations_count += 1;
+                let snapshot_threshold = (value.len() as u64).max(COLLECTION_SNAPSHOT_MIN_MUTATIONS);
+                (*mutations_count > snapshot_threshold).then(|| Command::SetCollection {
+                    key: key.clone(),
+                    collection_type,
+                    value: value.encode(),
+                })
+            },
+            _ => None,
+        };
+        drop(position);
+
+        if let Some(snapshot) = snapshot {
+            // A collection too large for a log file keeps its mutation records.
+            let fits = serialize::serialize_framed(&snapshot)?.len() as u64 <= self.max_batch_size();
+            if fits {
+                self.write(&mut internal, &snapshot)?;
+            }
+            let mut position = index.get_mut(&key);
+            if let Some(KvStorePosition::Collection { file_idx, mutations_count, .. }) = position.as_deref_mut() {
+                if fits {
+                    *file_idx = internal.active_file_idx;
+                }
+                *mutations_count = 0;
+            }
+        }
+        self.commit(internal)?;
+        Ok(result)
+    }
+
+    /// Calls `read` with the collection of the type `collection_type` with the key `key`, `None` for a missing key.
+    pub fn read_collection(
+        &self,
+        key: String,
+        collection_type: CollectionType,
+        read: &mut CollectionRead,
+    ) -> Result<()> {
+        match self.index().get(&key).as_deref() {
+            Some(KvStorePosition::Collection { value, .. }) if value.collection_type() == collection_type => {
+                read(Some(value))
+            },
+            Some(_) => Err(collections::wrong_type_error(collection_type)),
+            None => read(None),
+        }
+    }
+
+    /// Writes a set record of the value, then updates the index and notifies the watchers.
+    fn write_value(&self, internal: &mut KvLogStorageInternal, key: String, value: Vec<u8>) -> Result<()> {
+        let inline_value = (value.len() <= INLINE_VALUE_MAX_SIZE).then(|| value.clone());
+        let event = self.watchers.is_watched(&key).then(|| ChangeEvent::Set { key: key.clone(), value: value.clone() });
+        let cmd = self.set_record(key.clone(), value);
+        let pos = self.write(internal, &cmd)?.unwrap();
+        let inline_pos = inline_value.and_then(|value| KvStorePosition::inline(&value, pos.file_idx()));
+        self.index().insert(key, inline_pos.unwrap_or(pos));
+        if let Some(event) = event {
+            self.watchers.notify(event);
+        }
         Ok(())
     }
 
+    /// Sets multiple keys with a single sync of each written log file.
+    /// The batch is not atomic: the keys written before a failure stay set.
+    pub fn set_batch(&mut self, values: Vec<(String, Vec<u8>)>) -> Result<()> {
+        self.check_writable()?;
+        let mut internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+        let mut written_files = HashSet::new();
+        for (key, value) in values {
+            let inline_value = (value.len() <= INLINE_VALUE_MAX_SIZE).then(|| value.clone());
+            let event = self.watchers.is_watched(&key).then(|| ChangeEvent::Set { key: key.clone(), value: value.clone() });
+            let cmd = self.set_record(key.clone(), value);
+            let pos = self.write_unsynced(&mut internal, &cmd)?.unwrap();
+            written_files.insert(internal.active_file_idx);
+            let inline_pos = inline_value.and_then(|value| KvStorePosition::inline(&value, pos.file_idx()));
+            self.index().insert(key, inline_pos.unwrap_or(pos));
+            if let Some(event) = event {
+                self.watchers.notify(event);
+            }
+        }
+        self.sync_files(&mut internal, &written_files)?;
+        self.commit(internal)
+    }
+
+    /// Writes the set and remove commands atomically: all the records are appended to the active log file
+    /// with a single write and a single sync, and the index is updated only once the whole batch is written.
+    /// The batch must fit a single log file. The removes of the missing keys are skipped, like with `remove`.
+    /// A crash during the write may still leave a part of the batch in the log, like a torn single record.
+    pub fn write_batch(&mut self, commands: Vec<Command>) -> Result<()> {
+        self.check_writable()?;
+        let mut internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Whether the keys exist with the preceding commands of the batch applied.
+        let mut batch_keys = HashMap::<String, bool>::new();
+        let mut records = Vec::with_capacity(commands.len());
+        for command in commands {
+            match command {
+                Command::Set { key, value } => {
+                    batch_keys.insert(key.clone(), true);
+                    let inline_value = (value.len() <= INLINE_VALUE_MAX_SIZE).then(|| value.clone());
+                    let event = self.watchers.is_watched(&key)
+                        .then(|| ChangeEvent::Set { key: key.clone(), value: value.clone() });
+                    records.push((self.set_record(key, value), inline_value, event));
+                },
+                Command::Remove { key } => {
+                    let exists = batch_keys.get(&key).copied().unwrap_or_else(|| self.index().contains_key(&key));
+                    if exists {
+                        batch_keys.insert(key.clone(), false);
+                        let event = Some(ChangeEvent::Remove { key: key.clone() });
+                        records.push((Command::Remove { key }, None, event));
+                    }
+                },
+                command => return Err(Box::from(format!("Command {} cannot be written in a batch", command.name()))),
+            }
+        }
+        if records.is_empty() {
+            return Ok(())
+        }
+
+        let mut buffer = Vec::new();
+        let mut record_offsets = Vec::with_capacity(records.len());
+        for (record, _, _) in &records {
+            record_offsets.push(buffer.len() as u64);
+            buffer.extend(serialize::serialize_framed(record)?);
+        }
+        let max_batch_size = self.max_batch_size();
+        if buffer.len() as u64 > max_batch_size {
+            return Err(Box::from(format!("A batch size cannot exceed {}", max_batch_size)));
+        }
+
+        let file_offset = self.append_records(&mut internal, &buffer)?;
+        let file_idx = internal.active_file_idx;
+        self.sync_files(&mut internal, &HashSet::from([file_idx]))?;
+        for ((record, inline_value, event), record_offset) in records.into_iter().zip(record_offsets) {
+            match &record {
+                Command::Set { key, .. } | Command::SetFlagged { key, .. } => {
+                    let pos = record_position(&record, file_idx, file_offset + record_offset).unwrap();
+                    let inline_pos = inline_value.and_then(|value| KvStorePosition::inline(&value, file_idx));
+                    self.index().insert(key.clone(), inline_pos.unwrap_or(pos));
+                },
+                Command::Remove { key } => {
+                    self.index().remove(key);
+                },
+                _ => {},
+            }
+            if let Some(event) = event {
+                self.watchers.notify(event);
+            }
+        }
+        self.commit(internal)
+    }
+
+    /// Max total size of the records of a `write_batch`.
+    /// A new log file starts with the header, so a batch must fit the file along with it.
+    pub fn max_batch_size(&self) -> u64 {
+        self.options.segment_size.saturating_sub(serialize::SEGMENT_HEADER_SIZE)
+    }
+
+    /// Removes all the keys starting with `prefix` with the remove records written by `write_batch`.
+    /// The removes are split into as few batches as the log file size allows, each batch is applied atomically.
+    /// The keys set under the prefix while the batches are written may be kept.
+    /// Returns the number of the removed keys.
+    pub fn reset_prefix(&mut self, prefix: &str) -> Result<u64> {
+        self.check_writable()?;
+        let mut keys: Vec<String> = self.keys().into_iter().filter(|key| key.starts_with(prefix)).collect();
+        keys.sort_unstable();
+        let removed_count = keys.len() as u64;
+
+        let max_batch_size = self.max_batch_size();
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        for key in keys {
+            let command = Command::Remove { key };
+            let record_size = serialize::serialize_framed(&command)?.len() as u64;
+            if !batch.is_empty() && batch_size + record_size > max_batch_size {
+                self.write_batch(std::mem::take(&mut batch))?;
+                batch_size = 0;
+            }
+            batch_size += record_size;
+            batch.push(command);
+        }
+        self.write_batch(batch)?;
+        log::info!("Removed {} keys with the prefix '{}' from {}", removed_count, prefix, self.storage_dir.display());
+        Ok(removed_count)
+    }
+
     /// Removes key `key` from the storage.
     /// Returns `true` if the key existed.
     pub fn remove(&mut self, key: String) -> Result<bool> {
+        self.check_writable()?;
         let mut internal = match self.internal.lock() {
             Ok(guard) => guard,
             Err(poisoned) => poisoned.into_inner(),
         };
-        match self.index.remove(&key) {
+        if !self.remove_value(&mut internal, key)? {
+            return Ok(false);
+        }
+        self.commit(internal)?;
+        Ok(true)
+    }
+
+    /// Writes a remove record of an existing key, then notifies the watchers. Returns `false` for a missing key.
+    fn remove_value(&self, internal: &mut KvLogStorageInternal, key: String) -> Result<bool> {
+        match self.index().remove(&key) {
             Some(_) => {
-                self.write(&mut internal, Command::Remove { key: key })?;
+                self.write(internal, &Command::Remove { key: key.clone() })?;
+                self.watchers.notify(ChangeEvent::Remove { key });
                 Ok(true)
             },
             None => Ok(false),
@@ -501,19 +2151,223 @@ impl KvLogStorage {
     }
 
     /// Gets value with the key `key`. Returns `None` if the key doesn't exist in the storage.
+    /// Fails if the stored value is not a valid UTF-8 string.
     pub fn get(&self, key: String) -> Result<Option<String>> {
-        match self.index.get(&key) {
-            Some(position) => {
-                let value = Self::read_value(&self.storage_dir, &position)?;
+        match self.get_bytes(key)? {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets a binary value with the key `key`. Returns `None` if the key doesn't exist in the storage.
+    pub fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>> {
+        match self.index().get(&key).as_deref() {
+            Some(KvStorePosition::Inline { value, .. }) => Ok(Some(value.to_vec())),
+            Some(KvStorePosition::OnDisk { file_idx, file_offset, flags, size }) => {
+                let value = Self::read_value(&self.storage_dir, *file_idx, *file_offset, *flags, *size)?;
                 Ok(Some(value))
             },
+            Some(KvStorePosition::Collection { value, .. }) => {
+                Err(collections::collection_value_error(value.collection_type()))
+            },
             None => Ok(None),
         }
     }
 
+    /// Opens the binary value with the key `key` to be read in parts. Returns `None` if the key doesn't exist.
+    /// An uncompressed value is read from its log file by the ranges of the reads. The file is opened by the call,
+    /// so the value is read as it was even if the key is overwritten and its log file is compacted meanwhile.
+    pub fn open_value(&self, key: String) -> Result<Option<ValueReader>> {
+        let (file_idx, file_offset, size) = match self.index().get(&key).as_deref() {
+            Some(KvStorePosition::OnDisk { file_idx, file_offset, flags: 0, size }) => (*file_idx, *file_offset, *size),
+            Some(_) => return Ok(self.get_bytes(key)?.map(ValueReader::from_bytes)),
+            None => return Ok(None),
+        };
+        let mut file = OpenOptions::new().read(true).open(file_idx_to_path(&self.storage_dir, file_idx))?;
+        file.seek(io::SeekFrom::Start(file_offset))?;
+        Ok(Some(ValueReader { size: size as u64, reader: Box::new(io::Read::take(file, size as u64)) }))
+    }
+
+    /// Gets the binary values with the keys `keys`, `None` for the missing keys. The values are returned
+    /// in the order of the keys, but read grouped by the log files: each file is opened once
+    /// and its values are read in the order of their offsets.
+    pub fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut values = vec![None; keys.len()];
+        // Positions of the values on the disk by the log files: value offset, flags, size and the key position.
+        let mut file_positions = BTreeMap::<usize, Vec<(u64, u8, u32, usize)>>::new();
+        for (key_idx, key) in keys.iter().enumerate() {
+            match self.index().get(key).as_deref() {
+                Some(KvStorePosition::Inline { value, .. }) => values[key_idx] = Some(value.to_vec()),
+                Some(KvStorePosition::OnDisk { file_idx, file_offset, flags, size }) => {
+                    file_positions.entry(*file_idx).or_default().push((*file_offset, *flags, *size, key_idx));
+                },
+                Some(KvStorePosition::Collection { value, .. }) => {
+                    return Err(collections::collection_value_error(value.collection_type()));
+                },
+                None => {},
+            }
+        }
+
+        for (file_idx, mut positions) in file_positions {
+            positions.sort_unstable();
+            let file_path = file_idx_to_path(&self.storage_dir, file_idx);
+            let mut file = OpenOptions::new().read(true).open(file_path)?;
+            for (file_offset, flags, size, key_idx) in positions {
+                file.seek(io::SeekFrom::Start(file_offset))?;
+                let mut value = vec![0u8; size as usize];
+                io::Read::read_exact(&mut file, &mut value)?;
+                values[key_idx] = Some(decode_value(flags, value)?);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Returns a snapshot of the stored keys in arbitrary order.
+    pub fn keys(&self) -> Vec<String> {
+        self.index().iter().map(|entry| entry.key().clone()).collect()
+    }
+
+    /// Returns an iterator over the stored key/value pairs sorted by keys.
+    /// Fails on the values which are not valid UTF-8 strings, see `iter_bytes`.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(String, String)>> + use<> {
+        self.iter_bytes().map(|record| {
+            let (key, value) = record?;
+            Ok((key, String::from_utf8(value)?))
+        })
+    }
+
+    /// Returns an iterator over the stored key/binary value pairs sorted by keys, the collections are skipped.
+    /// The keys are snapshotted on the call and the values are read lazily, so the iterator
+    /// does not block the writes. A key removed meanwhile is skipped, a key overwritten meanwhile
+    /// is returned with the new value.
+    pub fn iter_bytes(&self) -> KvLogStorageIter {
+        let mut keys = self.keys();
+        keys.sort_unstable();
+        KvLogStorageIter { storage: self.clone(), keys: keys.into_iter() }
+    }
+
+    /// Returns the storage statistics. The garbage size is estimated as the size of the log files
+    /// not taken by the latest set records of the stored keys.
+    pub fn stats(&self) -> Result<models::StorageStats> {
+        let (active_file_idx, last_compaction, compaction_metrics) = {
+            let internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+            (internal.active_file_idx, internal.last_compaction, internal.compaction_metrics)
+        };
+
+        let mut segments_count = 0;
+        let mut disk_size = 0;
+        for file_idx in DEFAULT_FILE_IDX..active_file_idx + 1 {
+            match std::fs::metadata(file_idx_to_path(&self.storage_dir, file_idx)) {
+                Ok(metadata) => {
+                    segments_count += 1;
+                    disk_size += metadata.len();
+                },
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+                Err(err) => return Err(Box::new(err)),
+            }
+        }
+        let index = self.index();
+        let live_size: u64 = index.iter().map(|entry| entry.value().record_size(entry.key())).sum();
+
+        Ok(models::StorageStats {
+            keys_count: index.len() as u64,
+            segments_count,
+            disk_size,
+            live_size,
+            garbage_size: disk_size.saturating_sub(live_size),
+            last_compaction,
+            compacted_segments: compaction_metrics.compacted_segments,
+            reclaimed_size: compaction_metrics.reclaimed_size,
+            compaction_failures: compaction_metrics.failures,
+            compaction_time: compaction_metrics.time,
+        })
+    }
+
+    /// Subscribes to the changes of the keys matching `filter`. The events are sent in the order of the writes
+    /// once the records are written to the log, before the writes are acknowledged to the writers.
+    /// Resets and restores are sent to all the subscriptions. The collection mutations are not sent,
+    /// only the removal of an emptied collection.
+    pub fn subscribe(&self, filter: WatchFilter) -> Subscription {
+        WatchRegistry::subscribe(&self.watchers, filter)
+    }
+
+    /// Returns the storage of the namespace `name`, a separate set of keys kept in the `ns_<name>` subdirectory
+    /// with the same options. The namespace storage is opened and created on the first access and shares
+    /// the compaction threads, the group commit and the compaction scheduler of the storage.
+    /// Fails with `ERROR_CODE_SERVER_BUSY` once `KvLogStorageBuilder::max_namespaces` namespaces are opened.
+    /// Resets, compactions, stats and restores of a storage do not touch its namespaces.
+    pub fn namespace(&self, name: &str) -> Result<KvLogStorage> {
+        let path = namespace_path(&self.storage_dir, name)?;
+        let mut namespaces = self.namespaces.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(storage) = namespaces.get(name) {
+            return Ok(storage.clone());
+        }
+        if namespaces.len() >= self.options.max_namespaces {
+            return Err(Box::new(models::CommandError::new(
+                models::ERROR_CODE_SERVER_BUSY,
+                format!("Cannot open namespace {}, {} namespaces are opened already", name, namespaces.len()),
+            )));
+        }
+        log::info!("Opening namespace {} in {}", name, path.display());
+        let mut options = self.options.clone();
+        options.compaction_garbage_ratio = self.compaction_garbage_ratio();
+        let storage = Self::open_with_options(&path, options, Some(self))?;
+        if self.is_compaction_paused() {
+            storage.pause_compaction()?;
+        }
+        namespaces.insert(name.to_owned(), storage.clone());
+        Ok(storage)
+    }
+
+    /// Min share of the stale records in a rotated log file to rewrite it, see `KvLogStorageBuilder`.
+    pub fn compaction_garbage_ratio(&self) -> f64 {
+        f64::from_bits(self.compaction_garbage_ratio.load(std::sync::atomic::Ordering::Relaxed))
+    }
+
+    /// Changes the compaction garbage ratio of all the storage handles and the opened namespaces.
+    /// Applies to the compactions started from now on.
+    pub fn set_compaction_garbage_ratio(&self, ratio: f64) -> Result<()> {
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(Box::from(format!("Compaction garbage ratio must be from 0 to 1, got {}", ratio)));
+        }
+        self.compaction_garbage_ratio.store(ratio.to_bits(), std::sync::atomic::Ordering::Relaxed);
+        for storage in self.namespaces.lock().unwrap_or_else(|e| e.into_inner()).values() {
+            storage.set_compaction_garbage_ratio(ratio)?;
+        }
+        Ok(())
+    }
+
+    /// Sequence number of the last write, 0 if the storage doesn't record the sequence or is not written yet.
+    pub fn sequence(&self) -> u64 {
+        self.internal.lock().unwrap_or_else(|e| e.into_inner()).sequence
+    }
+
+    /// Estimated memory used by the index in bytes, including the inlined values.
+    pub fn index_memory_usage(&self) -> usize {
+        self.index().iter()
+            .map(|entry| {
+                let inlined_heap_size = match entry.value() {
+                    KvStorePosition::Inline { value, .. } if value.spilled() => value.capacity(),
+                    KvStorePosition::Collection { value, .. } => size_of::<Collection>() + value.encoded_size(),
+                    _ => 0,
+                };
+                size_of::<String>() + entry.key().capacity() + size_of::<KvStorePosition>() + inlined_heap_size
+            })
+            .sum()
+    }
+
     /// Removes all records in the storage.
+    /// The log files are marked obsolete first, so a reset interrupted by a crash is completed on open
+    /// and the records of the files left are not restored.
     pub fn reset(&mut self) -> Result<()> {
+        self.check_writable()?;
         let mut internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+        let active_file_idx = internal.active_file_idx;
+        internal.manifest.update(|segments| {
+            for file_idx in 1..active_file_idx + 1 {
+                segments.insert(file_idx, SegmentState::Obsolete);
+            }
+        })?;
         for file_idx in 1..internal.active_file_idx + 1 {
             let file_path = file_idx_to_path(&self.storage_dir, file_idx);
             log::info!("Removing log file {}", file_path.display());
@@ -525,9 +2379,461 @@ impl KvLogStorage {
                     return Err(Box::new(err));
                 }
             }
+            Self::remove_filter(&self.storage_dir, &self.filters, file_idx)?;
         }
+        internal.manifest.replace(&[])?;
         internal.active_file_idx = DEFAULT_FILE_IDX;
-        self.index.clear();
+        internal.generation += 1;
+        self.replace_index(dashmap::DashMap::new());
+        self.watchers.notify(ChangeEvent::Reset);
+        // Keep the last sequence number in the log, so the numbers keep increasing after a reopen.
+        if self.options.record_sequence {
+            self.append_records(&mut internal, &[])?;
+        }
+        Ok(())
+    }
+
+    /// Compacts all the sealed log files, i.e. all except the active one, regardless of the compaction
+    /// garbage ratio. Blocks until the compaction is completed. Writes are not blocked meanwhile.
+    /// The files being compacted by the background jobs at the moment are skipped.
+    /// Fails if the compaction is paused.
+    pub fn compact(&self) -> Result<()> {
+        self.check_writable()?;
+        let active_file_idx = self.internal.lock().unwrap_or_else(|e| e.into_inner()).active_file_idx;
+        log::info!("Compacting log files before idx={}", active_file_idx);
+        self.check_compaction_resumed()?;
+        for file_idx in DEFAULT_FILE_IDX..active_file_idx {
+            // The compaction may be paused meanwhile.
+            self.check_compaction_resumed()?;
+            // Fully compacted files are removed.
+            if !file_idx_to_path(&self.storage_dir, file_idx).exists() {
+                continue;
+            }
+            Self::compact_log_file_exclusive(
+                self.storage_dir.clone(),
+                self.internal.clone(),
+                self.index.clone(),
+                self.compacting_files.clone(),
+                self.filters.clone(),
+                file_idx,
+                0.0,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the active log file to the disk.
+    /// Waits for the writes in progress to complete.
+    pub fn flush(&self) -> Result<()> {
+        let internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+        let active_file_path = file_idx_to_path(&self.storage_dir, internal.active_file_idx);
+        if active_file_path.exists() && !self.options.read_only {
+            OpenOptions::new().append(true).open(&active_file_path)?.sync_all()?;
+        }
+        log::info!("Storage {} is flushed", self.storage_dir.display());
+        Ok(())
+    }
+
+    /// Waits for the queued and running compactions to complete and flushes the storage and its opened namespaces.
+    /// Called on shutdown, so the stopped process leaves no half-written compacted files behind.
+    pub fn close(&self) -> Result<()> {
+        self.wait_compactions();
+
+        let namespaces: Vec<KvLogStorage> = self.namespaces.lock().unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        for storage in namespaces {
+            storage.close()?;
+        }
+        self.flush()
+    }
+
+    /// Waits for the queued and running compaction jobs to complete.
+    fn wait_compactions(&self) {
+        // A compaction may be started by a write meanwhile, so the handles are taken until none are left.
+        loop {
+            let pending_compactions = std::mem::take(
+                &mut *self.pending_compactions.lock().unwrap_or_else(|e| e.into_inner())
+            );
+            if pending_compactions.is_empty() {
+                break;
+            }
+            log::info!("Waiting for {} compactions of {} to complete", pending_compactions.len(), self.storage_dir.display());
+            for (log_file_idx, handle) in pending_compactions {
+                Self::log_compaction_result(log_file_idx, handle.join());
+            }
+        }
+    }
+
+    /// Stops the background compaction of the storage and its opened namespaces, e.g. for a latency-critical
+    /// window or a filesystem snapshot. Waits for the queued and running compaction jobs to complete, so no
+    /// log file is rewritten once it returns. The log files rotated meanwhile are compacted on resume.
+    pub fn pause_compaction(&self) -> Result<()> {
+        self.paused_compactions.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(HashMap::new);
+        log::info!("Compaction of {} is paused", self.storage_dir.display());
+        self.wait_compactions();
+        for storage in self.namespaces.lock().unwrap_or_else(|e| e.into_inner()).values() {
+            storage.pause_compaction()?;
+        }
+        Ok(())
+    }
+
+    /// Resumes the compaction paused by `pause_compaction` and compacts the log files rotated meanwhile.
+    pub fn resume_compaction(&self) -> Result<()> {
+        let deferred = self.paused_compactions.lock().unwrap_or_else(|e| e.into_inner()).take();
+        log::info!("Compaction of {} is resumed", self.storage_dir.display());
+        let active_file_idx = self.internal.lock().unwrap_or_else(|e| e.into_inner()).active_file_idx;
+        let mut deferred: Vec<(usize, f64)> = deferred.unwrap_or_default().into_iter().collect();
+        deferred.sort_by_key(|(file_idx, _)| *file_idx);
+        for (file_idx, garbage_ratio) in deferred {
+            // The log files may be replaced by a reset or a restore meanwhile.
+            if file_idx < active_file_idx && file_idx_to_path(&self.storage_dir, file_idx).exists() {
+                self.run_compaction(file_idx, garbage_ratio);
+            }
+        }
+        for storage in self.namespaces.lock().unwrap_or_else(|e| e.into_inner()).values() {
+            storage.resume_compaction()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_compaction_paused(&self) -> bool {
+        self.paused_compactions.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+    }
+
+    fn check_compaction_resumed(&self) -> Result<()> {
+        if self.is_compaction_paused() {
+            return Err(Box::from(format!("Compaction of {} is paused", self.storage_dir.display())));
+        }
         Ok(())
     }
+
+    /// Returns the current map of the key positions.
+    fn index(&self) -> std::sync::Arc<dashmap::DashMap<String, KvStorePosition>> {
+        load_index(&self.index)
+    }
+
+    /// Replaces the whole index at once, so the readers never see a partially filled one.
+    fn replace_index(&self, index: dashmap::DashMap<String, KvStorePosition>) {
+        *self.index.write().unwrap_or_else(|e| e.into_inner()) = std::sync::Arc::new(index);
+    }
+
+    /// Fails with `ERROR_CODE_READ_ONLY` if the storage is opened by `open_read_only`.
+    fn check_writable(&self) -> Result<()> {
+        if self.options.read_only {
+            return Err(Box::new(models::CommandError::new(
+                models::ERROR_CODE_READ_ONLY,
+                format!("Storage {} is opened read-only", self.storage_dir.display()),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Backs up the storage segments to `backup_dir`.
+    /// Only segments created or rewritten since the previous backup in `backup_dir` are copied.
+    /// Writes and compaction swaps are blocked while the backup is running.
+    pub fn backup(&self, backup_dir: &Path) -> Result<backup::BackupManifest> {
+        let _internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+        backup::create_backup(&self.storage_dir, backup_dir)
+    }
+
+    /// Replaces the storage in `target_dir` with the latest backup from `backup_dir`.
+    /// The backup is restored to a staging directory and validated against the manifest checksums
+    /// and by building the index first, so a damaged backup leaves the existing storage untouched.
+    /// Only the storage files of `target_dir` are replaced. Fails if the storage is open.
+    pub fn restore(backup_dir: &Path, target_dir: &Path) -> Result<backup::BackupManifest> {
+        Self::restore_log(backup_dir, target_dir, None)
+    }
+
+    /// Same as `restore`, but replays the restored log only up to the recovery point.
+    /// The storage must be written with `KvLogStorageBuilder::record_sequence` to locate the point,
+    /// fails if the point is within the log files compacted before the backup.
+    /// The sequence numbers continue from the last replayed write.
+    pub fn restore_until(
+        backup_dir: &Path, target_dir: &Path, point: RecoveryPoint,
+    ) -> Result<backup::BackupManifest> {
+        Self::restore_log(backup_dir, target_dir, Some(point))
+    }
+
+    fn restore_log(
+        backup_dir: &Path, target_dir: &Path, point: Option<RecoveryPoint>,
+    ) -> Result<backup::BackupManifest> {
+        std::fs::create_dir_all(target_dir)?;
+        let _lock_file = lock_storage_dir(target_dir)?;
+        let staging_dir = target_dir.join("_restore_staging");
+        if staging_dir.exists() {
+            log::warn!(
+                "Restore staging directory {} already exists. It might be a result of a previous failed restore.",
+                staging_dir.display(),
+            );
+            std::fs::remove_dir_all(&staging_dir)?;
+        }
+
+        let stage = || -> Result<(backup::BackupManifest, Vec<usize>)> {
+            let manifest = backup::restore_backup(backup_dir, &staging_dir)?;
+            let mut file_idxs: Vec<usize> = manifest.segments.iter().map(|s| s.file_idx).collect();
+            if let Some(point) = point {
+                file_idxs = Self::truncate_log(&staging_dir, &file_idxs, point)?;
+            }
+            Self::restore_index(&staging_dir, &file_idxs, &HashMap::new())?;
+            Ok((manifest, file_idxs))
+        };
+        let (manifest, file_idxs) = match stage() {
+            Ok(staged) => staged,
+            Err(err) => {
+                if staging_dir.exists() {
+                    std::fs::remove_dir_all(&staging_dir)?;
+                }
+                return Err(Box::from(format!("Cannot restore from {}: {}", backup_dir.display(), err)));
+            },
+        };
+
+        // Remove the current log files and their filters, the rest of the directory is kept.
+        for entry in std::fs::read_dir(target_dir)? {
+            let path = entry?.path();
+            let is_storage_file = match path_to_idx(&path) {
+                Some(file_idx) => {
+                    path == file_idx_to_path(target_dir, file_idx) || path == bloom::filter_path(target_dir, file_idx)
+                },
+                None => false,
+            };
+            if is_storage_file {
+                log::info!("Removing {}", path.display());
+                remove_file(&path)?;
+            }
+        }
+        for file_idx in &file_idxs {
+            rename(file_idx_to_path(&staging_dir, *file_idx), file_idx_to_path(target_dir, *file_idx))?;
+        }
+        std::fs::remove_dir_all(&staging_dir)?;
+        SegmentManifest::read(target_dir)?.replace(&file_idxs)?;
+
+        log::info!("Storage {} is restored from {}", target_dir.display(), backup_dir.display());
+        Ok(manifest)
+    }
+
+    /// Get staging directory of a prepared restore.
+    fn restore_staging_dir(&self, token: &str) -> Result<PathBuf> {
+        if token.is_empty() || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Box::from(format!("Invalid restore token {}", token)));
+        }
+        Ok(self.storage_dir.join(format!("_restore_{}", token)))
+    }
+
+    /// First phase of a restore. Restores the latest backup from `backup_dir` into a staging directory
+    /// and validates it by building the index of the restored data. The live data is not changed.
+    /// Returns a token to commit or abort the restore.
+    pub fn prepare_restore(&self, backup_dir: &Path) -> Result<String> {
+        self.check_writable()?;
+        let token = format!("{:016x}", rand::random::<u64>());
+        let staging_dir = self.restore_staging_dir(&token)?;
+        log::info!("Preparing restore {} from {} in {}", token, backup_dir.display(), staging_dir.display());
+
+        let prepare = || -> Result<PreparedRestore> {
+            let manifest = backup::restore_backup(backup_dir, &staging_dir)?;
+            let file_idxs: Vec<usize> = manifest.segments.iter().map(|s| s.file_idx).collect();
+            let index = Self::restore_index(&staging_dir, &file_idxs, &HashMap::new())?;
+            Ok(PreparedRestore { staging_dir: staging_dir.clone(), file_idxs, index })
+        };
+        match prepare() {
+            Ok(prepared) => {
+                self.prepared_restores.lock().unwrap_or_else(|e| e.into_inner()).insert(token.clone(), prepared);
+                log::info!("Restore {} is prepared", token);
+                Ok(token)
+            },
+            Err(err) => {
+                if staging_dir.exists() {
+                    std::fs::remove_dir_all(&staging_dir)?;
+                }
+                Err(Box::from(format!("Cannot prepare restore from {}: {}", backup_dir.display(), err)))
+            },
+        }
+    }
+
+    /// Second phase of a restore. Atomically switches the storage to the data prepared with `token`.
+    /// Writes are blocked only while the log files are swapped, the index is already built on prepare.
+    pub fn commit_restore(&mut self, token: &str) -> Result<()> {
+        self.check_writable()?;
+        let prepared = self.prepared_restores.lock().unwrap_or_else(|e| e.into_inner()).remove(token)
+            .ok_or_else(|| models::CommandError::new(models::ERROR_CODE_NOT_FOUND, format!("Unknown restore token {}", token)))?;
+
+        let mut internal = self.internal.lock().unwrap_or_else(|e| e.into_inner());
+        log::info!("Committing restore {}", token);
+
+        // Retire the current log files first, so they can be put back if the switch fails.
+        let retired_dir = self.storage_dir.join(format!("_retired_{}", token));
+        std::fs::create_dir_all(&retired_dir)?;
+        let mut retired_idxs = Vec::new();
+        for file_idx in 1..internal.active_file_idx + 1 {
+            let file_path = file_idx_to_path(&self.storage_dir, file_idx);
+            if file_path.exists() {
+                rename(&file_path, file_idx_to_path(&retired_dir, file_idx))?;
+                retired_idxs.push(file_idx);
+            }
+        }
+
+        let mut switched_idxs = Vec::new();
+        for file_idx in &prepared.file_idxs {
+            if let Err(err) = rename(
+                file_idx_to_path(&prepared.staging_dir, *file_idx), file_idx_to_path(&self.storage_dir, *file_idx),
+            ) {
+                log::error!("Cannot switch to restored log files, rolling back: {}", err);
+                for switched_idx in switched_idxs {
+                    rename(
+                        file_idx_to_path(&self.storage_dir, switched_idx),
+                        file_idx_to_path(&prepared.staging_dir, switched_idx),
+                    )?;
+                }
+                for retired_idx in retired_idxs {
+                    rename(file_idx_to_path(&retired_dir, retired_idx), file_idx_to_path(&self.storage_dir, retired_idx))?;
+                }
+                // Keep the prepared data to retry the commit or abort it.
+                self.prepared_restores.lock().unwrap_or_else(|e| e.into_inner()).insert(token.to_owned(), prepared);
+                return Err(Box::new(err));
+            }
+            switched_idxs.push(*file_idx);
+        }
+
+        self.replace_index(prepared.index);
+        // The filters of the retired files are dropped, the restored files get theirs on compaction or reopen.
+        for file_idx in retired_idxs.iter().chain(prepared.file_idxs.iter()) {
+            Self::remove_filter(&self.storage_dir, &self.filters, *file_idx)?;
+        }
+        internal.manifest.replace(&prepared.file_idxs)?;
+        internal.active_file_idx = *prepared.file_idxs.iter().max().unwrap_or(&DEFAULT_FILE_IDX);
+        internal.generation += 1;
+        self.watchers.notify(ChangeEvent::Reset);
+        if self.options.record_sequence {
+            self.append_records(&mut internal, &[])?;
+        }
+        drop(internal);
+
+        std::fs::remove_dir_all(&retired_dir)?;
+        std::fs::remove_dir_all(&prepared.staging_dir)?;
+        log::info!("Restore {} is committed", token);
+        Ok(())
+    }
+
+    /// Aborts a prepared restore and removes the staged data.
+    /// Unknown tokens are accepted to clean up the restores prepared before a storage restart.
+    pub fn abort_restore(&self, token: &str) -> Result<()> {
+        let staging_dir = self.restore_staging_dir(token)?;
+        self.prepared_restores.lock().unwrap_or_else(|e| e.into_inner()).remove(token);
+        if staging_dir.exists() {
+            std::fs::remove_dir_all(&staging_dir)?;
+        }
+        log::info!("Restore {} is aborted", token);
+        Ok(())
+    }
+}
+
+impl KvStorage for KvLogStorage {
+    fn set_bytes(&mut self, key: String, value: Vec<u8>) -> Result<()> {
+        KvLogStorage::set_bytes(self, key, value)
+    }
+
+    fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>> {
+        KvLogStorage::get_bytes(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<bool> {
+        KvLogStorage::remove(self, key)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        KvLogStorage::reset(self)
+    }
+
+    fn reset_prefix(&mut self, prefix: String) -> Result<u64> {
+        KvLogStorage::reset_prefix(self, &prefix)
+    }
+
+    fn compact(&self) -> Result<()> {
+        KvLogStorage::compact(self)
+    }
+
+    fn stats(&self) -> Result<models::StorageStats> {
+        KvLogStorage::stats(self)
+    }
+
+    fn flush(&self) -> Result<()> {
+        KvLogStorage::flush(self)
+    }
+
+    fn prepare_restore(&self, backup_dir: &Path) -> Result<String> {
+        KvLogStorage::prepare_restore(self, backup_dir)
+    }
+
+    fn commit_restore(&mut self, token: &str) -> Result<()> {
+        KvLogStorage::commit_restore(self, token)
+    }
+
+    fn abort_restore(&self, token: &str) -> Result<()> {
+        KvLogStorage::abort_restore(self, token)
+    }
+
+    fn namespace(&self, name: &str) -> Result<Box<dyn KvStorage>> {
+        Ok(Box::new(KvLogStorage::namespace(self, name)?))
+    }
+
+    fn close(&self) -> Result<()> {
+        KvLogStorage::close(self)
+    }
+
+    fn set_compaction_garbage_ratio(&self, ratio: f64) -> Result<()> {
+        KvLogStorage::set_compaction_garbage_ratio(self, ratio)
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        KvLogStorage::get_many(self, keys)
+    }
+
+    fn open_value(&self, key: String) -> Result<Option<ValueReader>> {
+        KvLogStorage::open_value(self, key)
+    }
+
+    fn append(&mut self, key: String, suffix: Vec<u8>) -> Result<u64> {
+        KvLogStorage::append(self, key, suffix)
+    }
+
+    fn get_set(&mut self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        KvLogStorage::get_set(self, key, value)
+    }
+
+    fn set_nx(&mut self, key: String, value: Vec<u8>) -> Result<bool> {
+        KvLogStorage::set_nx(self, key, value)
+    }
+
+    fn update_collection(&mut self, mutation: Command) -> Result<MutationResult> {
+        KvLogStorage::update_collection(self, mutation)
+    }
+
+    fn read_collection(&self, key: String, collection_type: CollectionType, read: &mut CollectionRead) -> Result<()> {
+        KvLogStorage::read_collection(self, key, collection_type, read)
+    }
+
+    fn write_batch(&mut self, commands: Vec<Command>) -> Result<()> {
+        KvLogStorage::write_batch(self, commands)
+    }
+
+    fn max_batch_size(&self) -> Option<u64> {
+        Some(KvLogStorage::max_batch_size(self))
+    }
+
+    fn pause_compaction(&self) -> Result<()> {
+        KvLogStorage::pause_compaction(self)
+    }
+
+    fn resume_compaction(&self) -> Result<()> {
+        KvLogStorage::resume_compaction(self)
+    }
+
+    fn is_compaction_paused(&self) -> bool {
+        KvLogStorage::is_compaction_paused(self)
+    }
+
+    fn clone_box(&self) -> Box<dyn KvStorage> {
+        Box::new(self.clone())
+    }
 }
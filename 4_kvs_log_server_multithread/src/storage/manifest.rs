@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::models::Result;
+
+pub(crate) const MANIFEST_FILE_NAME: &str = "MANIFEST";
+
+/// State of a log file recorded in the segment manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentState {
+    /// The log file receiving the writes.
+    Active,
+    /// A complete log file, only read and compacted.
+    Sealed,
+    /// The log file is being rewritten by compaction to a temporary file.
+    Compacting,
+    /// All records of the log file are stale, the file is being removed.
+    Obsolete,
+}
+
+impl SegmentState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SegmentState::Active => "active",
+            SegmentState::Sealed => "sealed",
+            SegmentState::Compacting => "compacting",
+            SegmentState::Obsolete => "obsolete",
+        }
+    }
+
+    fn parse(value: &str) -> Result<SegmentState> {
+        match value {
+            "active" => Ok(SegmentState::Active),
+            "sealed" => Ok(SegmentState::Sealed),
+            "compacting" => Ok(SegmentState::Compacting),
+            "obsolete" => Ok(SegmentState::Obsolete),
+            _ => Err(Box::from(format!("Unknown segment state {}", value))),
+        }
+    }
+}
+
+/// Manifest of the log files of a storage directory and their states. Each change is written to the disk
+/// before the log files are changed, so on open the storage completes or rolls back the compactions
+/// and resets interrupted by a crash. The log files missing in the manifest are treated as live ones.
+#[derive(Clone)]
+pub struct SegmentManifest {
+    path: PathBuf,
+    segments: BTreeMap<usize, SegmentState>,
+}
+
+impl SegmentManifest {
+    /// Reads the manifest of the storage directory. The manifest is empty if the file doesn't exist yet.
+    pub fn read(storage_dir: &Path) -> Result<SegmentManifest> {
+        let path = storage_dir.join(MANIFEST_FILE_NAME);
+        let mut segments = BTreeMap::new();
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            for line in reader.lines() {
+                let line = line?;
+                if line.is_empty() {
+                    continue;
+                }
+                let (file_idx, state) = line.split_once(' ')
+                    .ok_or_else(|| format!("Invalid segment manifest record: {}", line))?;
+                segments.insert(file_idx.parse::<usize>()?, SegmentState::parse(state)?);
+            }
+        }
+        Ok(SegmentManifest { path, segments })
+    }
+
+    /// Log files recorded in the manifest by their indexes.
+    pub fn segments(&self) -> &BTreeMap<usize, SegmentState> {
+        &self.segments
+    }
+
+    /// Returns the state of the log file, if it is recorded.
+    pub fn state(&self, file_idx: usize) -> Option<SegmentState> {
+        self.segments.get(&file_idx).copied()
+    }
+
+    /// Records the state of the log file and writes the manifest.
+    pub fn set_state(&mut self, file_idx: usize, state: SegmentState) -> Result<()> {
+        self.update(|segments| { segments.insert(file_idx, state); })
+    }
+
+    /// Removes the log file from the manifest and writes the manifest.
+    pub fn remove(&mut self, file_idx: usize) -> Result<()> {
+        self.update(|segments| { segments.remove(&file_idx); })
+    }
+
+    /// Replaces the recorded log files with `file_idxs`, all sealed except the active last one, and writes the manifest.
+    pub fn replace(&mut self, file_idxs: &[usize]) -> Result<()> {
+        let active_file_idx = file_idxs.iter().max().copied();
+        self.update(|segments| {
+            segments.clear();
+            for file_idx in file_idxs {
+                let state = if Some(*file_idx) == active_file_idx { SegmentState::Active } else { SegmentState::Sealed };
+                segments.insert(*file_idx, state);
+            }
+        })
+    }
+
+    /// Applies a change to the recorded log files and writes the manifest.
+    pub fn update<F: FnOnce(&mut BTreeMap<usize, SegmentState>)>(&mut self, change: F) -> Result<()> {
+        change(&mut self.segments);
+        self.write()
+    }
+
+    /// Writes the manifest to a temporary file and renames it, then syncs the directory,
+    /// so the manifest is never partially written and the rename survives a crash.
+    fn write(&self) -> Result<()> {
+        let tmp_path = self.path.with_file_name(format!("_tmp_{}", MANIFEST_FILE_NAME));
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        for (file_idx, state) in &self.segments {
+            writeln!(file, "{} {}", file_idx, state.as_str())?;
+        }
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp_path, &self.path)?;
+        if let Some(storage_dir) = self.path.parent() {
+            File::open(storage_dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
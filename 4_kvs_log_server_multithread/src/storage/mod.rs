@@ -1,3 +1,27 @@
-pub use kv_log::KvLogStorage;
+pub use base::{CollectionRead, KvStorage, ValueReader};
+pub use collections::{Collection, MutationResult};
+pub use kv_log::{CompactionPolicy, FsyncPolicy, KvLogStorage, KvLogStorageBuilder, KvLogStorageIter, RecoveryPoint};
+pub use backup::{BackupManifest, restore_backup};
+pub use manifest::{SegmentManifest, SegmentState};
+pub use bloom::BloomFilter;
+pub use watch::{ChangeEvent, Subscription, WatchFilter};
+pub use cdc::{CdcEvent, CdcReader};
+pub use sharded::ShardedKvStorage;
+pub use faulty::FaultyStorage;
+pub use self::sled::SledStorage;
 
+pub mod base;
 pub mod kv_log;
+pub mod backup;
+pub mod manifest;
+pub mod bloom;
+pub mod watch;
+pub mod cdc;
+pub mod sharded;
+pub mod faulty;
+pub mod sled;
+pub mod collections;
+#[cfg(feature = "s3")]
+pub mod s3;
+mod group_commit;
+mod compaction_scheduler;
@@ -0,0 +1,394 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log;
+
+use crate::models::Result;
+use crate::storage::backup::{self, BackupManifest, SegmentRecord};
+use crate::storage::kv_log::{file_idx_to_path, path_to_idx};
+use crate::storage::manifest::{SegmentManifest, SegmentState};
+
+/// Default size of the multipart upload parts, segments up to this size are uploaded with a single request.
+pub const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// S3-compatible bucket the backups are uploaded to. The objects are addressed in the path style,
+/// `<endpoint>/<bucket>/<prefix>/<object>`, which is supported by AWS S3 and the self-hosted object storages.
+#[derive(Clone, Debug)]
+pub struct S3Target {
+    /// Base URL of the object storage, e.g. `https://s3.eu-west-1.amazonaws.com` or `http://127.0.0.1:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    /// Key prefix of the backup objects, empty to keep the backup at the bucket root.
+    pub prefix: String,
+    /// Region of the request signatures.
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Size of the multipart upload parts in bytes. The object storages usually require at least 5 MiB.
+    pub part_size: usize,
+}
+
+impl S3Target {
+    /// Creates a target of the bucket at the endpoint with the `us-east-1` region and the default part size.
+    pub fn new(endpoint: String, bucket: String, access_key: String, secret_key: String) -> S3Target {
+        S3Target {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            bucket,
+            prefix: String::new(),
+            region: "us-east-1".to_owned(),
+            access_key,
+            secret_key,
+            part_size: DEFAULT_PART_SIZE,
+        }
+    }
+
+    fn object_key(&self, name: &str) -> String {
+        match self.prefix.trim_matches('/') {
+            "" => name.to_owned(),
+            prefix => format!("{}/{}", prefix, name),
+        }
+    }
+
+    fn segment_key(&self, generation: u64, file_idx: usize) -> String {
+        let file_name = file_idx_to_path(Path::new(""), file_idx);
+        self.object_key(&format!("{}/{}", backup::generation_name(generation), file_name.display()))
+    }
+}
+
+/// Blocking client of the S3 object API signing the requests with AWS Signature Version 4.
+struct S3Client<'a> {
+    target: &'a S3Target,
+    agent: ureq::Agent,
+    /// Scheme and authority of the endpoint.
+    origin: String,
+    host: String,
+    base_path: String,
+}
+
+impl<'a> S3Client<'a> {
+    fn new(target: &'a S3Target) -> Result<S3Client<'a>> {
+        let (scheme, authority) = match target.endpoint.split_once("://") {
+            Some((scheme, rest)) if scheme == "http" || scheme == "https" => (scheme, rest),
+            _ => return Err(Box::from(format!("Invalid S3 endpoint {}, expected an http(s) URL", target.endpoint))),
+        };
+        let (host, base_path) = match authority.find('/') {
+            Some(pos) => (&authority[..pos], &authority[pos..]),
+            None => (authority, ""),
+        };
+        Ok(S3Client {
+            target,
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            origin: format!("{}://{}", scheme, host),
+            host: host.to_owned(),
+            base_path: base_path.trim_end_matches('/').to_owned(),
+        })
+    }
+
+    /// Sends a request to an object of the bucket.
+    fn send_signed(
+        &self, method: &str, key: &str, query: &[(&str, &str)], body: &[u8],
+    ) -> Result<std::result::Result<ureq::Response, ureq::Error>> {
+        let path = format!("{}/{}/{}", self.base_path, uri_encode(&self.target.bucket, false), uri_encode(key, true));
+        let mut query: Vec<String> = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", uri_encode(name, false), uri_encode(value, false)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+
+        let amz_date = amz_date(SystemTime::now())?;
+        let payload_hash = sha256_hex(body);
+        let headers = [
+            ("host", self.host.as_str()), ("x-amz-content-sha256", payload_hash.as_str()), ("x-amz-date", &amz_date),
+        ];
+        let authorization = signature_v4(self.target, method, &path, &query, &headers, &payload_hash, &amz_date);
+
+        let url = match query.as_str() {
+            "" => format!("{}{}", self.origin, path),
+            query => format!("{}{}?{}", self.origin, path, query),
+        };
+        let mut request = self.agent.request(method, &url).set("authorization", &authorization);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        Ok(request.send_bytes(body))
+    }
+
+    /// Sends a request to an object of the bucket, failing on the error statuses with the error response.
+    fn send(&self, method: &str, key: &str, query: &[(&str, &str)], body: &[u8]) -> Result<ureq::Response> {
+        self.send_signed(method, key, query, body)?.map_err(|err| request_error(method, key, err))
+    }
+
+    fn put_object(&self, key: &str, body: &[u8]) -> Result<()> {
+        self.send("PUT", key, &[], body)?;
+        Ok(())
+    }
+
+    /// Uploads `size` bytes of the reader with the multipart upload in parts of the target part size
+    /// and returns the checksum of the uploaded content. A failed upload is aborted,
+    /// so the object storage doesn't keep its parts.
+    fn put_multipart(&self, key: &str, reader: &mut impl Read, size: u64) -> Result<u64> {
+        let response = self.send("POST", key, &[("uploads", "")], &[])?;
+        let upload_id = xml_value(&response.into_string()?, "UploadId")
+            .ok_or("Multipart upload response has no upload id")?;
+        log::debug!("Multipart upload {} of {} is created", upload_id, key);
+
+        let result = self.upload_parts(key, &upload_id, reader, size).and_then(|(parts, checksum)| {
+            let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+            let response = self.send("POST", key, &[("uploadId", upload_id.as_str())], body.as_bytes())?;
+            // The completion may fail after the 200 status, the error is reported in the body then.
+            match xml_value(&response.into_string()?, "Code") {
+                Some(code) => Err(Box::from(format!("Multipart upload of {} is not completed: {}", key, code))),
+                None => Ok(checksum),
+            }
+        });
+        if result.is_err() {
+            let abort_result = self.send("DELETE", key, &[("uploadId", upload_id.as_str())], &[]);
+            if let Err(err) = abort_result {
+                log::warn!("Cannot abort multipart upload {} of {}: {}", upload_id, key, err);
+            }
+        }
+        result
+    }
+
+    /// Uploads the parts of a multipart upload. Returns the part list of the completion request
+    /// and the checksum of the uploaded content.
+    fn upload_parts(&self, key: &str, upload_id: &str, reader: &mut impl Read, size: u64) -> Result<(String, u64)> {
+        let mut parts = String::new();
+        let mut checksum = backup::CHECKSUM_SEED;
+        let mut remaining = size;
+        let mut part_number = 1;
+        while remaining > 0 {
+            let mut part = vec![0u8; remaining.min(self.target.part_size as u64) as usize];
+            reader.read_exact(&mut part)?;
+            checksum = backup::update_checksum(checksum, &part);
+            let part_number_str = part_number.to_string();
+            let query = [("partNumber", part_number_str.as_str()), ("uploadId", upload_id)];
+            let response = self.send("PUT", key, &query, &part)?;
+            let etag = response.header("etag").ok_or("Uploaded part has no ETag")?.to_owned();
+            parts.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", part_number, etag));
+            remaining -= part.len() as u64;
+            part_number += 1;
+        }
+        Ok((parts, checksum))
+    }
+
+    /// Returns the content reader of an object, `None` if the object is not found.
+    fn get_object(&self, key: &str) -> Result<Option<Box<dyn Read + Send + Sync>>> {
+        match self.send_signed("GET", key, &[], &[])? {
+            Ok(response) => Ok(Some(response.into_reader())),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(err) => Err(request_error("GET", key, err)),
+        }
+    }
+
+    fn read_manifest(&self) -> Result<Option<BackupManifest>> {
+        let key = self.target.object_key(backup::MANIFEST_FILE_NAME);
+        let mut reader = match self.get_object(&key)? {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        let lines: Vec<String> = content.lines().map(|line| line.to_owned()).collect();
+        Ok(Some(BackupManifest::parse(&lines, &key)?))
+    }
+}
+
+fn request_error(method: &str, key: &str, err: ureq::Error) -> Box<dyn std::error::Error> {
+    match err {
+        ureq::Error::Status(status, response) => {
+            let message = response.into_string().unwrap_or_default();
+            Box::from(format!("S3 {} {} failed with status {}: {}", method, key, status, message))
+        },
+        err => Box::new(err),
+    }
+}
+
+/// Lists the sealed segments of the storage sorted by file index: the segments recorded as sealed or compacting
+/// in the segment manifest and the unrecorded ones except the last, which is the active one of the older storages.
+fn sealed_segments(storage_dir: &Path) -> Result<Vec<usize>> {
+    let manifest = SegmentManifest::read(storage_dir)?;
+    let mut file_idxs = Vec::new();
+    for entry in fs::read_dir(storage_dir)? {
+        let path = entry?.path();
+        match path_to_idx(&path) {
+            // Skip temporary compaction files sharing the segment index.
+            Some(file_idx) if path == file_idx_to_path(storage_dir, file_idx) => file_idxs.push(file_idx),
+            _ => {},
+        }
+    }
+    file_idxs.sort();
+    let last_idx = file_idxs.last().copied();
+    file_idxs.retain(|file_idx| match manifest.state(*file_idx) {
+        Some(state) => matches!(state, SegmentState::Sealed | SegmentState::Compacting),
+        None => Some(*file_idx) != last_idx,
+    });
+    Ok(file_idxs)
+}
+
+/// Uploads the sealed segments of the storage in `storage_dir` to the S3 target.
+/// Like `KvLogStorage::backup`, only the segments created or rewritten since the previous backup are uploaded
+/// to a new generation, and the manifest object is replaced last, so an interrupted upload keeps the previous backup.
+/// The active log file is not uploaded, so the backup doesn't block the writes of a running server.
+pub fn upload_backup(storage_dir: &Path, target: &S3Target) -> Result<BackupManifest> {
+    let client = S3Client::new(target)?;
+    let previous = client.read_manifest()?;
+    let generation = previous.as_ref().map(|m| m.generation + 1).unwrap_or(1);
+
+    let mut segments = Vec::new();
+    for file_idx in sealed_segments(storage_dir)? {
+        let path = file_idx_to_path(storage_dir, file_idx);
+        // The fingerprint is taken from the opened file, which keeps its content if compaction replaces it.
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(Box::new(err)),
+        };
+        let metadata = file.metadata()?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+        let mut segment = SegmentRecord { file_idx, generation, size: metadata.len(), modified, checksum: None };
+
+        let backed_up = previous.as_ref().and_then(|m| m.segments.iter().find(|s| s.has_same_content(&segment)));
+        match backed_up {
+            Some(backed_up_segment) => {
+                segment.generation = backed_up_segment.generation;
+                segment.checksum = backed_up_segment.checksum;
+            },
+            None => {
+                let key = target.segment_key(generation, file_idx);
+                log::info!("Uploading segment {} to {}", path.display(), key);
+                let checksum = if segment.size > target.part_size as u64 {
+                    client.put_multipart(&key, &mut file, segment.size)?
+                } else {
+                    let mut content = Vec::with_capacity(segment.size as usize);
+                    (&mut file).take(segment.size).read_to_end(&mut content)?;
+                    client.put_object(&key, &content)?;
+                    backup::update_checksum(backup::CHECKSUM_SEED, &content)
+                };
+                segment.checksum = Some(checksum);
+            },
+        }
+        segments.push(segment);
+    }
+
+    let manifest = BackupManifest { generation, segments };
+    client.put_object(&target.object_key(backup::MANIFEST_FILE_NAME), manifest.format().as_bytes())?;
+    log::info!(
+        "Backup generation {} uploaded: {}/{} segments",
+        generation, manifest.copied_segments().len(), manifest.segments.len(),
+    );
+    Ok(manifest)
+}
+
+/// Downloads the latest backup from the S3 target into an empty local backup directory,
+/// which is then restored with `KvLogStorage::restore` validating the segment checksums.
+pub fn download_backup(target: &S3Target, backup_dir: &Path) -> Result<BackupManifest> {
+    let client = S3Client::new(target)?;
+    let manifest = client.read_manifest()?
+        .ok_or_else(|| format!("No backups found in {}/{}", target.endpoint, target.object_key("")))?;
+
+    for segment in &manifest.segments {
+        let key = target.segment_key(segment.generation, segment.file_idx);
+        let generation_dir = backup_dir.join(backup::generation_name(segment.generation));
+        fs::create_dir_all(&generation_dir)?;
+        let path = file_idx_to_path(&generation_dir, segment.file_idx);
+        log::info!("Downloading segment {} to {}", key, path.display());
+        let mut reader = client.get_object(&key)?.ok_or_else(|| format!("Backup segment {} is missing", key))?;
+        io::copy(&mut reader, &mut File::create(&path)?)?;
+    }
+    manifest.write(backup_dir)?;
+    Ok(manifest)
+}
+
+/// Builds the `Authorization` header of a request signed with AWS Signature Version 4.
+/// `headers` are the signed headers sorted by their lowercase names.
+fn signature_v4(
+    target: &S3Target,
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &[(&str, &str)],
+    payload_hash: &str,
+    amz_date: &str,
+) -> String {
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}", method, path, query, canonical_headers, signed_headers, payload_hash,
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, target.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, sha256_hex(canonical_request.as_bytes()),
+    );
+
+    let mut signing_key = format!("AWS4{}", target.secret_key).into_bytes();
+    for part in [date, target.region.as_str(), "s3", "aws4_request"] {
+        signing_key = hmac_sha256(&signing_key, part.as_bytes());
+    }
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        target.access_key, scope, signed_headers, signature,
+    )
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex(ring::digest::digest(&ring::digest::SHA256, data).as_ref())
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    ring::hmac::sign(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key), data).as_ref().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encodes all the characters except the unreserved ones, and `/` if `keep_slash` is set.
+fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Returns the text of the first `tag` element of an XML response.
+fn xml_value(content: &str, tag: &str) -> Option<String> {
+    let start = content.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + content[start..].find(&format!("</{}>", tag))?;
+    Some(content[start..end].to_owned())
+}
+
+/// Formats the time as the `x-amz-date` header, `YYYYMMDDTHHMMSSZ` in UTC.
+fn amz_date(time: SystemTime) -> Result<String> {
+    let secs = time.duration_since(UNIX_EPOCH)?.as_secs();
+    // Civil date of the days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let days = secs / 86400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    Ok(format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year, month, day, secs % 86400 / 3600, secs % 3600 / 60, secs % 60,
+    ))
+}
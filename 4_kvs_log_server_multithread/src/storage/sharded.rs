@@ -0,0 +1,392 @@
+use std::path::{Path, PathBuf};
+
+use crate::models::{CollectionType, Command, Result, StorageStats};
+use crate::storage::base::{check_engine, CollectionRead, KvStorage, ValueReader};
+use crate::storage::collections::MutationResult;
+use crate::storage::bloom::fnv1a;
+use crate::storage::kv_log::{has_log_files, KvLogStorage, KvLogStorageBuilder};
+
+/// File keeping the number of shards, so the storage is never reopened with a different key distribution.
+pub(crate) const SHARDS_FILE_NAME: &str = "shards";
+
+fn shard_path(storage_path: &Path, shard_idx: usize) -> PathBuf {
+    storage_path.join(format!("shard_{}", shard_idx))
+}
+
+/// Key-value storage partitioning the keys by hash across independent log storages,
+/// each in its own subdirectory with its own active log file and compaction.
+/// The writes to different shards are not serialized with each other.
+#[derive(Clone)]
+pub struct ShardedKvStorage {
+    shards: Vec<KvLogStorage>,
+}
+
+impl ShardedKvStorage {
+    /// Opens a directory as a storage of `shards_count` shards, each opened with the `builder` options.
+    /// Fails if the storage was created with a different number of shards or as an unsharded storage.
+    pub fn open(path: &Path, shards_count: usize, builder: KvLogStorageBuilder) -> Result<ShardedKvStorage> {
+        if shards_count == 0 {
+            return Err(Box::from("Shards count must be positive"));
+        }
+        check_engine(path, "kvs")?;
+        let shards_file_path = path.join(SHARDS_FILE_NAME);
+        match std::fs::read_to_string(&shards_file_path) {
+            Ok(content) => {
+                let stored_count: usize = content.trim().parse()
+                    .map_err(|err| format!("Invalid shards file {}: {}", shards_file_path.display(), err))?;
+                if stored_count != shards_count {
+                    return Err(Box::from(format!(
+                        "Storage {} has {} shards, cannot open it with {}", path.display(), stored_count, shards_count,
+                    )));
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                // The keys of an unsharded storage in the directory would be hidden by the shards.
+                if has_log_files(path)? {
+                    return Err(Box::from(format!(
+                        "Storage {} has an unsharded layout, cannot open it with {} shards",
+                        path.display(), shards_count,
+                    )));
+                }
+                std::fs::create_dir_all(path)?;
+                std::fs::write(&shards_file_path, shards_count.to_string())?;
+            },
+            Err(err) => return Err(Box::new(err)),
+        }
+
+        let mut shards = Vec::with_capacity(shards_count);
+        for shard_idx in 0..shards_count {
+            let shard_path = shard_path(path, shard_idx);
+            std::fs::create_dir_all(&shard_path)?;
+            shards.push(builder.clone().open(&shard_path)?);
+        }
+        log::info!("Sharded storage {} is opened with {} shards", path.display(), shards_count);
+        Ok(ShardedKvStorage { shards })
+    }
+
+    pub fn shards_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Index of the shard storing the key. The hash is stable across builds, so the keys stay in their shards.
+    pub fn shard_idx(&self, key: &str) -> usize {
+        (fnv1a(key.as_bytes(), 0) % self.shards.len() as u64) as usize
+    }
+
+    fn shard(&self, key: &str) -> &KvLogStorage {
+        &self.shards[self.shard_idx(key)]
+    }
+
+    fn shard_mut(&mut self, key: &str) -> &mut KvLogStorage {
+        let shard_idx = self.shard_idx(key);
+        &mut self.shards[shard_idx]
+    }
+
+    /// Set key `key` to value `value`.
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.shard_mut(&key).set(key, value)
+    }
+
+    /// Set key `key` to a binary value `value`.
+    pub fn set_bytes(&mut self, key: String, value: Vec<u8>) -> Result<()> {
+        self.shard_mut(&key).set_bytes(key, value)
+    }
+
+    /// Gets value with the key `key`. Returns `None` if the key doesn't exist in the storage.
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        self.shard(&key).get(key)
+    }
+
+    /// Gets the binary value with the key `key`. Returns `None` if the key doesn't exist in the storage.
+    pub fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>> {
+        self.shard(&key).get_bytes(key)
+    }
+
+    /// Opens the binary value with the key `key` to be read in parts, see `KvLogStorage::open_value`.
+    pub fn open_value(&self, key: String) -> Result<Option<ValueReader>> {
+        self.shard(&key).open_value(key)
+    }
+
+    /// Appends `suffix` to the binary value with the key `key`, the missing key is set to `suffix`.
+    /// Returns the length of the new value.
+    pub fn append(&mut self, key: String, suffix: Vec<u8>) -> Result<u64> {
+        self.shard_mut(&key).append(key, suffix)
+    }
+
+    /// Sets key `key` to a binary value `value` and returns the previous value, `None` if the key didn't exist.
+    pub fn get_set(&mut self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.shard_mut(&key).get_set(key, value)
+    }
+
+    /// Sets key `key` to a binary value `value` only if the key doesn't exist. Returns `true` if the value was set.
+    pub fn set_nx(&mut self, key: String, value: Vec<u8>) -> Result<bool> {
+        self.shard_mut(&key).set_nx(key, value)
+    }
+
+    /// Applies the collection mutation command `mutation` to the collection with the key of the command,
+    /// see `KvLogStorage::update_collection`.
+    pub fn update_collection(&mut self, mutation: Command) -> Result<MutationResult> {
+        let key = mutation.key().unwrap_or_default().to_owned();
+        self.shard_mut(&key).update_collection(mutation)
+    }
+
+    /// Calls `read` with the collection of the type `collection_type` with the key `key`, `None` for a missing key.
+    pub fn read_collection(
+        &self,
+        key: String,
+        collection_type: CollectionType,
+        read: &mut CollectionRead,
+    ) -> Result<()> {
+        self.shard(&key).read_collection(key, collection_type, read)
+    }
+
+    /// Gets the binary values with the keys `keys` in the same order, `None` for the missing keys.
+    /// The keys are grouped by the shards, so each shard reads its values at once.
+    pub fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut shard_keys: Vec<Vec<(usize, String)>> = vec![Vec::new(); self.shards.len()];
+        let keys_count = keys.len();
+        for (key_idx, key) in keys.into_iter().enumerate() {
+            shard_keys[self.shard_idx(&key)].push((key_idx, key));
+        }
+        let mut values = vec![None; keys_count];
+        for (shard, keys) in self.shards.iter().zip(shard_keys) {
+            if keys.is_empty() {
+                continue;
+            }
+            let (key_idxs, keys): (Vec<usize>, Vec<String>) = keys.into_iter().unzip();
+            for (key_idx, value) in key_idxs.into_iter().zip(shard.get_many(keys)?) {
+                values[key_idx] = value;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Removes key `key` from the storage.
+    /// Returns `true` if the key existed.
+    pub fn remove(&mut self, key: String) -> Result<bool> {
+        self.shard_mut(&key).remove(key)
+    }
+
+    /// All the stored keys, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.shards.iter().flat_map(|shard| shard.keys()).collect();
+        keys.sort();
+        keys
+    }
+
+    /// Writes the commands of each shard as a batch of the shard, see `KvLogStorage::write_batch`.
+    /// The batch is atomic within each shard, not across the shards.
+    pub fn write_batch(&mut self, commands: Vec<Command>) -> Result<()> {
+        let mut shard_commands: Vec<Vec<Command>> = vec![Vec::new(); self.shards.len()];
+        for command in commands {
+            let shard_idx = self.shard_idx(command.key().unwrap_or_default());
+            shard_commands[shard_idx].push(command);
+        }
+        for (shard, commands) in self.shards.iter_mut().zip(shard_commands) {
+            if !commands.is_empty() {
+                shard.write_batch(commands)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resets all the shards one by one. The reset is not atomic across the shards.
+    pub fn reset(&mut self) -> Result<()> {
+        for shard in self.shards.iter_mut() {
+            shard.reset()?;
+        }
+        Ok(())
+    }
+
+    /// Removes the keys starting with `prefix` from all the shards one by one, see `KvLogStorage::reset_prefix`.
+    /// Returns the number of the removed keys of all the shards.
+    pub fn reset_prefix(&mut self, prefix: &str) -> Result<u64> {
+        let mut removed_count = 0;
+        for shard in self.shards.iter_mut() {
+            removed_count += shard.reset_prefix(prefix)?;
+        }
+        Ok(removed_count)
+    }
+
+    /// Compacts the sealed log files of all the shards.
+    pub fn compact(&self) -> Result<()> {
+        for shard in &self.shards {
+            shard.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Storage statistics summed over the shards, with the latest compaction of any shard.
+    pub fn stats(&self) -> Result<StorageStats> {
+        let mut total = StorageStats {
+            keys_count: 0,
+            segments_count: 0,
+            disk_size: 0,
+            live_size: 0,
+            garbage_size: 0,
+            last_compaction: None,
+            compacted_segments: 0,
+            reclaimed_size: 0,
+            compaction_failures: 0,
+            compaction_time: std::time::Duration::ZERO,
+        };
+        for shard in &self.shards {
+            let stats = shard.stats()?;
+            total.keys_count += stats.keys_count;
+            total.segments_count += stats.segments_count;
+            total.disk_size += stats.disk_size;
+            total.live_size += stats.live_size;
+            total.garbage_size += stats.garbage_size;
+            total.last_compaction = total.last_compaction.max(stats.last_compaction);
+            total.compacted_segments += stats.compacted_segments;
+            total.reclaimed_size += stats.reclaimed_size;
+            total.compaction_failures += stats.compaction_failures;
+            total.compaction_time += stats.compaction_time;
+        }
+        Ok(total)
+    }
+
+    /// Returns the storage of the namespace `name`, partitioned across the namespaces of the same name
+    /// in each of the shards.
+    pub fn namespace(&self, name: &str) -> Result<ShardedKvStorage> {
+        let shards = self.shards.iter().map(|shard| shard.namespace(name)).collect::<Result<Vec<_>>>()?;
+        Ok(ShardedKvStorage { shards })
+    }
+
+    /// Syncs the active log files of all the shards.
+    pub fn flush(&self) -> Result<()> {
+        for shard in &self.shards {
+            shard.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the compactions of all the shards to complete and flushes them, see `KvLogStorage::close`.
+    pub fn close(&self) -> Result<()> {
+        for shard in &self.shards {
+            shard.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl KvStorage for ShardedKvStorage {
+    fn set_bytes(&mut self, key: String, value: Vec<u8>) -> Result<()> {
+        ShardedKvStorage::set_bytes(self, key, value)
+    }
+
+    fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>> {
+        ShardedKvStorage::get_bytes(self, key)
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        ShardedKvStorage::get_many(self, keys)
+    }
+
+    fn open_value(&self, key: String) -> Result<Option<ValueReader>> {
+        ShardedKvStorage::open_value(self, key)
+    }
+
+    fn append(&mut self, key: String, suffix: Vec<u8>) -> Result<u64> {
+        ShardedKvStorage::append(self, key, suffix)
+    }
+
+    fn get_set(&mut self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        ShardedKvStorage::get_set(self, key, value)
+    }
+
+    fn set_nx(&mut self, key: String, value: Vec<u8>) -> Result<bool> {
+        ShardedKvStorage::set_nx(self, key, value)
+    }
+
+    fn update_collection(&mut self, mutation: Command) -> Result<MutationResult> {
+        ShardedKvStorage::update_collection(self, mutation)
+    }
+
+    fn read_collection(&self, key: String, collection_type: CollectionType, read: &mut CollectionRead) -> Result<()> {
+        ShardedKvStorage::read_collection(self, key, collection_type, read)
+    }
+
+    fn remove(&mut self, key: String) -> Result<bool> {
+        ShardedKvStorage::remove(self, key)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        ShardedKvStorage::reset(self)
+    }
+
+    fn reset_prefix(&mut self, prefix: String) -> Result<u64> {
+        ShardedKvStorage::reset_prefix(self, &prefix)
+    }
+
+    fn write_batch(&mut self, commands: Vec<Command>) -> Result<()> {
+        ShardedKvStorage::write_batch(self, commands)
+    }
+
+    /// The commands of a batch are split between the shards, so any batch within the limit of a shard fits.
+    fn max_batch_size(&self) -> Option<u64> {
+        self.shards.iter().map(KvLogStorage::max_batch_size).min()
+    }
+
+    fn compact(&self) -> Result<()> {
+        ShardedKvStorage::compact(self)
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        ShardedKvStorage::stats(self)
+    }
+
+    fn flush(&self) -> Result<()> {
+        ShardedKvStorage::flush(self)
+    }
+
+    fn close(&self) -> Result<()> {
+        ShardedKvStorage::close(self)
+    }
+
+    /// Backups of a sharded storage are made and restored per shard directory.
+    fn prepare_restore(&self, _backup_dir: &Path) -> Result<String> {
+        Err(Box::from("Online restore is not supported by the sharded storage"))
+    }
+
+    fn commit_restore(&mut self, _token: &str) -> Result<()> {
+        Err(Box::from("Online restore is not supported by the sharded storage"))
+    }
+
+    fn abort_restore(&self, _token: &str) -> Result<()> {
+        Err(Box::from("Online restore is not supported by the sharded storage"))
+    }
+
+    fn namespace(&self, name: &str) -> Result<Box<dyn KvStorage>> {
+        Ok(Box::new(ShardedKvStorage::namespace(self, name)?))
+    }
+
+    fn set_compaction_garbage_ratio(&self, ratio: f64) -> Result<()> {
+        for shard in &self.shards {
+            shard.set_compaction_garbage_ratio(ratio)?;
+        }
+        Ok(())
+    }
+
+    fn pause_compaction(&self) -> Result<()> {
+        for shard in &self.shards {
+            shard.pause_compaction()?;
+        }
+        Ok(())
+    }
+
+    fn resume_compaction(&self) -> Result<()> {
+        for shard in &self.shards {
+            shard.resume_compaction()?;
+        }
+        Ok(())
+    }
+
+    fn is_compaction_paused(&self) -> bool {
+        self.shards.iter().any(KvLogStorage::is_compaction_paused)
+    }
+
+    fn clone_box(&self) -> Box<dyn KvStorage> {
+        Box::new(self.clone())
+    }
+}
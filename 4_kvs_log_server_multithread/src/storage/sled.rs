@@ -0,0 +1,308 @@
+use std::error::Error;
+use std::io;
+use std::path::Path;
+
+use sled;
+use sled::transaction::{ConflictableTransactionError, ConflictableTransactionResult, TransactionError};
+use sled::transaction::TransactionalTree;
+use sled::Transactional;
+
+use crate::models::{CollectionType, Command, Result, StorageStats};
+use crate::storage::base::{check_engine, CollectionRead, KvStorage};
+use crate::storage::collections::{self, Collection, MutationResult};
+use crate::storage::kv_log::validate_namespace;
+
+/// Decodes a collection stored as its type code followed by the encoded items.
+fn decode_collection(value: &[u8]) -> Result<Collection> {
+    let collection_type = value.first()
+        .and_then(|code| CollectionType::from_code(*code))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unknown collection type"))?;
+    Collection::decode(collection_type, &value[1..])
+}
+
+fn encode_collection(collection: &Collection) -> Vec<u8> {
+    let mut value = vec![collection.collection_type().code()];
+    value.extend(collection.encode());
+    value
+}
+
+/// Result of a transaction aborted by an error of the storage.
+type TransactionResult<T> = ConflictableTransactionResult<T, Box<dyn Error>>;
+
+/// Aborts a transaction with an error of the storage.
+fn abort<T>(result: Result<T>) -> TransactionResult<T> {
+    result.map_err(ConflictableTransactionError::Abort)
+}
+
+/// Key-value storage backed by the sled embedded database. The handles share the database,
+/// which is safe to use from many threads, and the namespaces are sled trees.
+/// The collections are kept in a tree of their own next to the plain values, so the type of a key
+/// is known by the tree holding it.
+/// Sled reclaims the space of the stale records by itself, so there is nothing to compact.
+#[derive(Clone)]
+pub struct SledStorage {
+    db: sled::Db,
+    tree: sled::Tree,
+    /// Collections by the keys, each stored as its type code followed by the encoded items.
+    collections: sled::Tree,
+}
+
+impl SledStorage {
+    /// Opens the sled database in the directory, created if missing.
+    pub fn open(path: &Path) -> Result<SledStorage> {
+        check_engine(path, "sled")?;
+        let db = sled::open(path)?;
+        let tree = (*db).clone();
+        let collections = db.open_tree("collections")?;
+        Ok(SledStorage { db, tree, collections })
+    }
+
+    /// Returns the storage of the namespace `name`, created on the first access.
+    pub fn namespace(&self, name: &str) -> Result<SledStorage> {
+        validate_namespace(name)?;
+        let tree = self.db.open_tree(format!("ns_{}", name))?;
+        let collections = self.db.open_tree(format!("collections_ns_{}", name))?;
+        Ok(SledStorage { db: self.db.clone(), tree, collections })
+    }
+
+    /// Runs `f` with the trees of the plain values and of the collections in a transaction,
+    /// retried on a conflict with a concurrent one.
+    fn transaction<T>(
+        &self,
+        f: impl Fn(&TransactionalTree, &TransactionalTree) -> TransactionResult<T>,
+    ) -> Result<T> {
+        let result = (&self.tree, &self.collections).transaction(|(tree, collections)| f(tree, collections));
+        self.tree.flush()?;
+        result.map_err(|err| match err {
+            TransactionError::Abort(err) => err,
+            TransactionError::Storage(err) => Box::new(err),
+        })
+    }
+
+    /// Fails with `ERROR_CODE_WRONG_TYPE` if the key holds a collection.
+    fn check_plain_value(collections: &TransactionalTree, key: &str) -> TransactionResult<()> {
+        if let Some(value) = collections.get(key)? {
+            let collection_type = abort(decode_collection(&value))?.collection_type();
+            return abort(Err(collections::collection_value_error(collection_type)));
+        }
+        Ok(())
+    }
+}
+
+impl KvStorage for SledStorage {
+    fn set_bytes(&mut self, key: String, value: Vec<u8>) -> Result<()> {
+        self.transaction(|tree, collections| {
+            tree.insert(key.as_bytes(), value.as_slice())?;
+            collections.remove(key.as_bytes())?;
+            Ok(())
+        })
+    }
+
+    fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.collections.get(&key)? {
+            return Err(collections::collection_value_error(decode_collection(&value)?.collection_type()));
+        }
+        Ok(self.tree.get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>> {
+        keys.into_iter().map(|key| self.get_bytes(key)).collect()
+    }
+
+    fn append(&mut self, key: String, suffix: Vec<u8>) -> Result<u64> {
+        self.transaction(|tree, collections| {
+            Self::check_plain_value(collections, &key)?;
+            let mut value = tree.get(key.as_bytes())?.map(|value| value.to_vec()).unwrap_or_default();
+            value.extend_from_slice(&suffix);
+            tree.insert(key.as_bytes(), value.as_slice())?;
+            Ok(value.len() as u64)
+        })
+    }
+
+    fn get_set(&mut self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.transaction(|tree, collections| {
+            Self::check_plain_value(collections, &key)?;
+            let previous_value = tree.insert(key.as_bytes(), value.as_slice())?;
+            Ok(previous_value.map(|value| value.to_vec()))
+        })
+    }
+
+    fn set_nx(&mut self, key: String, value: Vec<u8>) -> Result<bool> {
+        self.transaction(|tree, collections| {
+            if tree.get(key.as_bytes())?.is_some() || collections.get(key.as_bytes())?.is_some() {
+                return Ok(false);
+            }
+            tree.insert(key.as_bytes(), value.as_slice())?;
+            Ok(true)
+        })
+    }
+
+    /// The collection is read, changed and written back in a transaction.
+    fn update_collection(&mut self, mutation: Command) -> Result<MutationResult> {
+        let collection_type = collections::mutation_type(&mutation)
+            .ok_or_else(|| format!("Command {} is not a collection mutation", mutation.name()))?;
+        let key = mutation.key().unwrap_or_default().to_owned();
+        self.transaction(|tree, collections| {
+            if tree.get(key.as_bytes())?.is_some() {
+                return abort(Err(collections::wrong_type_error(collection_type)));
+            }
+            let mut collection = match collections.get(key.as_bytes())? {
+                Some(value) => abort(decode_collection(&value))?,
+                None => Collection::new(collection_type),
+            };
+            let result = abort(collection.apply(&mutation))?;
+            if result.changed {
+                match collection.is_empty() {
+                    true => { collections.remove(key.as_bytes())?; },
+                    false => { collections.insert(key.as_bytes(), encode_collection(&collection))?; },
+                }
+            }
+            Ok(result)
+        })
+    }
+
+    fn read_collection(&self, key: String, collection_type: CollectionType, read: &mut CollectionRead) -> Result<()> {
+        match self.collections.get(&key)? {
+            Some(value) => {
+                let collection = decode_collection(&value)?;
+                if collection.collection_type() != collection_type {
+                    return Err(collections::wrong_type_error(collection_type));
+                }
+                read(Some(&collection))
+            },
+            None if self.tree.contains_key(&key)? => Err(collections::wrong_type_error(collection_type)),
+            None => read(None),
+        }
+    }
+
+    fn remove(&mut self, key: String) -> Result<bool> {
+        self.transaction(|tree, collections| {
+            let old_value = tree.remove(key.as_bytes())?;
+            let old_collection = collections.remove(key.as_bytes())?;
+            Ok(old_value.is_some() || old_collection.is_some())
+        })
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.tree.clear()?;
+        self.collections.clear()?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    /// The keys are removed with a single transaction.
+    fn reset_prefix(&mut self, prefix: String) -> Result<u64> {
+        let mut batch = sled::Batch::default();
+        let mut collections_batch = sled::Batch::default();
+        let mut removed_count = 0;
+        for key in self.tree.scan_prefix(prefix.as_bytes()).keys() {
+            batch.remove(key?);
+            removed_count += 1;
+        }
+        for key in self.collections.scan_prefix(prefix.as_bytes()).keys() {
+            collections_batch.remove(key?);
+            removed_count += 1;
+        }
+        self.transaction(|tree, collections| {
+            tree.apply_batch(&batch)?;
+            collections.apply_batch(&collections_batch)?;
+            Ok(())
+        })?;
+        Ok(removed_count)
+    }
+
+    /// The batch is applied atomically with a single transaction.
+    fn write_batch(&mut self, commands: Vec<Command>) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        let mut collections_batch = sled::Batch::default();
+        for command in commands {
+            let key = match command {
+                Command::Set { key, value } => {
+                    batch.insert(key.as_bytes(), value);
+                    key
+                },
+                Command::Remove { key } => {
+                    batch.remove(key.as_bytes());
+                    key
+                },
+                command => return Err(Box::from(format!("Command {} cannot be written in a batch", command.name()))),
+            };
+            // Both setting and removing a key replace its collection.
+            collections_batch.remove(key.as_bytes());
+        }
+        self.transaction(|tree, collections| {
+            tree.apply_batch(&batch)?;
+            collections.apply_batch(&collections_batch)?;
+            Ok(())
+        })
+    }
+
+    fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// The disk size is the size of the whole database, including the other namespaces.
+    fn stats(&self) -> Result<StorageStats> {
+        let mut live_size = 0;
+        for record in self.tree.iter().chain(self.collections.iter()) {
+            let (key, value) = record?;
+            live_size += (key.len() + value.len()) as u64;
+        }
+        Ok(StorageStats {
+            keys_count: (self.tree.len() + self.collections.len()) as u64,
+            segments_count: 0,
+            disk_size: self.db.size_on_disk()?,
+            live_size,
+            garbage_size: 0,
+            last_compaction: None,
+            compacted_segments: 0,
+            reclaimed_size: 0,
+            compaction_failures: 0,
+            compaction_time: std::time::Duration::ZERO,
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn prepare_restore(&self, _backup_dir: &Path) -> Result<String> {
+        Err(Box::from("Online restore is not supported by the sled storage"))
+    }
+
+    fn commit_restore(&mut self, _token: &str) -> Result<()> {
+        Err(Box::from("Online restore is not supported by the sled storage"))
+    }
+
+    fn abort_restore(&self, _token: &str) -> Result<()> {
+        Err(Box::from("Online restore is not supported by the sled storage"))
+    }
+
+    fn namespace(&self, name: &str) -> Result<Box<dyn KvStorage>> {
+        Ok(Box::new(SledStorage::namespace(self, name)?))
+    }
+
+    /// Sled has no compaction settings, so the ratio is ignored.
+    fn set_compaction_garbage_ratio(&self, _ratio: f64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Sled compacts its files by itself, so there is no compaction to pause.
+    fn pause_compaction(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resume_compaction(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn clone_box(&self) -> Box<dyn KvStorage> {
+        Box::new(self.clone())
+    }
+}
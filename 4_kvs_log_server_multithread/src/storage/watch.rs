@@ -0,0 +1,126 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// Change of the stored data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChangeEvent {
+    Set { key: String, value: Vec<u8> },
+    Remove { key: String },
+    /// All the keys are removed or replaced at once by a reset or a restore.
+    Reset,
+}
+
+impl ChangeEvent {
+    /// Changed key. `None` for the changes of all the keys.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            ChangeEvent::Set { key, .. } | ChangeEvent::Remove { key } => Some(key),
+            ChangeEvent::Reset => None,
+        }
+    }
+}
+
+/// Keys watched by a subscription.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchFilter {
+    Key(String),
+    Prefix(String),
+}
+
+impl WatchFilter {
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            WatchFilter::Key(watched) => watched == key,
+            WatchFilter::Prefix(prefix) => key.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+struct Subscriber {
+    id: u64,
+    filter: WatchFilter,
+    sender: Sender<ChangeEvent>,
+}
+
+struct WatchState {
+    next_id: u64,
+    subscribers: Vec<Subscriber>,
+}
+
+/// Registry of the subscriptions to the storage changes.
+pub(crate) struct WatchRegistry {
+    state: Mutex<WatchState>,
+}
+
+impl WatchRegistry {
+    pub(crate) fn new() -> WatchRegistry {
+        WatchRegistry { state: Mutex::new(WatchState { next_id: 0, subscribers: Vec::new() }) }
+    }
+
+    pub(crate) fn subscribe(registry: &Arc<WatchRegistry>, filter: WatchFilter) -> Subscription {
+        let (sender, receiver) = mpsc::channel();
+        let mut state = registry.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.next_id += 1;
+        let id = state.next_id;
+        state.subscribers.push(Subscriber { id, filter, sender });
+        Subscription { id, receiver, registry: Arc::downgrade(registry) }
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.subscribers.retain(|subscriber| subscriber.id != id);
+    }
+
+    /// Returns `true` if any subscription watches the key, so the changed value is worth copying.
+    pub(crate) fn is_watched(&self, key: &str) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.subscribers.iter().any(|subscriber| subscriber.filter.matches(key))
+    }
+
+    /// Sends the event to the subscriptions watching the changed key, or to all of them for a reset.
+    /// Should be called under the storage write lock, so the events follow the order of the writes.
+    pub(crate) fn notify(&self, event: ChangeEvent) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        // Subscribers with a dropped receiver are removed along the way.
+        state.subscribers.retain(|subscriber| {
+            match event.key() {
+                Some(key) if !subscriber.filter.matches(key) => true,
+                _ => subscriber.sender.send(event.clone()).is_ok(),
+            }
+        });
+    }
+}
+
+/// Subscription to the storage changes. The events are queued without a limit until received,
+/// so a subscriber is expected to keep up with the writes. Dropping the subscription unsubscribes it.
+pub struct Subscription {
+    id: u64,
+    receiver: Receiver<ChangeEvent>,
+    registry: Weak<WatchRegistry>,
+}
+
+impl Subscription {
+    /// Blocks until the next change. Returns `None` once the storage is dropped.
+    pub fn recv(&self) -> Option<ChangeEvent> {
+        self.receiver.recv().ok()
+    }
+
+    /// Waits for the next change for up to `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ChangeEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Returns the next change if there is any.
+    pub fn try_recv(&self) -> Option<ChangeEvent> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(registry) = self.registry.upgrade() {
+            registry.unsubscribe(self.id);
+        }
+    }
+}
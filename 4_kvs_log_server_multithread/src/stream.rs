@@ -0,0 +1,62 @@
+use std::io;
+use std::net;
+use std::time::Duration;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use rustls;
+
+/// A bidirectional connection stream used by the server and the client.
+/// Hides whether the data goes over a plain TCP socket or an encrypted TLS session.
+pub trait Stream: io::Read + io::Write + Send {
+    /// Shuts down both halves of the connection.
+    fn shutdown(&mut self) -> io::Result<()>;
+
+    /// Sets the read timeout of the underlying socket, `None` to block reads indefinitely.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Stream for net::TcpStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        net::TcpStream::shutdown(self, net::Shutdown::Both)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        net::TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        UnixStream::shutdown(self, net::Shutdown::Both)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Stream for rustls::StreamOwned<rustls::ServerConnection, net::TcpStream> {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        let _ = io::Write::flush(self);
+        self.sock.shutdown(net::Shutdown::Both)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+}
+
+impl Stream for rustls::StreamOwned<rustls::ClientConnection, net::TcpStream> {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        let _ = io::Write::flush(self);
+        self.sock.shutdown(net::Shutdown::Both)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+}
@@ -1,7 +1,221 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
 use crate::models;
+use crate::threads::scheduler::{PeriodicJob, Scheduler};
 
 pub type Job = Box<dyn FnOnce() + Send + 'static>;
 
-pub trait ThreadPool {
-    fn spawn(&mut self, job: Job) -> models::Result<()>;
+/// Load counters of a thread pool, shared by the pool, its jobs and the readers of the metrics.
+#[derive(Debug, Default)]
+pub struct PoolCounters {
+    /// Number of the spawned jobs not started yet.
+    queued: AtomicUsize,
+    /// Number of the jobs running at the moment.
+    busy: AtomicUsize,
+    started: AtomicU64,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    /// Total time the started jobs waited for a worker in microseconds.
+    total_wait_micros: AtomicU64,
+}
+
+impl PoolCounters {
+    /// Counts `job` as queued and wraps it to record its wait time and outcome once a worker runs it.
+    /// A panic of the job is counted and then resumed, so the pools handle it as before.
+    pub fn instrument(self: &std::sync::Arc<Self>, job: Job) -> Job {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let counters = self.clone();
+        let spawned = std::time::Instant::now();
+        Box::new(move || {
+            counters.total_wait_micros.fetch_add(spawned.elapsed().as_micros() as u64, Ordering::SeqCst);
+            counters.started.fetch_add(1, Ordering::SeqCst);
+            counters.busy.fetch_add(1, Ordering::SeqCst);
+            counters.queued.fetch_sub(1, Ordering::SeqCst);
+
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job));
+            counters.busy.fetch_sub(1, Ordering::SeqCst);
+            match result {
+                Ok(()) => { counters.completed.fetch_add(1, Ordering::SeqCst); },
+                Err(payload) => {
+                    counters.panicked.fetch_add(1, Ordering::SeqCst);
+                    std::panic::resume_unwind(payload);
+                },
+            }
+        })
+    }
+
+    /// Number of the spawned jobs not started yet.
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Forgets `count` queued jobs dropped by the pool without running them.
+    pub fn discard_queued(&self, count: usize) {
+        self.queued.fetch_sub(count, Ordering::SeqCst);
+    }
+
+    pub fn snapshot(&self) -> models::ThreadPoolMetrics {
+        let started = self.started.load(Ordering::SeqCst);
+        let total_wait_micros = self.total_wait_micros.load(Ordering::SeqCst);
+        models::ThreadPoolMetrics {
+            queue_depth: self.queued.load(Ordering::SeqCst) as u64,
+            busy_workers: self.busy.load(Ordering::SeqCst) as u64,
+            completed_jobs: self.completed.load(Ordering::SeqCst),
+            panicked_jobs: self.panicked.load(Ordering::SeqCst),
+            avg_wait_time: std::time::Duration::from_micros(total_wait_micros.checked_div(started).unwrap_or(0)),
+        }
+    }
+}
+
+/// Handle of a spawned job to wait for its completion and result.
+/// Dropping the handle doesn't affect the job.
+#[derive(Debug)]
+pub struct JobHandle<T = ()> {
+    receiver: std::sync::mpsc::Receiver<std::result::Result<T, String>>,
+}
+
+impl<T: Send + 'static> JobHandle<T> {
+    /// Wraps `task` into a job sending its result to the returned handle.
+    /// A panic of the task is reported to the handle and then resumed, so the pools handle it as before.
+    pub fn wrap(task: impl FnOnce() -> models::Result<T> + Send + 'static) -> (Job, JobHandle<T>) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let job = Box::new(move || {
+            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(task)) {
+                // Errors are not `Send`, so only their messages are passed to the handle.
+                Ok(result) => { let _ = sender.send(result.map_err(|err| err.to_string())); },
+                Err(payload) => {
+                    let message = payload.downcast_ref::<&str>().copied()
+                        .or(payload.downcast_ref::<String>().map(String::as_str))
+                        .unwrap_or("");
+                    let _ = sender.send(Err(format!("Job panicked: {}", message)));
+                    std::panic::resume_unwind(payload);
+                },
+            }
+        });
+        (job, JobHandle { receiver })
+    }
+}
+
+impl<T> JobHandle<T> {
+    /// Waits for the job to complete and returns its result.
+    /// Fails if the job failed, panicked or was dropped without running on the pool shutdown.
+    pub fn join(self) -> models::Result<T> {
+        match self.receiver.recv() {
+            Ok(result) => result.map_err(Box::from),
+            Err(_) => Err(Box::from("Job is dropped without running")),
+        }
+    }
+
+    /// Same as `join`, but waits for at most `timeout`. Returns `None` if the job is not completed in time.
+    /// The result is returned only once, the next calls fail.
+    pub fn join_timeout(&self, timeout: std::time::Duration) -> Option<models::Result<T>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => Some(result.map_err(Box::from)),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => None,
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => Some(Err(Box::from("Job is dropped without running"))),
+        }
+    }
+}
+
+/// Returned by `ThreadPool::spawn` of a bounded pool when its job queue is full. The job is dropped.
+#[derive(Debug)]
+pub struct QueueFullError {
+    pub capacity: usize,
+}
+
+impl std::fmt::Display for QueueFullError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Thread pool queue is full with {} jobs", self.capacity)
+    }
+}
+
+impl std::error::Error for QueueFullError {}
+
+/// What happens to the queued jobs on a thread pool shutdown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// Run all the queued jobs before stopping the workers.
+    Graceful,
+    /// Drop the queued jobs. The running jobs are completed.
+    Immediate,
+    /// Run the queued jobs for at most the given time, then drop the rest of them.
+    /// The workers still busy at the deadline are not waited for, they stop once their jobs are completed.
+    Timeout(std::time::Duration),
+}
+
+/// A pool of worker threads. The jobs are spawned via a shared reference, so the pool can be shared between
+/// threads, e.g. in an `Arc`, without a mutex. Only the pool owner shuts it down.
+pub trait ThreadPool: Send + Sync {
+    /// Queues the job to run on the pool. The returned handle tells once the job is completed or panicked.
+    /// See `ThreadPoolExt::execute` to spawn a closure without boxing it.
+    fn spawn(&self, job: Job) -> models::Result<JobHandle>;
+
+    /// Stops the pool workers, the queued jobs are handled according to `mode`.
+    /// Returns once the workers are stopped or the timeout passes.
+    fn shutdown(&mut self, mode: ShutdownMode) -> models::Result<()>;
+
+    /// Returns the load counters of the pool. The handle stays valid after the pool is moved or dropped.
+    fn counters(&self) -> std::sync::Arc<PoolCounters>;
+
+    /// Returns a snapshot of the pool load: queue depth, busy workers, completed and panicked jobs, average wait time.
+    fn metrics(&self) -> models::ThreadPoolMetrics {
+        self.counters().snapshot()
+    }
+
+    /// Returns the timer of the delayed and periodic jobs of the pool.
+    fn scheduler(&self) -> &Scheduler;
+
+    /// Spawns `job` once `delay` passes. The job is dropped if the pool is shut down before that.
+    fn spawn_after(&self, delay: std::time::Duration, job: Job) -> models::Result<()> {
+        self.scheduler().schedule_once(delay, job)
+    }
+
+    /// Spawns `job` every `interval` until the pool is shut down, the first run is in `interval`.
+    /// A run is skipped if the previous one is not completed yet.
+    fn spawn_periodic(&self, interval: std::time::Duration, job: PeriodicJob) -> models::Result<()> {
+        self.scheduler().schedule_periodic(interval, job)
+    }
+}
+
+/// Generic helpers of all the thread pools, including `dyn ThreadPool`.
+pub trait ThreadPoolExt: ThreadPool {
+    /// Spawns the closure `job`, same as `spawn`.
+    fn execute(&self, job: impl FnOnce() + Send + 'static) -> models::Result<JobHandle> {
+        self.spawn(Box::new(job))
+    }
+
+    /// Spawns `task` on the pool, the returned handle gets the result of the task.
+    fn spawn_task<T: Send + 'static>(
+        &self,
+        task: impl FnOnce() -> models::Result<T> + Send + 'static,
+    ) -> models::Result<JobHandle<T>> {
+        let (job, handle) = JobHandle::wrap(task);
+        self.spawn(job)?;
+        Ok(handle)
+    }
+}
+
+impl<P: ThreadPool + ?Sized> ThreadPoolExt for P {}
+
+/// Joins the threads finished before `deadline`, the rest are detached. Without a deadline joins all the threads.
+pub(crate) fn join_threads(threads: Vec<std::thread::JoinHandle<()>>, deadline: Option<std::time::Instant>) {
+    if let Some(deadline) = deadline {
+        while !threads.iter().all(|thread| thread.is_finished()) && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    let mut detached_count = 0;
+    for thread in threads {
+        if deadline.is_some() && !thread.is_finished() {
+            detached_count += 1;
+            continue;
+        }
+        if let Err(err) = thread.join() {
+            log::warn!("Thread finished with err: {}", err.downcast_ref::<&str>().unwrap_or(&""));
+        }
+    }
+    if detached_count > 0 {
+        log::warn!("{} threads are still busy after the shutdown timeout, leaving them running", detached_count);
+    }
 }
@@ -2,4 +2,5 @@ pub mod base;
 pub mod naive;
 pub mod none;
 pub mod shared;
-pub mod rayon;
\ No newline at end of file
+pub mod rayon;
+pub mod scheduler;
\ No newline at end of file
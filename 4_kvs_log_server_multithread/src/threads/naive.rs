@@ -1,36 +1,165 @@
+use std::collections::VecDeque;
+
 use crate::threads::base;
+use crate::threads::scheduler::Scheduler;
 use crate::models;
 
 
+/// Threads and jobs of the naive pool, shared with its threads.
+#[derive(Default)]
+struct NaiveState {
+    /// Handles of the threads not joined yet. The finished ones are reaped on spawn.
+    threads: Vec<std::thread::JoinHandle<()>>,
+    /// Jobs waiting for a thread once the thread cap is reached.
+    queue: VecDeque<base::Job>,
+    /// Number of the threads running jobs.
+    running_threads: usize,
+    stopped: bool,
+}
+
 /// A naive thread pool implementation spawning a fresh thread on each task.
+/// With a thread cap the tasks beyond it are queued, and a thread completing its task
+/// takes the next queued one before exiting.
 pub struct NaiveThreadPool {
-    thread_handlers: std::sync::Mutex<Vec<std::thread::JoinHandle<()>>>,
+    state: std::sync::Arc<std::sync::Mutex<NaiveState>>,
+    counters: std::sync::Arc<base::PoolCounters>,
+    scheduler: Scheduler,
+    /// Max number of the running threads, unlimited if not set.
+    max_threads: Option<usize>,
+}
+
+/// Runs the job on a fresh thread, or queues it if `max_threads` threads are running already.
+fn submit(
+    state: &std::sync::Arc<std::sync::Mutex<NaiveState>>,
+    counters: &std::sync::Arc<base::PoolCounters>,
+    max_threads: Option<usize>,
+    job: base::Job,
+) -> models::Result<()> {
+    let mut state_guard = state.lock().unwrap_or_else(|e| e.into_inner());
+    if state_guard.stopped {
+        return Err(Box::from("Thread pool is shut down"));
+    }
+
+    let (finished, running): (Vec<_>, Vec<_>) = std::mem::take(&mut state_guard.threads)
+        .into_iter()
+        .partition(|thread| thread.is_finished());
+    state_guard.threads = running;
+    base::join_threads(finished, None);
+
+    let job = counters.instrument(job);
+    match max_threads {
+        Some(max_threads) if state_guard.running_threads >= max_threads => state_guard.queue.push_back(job),
+        _ => {
+            let thread_state = state.clone();
+            state_guard.threads.push(std::thread::spawn(move || run_jobs(thread_state, job)));
+            state_guard.running_threads += 1;
+        },
+    }
+    Ok(())
+}
+
+/// A single pool thread function: runs the job and then the queued jobs until the queue is empty.
+fn run_jobs(state: std::sync::Arc<std::sync::Mutex<NaiveState>>, job: base::Job) {
+    let mut job = job;
+    loop {
+        if let Err(err) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)) {
+            log::error!("Job in thread pool panicked: {}", err.downcast_ref::<&str>().unwrap_or(&""));
+        }
+
+        let mut state_guard = state.lock().unwrap_or_else(|e| e.into_inner());
+        match state_guard.queue.pop_front() {
+            Some(next_job) => job = next_job,
+            None => {
+                state_guard.running_threads -= 1;
+                return;
+            },
+        }
+    }
 }
 
 
 impl NaiveThreadPool {
+    /// Creates a pool spawning a thread for every task without a limit.
     pub fn new() -> Self {
-        NaiveThreadPool { thread_handlers: std::sync::Mutex::new(Vec::new()) }
+        Self::with_thread_cap(None)
+    }
+
+    /// Creates a pool running at most `max_threads` threads at once. The tasks beyond the limit are queued.
+    pub fn with_max_threads(max_threads: usize) -> Self {
+        assert!(max_threads > 0, "ThreadPool max threads must be greater than zero");
+        Self::with_thread_cap(Some(max_threads))
+    }
+
+    fn with_thread_cap(max_threads: Option<usize>) -> Self {
+        let state = std::sync::Arc::new(std::sync::Mutex::new(NaiveState::default()));
+        let counters = std::sync::Arc::new(base::PoolCounters::default());
+        let scheduler_state = state.clone();
+        let scheduler_counters = counters.clone();
+        NaiveThreadPool {
+            state,
+            counters,
+            scheduler: Scheduler::new(move |job| submit(&scheduler_state, &scheduler_counters, max_threads, job)),
+            max_threads,
+        }
+    }
+
+    /// Drops the queued jobs, returns the number of them.
+    fn drop_queued_jobs(&self) -> usize {
+        let dropped = std::mem::take(&mut self.state.lock().unwrap_or_else(|e| e.into_inner()).queue);
+        self.counters.discard_queued(dropped.len());
+        dropped.len()
     }
 }
 
 impl base::ThreadPool for NaiveThreadPool {
-    fn spawn(&mut self, job: base::Job) -> models::Result<()> {
-        let handle = std::thread::spawn(job);
-        let mut handlers_list = self.thread_handlers.lock().unwrap_or_else(|e| e.into_inner());
-        handlers_list.push(handle);
+    fn spawn(&self, job: base::Job) -> models::Result<base::JobHandle> {
+        let (job, handle) = base::JobHandle::wrap(move || { job(); Ok(()) });
+        submit(&self.state, &self.counters, self.max_threads, job)?;
+        Ok(handle)
+    }
+
+    fn shutdown(&mut self, mode: base::ShutdownMode) -> models::Result<()> {
+        self.scheduler.stop();
+        let handles = {
+            let mut state_guard = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state_guard.stopped = true;
+            std::mem::take(&mut state_guard.threads)
+        };
+
+        // The running threads take the queued jobs until the queue is empty, so no new threads are needed.
+        let mut dropped_count = 0;
+        let deadline = match mode {
+            base::ShutdownMode::Graceful => None,
+            base::ShutdownMode::Immediate => {
+                dropped_count += self.drop_queued_jobs();
+                None
+            },
+            base::ShutdownMode::Timeout(timeout) => Some(std::time::Instant::now() + timeout),
+        };
+        base::join_threads(handles, deadline);
+        dropped_count += self.drop_queued_jobs();
+
+        if dropped_count > 0 {
+            log::warn!("{} queued jobs are dropped on the thread pool shutdown", dropped_count);
+        }
         Ok(())
     }
+
+    fn counters(&self) -> std::sync::Arc<base::PoolCounters> {
+        self.counters.clone()
+    }
+
+    fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
 }
 
 impl Drop for NaiveThreadPool {
     fn drop(&mut self) {
-        // Drain and join all remaining handles
-        let mut handles = self.thread_handlers.lock().unwrap_or_else(|e| e.into_inner());
-        while let Some(handle) = handles.pop() {
-            if let Err(err) = handle.join() {
-                log::error!("Thread panicked: {:?}", err);
-            }
+        // Join all remaining handles
+        if let Err(err) = base::ThreadPool::shutdown(self, base::ShutdownMode::Graceful) {
+            log::error!("Cannot stop thread pool: {}", err);
         }
     }
 }
+
@@ -1,22 +1,51 @@
 use crate::threads::base;
+use crate::threads::scheduler::Scheduler;
 use crate::models;
 
 
 /// Dummy thread pool without actual threads.
 /// Each task is blocking and executed in the same thread.
 /// If the job panics, it is propagated to the current thread.
-pub struct NoneThreadPool {}
+/// The delayed and periodic jobs are executed in the pool timer thread.
+pub struct NoneThreadPool {
+    counters: std::sync::Arc<base::PoolCounters>,
+    scheduler: Scheduler,
+}
 
 
 impl NoneThreadPool {
     pub fn new() -> Self {
-        NoneThreadPool {}
+        let counters = std::sync::Arc::new(base::PoolCounters::default());
+        let scheduler_counters = counters.clone();
+        NoneThreadPool {
+            counters,
+            // A panic of a scheduled job must not stop the timer thread.
+            scheduler: Scheduler::new(move |job| {
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(scheduler_counters.instrument(job)))
+                    .map_err(|_| Box::from("Scheduled job panicked"))
+            }),
+        }
     }
 }
 
 impl base::ThreadPool for NoneThreadPool {
-    fn spawn(&mut self, job: base::Job) -> models::Result<()> {
-        job();
+    fn spawn(&self, job: base::Job) -> models::Result<base::JobHandle> {
+        let (job, handle) = base::JobHandle::wrap(move || { job(); Ok(()) });
+        self.counters.instrument(job)();
+        Ok(handle)
+    }
+
+    /// The jobs are completed on spawn, so there is nothing to stop.
+    fn shutdown(&mut self, _mode: base::ShutdownMode) -> models::Result<()> {
+        self.scheduler.stop();
         Ok(())
     }
+
+    fn counters(&self) -> std::sync::Arc<base::PoolCounters> {
+        self.counters.clone()
+    }
+
+    fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
 }
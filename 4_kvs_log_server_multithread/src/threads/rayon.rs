@@ -1,23 +1,55 @@
 use rayon;
 
-use crate::threads::base::{Job, ThreadPool};
+use crate::threads::base::{Job, JobHandle, PoolCounters, ShutdownMode, ThreadPool};
+use crate::threads::scheduler::Scheduler;
 use crate::models;
 
 pub struct RayonThreadPool {
-    internal_pool: rayon::ThreadPool,
+    internal_pool: std::sync::Arc<rayon::ThreadPool>,
+    counters: std::sync::Arc<PoolCounters>,
+    scheduler: Scheduler,
 }
 
 /// A thread pool wrapper for the `rayon` implementation. 
 impl RayonThreadPool {
     pub fn new(size: usize) -> models::Result<Self> {
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(size).build()?;
-        Ok(RayonThreadPool { internal_pool: pool })
+        let pool = std::sync::Arc::new(rayon::ThreadPoolBuilder::new().num_threads(size).build()?);
+        let counters = std::sync::Arc::new(PoolCounters::default());
+        let scheduler_pool = pool.clone();
+        let scheduler_counters = counters.clone();
+        // The scheduled jobs are spawned without waiting for them. Rayon aborts the process on a panic
+        // of such a job, so the panic is caught here.
+        let scheduler = Scheduler::new(move |job| {
+            let job = scheduler_counters.instrument(job);
+            scheduler_pool.spawn(move || {
+                if std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).is_err() {
+                    log::error!("Scheduled job panicked");
+                }
+            });
+            Ok(())
+        });
+        Ok(RayonThreadPool { internal_pool: pool, counters, scheduler })
     }
 }
 
 impl ThreadPool for RayonThreadPool {
-    fn spawn(&mut self, job: Job) -> models::Result<()> {
-        self.internal_pool.install(job);
+    fn spawn(&self, job: Job) -> models::Result<JobHandle> {
+        let (job, handle) = JobHandle::wrap(move || { job(); Ok(()) });
+        self.internal_pool.install(self.counters.instrument(job));
+        Ok(handle)
+    }
+
+    /// The jobs are completed on spawn, the workers are stopped once the pool is dropped.
+    fn shutdown(&mut self, _mode: ShutdownMode) -> models::Result<()> {
+        self.scheduler.stop();
         Ok(())
     }
+
+    fn counters(&self) -> std::sync::Arc<PoolCounters> {
+        self.counters.clone()
+    }
+
+    fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
 }
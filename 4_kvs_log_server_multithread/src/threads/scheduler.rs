@@ -0,0 +1,200 @@
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::threads::base::Job;
+use crate::models;
+
+/// A job run repeatedly by `ThreadPool::spawn_periodic`.
+pub type PeriodicJob = Box<dyn Fn() + Send + Sync + 'static>;
+
+/// Hands a due job over to the pool workers.
+type SubmitFn = Arc<dyn Fn(Job) -> models::Result<()> + Send + Sync>;
+
+struct PeriodicTask {
+    job: PeriodicJob,
+    interval: Duration,
+    /// Set while a run is queued or running, so the slow runs are skipped rather than piled up.
+    running: AtomicBool,
+}
+
+enum Task {
+    Once(Job),
+    Periodic(Arc<PeriodicTask>),
+}
+
+struct Timer {
+    due: Instant,
+    /// Keeps the timers due at the same time in the scheduling order.
+    seq: u64,
+    task: Task,
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.seq) == (other.due, other.seq)
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Reversed, so the max-heap pops the earliest timer first.
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (other.due, other.seq).cmp(&(self.due, self.seq))
+    }
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    timers: BinaryHeap<Timer>,
+    next_seq: u64,
+    stopped: bool,
+}
+
+/// Timer of the delayed and periodic jobs of a thread pool.
+/// A single timer thread, started on the first scheduled job, submits the due jobs to the pool,
+/// so the scheduled tasks do not need a thread each.
+pub struct Scheduler {
+    submit: SubmitFn,
+    state: Arc<(Mutex<SchedulerState>, Condvar)>,
+    thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler handing the due jobs to `submit`.
+    pub fn new(submit: impl Fn(Job) -> models::Result<()> + Send + Sync + 'static) -> Self {
+        Scheduler {
+            submit: Arc::new(submit),
+            state: Arc::new((Mutex::new(SchedulerState::default()), Condvar::new())),
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Submits `job` once `delay` passes.
+    pub fn schedule_once(&self, delay: Duration, job: Job) -> models::Result<()> {
+        self.schedule(delay, Task::Once(job))
+    }
+
+    /// Submits `job` every `interval`, starting in `interval`, until the scheduler is stopped.
+    /// A run is skipped if the previous one is still queued or running.
+    pub fn schedule_periodic(&self, interval: Duration, job: PeriodicJob) -> models::Result<()> {
+        if interval.is_zero() {
+            return Err(Box::from("Periodic job interval must be greater than zero"));
+        }
+        self.schedule(interval, Task::Periodic(Arc::new(PeriodicTask { job, interval, running: AtomicBool::new(false) })))
+    }
+
+    fn schedule(&self, delay: Duration, task: Task) -> models::Result<()> {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if state.stopped {
+            return Err(Box::from("Thread pool is shut down"));
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.timers.push(Timer { due: Instant::now() + delay, seq, task });
+        drop(state);
+        condvar.notify_one();
+
+        let mut thread = self.thread.lock().unwrap_or_else(|e| e.into_inner());
+        if thread.is_none() {
+            let state = self.state.clone();
+            let submit = self.submit.clone();
+            *thread = Some(
+                std::thread::Builder::new()
+                    .name("kvs-pool-timer".to_owned())
+                    .spawn(move || run_timers(state, submit))?
+            );
+        }
+        Ok(())
+    }
+
+    /// Stops the timer thread. The jobs not due yet are dropped and the periodic jobs are not run anymore.
+    pub fn stop(&self) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+        state.stopped = true;
+        let dropped_count = state.timers.len();
+        state.timers.clear();
+        drop(state);
+        condvar.notify_all();
+
+        let thread = self.thread.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if thread.is_some_and(|thread| thread.join().is_err()) {
+            log::warn!("Thread pool timer thread panicked");
+        }
+        if dropped_count > 0 {
+            log::debug!("{} scheduled jobs are dropped on the thread pool shutdown", dropped_count);
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The timer thread function: waits for the earliest timer and submits its job until the scheduler is stopped.
+fn run_timers(state: Arc<(Mutex<SchedulerState>, Condvar)>, submit: SubmitFn) {
+    let (lock, condvar) = &*state;
+    let mut guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        if guard.stopped {
+            return;
+        }
+        let now = Instant::now();
+        let due = match guard.timers.peek() {
+            Some(timer) => timer.due,
+            None => {
+                guard = condvar.wait(guard).unwrap_or_else(|e| e.into_inner());
+                continue;
+            },
+        };
+        if due > now {
+            guard = condvar.wait_timeout(guard, due - now).unwrap_or_else(|e| e.into_inner()).0;
+            continue;
+        }
+
+        let timer = guard.timers.pop().expect("the timer is peeked");
+        let job: Option<Job> = match timer.task {
+            Task::Once(job) => Some(job),
+            Task::Periodic(task) => {
+                // Keep the period regardless of the run time, but do not catch up on the missed runs.
+                let mut next_due = timer.due + task.interval;
+                if next_due <= now {
+                    next_due = now + task.interval;
+                }
+                let seq = guard.next_seq;
+                guard.next_seq += 1;
+                guard.timers.push(Timer { due: next_due, seq, task: Task::Periodic(task.clone()) });
+
+                if task.running.swap(true, Ordering::SeqCst) {
+                    log::debug!("Periodic job is still running, skipping the run");
+                    None
+                } else {
+                    // Reset once the run is completed or dropped by the pool without running.
+                    let task = scopeguard::guard(task, |task| task.running.store(false, Ordering::SeqCst));
+                    Some(Box::new(move || (task.job)()))
+                }
+            },
+        };
+
+        // The pool may run the job right away, e.g. the `NoneThreadPool`, so the state is not locked meanwhile.
+        if let Some(job) = job {
+            drop(guard);
+            if let Err(err) = submit(job) {
+                log::warn!("Cannot submit a scheduled job to the thread pool: {}", err);
+            }
+            guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
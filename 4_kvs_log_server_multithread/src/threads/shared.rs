@@ -1,49 +1,131 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
 use crossbeam::deque;
+use crossbeam::sync::{Parker, Unparker};
 use log;
 
-use crate::threads::base::{Job, ThreadPool};
+use crate::threads::base::{self, Job, JobHandle, PoolCounters, QueueFullError, ShutdownMode, ThreadPool};
+use crate::threads::scheduler::Scheduler;
 use crate::models;
 
-enum SharedMessage {
-    NewJob(Job),
-    Terminate,
+/// The pool accepts and runs new jobs.
+const STATE_RUNNING: u8 = 0;
+/// The workers run the queued jobs and stop once the queue is empty.
+const STATE_DRAINING: u8 = 1;
+/// The workers stop after the running jobs, the queued jobs are dropped.
+const STATE_STOPPED: u8 = 2;
+
+/// State shared by the pool and its workers.
+struct PoolShared {
+    /// Queue of the spawned jobs. The workers take the jobs from it in batches to their local deques.
+    injector: deque::Injector<Job>,
+    /// Stealers of the worker local deques by worker indexes.
+    stealers: Vec<deque::Stealer<Job>>,
+    /// Unparkers of the worker threads by worker indexes.
+    unparkers: Vec<Unparker>,
+    /// Flags of the workers parked while waiting for a job by worker indexes.
+    /// A push claims a flag to wake up exactly one sleeping worker, the idle workers are never woken up otherwise.
+    sleeping: Vec<AtomicBool>,
+    state: AtomicU8,
+    /// Counts the spawned jobs not started yet, both in the injector and in the local deques.
+    counters: std::sync::Arc<PoolCounters>,
+    /// Max number of the queued jobs, unbounded if not set.
+    queue_capacity: Option<usize>,
 }
 
-pub struct SharedThreadPool {
-    injector: std::sync::Arc<deque::Injector<SharedMessage>>,
-    threads: Vec<std::thread::JoinHandle<()>>,
-}
+impl PoolShared {
+    /// Queues the job for the workers, fails if the pool is shut down or its queue is full.
+    fn push(&self, job: Job) -> models::Result<()> {
+        if self.state.load(Ordering::SeqCst) != STATE_RUNNING {
+            return Err(Box::from("Thread pool is shut down"));
+        }
+        // The check and the push are not atomic, so the pool owner and the scheduler pushing at the same time
+        // may outgrow the capacity by a job.
+        match self.queue_capacity {
+            Some(capacity) if self.counters.queue_depth() >= capacity => {
+                return Err(Box::new(QueueFullError { capacity }))
+            },
+            _ => {},
+        }
+        self.injector.push(self.counters.instrument(job));
+        self.wake_worker();
+        Ok(())
+    }
 
-fn steal_msg(shared_injector: &std::sync::Arc<deque::Injector<SharedMessage>>) -> Option<SharedMessage> {
-    match shared_injector.steal() {
-        deque::Steal::Empty | deque::Steal::Retry => None,
-        deque::Steal::Success(msg) => Some(msg),
+    /// Takes a job from the local deque, a batch of jobs from the injector or a job from another worker.
+    fn find_job(&self, local: &deque::Worker<Job>) -> Option<Job> {
+        local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.injector.steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(|stealer| stealer.steal()).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(|steal| steal.success())
+        })
+    }
+
+    /// Wakes up one of the sleeping workers, if any.
+    fn wake_worker(&self) {
+        for (idx, sleeping) in self.sleeping.iter().enumerate() {
+            if sleeping.compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                self.unparkers[idx].unpark();
+                return;
+            }
+        }
+    }
+
+    fn wake_all_workers(&self) {
+        for unparker in &self.unparkers {
+            unparker.unpark();
+        }
     }
 }
 
+pub struct SharedThreadPool {
+    shared: std::sync::Arc<PoolShared>,
+    threads: Vec<std::thread::JoinHandle<()>>,
+    scheduler: Scheduler,
+}
+
 
 /// A single thread pool worker function.
-/// In polls the injector dequeue for new jobs in a loop.
-/// If a terminate message is received, it exits.
-fn thread_handle(shared_injector: std::sync::Arc<deque::Injector<SharedMessage>>) {
+/// It runs the jobs of its local deque, refilling it from the injector or stealing from the other workers,
+/// and sleeps until woken up once there are no jobs.
+/// It exits once the pool is stopped, or once the queue is empty if the pool is draining.
+fn thread_handle(shared: std::sync::Arc<PoolShared>, worker_idx: usize, local: deque::Worker<Job>, parker: Parker) {
     loop {
-        if let Some(msg) = steal_msg(&shared_injector) {
-            match msg {
-                SharedMessage::NewJob(job) => {
-                    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job())) {
-                        Ok(_) => {},
-                        Err(err) => {
-                            log::error!("Job in threapool panicked: {}", err.downcast_ref::<&str>().unwrap_or(&""));
-                        }
-                    }
-                },
-                SharedMessage::Terminate => {
-                    log::debug!("Thread pool worker received the terminate signal. Exiting.");
-                    return
-                },
+        if shared.state.load(Ordering::SeqCst) == STATE_STOPPED {
+            // Return the jobs taken in advance, so the pool drops them along with the rest of the queue.
+            while let Some(job) = local.pop() {
+                shared.injector.push(job);
+            }
+            log::debug!("Thread pool worker is stopped. Exiting.");
+            return
+        }
+
+        if let Some(job) = shared.find_job(&local) {
+            // Let a sleeping worker steal the rest of the taken batch meanwhile.
+            if !local.is_empty() {
+                shared.wake_worker();
             }
+            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job())) {
+                Ok(_) => {},
+                Err(err) => {
+                    log::error!("Job in threapool panicked: {}", err.downcast_ref::<&str>().unwrap_or(&""));
+                }
+            }
+        } else if shared.state.load(Ordering::SeqCst) == STATE_DRAINING {
+            log::debug!("Thread pool queue is drained. Exiting.");
+            return
         } else {
-            std::thread::sleep(std::time::Duration::from_millis(10));
+            // Check the queue once more after marking the worker as sleeping,
+            // so a job spawned meanwhile either is found here or wakes the worker up.
+            // The queued jobs include the ones in the local deques of the busy workers, those are stolen.
+            shared.sleeping[worker_idx].store(true, Ordering::SeqCst);
+            if shared.counters.queue_depth() == 0 && shared.state.load(Ordering::SeqCst) == STATE_RUNNING {
+                parker.park();
+            }
+            shared.sleeping[worker_idx].store(false, Ordering::SeqCst);
         }
     }
 }
@@ -51,47 +133,113 @@ fn thread_handle(shared_injector: std::sync::Arc<deque::Injector<SharedMessage>>
 
 /// A shared thread pool of constant size.
 /// Worker threads are preallocated on startup.
-/// A concurrent deque is used to distribute jobs between workers.
+/// Jobs are spawned to a concurrent injector queue, each worker takes them in batches to its local deque
+/// and steals from the other workers once the injector is empty.
 impl SharedThreadPool {
     pub fn new(size: usize) -> Self {
+        Self::with_queue_capacity(size, None)
+    }
+
+    /// Creates a pool queueing at most `queue_capacity` jobs waiting for a free worker.
+    /// Beyond that `spawn` fails with `QueueFullError`, so a flood of jobs cannot exhaust the memory.
+    pub fn bounded(size: usize, queue_capacity: usize) -> Self {
+        Self::with_queue_capacity(size, Some(queue_capacity))
+    }
+
+    fn with_queue_capacity(size: usize, queue_capacity: Option<usize>) -> Self {
         assert!(size > 0, "ThreadPool size must be greater than zero");
 
-        let injector = std::sync::Arc::new(deque::Injector::<SharedMessage>::new());
+        let locals: Vec<deque::Worker<Job>> = (0..size).map(|_| deque::Worker::new_fifo()).collect();
+        let parkers: Vec<Parker> = (0..size).map(|_| Parker::new()).collect();
+        let shared = std::sync::Arc::new(PoolShared {
+            injector: deque::Injector::new(),
+            stealers: locals.iter().map(|local| local.stealer()).collect(),
+            unparkers: parkers.iter().map(|parker| parker.unparker().clone()).collect(),
+            sleeping: (0..size).map(|_| AtomicBool::new(false)).collect(),
+            state: AtomicU8::new(STATE_RUNNING),
+            counters: std::sync::Arc::new(PoolCounters::default()),
+            queue_capacity,
+        });
 
         let mut threads = Vec::with_capacity(size);
-        for _ in 0..size {
-            let injector_ptr = injector.clone();
-            let thread_handle = std::thread::spawn(move || thread_handle(injector_ptr));
+        for (worker_idx, (local, parker)) in locals.into_iter().zip(parkers).enumerate() {
+            let shared_ptr = shared.clone();
+            let thread_handle = std::thread::spawn(move || thread_handle(shared_ptr, worker_idx, local, parker));
             threads.push(thread_handle);
         }
 
+        let scheduler_shared = shared.clone();
         SharedThreadPool {
-            injector: injector,
-            threads: threads,
+            shared,
+            threads,
+            scheduler: Scheduler::new(move |job| scheduler_shared.push(job)),
         }
     }
 }
 
 impl ThreadPool for SharedThreadPool {
-    fn spawn(&mut self, job: Job) -> models::Result<()> {
-        self.injector.push(SharedMessage::NewJob(job));
+    fn spawn(&self, job: Job) -> models::Result<JobHandle> {
+        let (job, handle) = JobHandle::wrap(move || { job(); Ok(()) });
+        self.shared.push(job)?;
+        Ok(handle)
+    }
+
+    fn shutdown(&mut self, mode: ShutdownMode) -> models::Result<()> {
+        if self.threads.is_empty() {
+            return Ok(());
+        }
+        self.scheduler.stop();
+
+        let deadline = match mode {
+            ShutdownMode::Graceful => {
+                self.shared.state.store(STATE_DRAINING, Ordering::SeqCst);
+                self.shared.wake_all_workers();
+                None
+            },
+            ShutdownMode::Immediate => {
+                self.shared.state.store(STATE_STOPPED, Ordering::SeqCst);
+                self.shared.wake_all_workers();
+                None
+            },
+            ShutdownMode::Timeout(timeout) => {
+                self.shared.state.store(STATE_DRAINING, Ordering::SeqCst);
+                self.shared.wake_all_workers();
+                let deadline = std::time::Instant::now() + timeout;
+                while !self.threads.iter().all(|thread| thread.is_finished()) && std::time::Instant::now() < deadline {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                self.shared.state.store(STATE_STOPPED, Ordering::SeqCst);
+                Some(deadline)
+            },
+        };
+
+        base::join_threads(std::mem::take(&mut self.threads), deadline);
+
+        // The stopped workers return the jobs of their local deques to the injector.
+        let dropped_count = std::iter::repeat_with(|| self.shared.injector.steal())
+            .take_while(|steal| !steal.is_empty())
+            .filter(|steal| steal.is_success())
+            .count();
+        self.shared.counters.discard_queued(dropped_count);
+        if dropped_count > 0 {
+            log::warn!("{} queued jobs are dropped on the thread pool shutdown", dropped_count);
+        }
         Ok(())
     }
+
+    fn counters(&self) -> std::sync::Arc<PoolCounters> {
+        self.shared.counters.clone()
+    }
+
+    fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
 }
 
 impl Drop for SharedThreadPool {
     fn drop(&mut self) {
-        for _ in &self.threads {
-            self.injector.push(SharedMessage::Terminate);
-        }
-
-        for thread in self.threads.drain(..) {
-            match thread.join() {
-                Ok(_) => {},
-                Err(err) => {
-                    log::warn!("Thread finished with err: {}", err.downcast_ref::<&str>().unwrap_or(&""));
-                }
-            }
+        if let Err(err) = self.shutdown(ShutdownMode::Graceful) {
+            log::error!("Cannot stop thread pool: {}", err);
         }
     }
 }
@@ -100,7 +248,7 @@ impl Drop for SharedThreadPool {
 #[test]
 fn test_shared_thread_pool_executes_tasks() {
     let pool_size = 4;
-    let mut pool = SharedThreadPool::new(pool_size);
+    let pool = SharedThreadPool::new(pool_size);
 
     let result = std::sync::Arc::new(std::sync::Mutex::new(0));
     let result_clone = std::sync::Arc::clone(&result);
@@ -119,7 +267,7 @@ fn test_shared_thread_pool_executes_tasks() {
 #[test]
 fn test_shared_thread_pool_multiple_tasks() {
     let pool_size = 2;
-    let mut pool = SharedThreadPool::new(pool_size);
+    let pool = SharedThreadPool::new(pool_size);
 
     let counter = std::sync::Arc::new(std::sync::Mutex::new(0));
 
@@ -144,7 +292,7 @@ fn test_shared_thread_pool_teardown() {
     let flag_clone = std::sync::Arc::clone(&flag);
 
     {
-        let mut pool = SharedThreadPool::new(pool_size);
+        let pool = SharedThreadPool::new(pool_size);
         pool.spawn(Box::new(move || {
             std::thread::sleep(std::time::Duration::from_millis(50));
             let mut done = flag_clone.lock().unwrap();
@@ -162,7 +310,7 @@ fn test_shared_thread_pool_panic() {
     let pool_size = 1;
     {
         // Run a panicking job.
-        let mut pool = SharedThreadPool::new(pool_size);
+        let pool = SharedThreadPool::new(pool_size);
         pool.spawn(Box::new(move || { panic!("Thread panicking!"); })).unwrap();
 
         std::thread::sleep(std::time::Duration::from_millis(50));
@@ -182,3 +330,264 @@ fn test_shared_thread_pool_panic() {
     }
 }
 
+
+/// Graceful shutdown should run all the queued jobs, while the immediate one should drop them.
+#[test]
+fn test_shared_thread_pool_shutdown_modes() {
+    for (mode, expected_count) in [(ShutdownMode::Graceful, 10), (ShutdownMode::Immediate, 1)] {
+        let mut pool = SharedThreadPool::new(1);
+        let counter = std::sync::Arc::new(std::sync::Mutex::new(0));
+        let (started_sender, started_receiver) = std::sync::mpsc::channel();
+        for _ in 0..10 {
+            let counter_clone = std::sync::Arc::clone(&counter);
+            let started_sender = started_sender.clone();
+            pool.spawn(Box::new(move || {
+                let _ = started_sender.send(());
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                *counter_clone.lock().unwrap() += 1;
+            })).unwrap();
+        }
+
+        // Wait for the single worker to pick up the first job.
+        started_receiver.recv().unwrap();
+        pool.shutdown(mode).unwrap();
+        assert_eq!(*counter.lock().unwrap(), expected_count);
+        assert!(pool.spawn(Box::new(|| {})).is_err());
+    }
+}
+
+/// Shutdown with a timeout should drop the jobs not started before the deadline and return without waiting for them.
+#[test]
+fn test_shared_thread_pool_shutdown_timeout() {
+    let mut pool = SharedThreadPool::new(1);
+    let (done_sender, done_receiver) = std::sync::mpsc::channel();
+    for _ in 0..10 {
+        let done_sender = done_sender.clone();
+        pool.spawn(Box::new(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            done_sender.send(()).unwrap();
+        })).unwrap();
+    }
+    drop(done_sender);
+
+    let started_at = std::time::Instant::now();
+    pool.shutdown(ShutdownMode::Timeout(std::time::Duration::from_millis(120))).unwrap();
+    assert!(started_at.elapsed() < std::time::Duration::from_millis(300));
+
+    // The job running at the deadline is completed, the rest are dropped.
+    // The channel is closed once the detached worker completes its job and the pool drops the rest.
+    drop(pool);
+    let completed_count = done_receiver.iter().count();
+    assert!((2..=4).contains(&completed_count), "{} jobs are completed", completed_count);
+}
+
+/// A bounded pool should reject the jobs beyond the queue capacity with `QueueFullError`.
+#[test]
+fn test_shared_thread_pool_queue_capacity() {
+    let pool = SharedThreadPool::bounded(1, 2);
+    let (sender, receiver) = std::sync::mpsc::channel::<()>();
+    let (started_sender, started_receiver) = std::sync::mpsc::channel();
+    pool.spawn(Box::new(move || {
+        started_sender.send(()).unwrap();
+        receiver.recv().ok();
+    })).unwrap();
+
+    // Wait for the single worker to pick up the blocked job, so the next ones are queued.
+    started_receiver.recv().unwrap();
+    let queued = [pool.spawn(Box::new(|| {})).unwrap(), pool.spawn(Box::new(|| {})).unwrap()];
+    let err = pool.spawn(Box::new(|| {})).unwrap_err();
+    assert!(err.is::<QueueFullError>());
+
+    drop(sender);
+    for handle in queued {
+        handle.join().unwrap();
+    }
+    pool.spawn(Box::new(|| {})).unwrap();
+}
+
+/// The jobs queued behind a long one should be stolen by the idle workers instead of waiting for it.
+#[test]
+fn test_shared_thread_pool_work_stealing() {
+    let pool = SharedThreadPool::new(2);
+    let counter = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    // The long job is blocked until the channel is closed, so the jobs behind it complete only if stolen.
+    let (sender, receiver) = std::sync::mpsc::channel::<()>();
+    pool.spawn(Box::new(move || { let _ = receiver.recv(); })).unwrap();
+    let handles: Vec<JobHandle> = (0..10).map(|_| {
+        let counter_clone = std::sync::Arc::clone(&counter);
+        pool.spawn(Box::new(move || { counter_clone.fetch_add(1, Ordering::SeqCst); })).unwrap()
+    }).collect();
+
+    for handle in handles {
+        let result = handle.join_timeout(std::time::Duration::from_secs(5));
+        result.expect("job should be stolen by the idle worker").unwrap();
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 10);
+    drop(sender);
+}
+
+/// The pool metrics should count the queued, running, completed and panicked jobs.
+#[test]
+fn test_shared_thread_pool_metrics() {
+    let pool = SharedThreadPool::new(1);
+    let (sender, receiver) = std::sync::mpsc::channel::<()>();
+
+    let (started_sender, started_receiver) = std::sync::mpsc::channel();
+
+    // The only worker is blocked until the channel is closed, so the next jobs stay in the queue.
+    pool.spawn(Box::new(move || {
+        started_sender.send(()).unwrap();
+        let _ = receiver.recv();
+    })).unwrap();
+    started_receiver.recv().unwrap();
+    pool.spawn(Box::new(|| panic!("Job panic"))).unwrap();
+    pool.spawn(Box::new(|| {})).unwrap();
+    let queued_at = std::time::Instant::now();
+
+    let metrics = pool.metrics();
+    assert_eq!(metrics.queue_depth, 2);
+    assert_eq!(metrics.busy_workers, 1);
+    assert_eq!(metrics.completed_jobs, 0);
+
+    let queued_for = queued_at.elapsed();
+    drop(sender);
+    // The counters are updated right after the jobs return.
+    let metrics = loop {
+        let metrics = pool.metrics();
+        if metrics.completed_jobs + metrics.panicked_jobs == 3 {
+            break metrics;
+        }
+        std::thread::yield_now();
+    };
+    assert_eq!(metrics.queue_depth, 0);
+    assert_eq!(metrics.busy_workers, 0);
+    assert_eq!(metrics.completed_jobs, 2);
+    assert_eq!(metrics.panicked_jobs, 1);
+    // Two of the three started jobs waited in the queue at least until the channel was closed.
+    assert!(metrics.avg_wait_time.as_micros() >= queued_for.as_micros() * 2 / 3);
+}
+
+/// The delayed job should be run once its delay passes.
+#[test]
+fn test_shared_thread_pool_spawn_after() {
+    let pool = SharedThreadPool::new(2);
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    let spawned = std::time::Instant::now();
+    pool.spawn_after(std::time::Duration::from_millis(100), Box::new(move || sender.send(()).unwrap())).unwrap();
+
+    assert!(receiver.recv_timeout(std::time::Duration::from_millis(50)).is_err());
+    receiver.recv_timeout(std::time::Duration::from_secs(1)).unwrap();
+    assert!(spawned.elapsed() >= std::time::Duration::from_millis(100));
+}
+
+/// The periodic job should be run every interval, skipping the runs overlapping a slow one, until the pool is shut down.
+#[test]
+fn test_shared_thread_pool_spawn_periodic() {
+    let mut pool = SharedThreadPool::new(2);
+    let runs = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let running = std::sync::Arc::new(AtomicBool::new(false));
+    let overlapped = std::sync::Arc::new(AtomicBool::new(false));
+
+    let (run_sender, run_receiver) = std::sync::mpsc::channel();
+
+    let (runs_clone, running_clone, overlapped_clone) = (runs.clone(), running.clone(), overlapped.clone());
+    pool.spawn_periodic(std::time::Duration::from_millis(20), Box::new(move || {
+        let _ = run_sender.send(());
+        if running_clone.swap(true, Ordering::SeqCst) {
+            overlapped_clone.store(true, Ordering::SeqCst);
+        }
+        // Every other run is longer than the interval.
+        if runs_clone.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        running_clone.store(false, Ordering::SeqCst);
+    })).unwrap();
+
+    for _ in 0..5 {
+        run_receiver.recv_timeout(std::time::Duration::from_secs(5)).expect("periodic job should be run");
+    }
+    pool.shutdown(ShutdownMode::Graceful).unwrap();
+    let runs_count = runs.load(Ordering::SeqCst);
+    assert!(runs_count >= 5, "only {} runs", runs_count);
+    assert!(!overlapped.load(Ordering::SeqCst));
+
+    // The periodic job is dropped on shutdown, which closes the channel after the runs made so far.
+    let sent_count = 5 + run_receiver.try_iter().count();
+    let closed = run_receiver.recv_timeout(std::time::Duration::from_secs(5));
+    assert_eq!(closed, Err(std::sync::mpsc::RecvTimeoutError::Disconnected));
+    assert_eq!(sent_count, runs_count);
+    assert_eq!(runs.load(Ordering::SeqCst), runs_count);
+    assert!(pool.spawn_after(std::time::Duration::ZERO, Box::new(|| {})).is_err());
+}
+
+/// The job handles should report the job results, panics and the jobs dropped on shutdown.
+#[test]
+fn test_shared_thread_pool_job_handles() {
+    use base::ThreadPoolExt;
+    let mut pool = SharedThreadPool::new(1);
+
+    let value = pool.spawn_task(|| Ok(42)).unwrap();
+    let failed = pool.spawn_task::<()>(|| Err(Box::from("Job error"))).unwrap();
+    let panicked = pool.spawn(Box::new(|| panic!("Job panic"))).unwrap();
+    assert_eq!(value.join().unwrap(), 42);
+    assert_eq!(failed.join().unwrap_err().to_string(), "Job error");
+    assert_eq!(panicked.join().unwrap_err().to_string(), "Job panicked: Job panic");
+
+    // The only worker is blocked, so the next job is still queued on shutdown.
+    let (sender, receiver) = std::sync::mpsc::channel::<()>();
+    let blocking = pool.spawn(Box::new(move || { let _ = receiver.recv_timeout(std::time::Duration::from_millis(100)); })).unwrap();
+    let queued = pool.spawn(Box::new(|| {})).unwrap();
+    assert!(queued.join_timeout(std::time::Duration::from_millis(20)).is_none());
+
+    pool.shutdown(ShutdownMode::Immediate).unwrap();
+    drop(sender);
+    assert!(blocking.join().is_ok());
+    assert_eq!(queued.join_timeout(std::time::Duration::ZERO).unwrap().unwrap_err().to_string(), "Job is dropped without running");
+}
+
+/// A job spawned to a pool of parked workers should wake one of them up and run.
+#[test]
+fn test_shared_thread_pool_idle_wakeup() {
+    let pool = SharedThreadPool::new(4);
+
+    for _ in 0..10 {
+        // Let the workers park.
+        while !pool.shared.sleeping.iter().all(|sleeping| sleeping.load(Ordering::SeqCst)) {
+            std::thread::yield_now();
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool.spawn(Box::new(move || sender.send(()).unwrap())).unwrap();
+        // The parked workers never wake up on their own, so the job runs only if a worker is woken up.
+        receiver.recv_timeout(std::time::Duration::from_secs(5)).expect("job should be started by a woken up worker");
+    }
+}
+
+/// The pool should accept closures from several threads sharing it without a mutex.
+#[test]
+fn test_shared_thread_pool_shared_spawn() {
+    use base::ThreadPoolExt;
+    let pool = std::sync::Arc::new(SharedThreadPool::new(2));
+    let counter = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    let producers: Vec<_> = (0..4).map(|_| {
+        let pool = pool.clone();
+        let counter = counter.clone();
+        std::thread::spawn(move || {
+            (0..10)
+                .map(|_| {
+                    let counter = counter.clone();
+                    pool.execute(move || { counter.fetch_add(1, Ordering::SeqCst); }).unwrap()
+                })
+                .collect::<Vec<_>>()
+        })
+    }).collect();
+
+    for producer in producers {
+        for handle in producer.join().unwrap() {
+            handle.join().unwrap();
+        }
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 40);
+}
@@ -0,0 +1,122 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+
+use crate::models::Result;
+
+/// Server certificate verification mode used by the client.
+#[derive(Clone)]
+pub enum TlsVerification {
+    /// Verify the server certificate chain against a PEM-encoded CA certificate file.
+    CaCert(PathBuf),
+    /// Accept any server certificate. Only suitable for testing and local development.
+    Insecure,
+}
+
+/// Client-side TLS options.
+#[derive(Clone)]
+pub struct TlsClientOptions {
+    pub verification: TlsVerification,
+    /// Name to verify the server certificate against. The connection host is used if not set.
+    pub server_name: Option<String>,
+}
+
+/// Builds a server TLS config from PEM-encoded certificate chain and private key files.
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<Arc<rustls::ServerConfig>> {
+    let certs = load_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("Cannot read private key {}: {}", key_path.display(), e))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// Builds a client TLS config according to the verification options.
+pub(crate) fn client_config(options: &TlsClientOptions) -> Result<Arc<rustls::ClientConfig>> {
+    let config = match &options.verification {
+        TlsVerification::CaCert(ca_path) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in load_certs(ca_path)? {
+                roots.add(cert)?;
+            }
+            rustls::ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth()
+        },
+        TlsVerification::Insecure => {
+            log::warn!("TLS server certificate verification is disabled");
+            rustls::ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoCertVerification::new()))
+                .with_no_client_auth()
+        },
+    };
+    Ok(Arc::new(config))
+}
+
+/// Reads all the certificates from a PEM file.
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| format!("Cannot read certificates {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(Box::from(format!("No certificates found in {}", path.display())));
+    }
+    Ok(certs)
+}
+
+/// Certificate verifier accepting any server certificate.
+/// Handshake signatures are still checked, so the peer must own the presented certificate key.
+#[derive(Debug)]
+struct NoCertVerification {
+    algorithms: rustls::crypto::WebPkiSupportedAlgorithms,
+}
+
+impl NoCertVerification {
+    fn new() -> Self {
+        NoCertVerification {
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        }
+    }
+}
+
+impl ServerCertVerifier for NoCertVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
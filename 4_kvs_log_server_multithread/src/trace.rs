@@ -0,0 +1,83 @@
+use std::cell::RefCell;
+
+thread_local! {
+    /// Fields of the spans entered on the current thread, space separated.
+    static CURRENT_SPAN: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Generates a random id of a request to tell its log lines apart.
+pub fn new_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Fields of the spans entered on the current thread, e.g. `conn=1 req=9f1c2a3b4d5e6f70`.
+pub fn current_span() -> Option<String> {
+    CURRENT_SPAN.with(|span| span.borrow().clone())
+}
+
+/// Logging context of the current thread, printed by `SpanLogger` in front of each log line.
+/// Entering a span appends its fields to the enclosing span, dropping it restores the enclosing one.
+pub struct Span {
+    previous: Option<String>,
+}
+
+impl Span {
+    pub fn enter(fields: String) -> Span {
+        CURRENT_SPAN.with(|span| {
+            let mut span = span.borrow_mut();
+            let fields = match span.as_ref() {
+                Some(enclosing) => format!("{} {}", enclosing, fields),
+                None => fields,
+            };
+            Span { previous: span.replace(fields) }
+        })
+    }
+
+    /// Enters the `fields` span as is, e.g. the span of another thread passed with `current_span`.
+    pub fn enter_copy(fields: Option<String>) -> Span {
+        CURRENT_SPAN.with(|span| Span { previous: std::mem::replace(&mut *span.borrow_mut(), fields) })
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        CURRENT_SPAN.with(|span| *span.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Logger prefixing the log lines with the fields of the current span, so all the lines of a single request
+/// can be found by its id. The lines are written by the wrapped logger.
+pub struct SpanLogger<L: log::Log> {
+    inner: L,
+}
+
+impl<L: log::Log> SpanLogger<L> {
+    pub fn new(inner: L) -> SpanLogger<L> {
+        SpanLogger { inner }
+    }
+}
+
+impl<L: log::Log> log::Log for SpanLogger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        match current_span() {
+            Some(span) => self.inner.log(
+                &log::Record::builder()
+                    .args(format_args!("[{}] {}", span, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
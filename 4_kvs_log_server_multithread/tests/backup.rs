@@ -0,0 +1,313 @@
+use tempfile::TempDir;
+
+use rust_kvs_server::{models, storage};
+
+// Should restore the backed up values into an empty directory.
+#[test]
+fn backup_and_restore() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let restore_dir = TempDir::new().expect("unable to create temporary restore directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key2".to_owned())?;
+
+    let manifest = store.backup(backup_dir.path())?;
+    assert_eq!(manifest.generation, 1);
+    assert_eq!(manifest.copied_segments().len(), 1);
+
+    storage::restore_backup(backup_dir.path(), restore_dir.path())?;
+    let restored = storage::KvLogStorage::open(restore_dir.path())?;
+    assert_eq!(restored.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(restored.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should copy only the segments changed since the previous backup
+// and restore the base backup layered with the increments.
+#[test]
+fn incremental_backup() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let restore_dir = TempDir::new().expect("unable to create temporary restore directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    // Fill more than a single segment with unique keys, so there is nothing to compact.
+    let values_count = 12;
+    let value_size = 400_000;
+    for idx in 0..values_count {
+        store.set(format!("key{}", idx), idx.to_string().repeat(value_size))?;
+    }
+
+    let manifest = store.backup(backup_dir.path())?;
+    assert_eq!(manifest.generation, 1);
+    assert_eq!(manifest.segments.len(), 2);
+    assert_eq!(manifest.copied_segments().len(), 2);
+
+    // Nothing changed - nothing to copy.
+    let manifest = store.backup(backup_dir.path())?;
+    assert_eq!(manifest.generation, 2);
+    assert_eq!(manifest.copied_segments().len(), 0);
+
+    // Only the active segment is changed.
+    store.set("small_key".to_owned(), "small_value".to_owned())?;
+    let manifest = store.backup(backup_dir.path())?;
+    assert_eq!(manifest.generation, 3);
+    assert_eq!(manifest.segments.len(), 2);
+    let copied = manifest.copied_segments();
+    assert_eq!(copied.len(), 1);
+    assert_eq!(copied[0].file_idx, 2);
+
+    storage::restore_backup(backup_dir.path(), restore_dir.path())?;
+    let restored = storage::KvLogStorage::open(restore_dir.path())?;
+    for idx in 0..values_count {
+        assert_eq!(restored.get(format!("key{}", idx))?, Some(idx.to_string().repeat(value_size)));
+    }
+    assert_eq!(restored.get("small_key".to_owned())?, Some("small_value".to_owned()));
+
+    Ok(())
+}
+
+// Should refuse to restore into a non-empty directory.
+#[test]
+fn restore_to_non_empty_dir() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.backup(backup_dir.path())?;
+
+    assert!(storage::restore_backup(backup_dir.path(), temp_dir.path()).is_err());
+    Ok(())
+}
+
+// Should switch the live storage to the prepared backup only on commit.
+#[test]
+fn prepare_and_commit_restore() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.backup(backup_dir.path())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+
+    // Prepared data is not visible yet.
+    let token = store.prepare_restore(backup_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+
+    store.commit_restore(&token)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    // The token cannot be committed twice.
+    assert!(store.commit_restore(&token).is_err());
+
+    // The restored data is persistent and writable.
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+
+    Ok(())
+}
+
+// Should keep the live storage and clean up the staged data on abort.
+#[test]
+fn prepare_and_abort_restore() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.backup(backup_dir.path())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+
+    let token = store.prepare_restore(backup_dir.path())?;
+    store.abort_restore(&token)?;
+    assert!(store.commit_restore(&token).is_err());
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+
+    // Only the log file, the engine file, the lock file and the manifest are left in the storage directory.
+    assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 4);
+
+    Ok(())
+}
+
+// Should refuse to prepare a restore without a valid backup.
+#[test]
+fn prepare_restore_without_backup() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    assert!(store.prepare_restore(backup_dir.path()).is_err());
+    assert!(store.abort_restore("../../etc").is_err());
+    Ok(())
+}
+
+// Should replace the storage files with the backup and keep the unrelated files.
+#[test]
+fn restore_replaces_storage() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.backup(backup_dir.path())?;
+
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+    std::fs::write(temp_dir.path().join("notes.txt"), b"unrelated")?;
+
+    let manifest = storage::KvLogStorage::restore(backup_dir.path(), temp_dir.path())?;
+    assert_eq!(manifest.generation, 1);
+    assert!(manifest.segments.iter().all(|segment| segment.checksum.is_some()));
+    assert!(temp_dir.path().join("notes.txt").exists());
+
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+// Should refuse a backup with a damaged segment and keep the storage untouched.
+#[test]
+fn restore_damaged_backup() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.backup(backup_dir.path())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    // Flip a byte of the value, the segment size is the same.
+    let segment_path = backup_dir.path().join("gen_1").join("kv_1.log");
+    let mut content = std::fs::read(&segment_path)?;
+    let last = content.len() - 1;
+    content[last] ^= 0xff;
+    std::fs::write(&segment_path, content)?;
+
+    let err = storage::KvLogStorage::restore(backup_dir.path(), temp_dir.path()).unwrap_err();
+    assert!(err.to_string().contains("checksum mismatch"));
+    assert!(!temp_dir.path().join("_restore_staging").exists());
+
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+fn open_sequenced(path: &std::path::Path) -> models::Result<storage::KvLogStorage> {
+    storage::KvLogStorage::builder().record_sequence(true).open(path)
+}
+
+// Should replay the restored log only up to the write with the given sequence number.
+#[test]
+fn restore_until_sequence() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let restore_dir = TempDir::new().expect("unable to create temporary restore directory");
+    let mut store = open_sequenced(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+    assert_eq!(store.sequence(), 4);
+    store.backup(backup_dir.path())?;
+
+    storage::KvLogStorage::restore_until(backup_dir.path(), restore_dir.path(), storage::RecoveryPoint::Sequence(2))?;
+    let mut restored = open_sequenced(restore_dir.path())?;
+    assert_eq!(restored.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(restored.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(restored.sequence(), 2);
+    restored.set("key3".to_owned(), "value4".to_owned())?;
+    assert_eq!(restored.sequence(), 3);
+    Ok(())
+}
+
+// Should replay the restored log only up to the writes at the given time.
+#[test]
+fn restore_until_timestamp() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let mut store = open_sequenced(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let timestamp_ms = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis() as u64;
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.backup(backup_dir.path())?;
+    drop(store);
+
+    let point = storage::RecoveryPoint::Timestamp(timestamp_ms);
+    storage::KvLogStorage::restore_until(backup_dir.path(), temp_dir.path(), point)?;
+    let store = open_sequenced(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+// Should refuse a recovery point within the compacted log files or a log without sequence records.
+#[test]
+fn restore_until_compacted() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let restore_dir = TempDir::new().expect("unable to create temporary restore directory");
+    let mut store = storage::KvLogStorage::builder().record_sequence(true).segment_size(200).open(temp_dir.path())?;
+    for i in 0..20 {
+        store.set(format!("key{}", i), "stale".to_owned())?;
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.compact()?;
+    store.backup(backup_dir.path())?;
+
+    let err = storage::KvLogStorage::restore_until(
+        backup_dir.path(), restore_dir.path(), storage::RecoveryPoint::Sequence(1),
+    ).unwrap_err();
+    assert!(err.to_string().contains("compacted"));
+    storage::KvLogStorage::restore_until(backup_dir.path(), restore_dir.path(), storage::RecoveryPoint::Sequence(40))?;
+    let restored = storage::KvLogStorage::open(restore_dir.path())?;
+    assert_eq!(restored.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(restored.get("key19".to_owned())?, Some("value19".to_owned()));
+    drop(restored);
+
+    let plain_dir = TempDir::new().expect("unable to create temporary working directory");
+    let plain_backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let mut plain = storage::KvLogStorage::open(plain_dir.path())?;
+    plain.set("key1".to_owned(), "value1".to_owned())?;
+    plain.backup(plain_backup_dir.path())?;
+    drop(plain);
+    let err = storage::KvLogStorage::restore_until(
+        plain_backup_dir.path(), plain_dir.path(), storage::RecoveryPoint::Sequence(1),
+    ).unwrap_err();
+    assert!(err.to_string().contains("no sequence records"));
+    Ok(())
+}
+
+// Should keep increasing the sequence numbers across resets and reopens.
+#[test]
+fn sequence_after_reset() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = open_sequenced(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.reset()?;
+    assert_eq!(store.sequence(), 3);
+    drop(store);
+
+    let mut store = open_sequenced(temp_dir.path())?;
+    assert_eq!(store.sequence(), 3);
+    assert_eq!(store.get("key1".to_owned())?, None);
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    assert_eq!(store.sequence(), 4);
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use assert_cmd::prelude::*;
+use std::process::Command;
+use tempfile::TempDir;
+
+use rust_kvs_server::{models, storage};
+use rust_kvs_server::storage::ChangeEvent;
+
+fn open_sequenced(path: &std::path::Path) -> models::Result<storage::KvLogStorage> {
+    storage::KvLogStorage::builder().record_sequence(true).open(path)
+}
+
+fn set_event(key: &str, value: &str) -> ChangeEvent {
+    ChangeEvent::Set { key: key.to_owned(), value: value.as_bytes().to_vec() }
+}
+
+// Should read the changes in the order of the writes and then only the new ones.
+#[test]
+fn cdc_reads_changes() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = open_sequenced(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key1".to_owned())?;
+
+    let mut reader = storage::CdcReader::open(temp_dir.path(), 0)?;
+    let events = reader.poll()?;
+    let changes: Vec<ChangeEvent> = events.iter().map(|event| event.change.clone()).collect();
+    assert_eq!(changes, vec![
+        set_event("key1", "value1"), set_event("key2", "value2"), ChangeEvent::Remove { key: "key1".to_owned() },
+    ]);
+    assert_eq!(events.iter().map(|event| event.seq).collect::<Vec<u64>>(), vec![1, 2, 3]);
+    assert!(events.iter().all(|event| event.timestamp_ms > 0));
+    assert!(reader.poll()?.is_empty());
+
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    let events = reader.poll()?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].seq, 4);
+    assert_eq!(events[0].change, set_event("key3", "value3"));
+    assert_eq!(reader.seq(), 4);
+    Ok(())
+}
+
+// Should skip the changes up to the sequence number and follow the log across the log files.
+#[test]
+fn cdc_resume_from_sequence() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder().record_sequence(true).segment_size(200).open(temp_dir.path())?;
+    for i in 0..30 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let mut reader = storage::CdcReader::open(temp_dir.path(), 10)?;
+    let events = reader.poll()?;
+    assert_eq!(events.len(), 20);
+    for (i, event) in events.iter().enumerate() {
+        assert_eq!(event.seq, i as u64 + 11);
+        assert_eq!(event.change, set_event(&format!("key{}", i + 10), &format!("value{}", i + 10)));
+    }
+    Ok(())
+}
+
+// Should refuse to resume from a sequence number within the compacted log files.
+#[test]
+fn cdc_resume_within_compacted() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder().record_sequence(true).segment_size(200).open(temp_dir.path())?;
+    for i in 0..20 {
+        store.set(format!("key{}", i), "stale".to_owned())?;
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.compact()?;
+
+    let mut reader = storage::CdcReader::open(temp_dir.path(), 1)?;
+    let err = reader.poll().unwrap_err();
+    assert!(err.to_string().contains("compacted"));
+
+    // The compacted files are read as the latest values of their keys.
+    let mut reader = storage::CdcReader::open(temp_dir.path(), 0)?;
+    let mut mirror = std::collections::HashMap::new();
+    for event in reader.poll()? {
+        if let ChangeEvent::Set { key, value } = event.change {
+            mirror.insert(key, value);
+        }
+    }
+    assert_eq!(mirror.len(), 20);
+    assert!(mirror.values().all(|value| value != b"stale"));
+    Ok(())
+}
+
+// Should report a reset of the storage followed by the new changes.
+#[test]
+fn cdc_reset() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = open_sequenced(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let mut reader = storage::CdcReader::open(temp_dir.path(), 0)?;
+    assert_eq!(reader.poll()?.len(), 1);
+
+    store.reset()?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    let events = reader.poll()?;
+    let changes: Vec<ChangeEvent> = events.iter().map(|event| event.change.clone()).collect();
+    assert_eq!(changes, vec![ChangeEvent::Reset, set_event("key2", "value2")]);
+    assert_eq!(events[1].seq, 3);
+    Ok(())
+}
+
+// Should serialize the changes as JSON lines, hex-encoding the binary values.
+#[test]
+fn cdc_export_json() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = open_sequenced(temp_dir.path())?;
+    store.set("key\"1".to_owned(), "value1".to_owned())?;
+    store.set_bytes("key2".to_owned(), vec![0xff, 0x00])?;
+    store.remove("key2".to_owned())?;
+
+    let mut reader = storage::CdcReader::open(temp_dir.path(), 0)?;
+    let mut sink = Vec::new();
+    assert_eq!(reader.export(&mut sink)?, 3);
+    let output = String::from_utf8(sink)?;
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("{\"seq\":1,\"timestamp_ms\":"));
+    assert!(lines[0].ends_with(",\"op\":\"set\",\"key\":\"key\\\"1\",\"value\":\"value1\"}"));
+    assert!(lines[1].ends_with(",\"op\":\"set\",\"key\":\"key2\",\"value_hex\":\"ff00\"}"));
+    assert!(lines[2].ends_with(",\"op\":\"remove\",\"key\":\"key2\"}"));
+    Ok(())
+}
+
+// `kvs_server cdc` should export the changes after the sequence number to a file.
+#[test]
+fn cdc_cli_export() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = open_sequenced(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    let output_path = temp_dir.path().join("changes.jsonl");
+    Command::cargo_bin("kvs_server").unwrap()
+        .args(["--path", temp_dir.path().to_str().unwrap(), "cdc", "--from-seq", "1"])
+        .args(["--output", output_path.to_str().unwrap()])
+        .assert()
+        .success();
+    let output = std::fs::read_to_string(&output_path)?;
+    assert_eq!(output.lines().count(), 1);
+    assert!(output.contains("\"seq\":2,"));
+    assert!(output.contains("\"key\":\"key2\",\"value\":\"value2\""));
+
+    Command::cargo_bin("kvs_server").unwrap()
+        .args(["--path", temp_dir.path().to_str().unwrap(), "cdc"])
+        .assert()
+        .failure();
+    Ok(())
+}
@@ -131,3 +131,18 @@ fn client_cli_version() {
         .assert()
         .stdout(contains(env!("CARGO_PKG_VERSION")));
 }
+
+// `kvs_client exec` should reject an invalid commands file before connecting to the server.
+#[test]
+fn client_cli_invalid_exec_file() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(temp_dir.path().join("commands.txt"), "set key1 value1\nget\n").unwrap();
+
+    Command::cargo_bin("kvs_client")
+        .unwrap()
+        .args(&["exec", "--file", "commands.txt"])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(1)
+        .stderr(contains("Line 2: Invalid command 'get'"));
+}
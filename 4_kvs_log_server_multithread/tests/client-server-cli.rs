@@ -24,10 +24,16 @@ impl Drop for ServerGuard {
 
 
 fn run_server(dir: &tempfile::TempDir, host: &str, port: u32) -> ServerGuard {
+    run_server_with_args(dir, host, port, &[])
+}
+
+
+fn run_server_with_args(dir: &tempfile::TempDir, host: &str, port: u32, args: &[&str]) -> ServerGuard {
     let (sender, receiver) = std::sync::mpsc::sync_channel::<()>(0);
     let mut server = Command::cargo_bin("kvs_server").unwrap();
     let mut child = server
         .args(&["--host", host, "--port", &port.to_string(), "-l", "debug"])
+        .args(args)
         .current_dir(&dir)
         .spawn()
         .unwrap();
@@ -175,6 +181,121 @@ fn kvs_remove_missing_key() {
 }
 
 
+#[serial_test::serial]
+#[test]
+fn kvs_append() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    // Append to a missing key creates it.
+    run_client_cmd(&temp_dir, HOST, PORT, &["append", "key", "value"])
+        .stdout(contains("APPEND OK 5"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["append", "key", "123"])
+        .stdout(contains("APPEND OK 8"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "key"])
+        .stdout(contains("GET OK value123"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_conditional_set() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["set-nx", "key", "value1"])
+        .stdout(contains("SET NX OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["set-nx", "key", "value2"])
+        .stdout(contains("SET NX EXISTS"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get-set", "key", "value3"])
+        .stdout(contains("GET SET OK value1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get-set", "missing", "value4"])
+        .stdout(contains("GET SET NONE"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "key"])
+        .stdout(contains("GET OK value3"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_list() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["rpush", "list", "b", "c"])
+        .stdout(contains("LIST PUSH OK 2"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["lpush", "list", "a"])
+        .stdout(contains("LIST PUSH OK 3"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["lrange", "list", "0", "-1"])
+        .stdout(contains("LIST RANGE OK a b c"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["lpop", "list"])
+        .stdout(contains("LIST POP OK a"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["rpop", "list"])
+        .stdout(contains("LIST POP OK c"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["rpop", "missing"])
+        .stdout(contains("LIST POP NONE"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_hash() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["hset", "hash", "a", "1"])
+        .stdout(contains("HASH SET OK 1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["hset", "hash", "b", "2"])
+        .stdout(contains("HASH SET OK 1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["hget", "hash", "a"])
+        .stdout(contains("HASH GET OK 1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["hdel", "hash", "a", "c"])
+        .stdout(contains("HASH DELETE OK 1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["hgetall", "hash"])
+        .stdout(contains("HASH GET ALL OK b=2"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_set_type() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["sadd", "tags", "b", "a", "b"])
+        .stdout(contains("SET ADD OK 2"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["sismember", "tags", "a"])
+        .stdout(contains("SET IS MEMBER TRUE"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["sismember", "tags", "c"])
+        .stdout(contains("SET IS MEMBER FALSE"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["smembers", "tags"])
+        .stdout(contains("SET MEMBERS OK a b"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["srem", "tags", "a", "c"])
+        .stdout(contains("SET REMOVE OK 1"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_sorted_set() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["zadd", "board", "2.5", "a"])
+        .stdout(contains("SORTED SET ADD OK 1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["zadd", "board", "-1", "b"])
+        .stdout(contains("SORTED SET ADD OK 1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["zadd", "board", "1", "a"])
+        .stdout(contains("SORTED SET ADD OK 0"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["zrangebyscore", "board", "-inf", "inf"])
+        .stdout(contains("SORTED SET RANGE OK b=-1 a=1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["zrem", "board", "b", "c"])
+        .stdout(contains("SORTED SET REMOVE OK 1"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["zrangebyscore", "board", "0", "10"])
+        .stdout(contains("SORTED SET RANGE OK a=1"));
+}
+
+
 #[serial_test::serial]
 #[test]
 fn kvs_reset() {
@@ -206,3 +327,207 @@ fn kvs_reset() {
     run_client_cmd(&temp_dir, HOST, PORT, &["get", "key2"])
         .stdout(contains("GET NONE"));
 }
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_two_phase_restore() {
+    let temp_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+    let backup_path = backup_dir.path().to_string_lossy().into_owned();
+
+    // Prepare a backup of another storage.
+    let source_dir = TempDir::new().unwrap();
+    let mut source = rust_kvs_server::KvLogStorage::open(source_dir.path()).unwrap();
+    source.set("key1".to_owned(), "restored".to_owned()).unwrap();
+    source.backup(backup_dir.path()).unwrap();
+
+    let _server_guard = run_server_with_args(&temp_dir, HOST, PORT, &["--backup-root", &backup_path]);
+    run_client_cmd(&temp_dir, HOST, PORT, &["set", "key1", "live"])
+        .stdout(contains("SET OK"));
+
+    // Backups outside of the backup root are rejected.
+    Command::cargo_bin("kvs_client")
+        .unwrap()
+        .args(&["--host", HOST, "--port", &PORT.to_string(), "prepare-restore", ".."])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("outside of the backup root"));
+
+    // Aborted restore doesn't change the data.
+    let output = run_client_cmd(&temp_dir, HOST, PORT, &["prepare-restore", &backup_path])
+        .stdout(contains("PREPARE RESTORE OK"))
+        .get_output()
+        .stdout
+        .clone();
+    let token = String::from_utf8_lossy(&output).split_whitespace().last().unwrap().to_owned();
+    run_client_cmd(&temp_dir, HOST, PORT, &["abort-restore", &token])
+        .stdout(contains("ABORT RESTORE OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "key1"])
+        .stdout(contains("live"));
+
+    // Committed restore switches the data.
+    let output = run_client_cmd(&temp_dir, HOST, PORT, &["prepare-restore", &backup_path])
+        .get_output()
+        .stdout
+        .clone();
+    let token = String::from_utf8_lossy(&output).split_whitespace().last().unwrap().to_owned();
+    run_client_cmd(&temp_dir, HOST, PORT, &["commit-restore", &token])
+        .stdout(contains("COMMIT RESTORE OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "key1"])
+        .stdout(contains("restored"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_command_error() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    Command::cargo_bin("kvs_client")
+        .unwrap()
+        .args(&["--host", HOST, "--port", &PORT.to_string(), "commit-restore", "0123"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Unknown restore token"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_compact() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["set", "key1", "value1"])
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["compact"])
+        .stdout(contains("COMPACT OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "key1"])
+        .stdout(contains("value1"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_pause_compaction() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["pause-compaction"])
+        .stdout(contains("PAUSE COMPACTION OK"));
+    Command::cargo_bin("kvs_client")
+        .unwrap()
+        .args(&["--host", HOST, "--port", &PORT.to_string(), "compact"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("is paused"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["resume-compaction"])
+        .stdout(contains("RESUME COMPACTION OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["compact"])
+        .stdout(contains("COMPACT OK"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_reset_prefix() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["set", "test/key1", "value1"])
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["set", "test/key2", "value2"])
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["set", "prod/key1", "value3"])
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["reset-prefix", "test/"])
+        .stdout(contains("RESET PREFIX OK 2"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "test/key1"])
+        .stdout(contains("GET NONE"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "prod/key1"])
+        .stdout(contains("GET OK value3"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_stats() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["set", "key1", "value1"])
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["stats"])
+        .stdout(contains("STATS OK keys=1 segments=1"))
+        .stdout(contains("last_compaction=never"))
+        .stdout(contains("compacted_segments=0 reclaimed_size=0 compaction_failures=0"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_info() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["set", "key1", "value1"])
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["pause-compaction"])
+        .stdout(contains("PAUSE COMPACTION OK"));
+    run_client_cmd(&temp_dir, HOST, PORT, &["info"])
+        .stdout(contains(format!("INFO OK version={} uptime_s=", env!("CARGO_PKG_VERSION"))))
+        .stdout(contains("compaction_paused=true keys=1 segments=1"))
+        .stdout(contains("pool_busy_workers=1"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_exec_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    let mut commands = String::from("# Seed data\nset key1 value with spaces\n\nget key1\nremove key1\nget key1\n");
+    for idx in 0..1500 {
+        commands.push_str(&format!("set seed{} value{}\n", idx, idx));
+    }
+    commands.push_str("get seed1499\n");
+    std::fs::write(temp_dir.path().join("commands.txt"), commands).unwrap();
+
+    run_client_cmd(&temp_dir, HOST, PORT, &["exec", "--file", "commands.txt"])
+        .stdout(contains("Line 2: SET OK"))
+        .stdout(contains("Line 4: GET OK value with spaces"))
+        .stdout(contains("Line 5: REMOVE OK"))
+        .stdout(contains("Line 6: GET NONE"))
+        .stdout(contains("Line 1507: GET OK value1499"))
+        .stdout(contains("EXEC OK 1505 commands"));
+}
+
+
+#[serial_test::serial]
+#[test]
+fn kvs_bench_run() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, HOST, PORT);
+
+    Command::cargo_bin("kvs_bench")
+        .unwrap()
+        .args(&["--host", HOST, "--port", &PORT.to_string(),
+                "--key-count", "100", "--concurrency", "2", "--duration", "1", "--read-ratio", "0.9"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("Running 2 clients"))
+        .stdout(contains("0 errors"))
+        .stdout(contains("Read: "))
+        .stdout(contains("p999"));
+
+    // The load phase sets all the keys.
+    run_client_cmd(&temp_dir, HOST, PORT, &["get", "key99"])
+        .stdout(contains("GET OK"));
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use tempfile::TempDir;
+
+use rust_kvs_server::{models, storage, threads, ClusterKvsClient, KvsClient, KvsServer};
+use rust_kvs_server::server::ShutdownHandle;
+
+
+const HOST: &str = "127.0.0.1";
+const PORTS: [u32; 3] = [4013, 4014, 4015];
+
+fn start_server(dir: &TempDir, port: u32) -> (ShutdownHandle, std::thread::JoinHandle<Result<(), String>>) {
+    let storage_path = dir.path().to_path_buf();
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    // The server is created in the server thread, which owns it until the shutdown.
+    let handle = std::thread::spawn(move || {
+        let engine = storage::KvLogStorage::open(&storage_path).map_err(|e| e.to_string())?;
+        let thread_pool = Box::new(threads::shared::SharedThreadPool::new(2));
+        let mut server = KvsServer::new(engine, thread_pool);
+        sender.send(server.shutdown_handle()).unwrap();
+        server.listen(HOST.to_owned(), port).map_err(|e| e.to_string())
+    });
+    let shutdown_handle = receiver.recv().unwrap();
+    (shutdown_handle, handle)
+}
+
+fn get_from_server(port: u32, key: &str) -> models::Result<Option<Vec<u8>>> {
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), port, Duration::from_secs(5))?;
+    let response = client.execute_one(models::Command::Get { key: key.to_owned() }, false)?;
+    match response.commands.into_iter().next() {
+        Some(models::ResponseCommand::Get { value }) => Ok(value),
+        _ => Err(Box::from("Unexpected response")),
+    }
+}
+
+// Keys should be spread across the servers and routed to the next server once their server is down.
+#[serial_test::serial]
+#[test]
+fn consistent_hashing() -> models::Result<()> {
+    let dirs: Vec<TempDir> = PORTS.iter().map(|_| TempDir::new().unwrap()).collect();
+    let servers: Vec<_> = dirs.iter().zip(PORTS).map(|(dir, port)| start_server(dir, port)).collect();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let addrs = PORTS.iter().map(|port| (HOST.to_owned(), *port)).collect();
+    let mut cluster = ClusterKvsClient::new(addrs, Duration::from_secs(5))?;
+    let keys: Vec<String> = (0..60).map(|idx| format!("key{}", idx)).collect();
+    for key in &keys {
+        cluster.set(key.clone(), key.as_bytes().to_vec())?;
+    }
+
+    // Each key is stored on its own server only.
+    let mut keys_per_server = vec![0; PORTS.len()];
+    for key in &keys {
+        let owner = cluster.node_for(key).unwrap();
+        for (idx, port) in PORTS.iter().enumerate() {
+            let is_owner = owner == format!("{}:{}", HOST, port);
+            assert_eq!(get_from_server(*port, key)?.is_some(), is_owner);
+            if is_owner {
+                keys_per_server[idx] += 1;
+            }
+        }
+    }
+    assert!(keys_per_server.iter().all(|count| *count > 0));
+    cluster.remove("key0".to_owned())?;
+    assert_eq!(cluster.get("key0".to_owned())?, None);
+
+    // Stop a server: its keys move to the rest of the servers, the other keys stay in place.
+    let (shutdown_handle, server_thread) = &servers[0];
+    shutdown_handle.shutdown();
+    let stopped_addr = format!("{}:{}", HOST, PORTS[0]);
+    let owners: Vec<String> = keys.iter().map(|key| cluster.node_for(key).unwrap()).collect();
+    while !server_thread.is_finished() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    for (key, owner) in keys.iter().zip(owners.iter()).skip(1) {
+        let value = cluster.get(key.clone())?;
+        if *owner == stopped_addr {
+            assert_eq!(value, None);
+            cluster.set(key.clone(), key.as_bytes().to_vec())?;
+            assert_eq!(cluster.get(key.clone())?, Some(key.as_bytes().to_vec()));
+        } else {
+            assert_eq!(value, Some(key.as_bytes().to_vec()));
+            assert_eq!(cluster.node_for(key).as_ref(), Some(owner));
+        }
+    }
+    assert!(!cluster.nodes().contains(&stopped_addr));
+
+    for (shutdown_handle, server_thread) in servers {
+        shutdown_handle.shutdown();
+        server_thread.join().unwrap()?;
+    }
+    Ok(())
+}
@@ -1,7 +1,10 @@
+use std::io::Read;
+
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
 use rust_kvs_server::{models, storage};
+use rust_kvs_server::storage::KvStorage;
 
 // Should get previously stored value.
 #[test]
@@ -138,3 +141,1496 @@ fn compaction() -> models::Result<()> {
 
     Ok(())
 }
+
+// Small values should be served from the index without reading the log files.
+#[test]
+fn inline_small_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    let large_value = "v".repeat(1000);
+    store.set("small".to_owned(), "value".to_owned())?;
+    let small_usage = store.index_memory_usage();
+    store.set("large".to_owned(), large_value.clone())?;
+    assert!(store.index_memory_usage() > small_usage);
+
+    // Inlined values are restored from the log on startup.
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("small".to_owned())?, Some("value".to_owned()));
+    assert_eq!(store.get("large".to_owned())?, Some(large_value));
+
+    // Only the large value requires the log file.
+    std::fs::remove_file(temp_dir.path().join("kv_1.log"))?;
+    assert_eq!(store.get("small".to_owned())?, Some("value".to_owned()));
+    assert!(store.get("large".to_owned()).is_err());
+
+    Ok(())
+}
+
+// Compaction should keep both inlined and on-disk values readable.
+#[test]
+fn compaction_with_inline_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    let value_size = 400_000;
+    for idx in 0..12 {
+        store.set(format!("small{}", idx % 3), idx.to_string())?;
+        store.set("large".to_owned(), idx.to_string().repeat(value_size))?;
+    }
+
+    // Wait for compaction of the rotated files.
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    for idx in 9..12 {
+        assert_eq!(store.get(format!("small{}", idx % 3))?, Some(idx.to_string()));
+    }
+    assert_eq!(store.get("large".to_owned())?, Some(11.to_string().repeat(value_size)));
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    for idx in 9..12 {
+        assert_eq!(store.get(format!("small{}", idx % 3))?, Some(idx.to_string()));
+    }
+    assert_eq!(store.get("large".to_owned())?, Some(11.to_string().repeat(value_size)));
+
+    Ok(())
+}
+
+// Should store arbitrary binary values, both inlined and on disk.
+#[test]
+fn binary_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    let small_value = vec![0u8, 159, 146, 150, 255];
+    let large_value: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+    store.set_bytes("small".to_owned(), small_value.clone())?;
+    store.set_bytes("large".to_owned(), large_value.clone())?;
+    assert_eq!(store.get_bytes("small".to_owned())?, Some(small_value.clone()));
+    assert_eq!(store.get_bytes("large".to_owned())?, Some(large_value.clone()));
+
+    // Non UTF-8 values are not available with the string API.
+    assert!(store.get("small".to_owned()).is_err());
+
+    // String values are available as bytes.
+    store.set("string".to_owned(), "value".to_owned())?;
+    assert_eq!(store.get_bytes("string".to_owned())?, Some(b"value".to_vec()));
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get_bytes("small".to_owned())?, Some(small_value));
+    assert_eq!(store.get_bytes("large".to_owned())?, Some(large_value));
+
+    Ok(())
+}
+
+// Should compress large values and read both compressed and uncompressed records.
+#[test]
+fn compressed_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+
+    let large_value = "{\"field\": \"value\"}".repeat(1000);
+    store.set("plain".to_owned(), large_value.clone())?;
+    let plain_size = std::fs::metadata(temp_dir.path().join("kv_1.log"))?.len();
+
+    store.set_compression_threshold(Some(128));
+    store.set("compressed".to_owned(), large_value.clone())?;
+    store.set("small".to_owned(), "value".to_owned())?;
+    let compressed_size = std::fs::metadata(temp_dir.path().join("kv_1.log"))?.len() - plain_size;
+    assert!(compressed_size < plain_size / 2);
+
+    assert_eq!(store.get("plain".to_owned())?, Some(large_value.clone()));
+    assert_eq!(store.get("compressed".to_owned())?, Some(large_value.clone()));
+
+    // Compressed records are read with the compression disabled.
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("plain".to_owned())?, Some(large_value.clone()));
+    assert_eq!(store.get("compressed".to_owned())?, Some(large_value));
+    assert_eq!(store.get("small".to_owned())?, Some("value".to_owned()));
+
+    Ok(())
+}
+
+// Compaction should keep the compressed records readable.
+#[test]
+fn compaction_with_compressed_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    store.set_compression_threshold(Some(128));
+
+    let compressed_value = "compressible".repeat(1000);
+    store.set("compressed".to_owned(), compressed_value.clone())?;
+
+    // Random values are not compressible and fill the first segment to trigger the compaction.
+    let values_count = 10;
+    let value_size = 4_000_000 / values_count;
+    let mut last_value = Vec::new();
+    for _ in 0..values_count {
+        last_value = (0..value_size).map(|_| rand::random::<u8>()).collect();
+        store.set_bytes("key".to_owned(), last_value.clone())?;
+    }
+
+    // Wait for the first segment to shrink after compaction.
+    let first_segment = temp_dir.path().join("kv_1.log");
+    let mut compaction_detected = false;
+    for _ in 0..20 {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        if std::fs::metadata(&first_segment)?.len() < 2 * value_size as u64 {
+            compaction_detected = true;
+            break;
+        }
+    }
+    assert!(compaction_detected, "No compaction detected!");
+
+    assert_eq!(store.get("compressed".to_owned())?, Some(compressed_value.clone()));
+    assert_eq!(store.get_bytes("key".to_owned())?, Some(last_value.clone()));
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("compressed".to_owned())?, Some(compressed_value));
+    assert_eq!(store.get_bytes("key".to_owned())?, Some(last_value));
+
+    Ok(())
+}
+
+// Should apply the builder options and reject the invalid ones.
+#[test]
+fn builder_options() -> models::Result<()> {
+    let log_files_size = |path: &std::path::Path| -> (usize, u64) {
+        let files: Vec<_> = std::fs::read_dir(path).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension() == Some(std::ffi::OsStr::new("log")))
+            .collect();
+        (files.len(), files.iter().map(|path| std::fs::metadata(path).unwrap().len()).sum())
+    };
+    let write_records = |store: &mut storage::KvLogStorage| -> models::Result<()> {
+        for idx in 0..30 {
+            store.set("key".to_owned(), idx.to_string().repeat(100))?;
+        }
+        Ok(())
+    };
+
+    // Small segments are rotated and compacted often.
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(1000)
+        .fsync_policy(storage::FsyncPolicy::Never)
+        .open(temp_dir.path())?;
+    write_records(&mut store)?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let (_, compacted_size) = log_files_size(temp_dir.path());
+    assert_eq!(store.get("key".to_owned())?, Some(29.to_string().repeat(100)));
+
+    // The ratio of 1 disables the compaction on rotation.
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(1000)
+        .compaction_garbage_ratio(1.0)
+        .open(temp_dir.path())?;
+    write_records(&mut store)?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let (_, uncompacted_size) = log_files_size(temp_dir.path());
+    assert!(uncompacted_size > compacted_size);
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some(29.to_string().repeat(100)));
+
+    assert!(storage::KvLogStorage::builder().segment_size(0).open(temp_dir.path()).is_err());
+    assert!(storage::KvLogStorage::builder().compaction_pool_size(0).open(temp_dir.path()).is_err());
+    assert!(storage::KvLogStorage::builder().compaction_garbage_ratio(1.5).open(temp_dir.path()).is_err());
+
+    Ok(())
+}
+
+// Should compact all the sealed log files on demand.
+#[test]
+fn manual_compaction() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log_files_size = || -> u64 {
+        std::fs::read_dir(temp_dir.path()).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension() == Some(std::ffi::OsStr::new("log")))
+            .map(|path| std::fs::metadata(path).unwrap().len())
+            .sum()
+    };
+
+    // The automatic compaction is disabled with the garbage ratio of 1.
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(1000)
+        .compaction_garbage_ratio(1.0)
+        .open(temp_dir.path())?;
+    for idx in 0..30 {
+        store.set(format!("key{}", idx % 2), idx.to_string().repeat(100))?;
+    }
+    store.remove("key0".to_owned())?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let initial_size = log_files_size();
+
+    store.compact()?;
+    assert!(log_files_size() < initial_size);
+    assert_eq!(store.get("key0".to_owned())?, None);
+    assert_eq!(store.get("key1".to_owned())?, Some(29.to_string().repeat(100)));
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key0".to_owned())?, None);
+    assert_eq!(store.get("key1".to_owned())?, Some(29.to_string().repeat(100)));
+
+    Ok(())
+}
+
+// Should report the keys count, the log files and the garbage left by the overwrites.
+#[test]
+fn storage_stats() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(1000)
+        .compaction_garbage_ratio(1.0)
+        .open(temp_dir.path())?;
+    let stats = store.stats()?;
+    assert_eq!((stats.keys_count, stats.live_size, stats.garbage_size), (0, 0, 0));
+    assert_eq!(stats.last_compaction, None);
+    assert_eq!((stats.compacted_segments, stats.reclaimed_size, stats.compaction_failures), (0, 0, 0));
+
+    // A record of a 4 bytes key with a 100 bytes value takes 2 + 1 + (1 + 1 + 4 + 1 + 100) + 4 bytes.
+    // Each log file starts with a 6 bytes header.
+    let record_size = 114;
+    let header_size = 6;
+    for idx in 0..30 {
+        store.set(format!("key{}", idx % 3), format!("{:0>100}", idx))?;
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let stats = store.stats()?;
+    assert_eq!(stats.keys_count, 3);
+    assert!(stats.segments_count > 1);
+    assert_eq!(stats.live_size, 3 * record_size);
+    assert_eq!(stats.disk_size, 30 * record_size + stats.segments_count * header_size);
+    assert_eq!(stats.garbage_size, 27 * record_size + stats.segments_count * header_size);
+
+    // The reclaimed bytes are the difference of the log files size before and after compaction.
+    let disk_size = stats.disk_size;
+    store.compact()?;
+    let stats = store.stats()?;
+    assert_eq!(stats.keys_count, 3);
+    assert!(stats.garbage_size < 27 * record_size);
+    assert!(stats.last_compaction.is_some());
+    assert!(stats.compacted_segments > 0);
+    assert_eq!(stats.reclaimed_size, disk_size - stats.disk_size);
+    assert_eq!(stats.compaction_failures, 0);
+
+    // The value sizes are restored from the log files.
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    let reopened_stats = store.stats()?;
+    assert_eq!(reopened_stats.live_size, stats.live_size);
+    assert_eq!(reopened_stats.disk_size, stats.disk_size);
+    Ok(())
+}
+
+// Subscriptions should receive the changes of the watched keys in the order of the writes.
+#[test]
+fn watch_changes() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    let key_subscription = store.subscribe(storage::WatchFilter::Key("user1".to_owned()));
+    let prefix_subscription = store.subscribe(storage::WatchFilter::Prefix("user".to_owned()));
+
+    store.set("user1".to_owned(), "value1".to_owned())?;
+    store.set("other".to_owned(), "value".to_owned())?;
+    store.set_batch(vec![("user2".to_owned(), b"value2".to_vec())])?;
+    store.remove("user1".to_owned())?;
+    // Removal of a missing key is not a change.
+    store.remove("user3".to_owned())?;
+    store.reset()?;
+
+    let key_events: Vec<_> = std::iter::from_fn(|| key_subscription.try_recv()).collect();
+    assert_eq!(
+        key_events,
+        vec![
+            storage::ChangeEvent::Set { key: "user1".to_owned(), value: b"value1".to_vec() },
+            storage::ChangeEvent::Remove { key: "user1".to_owned() },
+            storage::ChangeEvent::Reset,
+        ],
+    );
+    let prefix_events: Vec<_> = std::iter::from_fn(|| prefix_subscription.try_recv()).collect();
+    assert_eq!(
+        prefix_events,
+        vec![
+            storage::ChangeEvent::Set { key: "user1".to_owned(), value: b"value1".to_vec() },
+            storage::ChangeEvent::Set { key: "user2".to_owned(), value: b"value2".to_vec() },
+            storage::ChangeEvent::Remove { key: "user1".to_owned() },
+            storage::ChangeEvent::Reset,
+        ],
+    );
+
+    // Changes made by the other storage handles are delivered as well, a dropped subscription gets nothing.
+    drop(key_subscription);
+    let mut writer = store.clone();
+    let handle = std::thread::spawn(move || writer.set("user4".to_owned(), "value4".to_owned()).is_ok());
+    assert_eq!(
+        prefix_subscription.recv_timeout(std::time::Duration::from_secs(5)),
+        Some(storage::ChangeEvent::Set { key: "user4".to_owned(), value: b"value4".to_vec() }),
+    );
+    assert!(handle.join().unwrap());
+
+    drop(store);
+    assert_eq!(prefix_subscription.recv(), None);
+    Ok(())
+}
+
+// Sharded storage should spread the keys across the shards and keep them in their shards after reopening.
+#[test]
+fn sharded_storage() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::ShardedKvStorage::open(temp_dir.path(), 4, storage::KvLogStorage::builder())?;
+    for idx in 0..100 {
+        store.set(format!("key{}", idx), format!("value{}", idx))?;
+    }
+    assert!(store.remove("key0".to_owned())?);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key0".to_owned())?, None);
+    assert_eq!(store.keys().len(), 99);
+    assert_eq!(store.stats()?.keys_count, 99);
+    let values = store.get_many(vec!["key2".to_owned(), "key0".to_owned(), "key3".to_owned()])?;
+    assert_eq!(values, vec![Some(b"value2".to_vec()), None, Some(b"value3".to_vec())]);
+
+    // Every shard gets a part of the keys.
+    let mut shard_sizes = vec![0; store.shards_count()];
+    for idx in 1..100 {
+        shard_sizes[store.shard_idx(&format!("key{}", idx))] += 1;
+    }
+    assert!(shard_sizes.iter().all(|size| *size > 0));
+
+    drop(store);
+    for shard_idx in 0..4 {
+        let shard = storage::KvLogStorage::open(&temp_dir.path().join(format!("shard_{}", shard_idx)))?;
+        assert_eq!(shard.keys().len(), shard_sizes[shard_idx]);
+    }
+    assert!(storage::ShardedKvStorage::open(temp_dir.path(), 2, storage::KvLogStorage::builder()).is_err());
+    let mut store = storage::ShardedKvStorage::open(temp_dir.path(), 4, storage::KvLogStorage::builder())?;
+    for idx in 1..100 {
+        assert_eq!(store.get(format!("key{}", idx))?, Some(format!("value{}", idx)));
+    }
+
+    store.reset()?;
+    assert!(store.keys().is_empty());
+    Ok(())
+}
+
+// An unsharded storage should not be opened with shards and a sharded storage should not be opened unsharded,
+// so no keys are hidden by the other layout.
+#[test]
+fn sharded_layout_mismatch() -> models::Result<()> {
+    let unsharded_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(unsharded_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    let err = storage::ShardedKvStorage::open(unsharded_dir.path(), 4, storage::KvLogStorage::builder())
+        .err().expect("an unsharded storage should not be opened with shards");
+    assert!(err.to_string().contains("unsharded layout"));
+    assert!(!unsharded_dir.path().join("shards").exists());
+    assert_eq!(storage::KvLogStorage::open(unsharded_dir.path())?.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    let sharded_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::ShardedKvStorage::open(sharded_dir.path(), 4, storage::KvLogStorage::builder())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    let err = storage::KvLogStorage::open(sharded_dir.path())
+        .err().expect("a sharded storage should not be opened unsharded");
+    assert!(err.to_string().contains("sharded layout"));
+    assert!(storage::KvLogStorage::open_read_only(sharded_dir.path()).is_err());
+    assert!(!sharded_dir.path().join("kv_1.log").exists());
+    let store = storage::ShardedKvStorage::open(sharded_dir.path(), 4, storage::KvLogStorage::builder())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// Namespaces should keep separate sets of keys in their own subdirectories and be reset independently.
+#[test]
+fn storage_namespaces() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    let mut first = store.namespace("first")?;
+    let mut second = store.namespace("second")?;
+    store.set("key".to_owned(), "default".to_owned())?;
+    first.set("key".to_owned(), "first".to_owned())?;
+    second.set("key".to_owned(), "second".to_owned())?;
+    first.set("key1".to_owned(), "value1".to_owned())?;
+
+    assert_eq!(store.get("key".to_owned())?, Some("default".to_owned()));
+    assert_eq!(store.namespace("first")?.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(second.get("key1".to_owned())?, None);
+    assert_eq!(store.keys(), vec!["key".to_owned()]);
+    assert!(temp_dir.path().join("ns_first").is_dir());
+
+    first.reset()?;
+    assert!(first.keys().is_empty());
+    assert_eq!(second.get("key".to_owned())?, Some("second".to_owned()));
+    store.reset()?;
+    assert!(store.keys().is_empty());
+    assert_eq!(second.get("key".to_owned())?, Some("second".to_owned()));
+
+    assert!(store.namespace("").is_err());
+    assert!(store.namespace("../escape").is_err());
+
+    drop((store, first, second));
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.namespace("second")?.get("key".to_owned())?, Some("second".to_owned()));
+    Ok(())
+}
+
+// Bloom filter should never report a false negative and should survive a write/read round trip.
+#[test]
+fn bloom_filter() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut filter = storage::BloomFilter::new(1000, 42);
+    for idx in 0..1000 {
+        filter.insert(format!("key{}", idx).as_bytes());
+    }
+    assert!((0..1000).all(|idx| filter.may_contain(format!("key{}", idx).as_bytes())));
+    let false_positives = (1000..11000).filter(|idx| filter.may_contain(format!("key{}", idx).as_bytes())).count();
+    assert!(false_positives < 300);
+
+    let path = temp_dir.path().join("kv_1.bloom");
+    filter.write(&path)?;
+    assert_eq!(storage::BloomFilter::read(&path), Some(filter));
+    assert_eq!(storage::BloomFilter::read(&temp_dir.path().join("missing.bloom")), None);
+    Ok(())
+}
+
+// Log files of the v1 record format should stay readable, the new records should be appended as v2 frames.
+#[test]
+fn record_formats() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log_path = temp_dir.path().join("kv_1.log");
+
+    // v1 records: a command code and the fields with 4 bytes big-endian length prefixes.
+    let mut v1_records = Vec::new();
+    for (key, value) in [("key1", "value1".to_owned()), ("key2", "2".repeat(100))] {
+        v1_records.push(b's');
+        v1_records.extend((key.len() as u32).to_be_bytes());
+        v1_records.extend(key.as_bytes());
+        v1_records.extend((value.len() as u32).to_be_bytes());
+        v1_records.extend(value.as_bytes());
+    }
+    std::fs::write(&log_path, &v1_records)?;
+
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("2".repeat(100)));
+    store.set("key3".to_owned(), "3".repeat(200))?;
+    store.remove("key1".to_owned())?;
+    drop(store);
+
+    // A v2 set frame of a 4 bytes key with a 200 bytes value: the magic bytes, a 2 bytes varint body size,
+    // the body of 1 + 1 + 4 + 2 + 200 bytes and a 4 bytes checksum.
+    let file_size = std::fs::metadata(&log_path)?.len();
+    let set_frame_size = 2 + 2 + 208 + 4;
+    assert_eq!(file_size, v1_records.len() as u64 + set_frame_size + 2 + 1 + 6 + 4);
+
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("2".repeat(100)));
+    assert_eq!(store.get("key3".to_owned())?, Some("3".repeat(200)));
+    drop(store);
+
+    // A damaged frame is detected by its checksum.
+    let mut content = std::fs::read(&log_path)?;
+    content[v1_records.len() + 100] ^= 0xff;
+    std::fs::write(&log_path, &content)?;
+    let err = storage::KvLogStorage::open(temp_dir.path()).err().expect("damaged frame is not detected");
+    assert!(err.to_string().contains("checksum mismatch"));
+    Ok(())
+}
+
+// Sealed log files should get persisted filters, which let compaction drop the useless tombstones.
+#[test]
+fn segment_filters() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let file_path = |name: &str| temp_dir.path().join(name);
+
+    // A set record with a 60 bytes value takes 72 bytes and a log file starts with a 6 bytes header,
+    // so each one starts a new log file. Each rotated log file is compacted.
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(116)
+        .open(temp_dir.path())?;
+    store.set("k1".to_owned(), "1".repeat(60))?;
+    store.set("k2".to_owned(), "2".repeat(60))?;
+
+    // The filters are built when the log files are sealed, the active one has none.
+    // The first log file is compacted before "k1" is removed, so it keeps the record of "k1".
+    for _ in 0..50 {
+        if file_path("kv_1.bloom").exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    store.set("k3".to_owned(), "v".to_owned())?;
+    store.remove("k3".to_owned())?;
+    store.remove("k1".to_owned())?;
+    store.set("k4".to_owned(), "4".repeat(60))?;
+
+    // The tombstone of "k3" is dropped as the key is not set in the older files, the one of "k1" is kept.
+    for _ in 0..50 {
+        if file_path("kv_2.bloom").exists()
+            && std::fs::metadata(file_path("kv_2.log"))?.len() == 6 + 72 + 11 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(file_path("kv_1.bloom").exists());
+    assert!(file_path("kv_2.bloom").exists());
+    assert!(!file_path("kv_3.bloom").exists());
+    assert_eq!(std::fs::metadata(file_path("kv_2.log"))?.len(), 6 + 72 + 11);
+
+    // A missing filter is rebuilt on open.
+    drop(store);
+    std::fs::remove_file(file_path("kv_1.bloom"))?;
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert!(file_path("kv_1.bloom").exists());
+    assert_eq!(store.get("k1".to_owned())?, None);
+    assert_eq!(store.get("k2".to_owned())?, Some("2".repeat(60)));
+    assert_eq!(store.get("k3".to_owned())?, None);
+    assert_eq!(store.get("k4".to_owned())?, Some("4".repeat(60)));
+    Ok(())
+}
+
+// Concurrent writes should be acknowledged by the group commit and persisted.
+#[test]
+fn group_commit() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let policy = storage::FsyncPolicy::Group { interval: std::time::Duration::from_millis(5), max_batch_size: 16 };
+    let store = storage::KvLogStorage::builder().fsync_policy(policy).open(temp_dir.path())?;
+
+    let threads: Vec<_> = (0..8)
+        .map(|thread_idx| {
+            let mut store = store.clone();
+            std::thread::spawn(move || {
+                for idx in 0..50 {
+                    store.set(format!("key_{}_{}", thread_idx, idx), idx.to_string().repeat(100)).unwrap();
+                }
+                store.remove(format!("key_{}_0", thread_idx)).unwrap();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    for thread_idx in 0..8 {
+        assert_eq!(store.get(format!("key_{}_0", thread_idx))?, None);
+        for idx in 1..50 {
+            assert_eq!(store.get(format!("key_{}_{}", thread_idx, idx))?, Some(idx.to_string().repeat(100)));
+        }
+    }
+
+    let policy = storage::FsyncPolicy::Group { interval: std::time::Duration::from_millis(5), max_batch_size: 0 };
+    assert!(storage::KvLogStorage::builder().fsync_policy(policy).open(temp_dir.path()).is_err());
+    Ok(())
+}
+
+// Batched writes should be persisted and span several log files.
+#[test]
+fn set_batch() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder().segment_size(10_000).open(temp_dir.path())?;
+    let values: Vec<(String, Vec<u8>)> = (0..100)
+        .map(|idx| (format!("key{}", idx), idx.to_string().repeat(1000).into_bytes()))
+        .collect();
+    store.set_batch(values.clone())?;
+    assert_eq!(store.keys().len(), 100);
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    for (key, value) in values {
+        assert_eq!(store.get_bytes(key)?, Some(value));
+    }
+    Ok(())
+}
+
+// Should iterate over the key/value pairs sorted by keys, reading the values lazily.
+#[test]
+fn iterate_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key3".to_owned(), "3".repeat(1000))?;
+    store.set("key4".to_owned(), "value4".to_owned())?;
+    store.remove("key4".to_owned())?;
+
+    let values: Vec<(String, String)> = store.iter().collect::<models::Result<_>>()?;
+    assert_eq!(
+        values,
+        vec![
+            ("key1".to_owned(), "value1".to_owned()),
+            ("key2".to_owned(), "value2".to_owned()),
+            ("key3".to_owned(), "3".repeat(1000)),
+        ],
+    );
+
+    // Changes made after the snapshot are seen for the snapshotted keys only.
+    let mut iter = store.iter_bytes();
+    store.remove("key2".to_owned())?;
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    store.set("key0".to_owned(), "value0".to_owned())?;
+    let values: Vec<(String, Vec<u8>)> = iter.by_ref().collect::<models::Result<_>>()?;
+    assert_eq!(
+        values,
+        vec![("key1".to_owned(), b"value1".to_vec()), ("key3".to_owned(), b"value3".to_vec())],
+    );
+
+    // Binary values are returned by the bytes iterator only.
+    store.set_bytes("key5".to_owned(), vec![0xff, 0xfe])?;
+    assert!(store.iter().any(|record| record.is_err()));
+    assert!(store.iter_bytes().all(|record| record.is_ok()));
+    Ok(())
+}
+
+// Closing should wait for the background compactions, the temporary files of the interrupted ones are removed on open.
+#[test]
+fn close_storage() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let file_path = |name: &str| temp_dir.path().join(name);
+
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(110)
+        .open(temp_dir.path())?;
+    for idx in 0..10 {
+        store.set(format!("key{}", idx % 3), idx.to_string().repeat(60))?;
+    }
+
+    // The sealed log files are compacted and get their filters before `close` returns.
+    store.close()?;
+    for file_idx in 1..5 {
+        assert!(!file_path(&format!("_tmp_kv_{}.log", file_idx)).exists());
+        assert!(!file_path(&format!("kv_{}.log", file_idx)).exists() || file_path(&format!("kv_{}.bloom", file_idx)).exists());
+    }
+
+    drop(store);
+    std::fs::write(file_path("_tmp_kv_1.log"), "partially compacted")?;
+    std::fs::write(file_path("kv_1.bloom.tmp"), "partially written")?;
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert!(!file_path("_tmp_kv_1.log").exists());
+    assert!(!file_path("kv_1.bloom.tmp").exists());
+    assert_eq!(store.get("key0".to_owned())?, Some(9.to_string().repeat(60)));
+    assert_eq!(store.get("key1".to_owned())?, Some(7.to_string().repeat(60)));
+    assert_eq!(store.get("key2".to_owned())?, Some(8.to_string().repeat(60)));
+    Ok(())
+}
+
+// A storage directory should be opened only by the engine which created it.
+#[test]
+fn engine_mismatch() -> models::Result<()> {
+    let kvs_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(kvs_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    let err = storage::SledStorage::open(kvs_dir.path()).err().expect("sled should not open a kvs storage");
+    assert!(err.to_string().contains("created by the kvs engine"));
+    assert_eq!(storage::KvLogStorage::open(kvs_dir.path())?.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    let sled_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(storage::SledStorage::open(sled_dir.path())?);
+    let builder = storage::KvLogStorage::builder();
+    let err = storage::ShardedKvStorage::open(sled_dir.path(), 2, builder).err().expect("kvs should not open a sled storage");
+    assert!(err.to_string().contains("created by the sled engine"));
+    assert_eq!(std::fs::read_to_string(sled_dir.path().join(storage::base::ENGINE_FILE_NAME))?, "sled");
+
+    // A storage created before the engine file is recognized by its files.
+    std::fs::remove_file(sled_dir.path().join(storage::base::ENGINE_FILE_NAME))?;
+    let err = storage::KvLogStorage::open(sled_dir.path()).err().expect("kvs should not open a legacy sled storage");
+    assert!(err.to_string().contains("created by the sled engine"));
+    assert!(!sled_dir.path().join(storage::base::ENGINE_FILE_NAME).exists());
+    Ok(())
+}
+
+// A storage directory should be open by a single storage at a time, the lock is released with the last handle.
+#[test]
+fn storage_lock() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.backup(backup_dir.path())?;
+
+    let err = storage::KvLogStorage::open(temp_dir.path()).err().expect("storage should be locked");
+    assert!(err.to_string().contains("already in use"));
+    assert!(storage::KvLogStorage::restore(backup_dir.path(), temp_dir.path()).is_err());
+
+    let handle = store.clone();
+    drop(store);
+    assert!(storage::KvLogStorage::open(temp_dir.path()).is_err());
+    drop(handle);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// A record torn by a crash should be dropped on open, the complete records before it should be kept.
+// The log file spans many buffers of the reader, so the records crossing the buffer boundaries are read too.
+#[test]
+fn torn_write_recovery() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log_path = temp_dir.path().join("kv_1.log");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    for idx in 0..5000 {
+        store.set(format!("key{}", idx), "v".repeat(idx % 37))?;
+    }
+    let complete_size = std::fs::metadata(&log_path)?.len();
+    store.set("torn".to_owned(), "value".repeat(10))?;
+    drop(store);
+
+    // Cut the last record in the middle and pad it with zeros, as a crash during the write may leave the file.
+    let file = std::fs::OpenOptions::new().write(true).open(&log_path)?;
+    file.set_len(complete_size + 20)?;
+    file.set_len(complete_size + 64)?;
+    drop(file);
+
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(std::fs::metadata(&log_path)?.len(), complete_size);
+    for idx in 0..5000 {
+        assert_eq!(store.get(format!("key{}", idx))?, Some("v".repeat(idx % 37)));
+    }
+    assert_eq!(store.get("torn".to_owned())?, None);
+
+    // The writes are appended after the last complete record.
+    store.set("torn".to_owned(), "value".to_owned())?;
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("torn".to_owned())?, Some("value".to_owned()));
+    assert_eq!(store.get("key4999".to_owned())?, Some("v".repeat(4999 % 37)));
+    Ok(())
+}
+
+// A read-only storage should open a locked directory, skip a torn record without truncating it
+// and reject the writes, leaving the files unchanged.
+#[test]
+fn read_only_storage() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log_path = temp_dir.path().join("kv_1.log");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key2".to_owned())?;
+
+    let mut reader = storage::KvLogStorage::open_read_only(temp_dir.path())?;
+    assert_eq!(reader.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(reader.get("key2".to_owned())?, None);
+    let errors = [
+        reader.set("key3".to_owned(), "value3".to_owned()).err(),
+        reader.remove("key1".to_owned()).err(),
+        reader.list_push("list".to_owned(), vec![b"a".to_vec()], true).err(),
+        reader.reset().err(),
+        reader.compact().err(),
+    ];
+    for err in errors {
+        let err = err.expect("write should fail");
+        let err = err.downcast_ref::<models::CommandError>().expect("should be a command error");
+        assert_eq!(err.status(), models::StatusCode::ReadOnly);
+    }
+    reader.close()?;
+    drop(reader);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    // The record torn at the end of the active log file is skipped and left in place.
+    let complete_size = std::fs::metadata(&log_path)?.len();
+    store.set("torn".to_owned(), "value".repeat(10))?;
+    drop(store);
+    let file = std::fs::OpenOptions::new().write(true).open(&log_path)?;
+    file.set_len(complete_size + 20)?;
+    file.set_len(complete_size + 64)?;
+    drop(file);
+    let reader = storage::KvLogStorage::open_read_only(temp_dir.path())?;
+    assert_eq!(reader.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(reader.get("torn".to_owned())?, None);
+    assert_eq!(std::fs::metadata(&log_path)?.len(), complete_size + 64);
+
+    assert!(storage::KvLogStorage::open_read_only(&temp_dir.path().join("missing")).is_err());
+    assert!(!temp_dir.path().join("missing").exists());
+    Ok(())
+}
+
+// New log files should start with a versioned header. Migration should rewrite the legacy log files
+// of the storage and its namespaces into the current format, the newer formats should be rejected.
+#[test]
+fn segment_migration() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let header = [0xC6, 0x4B, 0, 0, 0, 1];
+
+    // Legacy log files of v1 records without a header.
+    let mut v1_records = Vec::new();
+    for (key, value) in [("key1", "value1".to_owned()), ("key2", "2".repeat(100))] {
+        v1_records.push(b's');
+        v1_records.extend((key.len() as u32).to_be_bytes());
+        v1_records.extend(key.as_bytes());
+        v1_records.extend((value.len() as u32).to_be_bytes());
+        v1_records.extend(value.as_bytes());
+    }
+    std::fs::write(temp_dir.path().join("kv_1.log"), &v1_records)?;
+    std::fs::create_dir(temp_dir.path().join("ns_users"))?;
+    std::fs::write(temp_dir.path().join("ns_users").join("kv_1.log"), &v1_records)?;
+
+    // The storage must be closed while migrating.
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert!(storage::KvLogStorage::migrate(temp_dir.path()).is_err());
+    drop(store);
+
+    assert_eq!(storage::KvLogStorage::migrate(temp_dir.path())?, 2);
+    assert_eq!(storage::KvLogStorage::migrate(temp_dir.path())?, 0);
+    for path in [temp_dir.path().join("kv_1.log"), temp_dir.path().join("ns_users").join("kv_1.log")] {
+        assert_eq!(std::fs::read(path)?[..6], header);
+    }
+
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("2".repeat(100)));
+    assert_eq!(store.namespace("users")?.get("key2".to_owned())?, Some("2".repeat(100)));
+
+    // A new log file gets the header with its first record.
+    store.reset()?;
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    drop(store);
+    assert_eq!(std::fs::read(temp_dir.path().join("kv_1.log"))?[..6], header);
+
+    // A log file of a newer format version is not opened.
+    let mut content = std::fs::read(temp_dir.path().join("kv_1.log"))?;
+    content[5] = 2;
+    std::fs::write(temp_dir.path().join("kv_1.log"), &content)?;
+    let err = storage::KvLogStorage::open(temp_dir.path()).err().expect("newer format version is not rejected");
+    assert!(err.to_string().contains("Unsupported segment format version 2"));
+    Ok(())
+}
+
+// The manifest should record the states of the log files. On open the obsolete log files should be removed
+// and the interrupted compactions should be rolled back.
+#[test]
+fn segment_manifest() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let file_path = |name: &str| temp_dir.path().join(name);
+
+    // Each set record with a 60 bytes value starts a new log file.
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(116)
+        .open(temp_dir.path())?;
+    for key in ["k1", "k2", "k3"] {
+        store.set(key.to_owned(), "v".repeat(60))?;
+    }
+    drop(store);
+    assert_eq!(std::fs::read_to_string(file_path("MANIFEST"))?, "1 sealed\n2 sealed\n3 active\n");
+
+    // A crash after the first log file is marked obsolete and while the second one is compacted.
+    std::fs::write(file_path("MANIFEST"), "1 obsolete\n2 compacting\n3 active\n")?;
+    std::fs::write(file_path("_tmp_kv_2.log"), "partially compacted")?;
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert!(!file_path("kv_1.log").exists());
+    assert!(!file_path("_tmp_kv_2.log").exists());
+    assert_eq!(store.get("k1".to_owned())?, None);
+    assert_eq!(store.get("k2".to_owned())?, Some("v".repeat(60)));
+    assert_eq!(store.get("k3".to_owned())?, Some("v".repeat(60)));
+    assert_eq!(std::fs::read_to_string(file_path("MANIFEST"))?, "2 sealed\n3 active\n");
+
+    store.reset()?;
+    assert_eq!(std::fs::read_to_string(file_path("MANIFEST"))?, "");
+    Ok(())
+}
+
+// The compaction scheduler should compact the sealed log files with too many dead bytes,
+// and the files with the most dead bytes once all the dead bytes exceed the max garbage size.
+#[test]
+fn compaction_policy() -> models::Result<()> {
+    // Two set records of 1 byte keys with 60 bytes values fill a log file of 6 + 2 * 71 bytes.
+    // The ratio of 1 disables the compaction on rotation, so only the scheduler compacts the files.
+    let open_store = |path: &std::path::Path, dead_ratio: f64, max_garbage_size: Option<u64>| {
+        storage::KvLogStorage::builder()
+            .segment_size(160)
+            .compaction_garbage_ratio(1.0)
+            .compaction_policy(storage::CompactionPolicy {
+                dead_ratio,
+                max_garbage_size,
+                interval: Some(std::time::Duration::from_millis(20)),
+            })
+            .open(path)
+    };
+    let wait_for_size = |path: &std::path::Path, size: u64| -> models::Result<u64> {
+        for _ in 0..100 {
+            if std::fs::metadata(path)?.len() == size {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        Ok(std::fs::metadata(path)?.len())
+    };
+
+    // A half of the first log file is dead.
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = open_store(temp_dir.path(), 0.4, None)?;
+    for key in ["a", "b", "c", "d", "a", "e", "f"] {
+        store.set(key.to_owned(), key.repeat(60))?;
+    }
+    assert_eq!(wait_for_size(&temp_dir.path().join("kv_1.log"), 6 + 71)?, 6 + 71);
+    assert_eq!(std::fs::metadata(temp_dir.path().join("kv_2.log"))?.len(), 6 + 2 * 71);
+    assert_eq!(store.get("a".to_owned())?, Some("a".repeat(60)));
+    assert_eq!(store.get("b".to_owned())?, Some("b".repeat(60)));
+
+    // The scheduler stops with the last handle, so the storage can be reopened.
+    drop(store);
+    drop(storage::KvLogStorage::open(temp_dir.path())?);
+
+    // Both first log files are half dead, compacting one of them is enough to fit the max garbage size.
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = open_store(temp_dir.path(), 1.0, Some(100))?;
+    for key in ["a", "b", "c", "d", "a", "c", "e"] {
+        store.set(key.to_owned(), key.repeat(60))?;
+    }
+    assert_eq!(wait_for_size(&temp_dir.path().join("kv_1.log"), 6 + 71)?, 6 + 71);
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert_eq!(std::fs::metadata(temp_dir.path().join("kv_2.log"))?.len(), 6 + 2 * 71);
+    assert_eq!(store.get("c".to_owned())?, Some("c".repeat(60)));
+    drop(store);
+
+    assert!(open_store(temp_dir.path(), 1.5, None).is_err());
+    Ok(())
+}
+
+// The number of the opened namespaces should be limited and the namespaces should be compacted by the scheduler
+// of their storage.
+#[test]
+fn storage_namespaces_shared() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = storage::KvLogStorage::builder()
+        .segment_size(160)
+        .compaction_garbage_ratio(1.0)
+        .compaction_policy(storage::CompactionPolicy {
+            dead_ratio: 0.4,
+            max_garbage_size: None,
+            interval: Some(std::time::Duration::from_millis(20)),
+        })
+        .max_namespaces(2)
+        .open(temp_dir.path())?;
+    let mut first = store.namespace("first")?;
+    store.namespace("second")?;
+    let err = store.namespace("third").err().expect("namespaces should be limited");
+    let err = err.downcast_ref::<models::CommandError>().expect("should be a command error");
+    assert_eq!(err.code, models::ERROR_CODE_SERVER_BUSY);
+    assert!(store.namespace("first").is_ok());
+    assert!(!temp_dir.path().join("ns_third").exists());
+
+    // A half of the first log file of the namespace is dead, see `compaction_policy`.
+    for key in ["a", "b", "c", "d", "a", "e", "f"] {
+        first.set(key.to_owned(), key.repeat(60))?;
+    }
+    let log_path = temp_dir.path().join("ns_first").join("kv_1.log");
+    for _ in 0..100 {
+        if std::fs::metadata(&log_path)?.len() == 6 + 71 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert_eq!(std::fs::metadata(&log_path)?.len(), 6 + 71);
+    assert_eq!(first.get("a".to_owned())?, Some("a".repeat(60)));
+    Ok(())
+}
+
+// A write batch should apply all its commands or none of them, the removals of the missing keys are skipped.
+#[test]
+fn write_batch() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder().segment_size(1000).open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.write_batch(vec![
+        models::Command::Set { key: "key2".to_owned(), value: b"value2".to_vec() },
+        models::Command::Remove { key: "key1".to_owned() },
+        models::Command::Set { key: "key1".to_owned(), value: b"value3".to_vec() },
+        models::Command::Remove { key: "key2".to_owned() },
+        models::Command::Remove { key: "key4".to_owned() },
+    ])?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    // The unsupported commands and the batches exceeding a log file are rejected as a whole.
+    let disk_size = store.stats()?.disk_size;
+    assert!(store.write_batch(vec![
+        models::Command::Set { key: "key3".to_owned(), value: b"value3".to_vec() },
+        models::Command::Get { key: "key1".to_owned() },
+    ]).is_err());
+    let large_batch = (0..10)
+        .map(|idx| models::Command::Set { key: format!("key{}", idx), value: vec![b'a'; 100] })
+        .collect();
+    assert!(store.write_batch(large_batch).is_err());
+    assert!(store.write_batch(vec![models::Command::Remove { key: "key4".to_owned() }]).is_ok());
+    assert_eq!(store.stats()?.disk_size, disk_size);
+    assert_eq!(store.get("key3".to_owned())?, None);
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, None);
+    Ok(())
+}
+
+// A prefix reset should remove only the keys under the prefix, in several batches if they don't fit a log file.
+#[test]
+fn reset_prefix() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder().segment_size(1000).open(temp_dir.path())?;
+    for idx in 0..100 {
+        store.set(format!("tenant1/key{}", idx), "value".to_owned())?;
+    }
+    store.set("tenant10/key".to_owned(), "value".to_owned())?;
+    store.set("tenant2/key".to_owned(), "value".to_owned())?;
+    assert_eq!(store.reset_prefix("tenant1/")?, 100);
+    assert_eq!(store.reset_prefix("tenant1/")?, 0);
+    assert_eq!(store.get("tenant1/key0".to_owned())?, None);
+    assert_eq!(store.get("tenant10/key".to_owned())?, Some("value".to_owned()));
+
+    drop(store);
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    let mut keys = store.keys();
+    keys.sort();
+    assert_eq!(keys, vec!["tenant10/key".to_owned(), "tenant2/key".to_owned()]);
+    assert_eq!(store.reset_prefix("")?, 2);
+
+    // The sled storage removes the keys with a single batch.
+    let sled_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut sled = storage::SledStorage::open(sled_dir.path())?;
+    sled.set_bytes("tenant1/key".to_owned(), b"value".to_vec())?;
+    sled.set_bytes("tenant2/key".to_owned(), b"value".to_vec())?;
+    assert_eq!(sled.reset_prefix("tenant1/".to_owned())?, 1);
+    assert_eq!(sled.get_bytes("tenant1/key".to_owned())?, None);
+    assert_eq!(sled.get_bytes("tenant2/key".to_owned())?, Some(b"value".to_vec()));
+    Ok(())
+}
+
+// Multi-get should return the values in the order of the keys, reading the inline and on-disk values of many log files.
+#[test]
+fn get_many() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder().segment_size(1000).open(temp_dir.path())?;
+    let values: Vec<(String, Vec<u8>)> = (0..20)
+        .map(|idx| (format!("key{}", idx), idx.to_string().repeat(idx * 10).into_bytes()))
+        .collect();
+    for (key, value) in values.iter().rev() {
+        store.set_bytes(key.clone(), value.clone())?;
+    }
+    assert!(store.stats()?.segments_count > 1);
+
+    let mut keys: Vec<String> = values.iter().map(|(key, _)| key.clone()).collect();
+    keys.insert(5, "missing".to_owned());
+    let mut expected: Vec<Option<Vec<u8>>> = values.into_iter().map(|(_, value)| Some(value)).collect();
+    expected.insert(5, None);
+    assert_eq!(store.get_many(keys)?, expected);
+    assert_eq!(store.get_many(Vec::new())?, Vec::<Option<Vec<u8>>>::new());
+    Ok(())
+}
+
+// An opened value should be read in parts from its log file, even if the key is overwritten and compacted meanwhile.
+#[test]
+fn open_value() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(10_000)
+        .compression_threshold(Some(1000))
+        .open(temp_dir.path())?;
+    let value: Vec<u8> = (0..5000u32).map(|idx| (idx % 251) as u8).collect();
+    store.set_bytes("large".to_owned(), value.clone())?;
+    store.set_bytes("small".to_owned(), b"value".to_vec())?;
+    store.set_bytes("compressed".to_owned(), vec![b'c'; 2000])?;
+
+    let mut opened = store.open_value("large".to_owned())?.expect("missing value");
+    assert_eq!(opened.size, 5000);
+    let mut head = vec![0u8; 100];
+    opened.reader.read_exact(&mut head)?;
+    assert_eq!(head, value[..100]);
+    for _ in 0..5 {
+        store.set_bytes("large".to_owned(), vec![b'x'; 5000])?;
+    }
+    store.compact()?;
+    let mut tail = Vec::new();
+    opened.reader.read_to_end(&mut tail)?;
+    assert_eq!(tail, value[100..]);
+
+    for (key, expected) in [("small", b"value".to_vec()), ("compressed", vec![b'c'; 2000])] {
+        let mut opened = store.open_value(key.to_owned())?.expect("missing value");
+        assert_eq!(opened.size, expected.len() as u64);
+        let mut read = Vec::new();
+        opened.reader.read_to_end(&mut read)?;
+        assert_eq!(read, expected);
+    }
+    assert!(store.open_value("missing".to_owned())?.is_none());
+    Ok(())
+}
+
+// Append should create the missing keys and extend the existing values, the concurrent appends are never lost.
+#[test]
+fn append_value() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.append("key".to_owned(), b"abc".to_vec())?, 3);
+    assert_eq!(store.append("key".to_owned(), b"def".to_vec())?, 6);
+    assert_eq!(store.get("key".to_owned())?, Some("abcdef".to_owned()));
+
+    // The value grows past the inline size and is read from the disk.
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let mut store = store.clone();
+            std::thread::spawn(move || {
+                for _ in 0..25 {
+                    store.append("key".to_owned(), b"x".to_vec()).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let expected = format!("abcdef{}", "x".repeat(100));
+    assert_eq!(store.get("key".to_owned())?, Some(expected.clone()));
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some(expected));
+    Ok(())
+}
+
+// GetSet should return the replaced values, SetNx should set only the missing keys, a single one of the concurrent ones.
+#[test]
+fn conditional_set() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get_set("key".to_owned(), b"value1".to_vec())?, None);
+    assert_eq!(store.get_set("key".to_owned(), b"value2".to_vec())?, Some(b"value1".to_vec()));
+    assert!(!store.set_nx("key".to_owned(), b"value3".to_vec())?);
+    assert_eq!(store.get("key".to_owned())?, Some("value2".to_owned()));
+
+    let threads: Vec<_> = (0..4)
+        .map(|thread_idx| {
+            let mut store = store.clone();
+            std::thread::spawn(move || store.set_nx("lock".to_owned(), vec![thread_idx]).unwrap())
+        })
+        .collect();
+    let set_count = threads.into_iter().map(|thread| thread.join().unwrap()).filter(|is_set| *is_set).count();
+    assert_eq!(set_count, 1);
+
+    store.remove("key".to_owned())?;
+    assert!(store.set_nx("key".to_owned(), b"value4".to_vec())?);
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some("value4".to_owned()));
+    Ok(())
+}
+
+// List commands should push, pop and read the list values, the concurrent pushes are never lost.
+#[test]
+fn list_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.list_push("list".to_owned(), vec![b"b".to_vec(), b"c".to_vec()], false)?, 2);
+    assert_eq!(store.list_push("list".to_owned(), vec![b"a".to_vec()], true)?, 3);
+    assert_eq!(store.list_range("list".to_owned(), 0, -1)?, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+    assert_eq!(store.list_range("list".to_owned(), -2, 10)?, vec![b"b".to_vec(), b"c".to_vec()]);
+    assert!(store.list_range("list".to_owned(), 2, 1)?.is_empty());
+    assert_eq!(store.list_pop("list".to_owned(), true)?, Some(b"a".to_vec()));
+    assert_eq!(store.list_pop("list".to_owned(), false)?, Some(b"c".to_vec()));
+
+    // The emptied list is removed.
+    assert_eq!(store.list_pop("list".to_owned(), false)?, Some(b"b".to_vec()));
+    assert_eq!(store.list_pop("list".to_owned(), false)?, None);
+    assert_eq!(store.get_bytes("list".to_owned())?, None);
+
+    // A plain value is not a list.
+    store.set("key".to_owned(), "value".to_owned())?;
+    let err = store.list_push("key".to_owned(), vec![b"a".to_vec()], true).err().expect("value should not be a list");
+    let err = err.downcast_ref::<models::CommandError>().expect("should be a command error");
+    assert_eq!(err.status(), models::StatusCode::WrongType);
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let mut store = store.clone();
+            std::thread::spawn(move || {
+                for _ in 0..25 {
+                    store.list_push("queue".to_owned(), vec![b"x".to_vec()], false).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.list_range("queue".to_owned(), 0, -1)?.len(), 100);
+    Ok(())
+}
+
+// Each list push should be written as a record of its own, not as the whole list.
+#[test]
+fn list_push_records() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    for idx in 0..200 {
+        store.list_push("list".to_owned(), vec![format!("value{:05}", idx).into_bytes()], false)?;
+    }
+    // Rewriting the list on each push would take about 200 * 100 * 12 bytes.
+    assert!(store.stats()?.disk_size < 20_000);
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    let values = store.list_range("list".to_owned(), 0, -1)?;
+    assert_eq!(values.len(), 200);
+    assert_eq!(values[199], b"value00199".to_vec());
+    Ok(())
+}
+
+// A plain binary value should never be taken for a collection, whatever bytes it starts with.
+#[test]
+fn plain_value_is_not_collection() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::open(temp_dir.path())?;
+    let value = vec![0xc7, 0x4b, 0x6c, 0x01, 0x01, b'a'];
+    store.set_bytes("binary".to_owned(), value.clone())?;
+    assert_eq!(store.get_bytes("binary".to_owned())?, Some(value.clone()));
+    let err = store.list_range("binary".to_owned(), 0, -1).err().expect("value should not be a list");
+    let err = err.downcast_ref::<models::CommandError>().expect("should be a command error");
+    assert_eq!(err.status(), models::StatusCode::WrongType);
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get_bytes("binary".to_owned())?, Some(value));
+    Ok(())
+}
+
+// A queue churned by pushes and pops should be compacted to the size of its items.
+#[test]
+fn list_queue_compaction() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(1000)
+        .compaction_garbage_ratio(1.0)
+        .open(temp_dir.path())?;
+    store.list_push("queue".to_owned(), (0..5).map(|idx| format!("item{}", idx).into_bytes()).collect(), false)?;
+    for idx in 5..1000 {
+        store.list_push("queue".to_owned(), vec![format!("item{}", idx).into_bytes()], false)?;
+        assert_eq!(store.list_pop("queue".to_owned(), true)?, Some(format!("item{}", idx - 5).into_bytes()));
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let initial_stats = store.stats()?;
+    store.compact()?;
+    let stats = store.stats()?;
+    assert!(stats.disk_size < initial_stats.disk_size / 10);
+    assert!(stats.segments_count <= 3);
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    let expected: Vec<Vec<u8>> = (995..1000).map(|idx| format!("item{}", idx).into_bytes()).collect();
+    assert_eq!(store.list_range("queue".to_owned(), 0, -1)?, expected);
+    Ok(())
+}
+
+// Hash commands should update the fields one by one, the compaction should keep only the latest fields.
+#[test]
+fn hash_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(1000)
+        .compaction_garbage_ratio(1.0)
+        .open(temp_dir.path())?;
+    let fields = vec![("a".to_owned(), b"1".to_vec()), ("b".to_owned(), b"2".to_vec())];
+    assert_eq!(store.hash_set("hash".to_owned(), fields)?, 2);
+    assert_eq!(store.hash_set("hash".to_owned(), vec![("a".to_owned(), b"3".to_vec())])?, 0);
+    assert_eq!(store.hash_get("hash".to_owned(), "a".to_owned())?, Some(b"3".to_vec()));
+    assert_eq!(store.hash_get("hash".to_owned(), "c".to_owned())?, None);
+    assert_eq!(store.hash_delete("hash".to_owned(), vec!["b".to_owned(), "c".to_owned()])?, 1);
+    assert_eq!(store.hash_get_all("hash".to_owned())?, vec![("a".to_owned(), b"3".to_vec())]);
+
+    // The emptied hash is removed, a list is not a hash.
+    assert_eq!(store.hash_delete("hash".to_owned(), vec!["a".to_owned()])?, 1);
+    assert_eq!(store.get_bytes("hash".to_owned())?, None);
+    store.list_push("list".to_owned(), vec![b"a".to_vec()], true)?;
+    let err = store.hash_get("list".to_owned(), "a".to_owned()).err().expect("list should not be a hash");
+    let err = err.downcast_ref::<models::CommandError>().expect("should be a command error");
+    assert_eq!(err.status(), models::StatusCode::WrongType);
+
+    for idx in 0..30 {
+        store.hash_set("record".to_owned(), vec![(format!("field{}", idx % 3), idx.to_string().repeat(20).into_bytes())])?;
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let initial_stats = store.stats()?;
+    store.compact()?;
+    assert!(store.stats()?.disk_size < initial_stats.disk_size);
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    let expected: Vec<(String, Vec<u8>)> = (27..30)
+        .map(|idx| (format!("field{}", idx % 3), idx.to_string().repeat(20).into_bytes()))
+        .collect();
+    assert_eq!(store.hash_get_all("record".to_owned())?, expected);
+    Ok(())
+}
+
+// Set commands should ignore the duplicate members, the compaction should collapse the membership churn.
+#[test]
+fn set_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(1000)
+        .compaction_garbage_ratio(1.0)
+        .open(temp_dir.path())?;
+    assert_eq!(store.set_add("tags".to_owned(), vec![b"b".to_vec(), b"a".to_vec(), b"b".to_vec()])?, 2);
+    assert_eq!(store.set_add("tags".to_owned(), vec![b"a".to_vec(), b"c".to_vec()])?, 1);
+    assert!(store.set_is_member("tags".to_owned(), b"c".to_vec())?);
+    assert!(!store.set_is_member("tags".to_owned(), b"d".to_vec())?);
+    assert!(!store.set_is_member("missing".to_owned(), b"a".to_vec())?);
+    assert_eq!(store.set_remove("tags".to_owned(), vec![b"a".to_vec(), b"d".to_vec()])?, 1);
+    assert_eq!(store.set_members("tags".to_owned())?, vec![b"b".to_vec(), b"c".to_vec()]);
+
+    // The emptied set is removed, a plain value is not a set.
+    assert_eq!(store.set_remove("tags".to_owned(), vec![b"b".to_vec(), b"c".to_vec()])?, 2);
+    assert_eq!(store.get_bytes("tags".to_owned())?, None);
+    store.set("plain".to_owned(), "value".to_owned())?;
+    let err = store.set_members("plain".to_owned()).err().expect("plain value should not be a set");
+    let err = err.downcast_ref::<models::CommandError>().expect("should be a command error");
+    assert_eq!(err.status(), models::StatusCode::WrongType);
+
+    for idx in 0..30 {
+        let member = format!("member{}", idx % 3).repeat(10).into_bytes();
+        if idx % 2 == 0 {
+            store.set_add("seen".to_owned(), vec![member])?;
+        } else {
+            store.set_remove("seen".to_owned(), vec![member])?;
+        }
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let initial_stats = store.stats()?;
+    store.compact()?;
+    assert!(store.stats()?.disk_size < initial_stats.disk_size);
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.set_members("seen".to_owned())?, vec!["member1".repeat(10).into_bytes()]);
+    Ok(())
+}
+
+// Sorted set commands should read the members ordered by the scores, the compaction should keep only the latest scores.
+#[test]
+fn sorted_set_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(1000)
+        .compaction_garbage_ratio(1.0)
+        .open(temp_dir.path())?;
+    let members = vec![(b"c".to_vec(), 2.0), (b"a".to_vec(), 2.0), (b"b".to_vec(), -1.5)];
+    assert_eq!(store.sorted_set_add("board".to_owned(), members)?, 3);
+    assert_eq!(store.sorted_set_add("board".to_owned(), vec![(b"b".to_vec(), 3.0), (b"d".to_vec(), 0.0)])?, 1);
+    assert_eq!(
+        store.sorted_set_range_by_score("board".to_owned(), f64::NEG_INFINITY, f64::INFINITY)?,
+        vec![(b"d".to_vec(), 0.0), (b"a".to_vec(), 2.0), (b"c".to_vec(), 2.0), (b"b".to_vec(), 3.0)],
+    );
+    assert_eq!(
+        store.sorted_set_range_by_score("board".to_owned(), 1.0, 2.0)?,
+        vec![(b"a".to_vec(), 2.0), (b"c".to_vec(), 2.0)],
+    );
+    assert!(store.sorted_set_range_by_score("board".to_owned(), 4.0, 5.0)?.is_empty());
+    assert!(store.sorted_set_add("board".to_owned(), vec![(b"e".to_vec(), f64::NAN)]).is_err());
+    assert_eq!(store.sorted_set_remove("board".to_owned(), vec![b"a".to_vec(), b"e".to_vec()])?, 1);
+
+    // The emptied sorted set is removed, a set is not a sorted set.
+    assert_eq!(store.sorted_set_remove("board".to_owned(), vec![b"b".to_vec(), b"c".to_vec(), b"d".to_vec()])?, 3);
+    assert_eq!(store.get_bytes("board".to_owned())?, None);
+    store.set_add("set".to_owned(), vec![b"a".to_vec()])?;
+    let err = store.sorted_set_range_by_score("set".to_owned(), 0.0, 1.0).err().expect("set should not be a sorted set");
+    let err = err.downcast_ref::<models::CommandError>().expect("should be a command error");
+    assert_eq!(err.status(), models::StatusCode::WrongType);
+
+    for idx in 0..30 {
+        let member = format!("task{}", idx % 3).repeat(10).into_bytes();
+        store.sorted_set_add("queue".to_owned(), vec![(member, idx as f64)])?;
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let initial_stats = store.stats()?;
+    store.compact()?;
+    assert!(store.stats()?.disk_size < initial_stats.disk_size);
+
+    drop(store);
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    let expected: Vec<(Vec<u8>, f64)> = (27..30)
+        .map(|idx| (format!("task{}", idx % 3).repeat(10).into_bytes(), idx as f64))
+        .collect();
+    assert_eq!(store.sorted_set_range_by_score("queue".to_owned(), 0.0, 100.0)?, expected);
+    Ok(())
+}
+
+fn assert_wrong_type<T: std::fmt::Debug>(result: models::Result<T>) {
+    let err = result.err().expect("should be a type error");
+    let err = err.downcast_ref::<models::CommandError>().expect("should be a command error");
+    assert_eq!(err.code, models::ERROR_CODE_WRONG_TYPE);
+}
+
+fn check_plain_commands_on_list(store: &mut dyn KvStorage) -> models::Result<()> {
+    store.list_push("list".to_owned(), vec![b"a".to_vec(), b"b".to_vec()], false)?;
+    store.set_bytes("key".to_owned(), b"value".to_vec())?;
+    assert_wrong_type(store.get_bytes("list".to_owned()));
+    assert_wrong_type(store.get_many(vec!["key".to_owned(), "list".to_owned()]));
+    assert_wrong_type(store.append("list".to_owned(), b"c".to_vec()));
+    assert_wrong_type(store.get_set("list".to_owned(), b"value".to_vec()));
+    assert!(!store.set_nx("list".to_owned(), b"value".to_vec())?);
+    assert_eq!(store.list_range("list".to_owned(), 0, -1)?, vec![b"a".to_vec(), b"b".to_vec()]);
+
+    // A set replaces the list.
+    store.set_bytes("list".to_owned(), b"value".to_vec())?;
+    assert_wrong_type(store.list_range("list".to_owned(), 0, -1));
+    assert_eq!(store.get_bytes("list".to_owned())?, Some(b"value".to_vec()));
+    Ok(())
+}
+
+// The plain value commands on a list should fail with the wrong type error and leave the list unchanged.
+#[test]
+fn plain_commands_on_collection() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_plain_commands_on_list(&mut storage::KvLogStorage::open(temp_dir.path())?)?;
+    let sled_dir = TempDir::new().expect("unable to create temporary working directory");
+    check_plain_commands_on_list(&mut storage::SledStorage::open(sled_dir.path())?)
+}
+
+// Paused compaction should leave the rotated log files as is until it is resumed.
+#[test]
+fn pause_compaction() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(1000)
+        .open(temp_dir.path())?;
+    store.pause_compaction()?;
+    assert!(store.is_compaction_paused());
+
+    // A record of a 3 bytes key with a 100 bytes value takes 113 bytes, so 8 records fill a log file.
+    for idx in 0..30 {
+        store.set("key".to_owned(), format!("{:0>100}", idx))?;
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let stats = store.stats()?;
+    assert_eq!(stats.segments_count, 4);
+    assert_eq!(stats.compacted_segments, 0);
+    assert!(store.compact().is_err());
+
+    // The log files rotated meanwhile are compacted on resume, all their records are stale.
+    store.resume_compaction()?;
+    assert!(!store.is_compaction_paused());
+    for _ in 0..50 {
+        if store.stats()?.segments_count == 1 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let stats = store.stats()?;
+    assert_eq!(stats.segments_count, 1);
+    assert_eq!(stats.compacted_segments, 3);
+    assert_eq!(store.get("key".to_owned())?, Some(format!("{:0>100}", 29)));
+    store.compact()?;
+    Ok(())
+}
@@ -0,0 +1,176 @@
+#![cfg(feature = "s3")]
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tempfile::TempDir;
+
+use rust_kvs_server::{models, storage};
+
+/// In-memory object storage serving the S3 requests of the backups.
+#[derive(Clone, Default)]
+struct MockS3 {
+    objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    /// Parts of the multipart uploads by the object keys and the part numbers.
+    parts: Arc<Mutex<BTreeMap<(String, u32), Vec<u8>>>>,
+    multipart_uploads: Arc<AtomicUsize>,
+}
+
+impl MockS3 {
+    const ACCESS_KEY: &'static str = "test-key";
+
+    /// Starts serving the mock on a free port. Returns the mock and the endpoint URL.
+    fn start() -> (MockS3, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let mock = MockS3::default();
+        let server = mock.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let server = server.clone();
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.unwrap());
+                    while let Some((status, headers, body)) = server.handle(&mut reader) {
+                        let head = format!("HTTP/1.1 {} Mock\r\nContent-Length: {}\r\n", status, body.len());
+                        reader.get_mut().write_all(format!("{}{}\r\n", head, headers).as_bytes()).unwrap();
+                        reader.get_mut().write_all(&body).unwrap();
+                    }
+                });
+            }
+        });
+        (mock, endpoint)
+    }
+
+    /// Handles a single request. Returns the response status, headers and body or `None` on the connection end.
+    fn handle(&self, reader: &mut BufReader<TcpStream>) -> Option<(u16, String, Vec<u8>)> {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).ok()? == 0 {
+            return None;
+        }
+        let (mut content_length, mut authorization) = (0, String::new());
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).ok()?;
+            match line.trim_end().split_once(": ") {
+                Some((name, value)) if name.eq_ignore_ascii_case("content-length") => {
+                    content_length = value.parse().ok()?;
+                },
+                Some((name, value)) if name.eq_ignore_ascii_case("authorization") => authorization = value.to_owned(),
+                Some(_) => {},
+                None => break,
+            }
+        }
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body).ok()?;
+
+        let mut request_parts = request_line.split(' ');
+        let (method, target) = (request_parts.next()?, request_parts.next()?);
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let key = path.trim_start_matches("/backups/").to_owned();
+        let query: HashMap<&str, &str> = query.split('&').filter_map(|param| param.split_once('=')).collect();
+        if !authorization.starts_with(&format!("AWS4-HMAC-SHA256 Credential={}/", Self::ACCESS_KEY)) {
+            return Some((403, String::new(), b"<Error><Code>AccessDenied</Code></Error>".to_vec()));
+        }
+
+        let mut objects = self.objects.lock().unwrap();
+        let mut parts = self.parts.lock().unwrap();
+        match (method, query.get("uploadId"), query.get("partNumber")) {
+            ("POST", None, _) => {
+                let body = format!("<InitiateMultipartUploadResult><UploadId>{}</UploadId>", key);
+                Some((200, String::new(), (body + "</InitiateMultipartUploadResult>").into_bytes()))
+            },
+            ("PUT", Some(_), Some(part_number)) => {
+                let part_number: u32 = part_number.parse().ok()?;
+                parts.insert((key, part_number), body);
+                Some((200, format!("ETag: \"{}\"\r\n", part_number), Vec::new()))
+            },
+            ("POST", Some(_), _) => {
+                let upload_parts: Vec<(String, u32)> = parts.keys().filter(|(k, _)| *k == key).cloned().collect();
+                let content = upload_parts.iter().flat_map(|part| parts.remove(part).unwrap()).collect();
+                objects.insert(key, content);
+                self.multipart_uploads.fetch_add(1, Ordering::SeqCst);
+                Some((200, String::new(), b"<CompleteMultipartUploadResult></CompleteMultipartUploadResult>".to_vec()))
+            },
+            ("PUT", None, _) => {
+                objects.insert(key, body);
+                Some((200, String::new(), Vec::new()))
+            },
+            ("GET", _, _) => match objects.get(&key) {
+                Some(content) => Some((200, String::new(), content.clone())),
+                None => Some((404, String::new(), b"<Error><Code>NoSuchKey</Code></Error>".to_vec())),
+            },
+            _ => Some((400, String::new(), Vec::new())),
+        }
+    }
+}
+
+fn s3_target(endpoint: &str) -> storage::s3::S3Target {
+    let mut target = storage::s3::S3Target::new(
+        endpoint.to_owned(), "backups".to_owned(), MockS3::ACCESS_KEY.to_owned(), "test-secret".to_owned(),
+    );
+    target.prefix = "node1".to_owned();
+    target.part_size = 100_000;
+    target
+}
+
+// Should upload only the sealed segments changed since the previous backup, the large ones in parts,
+// and restore the storage from the downloaded backup.
+#[test]
+fn s3_backup_and_restore() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let download_dir = TempDir::new().expect("unable to create temporary backup directory");
+    let restore_dir = TempDir::new().expect("unable to create temporary restore directory");
+    let (mock, endpoint) = MockS3::start();
+    let target = s3_target(&endpoint);
+    // Compaction is disabled, so the sealed segments are not rewritten between the backups.
+    let mut store = storage::KvLogStorage::builder()
+        .segment_size(300_000)
+        .compaction_garbage_ratio(1.0)
+        .open(temp_dir.path())?;
+    let mut values = Vec::new();
+    for idx in 0..8 {
+        values.push((format!("key{}", idx), idx.to_string().repeat(100_000)));
+        store.set(values[idx].0.clone(), values[idx].1.clone())?;
+    }
+
+    let manifest = storage::s3::upload_backup(temp_dir.path(), &target)?;
+    assert_eq!(manifest.generation, 1);
+    assert!(!manifest.segments.is_empty());
+    assert_eq!(manifest.copied_segments().len(), manifest.segments.len());
+    assert!(mock.multipart_uploads.load(Ordering::SeqCst) > 0);
+    assert!(mock.objects.lock().unwrap().contains_key("node1/MANIFEST"));
+    assert!(mock.objects.lock().unwrap().contains_key("node1/gen_1/kv_1.log"));
+    // The active log file is not uploaded.
+    let active_idx = manifest.segments.iter().map(|s| s.file_idx).max().unwrap() + 1;
+    assert!(temp_dir.path().join(format!("kv_{}.log", active_idx)).exists());
+
+    let manifest = storage::s3::upload_backup(temp_dir.path(), &target)?;
+    assert_eq!(manifest.generation, 2);
+    assert_eq!(manifest.copied_segments().len(), 0);
+
+    for idx in 8..12 {
+        values.push((format!("key{}", idx), idx.to_string().repeat(100_000)));
+        store.set(values[idx].0.clone(), values[idx].1.clone())?;
+    }
+    let manifest = storage::s3::upload_backup(temp_dir.path(), &target)?;
+    assert_eq!(manifest.generation, 3);
+    assert!(!manifest.copied_segments().is_empty());
+    assert!(manifest.copied_segments().iter().all(|s| s.file_idx >= active_idx));
+
+    storage::s3::download_backup(&target, download_dir.path())?;
+    storage::KvLogStorage::restore(download_dir.path(), restore_dir.path())?;
+    let restored = storage::KvLogStorage::open(restore_dir.path())?;
+    assert_eq!(restored.get("key0".to_owned())?, Some(values[0].1.clone()));
+    for (key, value) in &values {
+        let restored_value = restored.get(key.clone())?;
+        assert!(restored_value.is_none() || restored_value.as_ref() == Some(value));
+    }
+
+    let mut wrong_target = s3_target(&endpoint);
+    wrong_target.access_key = "other-key".to_owned();
+    assert!(storage::s3::upload_backup(temp_dir.path(), &wrong_target).is_err());
+    Ok(())
+}
@@ -61,3 +61,58 @@ fn cli_invalid_path() {
         .assert()
         .failure();
 }
+
+// TLS certificate and private key should be set together.
+#[test]
+fn cli_tls_cert_without_key() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut cmd = Command::cargo_bin("kvs_server").unwrap();
+    cmd.args(&["--tls-cert", "server.pem"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// A missing backup should fail the server start and keep the storage.
+#[test]
+fn cli_restore_from_missing_backup() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(temp_dir.path().join("kv_1.log"), b"").unwrap();
+    let mut cmd = Command::cargo_bin("kvs_server").unwrap();
+    cmd.args(&["--restore-from", "missing_backup"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("No backups found"));
+    assert!(temp_dir.path().join("kv_1.log").exists());
+}
+
+// `kvs_server export` and `kvs_server import` should move all of the stored pairs between storages.
+#[test]
+fn cli_export_import() {
+    for format in ["json", "csv"] {
+        let source_dir = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let export_path = target_dir.path().join("export").to_string_lossy().into_owned();
+        let mut store = rust_kvs_server::KvLogStorage::open(source_dir.path()).unwrap();
+        store.set("key1".to_owned(), "value1".to_owned()).unwrap();
+        store.set("key,2".to_owned(), "value \"2\"\n".to_owned()).unwrap();
+        drop(store);
+
+        Command::cargo_bin("kvs_server").unwrap()
+            .args(["export", "--file", &export_path, "--format", format])
+            .current_dir(&source_dir)
+            .assert()
+            .success();
+        Command::cargo_bin("kvs_server").unwrap()
+            .args(["import", "--file", &export_path, "--format", format])
+            .current_dir(&target_dir)
+            .assert()
+            .success();
+
+        let store = rust_kvs_server::KvLogStorage::open(target_dir.path()).unwrap();
+        assert_eq!(store.get("key1".to_owned()).unwrap(), Some("value1".to_owned()));
+        assert_eq!(store.get("key,2".to_owned()).unwrap(), Some("value \"2\"\n".to_owned()));
+        assert_eq!(store.keys().len(), 2);
+    }
+}
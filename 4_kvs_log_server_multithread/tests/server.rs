@@ -0,0 +1,1465 @@
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use assert_cmd::prelude::*;
+use tempfile::TempDir;
+
+use rust_kvs_server::{models, storage, threads, KvsClient, KvsServer};
+use rust_kvs_server::access_log::{self, AccessLog};
+use rust_kvs_server::server::ShutdownHandle;
+use rust_kvs_server::trace;
+use rust_kvs_server::storage::KvStorage;
+
+
+const HOST: &str = "127.0.0.1";
+const PORT: u32 = 4012;
+
+fn start_server(dir: &TempDir) -> (ShutdownHandle, std::thread::JoinHandle<Result<(), String>>) {
+    start_server_with(dir, |_| {})
+}
+
+/// Same as `start_server` with the server options set by `configure`.
+fn start_server_with(
+    dir: &TempDir,
+    configure: impl FnOnce(&mut KvsServer) + Send + 'static,
+) -> (ShutdownHandle, std::thread::JoinHandle<Result<(), String>>) {
+    let storage_path = dir.path().to_path_buf();
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    // The server is created in the server thread, which owns it until the shutdown.
+    let handle = std::thread::spawn(move || {
+        let engine = storage::KvLogStorage::open(&storage_path).map_err(|e| e.to_string())?;
+        let thread_pool = Box::new(threads::shared::SharedThreadPool::new(2));
+        let mut server = KvsServer::new(engine, thread_pool);
+        configure(&mut server);
+        sender.send(server.shutdown_handle()).unwrap();
+        server.listen(HOST.to_owned(), PORT).map_err(|e| e.to_string())
+    });
+    let shutdown_handle = receiver.recv().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    (shutdown_handle, handle)
+}
+
+// Shutdown should stop the server, close idle keep-alive connections and keep the stored data.
+#[serial_test::serial]
+#[test]
+fn graceful_shutdown() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    assert_eq!(client.execute_one(set, true)?.commands, vec![models::ResponseCommand::Set {}]);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+
+    // The idle keep-alive connection is closed and no new connections are accepted.
+    let get = models::Command::Get { key: "key1".to_owned() };
+    assert!(client.execute_one(get, true).is_err());
+    assert!(KvsClient::new().connect(HOST.to_owned(), PORT, Duration::from_secs(1)).is_err());
+
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// `kvs_server` should exit successfully on SIGTERM.
+#[cfg(unix)]
+#[serial_test::serial]
+#[test]
+fn server_cli_sigterm() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut child = std::process::Command::cargo_bin("kvs_server")
+        .unwrap()
+        .args(["--host", HOST, "--port", &PORT.to_string()])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+
+    std::process::Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+
+    let mut status = None;
+    for _ in 0..50 {
+        status = child.try_wait().unwrap();
+        if status.is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    match status {
+        Some(status) => assert!(status.success()),
+        None => {
+            child.kill().unwrap();
+            let _ = child.wait();
+            panic!("Server is not stopped on SIGTERM");
+        },
+    }
+}
+
+// A failed command should be reported with an error response without aborting the rest of the batch.
+#[serial_test::serial]
+#[test]
+fn per_command_errors() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_root = temp_dir.path().to_path_buf();
+    let (shutdown_handle, server_thread) = start_server_with(&temp_dir, |server| {
+        server.set_backup_root(Some(backup_root));
+    });
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let response = client.execute(
+        vec![
+            models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() },
+            models::Command::PrepareRestore { backup_dir: "missing".to_owned() },
+            models::Command::Get { key: "key1".to_owned() },
+        ],
+        true,
+    )?;
+
+    assert_eq!(response.commands.len(), 3);
+    assert_eq!(response.commands[0], models::ResponseCommand::Set {});
+    match &response.commands[1] {
+        models::ResponseCommand::Error { code, message } => {
+            assert_eq!(*code, models::ERROR_CODE_INTERNAL);
+            assert!(message.contains("No backups found"));
+        },
+        other => panic!("Expected an error response, got {:?}", other),
+    }
+    assert_eq!(response.commands[2], models::ResponseCommand::Get { value: Some(b"value1".to_vec()) });
+
+    // The connection is still usable.
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let response = client.execute_one(get, false)?;
+    assert!(response.commands.into_iter().next().unwrap().into_result().is_ok());
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// Restores should be rejected without a backup root and for the backups outside of it.
+#[serial_test::serial]
+#[test]
+fn restore_outside_backup_root() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_root = temp_dir.path().join("backups");
+    std::fs::create_dir(&backup_root)?;
+    let outside_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server_with(&temp_dir, |server| {
+        server.set_backup_root(Some(backup_root));
+    });
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let outside_dirs = [outside_dir.path().to_string_lossy().into_owned(), "../backups/../..".to_owned()];
+    for backup_dir in outside_dirs {
+        let restore = models::Command::PrepareRestore { backup_dir };
+        let response = client.execute_one(restore, true)?;
+        assert!(matches!(
+            response.commands[0], models::ResponseCommand::Error { code: models::ERROR_CODE_UNAUTHORIZED, .. }
+        ));
+    }
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let restore = models::Command::PrepareRestore { backup_dir: "backups".to_owned() };
+    let response = client.execute_one(restore, true)?;
+    assert!(matches!(
+        response.commands[0], models::ResponseCommand::Error { code: models::ERROR_CODE_UNAUTHORIZED, .. }
+    ));
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// Binary values should be passed through the protocol unchanged.
+#[serial_test::serial]
+#[test]
+fn binary_values() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let value = vec![0u8, 1, 2, 255, 254, 0];
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let response = client.execute(
+        vec![
+            models::Command::Set { key: "key1".to_owned(), value: value.clone() },
+            models::Command::Get { key: "key1".to_owned() },
+        ],
+        false,
+    )?;
+    assert_eq!(response.commands[1], models::ResponseCommand::Get { value: Some(value) });
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// A large value should be streamed in several frames, leaving the connection usable afterwards.
+#[serial_test::serial]
+#[test]
+fn streamed_get() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let value: Vec<u8> = (0..300_000u32).map(|idx| (idx % 251) as u8).collect();
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    client.execute(
+        vec![
+            models::Command::Set { key: "large".to_owned(), value: value.clone() },
+            models::Command::Set { key: "empty".to_owned(), value: Vec::new() },
+        ],
+        true,
+    )?;
+
+    let mut streamed = Vec::new();
+    client.get_stream("large".to_owned())?.expect("missing value").read_to_end(&mut streamed)?;
+    assert_eq!(streamed, value);
+    streamed.clear();
+    client.get_stream("empty".to_owned())?.expect("missing value").read_to_end(&mut streamed)?;
+    assert!(streamed.is_empty());
+    assert!(client.get_stream("missing".to_owned())?.is_none());
+
+    // A stream dropped before its end should not break the next requests.
+    let mut head = [0u8; 16];
+    client.get_stream("large".to_owned())?.expect("missing value").read_exact(&mut head)?;
+    assert_eq!(head, value[..16]);
+    let response = client.execute_one(models::Command::Get { key: "empty".to_owned() }, false)?;
+    assert_eq!(response.commands, vec![models::ResponseCommand::Get { value: Some(Vec::new()) }]);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// Commands of a request with an exceeded deadline should be skipped.
+#[serial_test::serial]
+#[test]
+fn deadline_exceeded() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let expired = std::time::SystemTime::now() - Duration::from_secs(1);
+    let response = client.execute_with_deadline(
+        vec![
+            models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() },
+            models::Command::Get { key: "key1".to_owned() },
+        ],
+        true,
+        Some(expired),
+    )?;
+    assert_eq!(response.commands.len(), 2);
+    for response_command in response.commands {
+        match response_command {
+            models::ResponseCommand::Error { code, .. } => assert_eq!(code, models::ERROR_CODE_DEADLINE_EXCEEDED),
+            other => panic!("Expected an error response, got {:?}", other),
+        }
+    }
+
+    // A request within the deadline is handled, the skipped command is not applied.
+    let deadline = std::time::SystemTime::now() + Duration::from_secs(10);
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let response = client.execute_with_deadline(vec![get], false, Some(deadline))?;
+    assert_eq!(response.commands, vec![models::ResponseCommand::Get { value: None }]);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// The server should serve the clients on a unix socket, replacing a stale socket file and removing it on shutdown.
+#[cfg(unix)]
+#[test]
+fn unix_socket() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let socket_dir = TempDir::new().expect("unable to create temporary socket directory");
+    let socket_path = socket_dir.path().join("kvs.sock");
+    // A socket file left by a stopped server.
+    drop(std::os::unix::net::UnixListener::bind(&socket_path)?);
+
+    let storage_path = temp_dir.path().to_path_buf();
+    let server_socket_path = socket_path.clone();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let server_thread = std::thread::spawn(move || {
+        let engine = storage::KvLogStorage::open(&storage_path).map_err(|e| e.to_string())?;
+        let thread_pool = Box::new(threads::shared::SharedThreadPool::new(2));
+        let mut server = KvsServer::new(engine, thread_pool);
+        sender.send(server.shutdown_handle()).unwrap();
+        server.listen_unix(&server_socket_path).map_err(|e| e.to_string())
+    });
+    let shutdown_handle = receiver.recv().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let mut client = KvsClient::new();
+    client.connect_unix(&socket_path, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    assert_eq!(client.execute_one(set, true)?.commands, vec![models::ResponseCommand::Set {}]);
+    let get = models::Command::Get { key: "key1".to_owned() };
+    assert_eq!(
+        client.execute_one(get, false)?.commands,
+        vec![models::ResponseCommand::Get { value: Some(b"value1".to_vec()) }],
+    );
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    assert!(!socket_path.exists());
+    Ok(())
+}
+
+// Queued commands should be sent together on flush and matched with their responses in order.
+#[serial_test::serial]
+#[test]
+fn queued_commands() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    assert_eq!(client.flush_queue(true)?, vec![]);
+
+    client.queue(models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() });
+    let get_idx = client.queue(models::Command::Get { key: "key1".to_owned() });
+    let remove_idx = client.queue(models::Command::Remove { key: "key2".to_owned() });
+    assert_eq!(client.queued_count(), 3);
+    let responses = client.flush_queue(true)?;
+    assert_eq!(client.queued_count(), 0);
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[get_idx], models::ResponseCommand::Get { value: Some(b"value1".to_vec()) });
+    assert_eq!(responses[remove_idx], models::ResponseCommand::Remove {});
+
+    // More commands than fit in a single request are split into several requests.
+    let commands_count = u16::MAX as usize + 10;
+    for _ in 0..commands_count {
+        client.queue(models::Command::Get { key: "key1".to_owned() });
+    }
+    let responses = client.flush_queue(false)?;
+    assert_eq!(responses.len(), commands_count);
+    assert!(responses.iter().all(|response| *response == models::ResponseCommand::Get { value: Some(b"value1".to_vec()) }));
+    assert!(!client.is_connected());
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// A keep-alive client should reconnect to a restarted server and resend the request.
+#[serial_test::serial]
+#[test]
+fn reconnect_after_restart() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    assert_eq!(client.execute_one(set, true)?.commands, vec![models::ResponseCommand::Set {}]);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let get = models::Command::Get { key: "key1".to_owned() };
+    assert_eq!(
+        client.execute_one(get.clone(), true)?.commands,
+        vec![models::ResponseCommand::Get { value: Some(b"value1".to_vec()) }],
+    );
+
+    // With the reconnects disabled the closed connection is reported to the caller.
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+    client.set_max_reconnects(0);
+    assert!(client.execute_one(get, true).is_err());
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// A lost request should be resent only if it wasn't written or all of its commands are read-only.
+#[test]
+fn reconnect_resends_read_only() -> models::Result<()> {
+    // Fake server reading a single request per connection and closing it without a response.
+    let listener = std::net::TcpListener::bind((HOST, 0))?;
+    let port = listener.local_addr()?.port() as u32;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut buffer = [0u8; 1024];
+            let _ = stream.read(&mut buffer);
+            let _ = sender.send(());
+        }
+    });
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), port, Duration::from_secs(5))?;
+    let append = models::Command::Append { key: "key1".to_owned(), suffix: b"suffix".to_vec() };
+    assert!(client.execute_one(append, true).is_err());
+    assert_eq!(receiver.try_iter().count(), 1);
+
+    client.connect(HOST.to_owned(), port, Duration::from_secs(5))?;
+    let get = models::Command::Get { key: "key1".to_owned() };
+    assert!(client.execute_one(get, true).is_err());
+    // The first request and the requests of the 3 reconnects.
+    assert_eq!(receiver.try_iter().count(), 4);
+    Ok(())
+}
+
+// The server should serve a sharded storage, online restores are rejected.
+#[serial_test::serial]
+#[test]
+fn sharded_server() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let storage_path = temp_dir.path().to_path_buf();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let server_thread = std::thread::spawn(move || {
+        let engine = storage::ShardedKvStorage::open(&storage_path, 3, storage::KvLogStorage::builder())
+            .map_err(|e| e.to_string())?;
+        let thread_pool = Box::new(threads::shared::SharedThreadPool::new(2));
+        let mut server = KvsServer::new(engine, thread_pool);
+        sender.send(server.shutdown_handle()).unwrap();
+        server.listen(HOST.to_owned(), PORT).map_err(|e| e.to_string())
+    });
+    let shutdown_handle = receiver.recv().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    for idx in 0..10 {
+        client.queue(models::Command::Set { key: format!("key{}", idx), value: format!("value{}", idx).into_bytes() });
+    }
+    let get_idx = client.queue(models::Command::Get { key: "key7".to_owned() });
+    let stats_idx = client.queue(models::Command::Stats {});
+    let restore_idx = client.queue(models::Command::PrepareRestore { backup_dir: "backup".to_owned() });
+    let responses = client.flush_queue(false)?;
+    assert_eq!(responses[get_idx], models::ResponseCommand::Get { value: Some(b"value7".to_vec()) });
+    match &responses[stats_idx] {
+        models::ResponseCommand::Stats { stats, .. } => assert_eq!(stats.keys_count, 10),
+        response => panic!("unexpected response {:?}", response),
+    }
+    assert!(matches!(responses[restore_idx], models::ResponseCommand::Error { .. }));
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// A framed client should exchange checksummed frames with the server, a corrupted frame should be rejected.
+#[serial_test::serial]
+#[test]
+fn framed_protocol() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.set_framed(true);
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let response = client.execute(vec![set, get], true)?;
+    assert_eq!(response.header.version, 5);
+    assert_eq!(response.commands, vec![
+        models::ResponseCommand::Set {},
+        models::ResponseCommand::Get { value: Some(b"value1".to_vec()) },
+    ]);
+    let mut streamed = Vec::new();
+    client.get_stream("key1".to_owned())?.expect("missing value").read_to_end(&mut streamed)?;
+    assert_eq!(streamed, b"value1");
+
+    // The unframed requests are still replied with the unframed responses.
+    client.set_framed(false);
+    let response = client.execute_one(models::Command::Get { key: "key1".to_owned() }, true)?;
+    assert_eq!(response.header.version, 4);
+
+    // A get of "key1" with a zeroed checksum closes the connection without a response.
+    let frame = [&[0xC5, 0x4B, 6, b'g', 4][..], b"key1", &[0, 0, 0, 0]].concat();
+    let mut request = vec![5u8, 0, 0, 1];
+    request.extend((frame.len() as u32).to_be_bytes());
+    request.extend([0u8; 4 + 8 + 4 + 4]);
+    request.extend(frame);
+    let mut stream = std::net::TcpStream::connect(format!("{}:{}", HOST, PORT))?;
+    stream.write_all(&request)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    assert!(response.is_empty());
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// Commands should be executed in the namespace of the request, the reset should affect only that namespace.
+#[serial_test::serial]
+#[test]
+fn server_namespaces() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"default".to_vec() };
+    client.execute_one(set, true)?;
+    client.set_namespace(Some("dataset".to_owned()));
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"dataset".to_vec() };
+    client.execute_one(set, true)?;
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let response = client.execute(vec![get.clone(), models::Command::Reset {}, get.clone()], true)?;
+    assert_eq!(response.commands, vec![
+        models::ResponseCommand::Get { value: Some(b"dataset".to_vec()) },
+        models::ResponseCommand::Reset {},
+        models::ResponseCommand::Get { value: None },
+    ]);
+
+    client.set_namespace(None);
+    let response = client.execute_one(get.clone(), true)?;
+    assert_eq!(response.commands, vec![models::ResponseCommand::Get { value: Some(b"default".to_vec()) }]);
+
+    client.set_namespace(Some("no/such".to_owned()));
+    let response = client.execute_one(get, false)?;
+    assert!(matches!(response.commands[..], [models::ResponseCommand::Error { code: models::ERROR_CODE_INTERNAL, .. }]));
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    assert!(temp_dir.path().join("ns_dataset").is_dir());
+    Ok(())
+}
+
+// Named stores should be isolated from the default store and have their own namespaces.
+#[serial_test::serial]
+#[test]
+fn server_stores() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let users_dir = TempDir::new().expect("unable to create temporary working directory");
+    let users_path = users_dir.path().to_path_buf();
+    let (shutdown_handle, server_thread) = start_server_with(&temp_dir, move |server| {
+        let engine = storage::KvLogStorage::open(&users_path).unwrap();
+        server.add_store("users".to_owned(), Box::new(engine)).unwrap();
+        let engine = storage::KvLogStorage::open(&users_path.join("other")).unwrap();
+        assert!(server.add_store("users".to_owned(), Box::new(engine)).is_err());
+    });
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"default".to_vec() };
+    client.execute_one(set, true)?;
+    client.set_store(Some("users".to_owned()));
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"users".to_vec() };
+    client.execute_one(set, true)?;
+    client.set_namespace(Some("dataset".to_owned()));
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"dataset".to_vec() };
+    client.execute_one(set, true)?;
+
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let response = client.execute_one(get.clone(), true)?;
+    assert_eq!(response.commands, vec![models::ResponseCommand::Get { value: Some(b"dataset".to_vec()) }]);
+    client.set_namespace(None);
+    let response = client.execute_one(get.clone(), true)?;
+    assert_eq!(response.commands, vec![models::ResponseCommand::Get { value: Some(b"users".to_vec()) }]);
+    client.set_store(None);
+    let response = client.execute_one(get.clone(), true)?;
+    assert_eq!(response.commands, vec![models::ResponseCommand::Get { value: Some(b"default".to_vec()) }]);
+
+    client.set_store(Some("no_such".to_owned()));
+    let response = client.execute_one(get, false)?;
+    let code = models::ERROR_CODE_NOT_FOUND;
+    assert!(matches!(response.commands[..], [models::ResponseCommand::Error { code: error_code, .. }] if error_code == code));
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    assert!(users_dir.path().join("ns_dataset").is_dir());
+    assert!(!temp_dir.path().join("ns_dataset").exists());
+    Ok(())
+}
+
+// The consecutive writes exceeding a log file should be split into batches, a write failing in a batch should fail
+// on its own.
+#[serial_test::serial]
+#[test]
+fn write_batch_split() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let small_dir = TempDir::new().expect("unable to create temporary working directory");
+    let small_path = small_dir.path().to_path_buf();
+    let (shutdown_handle, server_thread) = start_server_with(&temp_dir, move |server| {
+        let engine = storage::KvLogStorage::builder().segment_size(200).open(&small_path).unwrap();
+        server.add_store("small".to_owned(), Box::new(engine)).unwrap();
+    });
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    client.set_store(Some("small".to_owned()));
+    let mut writes: Vec<models::Command> = (0..10)
+        .map(|i| models::Command::Set { key: format!("key{}", i), value: vec![b'v'; 20] })
+        .collect();
+    writes.insert(5, models::Command::Set { key: "large".to_owned(), value: vec![b'v'; 300] });
+    writes.push(models::Command::Remove { key: "key0".to_owned() });
+    let response = client.execute(writes, true)?;
+
+    assert_eq!(response.commands.len(), 12);
+    assert_eq!(response.commands[5].status(), models::StatusCode::Internal);
+    for (idx, command) in response.commands.iter().enumerate().filter(|(idx, _)| *idx != 5) {
+        let expected = if idx == 11 { models::ResponseCommand::Remove {} } else { models::ResponseCommand::Set {} };
+        assert_eq!(*command, expected);
+    }
+
+    let gets = ["key0", "key1", "key9", "large"].iter()
+        .map(|key| models::Command::Get { key: key.to_string() })
+        .collect();
+    let response = client.execute(gets, false)?;
+    assert_eq!(response.commands, vec![
+        models::ResponseCommand::Get { value: None },
+        models::ResponseCommand::Get { value: Some(vec![b'v'; 20]) },
+        models::ResponseCommand::Get { value: Some(vec![b'v'; 20]) },
+        models::ResponseCommand::Get { value: None },
+    ]);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// Stores should be added by the config file and the command line options of the server binary.
+#[serial_test::serial]
+#[test]
+fn server_cli_stores() -> models::Result<()> {
+    let config_dir = TempDir::new().expect("unable to create temporary working directory");
+    let storage_dir = TempDir::new().expect("unable to create temporary working directory");
+    let file_store_dir = TempDir::new().expect("unable to create temporary working directory");
+    let cli_store_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config_path = config_dir.path().join("server.toml");
+    std::fs::write(&config_path, format!(
+        "host = \"{}\"\nport = {}\npath = \"{}\"\nlog_level = \"warning\"\nstore = [\"users={}\"]\n",
+        HOST, PORT, storage_dir.path().display(), file_store_dir.path().display(),
+    ))?;
+
+    let cli_store = format!("logs={}", cli_store_dir.path().display());
+    let mut child = std::process::Command::cargo_bin("kvs_server")
+        .unwrap()
+        .args(["--config", config_path.to_str().unwrap(), "--store", &cli_store])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+
+    let result = (|| -> models::Result<()> {
+        let mut client = KvsClient::new();
+        client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+        for store in ["users", "logs"] {
+            client.set_store(Some(store.to_owned()));
+            let set = models::Command::Set { key: "key1".to_owned(), value: store.as_bytes().to_vec() };
+            client.execute_one(set, true)?;
+        }
+        Ok(())
+    })();
+    std::process::Command::new("kill").args(["-TERM", &child.id().to_string()]).status()?;
+    child.wait()?;
+    result?;
+
+    assert!(file_store_dir.path().join("kv_1.log").exists());
+    assert!(cli_store_dir.path().join("kv_1.log").exists());
+    assert!(!storage_dir.path().join("kv_1.log").exists());
+
+    std::process::Command::cargo_bin("kvs_server")
+        .unwrap()
+        .args(["--path", storage_dir.path().to_str().unwrap(), "--store", "=no_name"])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// Failed commands should be reported with the error codes of their failure kinds.
+#[serial_test::serial]
+#[test]
+fn error_status_codes() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    // Large enough not to be inlined into the index, so it's read from the log file.
+    let set = models::Command::Set { key: "key1".to_owned(), value: vec![b'v'; 1024] };
+    let response = client.execute_one(set, true)?;
+    assert_eq!(response.commands[0].status(), models::StatusCode::Ok);
+
+    let commit = models::Command::CommitRestore { token: "0123456789abcdef".to_owned() };
+    let response = client.execute_one(commit, true)?;
+    assert_eq!(response.commands[0].status(), models::StatusCode::NotFound);
+    let err = response.commands.into_iter().next().unwrap().into_result().unwrap_err();
+    let command_err = err.downcast_ref::<models::CommandError>().unwrap();
+    assert_eq!(command_err.code, models::ERROR_CODE_NOT_FOUND);
+    assert_eq!(command_err.message, "Unknown restore token 0123456789abcdef");
+
+    // The stored value is cut off behind the storage back.
+    let log_file = std::fs::OpenOptions::new().write(true).open(temp_dir.path().join("kv_1.log"))?;
+    log_file.set_len(100)?;
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let response = client.execute_one(get, false)?;
+    assert_eq!(response.commands[0].status(), models::StatusCode::Corruption);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    assert_eq!(models::StatusCode::from_error_code(models::ERROR_CODE_SERVER_BUSY), models::StatusCode::ServerBusy);
+    assert_eq!(models::StatusCode::from_error_code(100), models::StatusCode::Unknown(100));
+    Ok(())
+}
+
+// Get and append of a list should fail with wrong type errors on their own, leaving the list unchanged.
+#[serial_test::serial]
+#[test]
+fn wrong_type_commands() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    client.queue(models::Command::ListPush { key: "list".to_owned(), values: vec![b"a".to_vec()], front: false });
+    client.queue(models::Command::Set { key: "key".to_owned(), value: b"value".to_vec() });
+    client.flush_queue(true)?;
+
+    let list_get_idx = client.queue(models::Command::Get { key: "list".to_owned() });
+    let get_idx = client.queue(models::Command::Get { key: "key".to_owned() });
+    let append_idx = client.queue(models::Command::Append { key: "list".to_owned(), suffix: b"b".to_vec() });
+    let range_idx = client.queue(models::Command::ListRange { key: "list".to_owned(), start: 0, stop: -1 });
+    let responses = client.flush_queue(true)?;
+    assert_eq!(responses[list_get_idx].status(), models::StatusCode::WrongType);
+    assert_eq!(responses[get_idx], models::ResponseCommand::Get { value: Some(b"value".to_vec()) });
+    assert!(matches!(
+        responses[append_idx], models::ResponseCommand::Error { code: models::ERROR_CODE_WRONG_TYPE, .. }
+    ));
+    assert_eq!(responses[range_idx], models::ResponseCommand::ListRange { values: vec![b"a".to_vec()] });
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+/// Waits for the server to close the connection. Returns `false` if it's still open after `timeout`.
+fn wait_closed(stream: &mut std::net::TcpStream, timeout: Duration) -> bool {
+    stream.set_read_timeout(Some(timeout)).unwrap();
+    let mut buffer = [0u8; 16];
+    match stream.read(&mut buffer) {
+        Ok(size) => size == 0,
+        Err(err) => !matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut),
+    }
+}
+
+// Requests of the connections beyond the limit should fail with server busy errors,
+// silent and stuck connections should be closed after their timeouts.
+#[serial_test::serial]
+#[test]
+fn connection_timeouts() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server_with(&temp_dir, |server| {
+        server.set_idle_timeout(Some(Duration::from_millis(500)));
+        server.set_request_read_timeout(Some(Duration::from_millis(500)));
+        server.set_max_connections(Some(2));
+    });
+    let addr = format!("{}:{}", HOST, PORT);
+
+    let started = Instant::now();
+    let mut idle_streams = vec![std::net::TcpStream::connect(&addr)?, std::net::TcpStream::connect(&addr)?];
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(shutdown_handle.connections_count(), 2);
+    let mut rejected_client = KvsClient::new();
+    rejected_client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let response = rejected_client.execute(vec![get.clone(), get.clone()], false)?;
+    let statuses: Vec<models::StatusCode> = response.commands.iter().map(|command| command.status()).collect();
+    assert_eq!(statuses, vec![models::StatusCode::ServerBusy; 2]);
+    assert_eq!(shutdown_handle.connections_count(), 2);
+
+    for stream in idle_streams.iter_mut() {
+        assert!(wait_closed(stream, Duration::from_secs(5)));
+    }
+    assert!(started.elapsed() >= Duration::from_millis(400));
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(shutdown_handle.connections_count(), 0);
+
+    // A request header cut in the middle.
+    let mut stuck_stream = std::net::TcpStream::connect(&addr)?;
+    stuck_stream.write_all(&[1, 0, 0])?;
+    assert!(wait_closed(&mut stuck_stream, Duration::from_secs(5)));
+
+    // Active connections are not affected.
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    for _ in 0..3 {
+        std::thread::sleep(Duration::from_millis(200));
+        let response = client.execute_one(get.clone(), true)?;
+        assert_eq!(response.commands[0].status(), models::StatusCode::Ok);
+    }
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+/// Waits for the server to have `count` open connections. Returns `false` if it still has another number
+/// after `timeout`.
+fn wait_connections(shutdown_handle: &ShutdownHandle, count: usize, timeout: Duration) -> bool {
+    let started = Instant::now();
+    while shutdown_handle.connections_count() != count {
+        if started.elapsed() >= timeout {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+// Silent rejected connections should be replied off the accepting thread, so the next connections are accepted
+// without waiting for their requests.
+#[serial_test::serial]
+#[test]
+fn rejected_connections_not_blocking_accept() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server_with(&temp_dir, |server| {
+        server.set_max_connections(Some(1));
+    });
+    let addr = format!("{}:{}", HOST, PORT);
+
+    let idle_stream = std::net::TcpStream::connect(&addr)?;
+    assert!(wait_connections(&shutdown_handle, 1, Duration::from_secs(5)));
+    // Each silent connection holds the reply thread for 200 ms.
+    let mut silent_streams = Vec::new();
+    for _ in 0..10 {
+        silent_streams.push(std::net::TcpStream::connect(&addr)?);
+    }
+    assert!(wait_closed(&mut silent_streams[0], Duration::from_secs(5)));
+
+    drop(idle_stream);
+    assert!(wait_connections(&shutdown_handle, 0, Duration::from_secs(5)));
+    let started = Instant::now();
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let response = client.execute_one(models::Command::Get { key: "key1".to_owned() }, false)?;
+    assert_eq!(response.commands[0].status(), models::StatusCode::Ok);
+    // The rest of the silent connections take 1.8 s to reply.
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+/// Storage stuck for a while on reading the key `stalled`, like on a hanging disk.
+struct StalledStorage {
+    storage: storage::KvLogStorage,
+}
+
+impl KvStorage for StalledStorage {
+    fn set_bytes(&mut self, key: String, value: Vec<u8>) -> models::Result<()> {
+        self.storage.set_bytes(key, value)
+    }
+
+    fn get_bytes(&self, key: String) -> models::Result<Option<Vec<u8>>> {
+        if key == "stalled" {
+            std::thread::sleep(Duration::from_secs(2));
+        }
+        self.storage.get_bytes(key)
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> models::Result<Vec<Option<Vec<u8>>>> {
+        keys.into_iter().map(|key| self.get_bytes(key)).collect()
+    }
+
+    fn append(&mut self, key: String, suffix: Vec<u8>) -> models::Result<u64> {
+        self.storage.append(key, suffix)
+    }
+
+    fn get_set(&mut self, key: String, value: Vec<u8>) -> models::Result<Option<Vec<u8>>> {
+        self.storage.get_set(key, value)
+    }
+
+    fn set_nx(&mut self, key: String, value: Vec<u8>) -> models::Result<bool> {
+        self.storage.set_nx(key, value)
+    }
+
+    fn update_collection(&mut self, mutation: models::Command) -> models::Result<storage::MutationResult> {
+        self.storage.update_collection(mutation)
+    }
+
+    fn read_collection(
+        &self,
+        key: String,
+        collection_type: models::CollectionType,
+        read: &mut storage::CollectionRead,
+    ) -> models::Result<()> {
+        self.storage.read_collection(key, collection_type, read)
+    }
+
+    fn remove(&mut self, key: String) -> models::Result<bool> {
+        KvStorage::remove(&mut self.storage, key)
+    }
+
+    fn reset(&mut self) -> models::Result<()> {
+        KvStorage::reset(&mut self.storage)
+    }
+
+    fn reset_prefix(&mut self, prefix: String) -> models::Result<u64> {
+        KvStorage::reset_prefix(&mut self.storage, prefix)
+    }
+
+    fn write_batch(&mut self, commands: Vec<models::Command>) -> models::Result<()> {
+        KvStorage::write_batch(&mut self.storage, commands)
+    }
+
+    fn compact(&self) -> models::Result<()> {
+        KvStorage::compact(&self.storage)
+    }
+
+    fn stats(&self) -> models::Result<models::StorageStats> {
+        KvStorage::stats(&self.storage)
+    }
+
+    fn flush(&self) -> models::Result<()> {
+        KvStorage::flush(&self.storage)
+    }
+
+    fn close(&self) -> models::Result<()> {
+        KvStorage::close(&self.storage)
+    }
+
+    fn prepare_restore(&self, backup_dir: &std::path::Path) -> models::Result<String> {
+        KvStorage::prepare_restore(&self.storage, backup_dir)
+    }
+
+    fn commit_restore(&mut self, token: &str) -> models::Result<()> {
+        KvStorage::commit_restore(&mut self.storage, token)
+    }
+
+    fn abort_restore(&self, token: &str) -> models::Result<()> {
+        KvStorage::abort_restore(&self.storage, token)
+    }
+
+    fn namespace(&self, name: &str) -> models::Result<Box<dyn KvStorage>> {
+        KvStorage::namespace(&self.storage, name)
+    }
+
+    fn set_compaction_garbage_ratio(&self, ratio: f64) -> models::Result<()> {
+        KvStorage::set_compaction_garbage_ratio(&self.storage, ratio)
+    }
+
+    fn pause_compaction(&self) -> models::Result<()> {
+        KvStorage::pause_compaction(&self.storage)
+    }
+
+    fn resume_compaction(&self) -> models::Result<()> {
+        KvStorage::resume_compaction(&self.storage)
+    }
+
+    fn is_compaction_paused(&self) -> bool {
+        KvStorage::is_compaction_paused(&self.storage)
+    }
+
+    fn clone_box(&self) -> Box<dyn KvStorage> {
+        Box::new(StalledStorage { storage: self.storage.clone() })
+    }
+}
+
+/// Starts a server of a `StalledStorage` with the request timeout.
+fn start_stalled_server(
+    dir: &TempDir,
+    request_timeout: Option<Duration>,
+) -> (ShutdownHandle, std::thread::JoinHandle<Result<(), String>>) {
+    let storage_path = dir.path().to_path_buf();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let server_thread = std::thread::spawn(move || {
+        let engine = StalledStorage { storage: storage::KvLogStorage::open(&storage_path).map_err(|e| e.to_string())? };
+        let thread_pool = Box::new(threads::shared::SharedThreadPool::new(2));
+        let mut server = KvsServer::new(engine, thread_pool);
+        server.set_request_timeout(request_timeout);
+        sender.send(server.shutdown_handle()).unwrap();
+        server.listen(HOST.to_owned(), PORT).map_err(|e| e.to_string())
+    });
+    let shutdown_handle = receiver.recv().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    (shutdown_handle, server_thread)
+}
+
+// Only the commands skipped after the client deadline should be reported as exceeded,
+// the commands handled before it should keep their results.
+#[serial_test::serial]
+#[test]
+fn deadline_exceeded_during_request() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_stalled_server(&temp_dir, None);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let deadline = std::time::SystemTime::now() + Duration::from_millis(500);
+    let response = client.execute_with_deadline(
+        vec![
+            models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() },
+            models::Command::Get { key: "stalled".to_owned() },
+            models::Command::Set { key: "key2".to_owned(), value: b"value2".to_vec() },
+        ],
+        true,
+        Some(deadline),
+    )?;
+    let statuses: Vec<models::StatusCode> = response.commands.iter().map(|command| command.status()).collect();
+    assert_eq!(
+        statuses, vec![models::StatusCode::Ok, models::StatusCode::Ok, models::StatusCode::DeadlineExceeded],
+    );
+
+    let gets = vec![models::Command::Get { key: "key1".to_owned() }, models::Command::Get { key: "key2".to_owned() }];
+    let response = client.execute(gets, false)?;
+    assert_eq!(response.commands, vec![
+        models::ResponseCommand::Get { value: Some(b"value1".to_vec()) }, models::ResponseCommand::Get { value: None },
+    ]);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// The commands not started within the request timeout should fail with deadline exceeded errors,
+// a command stuck on the storage should complete and keep its result.
+#[serial_test::serial]
+#[test]
+fn request_timeout() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_stalled_server(&temp_dir, Some(Duration::from_millis(300)));
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    let get_stalled = models::Command::Get { key: "stalled".to_owned() };
+    let response = client.execute(vec![get_stalled, set], true)?;
+    assert_eq!(response.commands[0], models::ResponseCommand::Get { value: None });
+    assert_eq!(response.commands[1].status(), models::StatusCode::DeadlineExceeded);
+
+    // The connection keeps serving the requests. The skipped set is never applied.
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let response = client.execute_one(get, true)?;
+    assert_eq!(response.commands, vec![models::ResponseCommand::Get { value: None }]);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// Handled requests should be written to the access log as JSON lines, the full log files should be rotated.
+#[serial_test::serial]
+#[test]
+fn access_log_rotation() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log_path = log_dir.path().join("access.log");
+    let access_log = AccessLog::open(&log_path, 1000, 2)?;
+    let (shutdown_handle, server_thread) = start_server_with(&temp_dir, move |server| {
+        server.set_access_log(Some(access_log));
+    });
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    let get = models::Command::Get { key: "key1".to_owned() };
+    client.execute(vec![set, get, models::Command::Stats {}], true)?;
+    let commit = models::Command::CommitRestore { token: "0123456789abcdef".to_owned() };
+    client.execute_one(commit, true)?;
+    // The entries are written after the responses are sent.
+    std::thread::sleep(Duration::from_millis(100));
+
+    let lines: Vec<String> = std::fs::read_to_string(&log_path)?.lines().map(str::to_owned).collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("{\"timestamp_ms\":"));
+    let request_ids: Vec<&str> = lines.iter().map(|line| {
+        let start = line.find("\"request_id\":\"").unwrap() + 14;
+        &line[start..start + 16]
+    }).collect();
+    assert!(request_ids.iter().all(|id| id.chars().all(|c| c.is_ascii_hexdigit())));
+    assert_ne!(request_ids[0], request_ids[1]);
+    assert!(lines[0].contains("\"client\":\"127.0.0.1:"));
+    assert!(lines[0].contains("\"namespace\":\"\",\"commands\":[\"set\",\"get\",\"stats\"],\"keys\":2,"));
+    assert!(lines[0].ends_with("\"result\":\"ok\",\"errors\":0}"));
+    assert!(lines[1].contains("\"commands\":[\"commit_restore\"],\"keys\":0,"));
+    assert!(lines[1].ends_with("\"result\":\"not found\",\"errors\":1}"));
+
+    // Each entry is about 170 bytes, so 20 more requests fill the log file and both rotated ones.
+    for _ in 0..20 {
+        client.execute_one(models::Command::Get { key: "key1".to_owned() }, true)?;
+    }
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(access_log::rotated_path(&log_path, 1).exists());
+    assert!(access_log::rotated_path(&log_path, 2).exists());
+    assert!(!access_log::rotated_path(&log_path, 3).exists());
+    for path in [log_path.clone(), access_log::rotated_path(&log_path, 1)] {
+        let size = std::fs::metadata(&path)?.len();
+        assert!(size > 0 && size <= 1000);
+    }
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// Nested spans should extend the logging context of the thread, dropped ones should restore it.
+#[test]
+fn log_spans() {
+    assert_eq!(trace::current_span(), None);
+    let connection_span = trace::Span::enter("conn=1".to_owned());
+    {
+        let _request_span = trace::Span::enter("req=0123456789abcdef".to_owned());
+        assert_eq!(trace::current_span().as_deref(), Some("conn=1 req=0123456789abcdef"));
+
+        let span = trace::current_span();
+        let copied = std::thread::spawn(move || {
+            let _span = trace::Span::enter_copy(span);
+            trace::current_span()
+        }).join().unwrap();
+        assert_eq!(copied.as_deref(), Some("conn=1 req=0123456789abcdef"));
+    }
+    assert_eq!(trace::current_span().as_deref(), Some("conn=1"));
+    drop(connection_span);
+    assert_eq!(trace::current_span(), None);
+    assert_ne!(trace::new_request_id(), trace::new_request_id());
+}
+
+// The server should take its options from the config file, the command line options should override them.
+#[serial_test::serial]
+#[test]
+fn server_config_file() -> models::Result<()> {
+    let config_dir = TempDir::new().expect("unable to create temporary working directory");
+    let file_storage_dir = TempDir::new().expect("unable to create temporary working directory");
+    let cli_storage_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config_path = config_dir.path().join("server.toml");
+    std::fs::write(&config_path, format!(
+        "host = \"{}\"\nport = {}\npath = \"{}\"\nlog_level = \"warning\"\nsegment_size = 1000\n",
+        HOST, PORT, file_storage_dir.path().display(),
+    ))?;
+
+    let mut child = std::process::Command::cargo_bin("kvs_server")
+        .unwrap()
+        .args(["--config", config_path.to_str().unwrap(), "--path", cli_storage_dir.path().to_str().unwrap()])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+
+    let result = (|| -> models::Result<()> {
+        let mut client = KvsClient::new();
+        client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+        let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+        client.execute_one(set, false)?;
+        Ok(())
+    })();
+    std::process::Command::new("kill").args(["-TERM", &child.id().to_string()]).status()?;
+    child.wait()?;
+    result?;
+
+    assert!(cli_storage_dir.path().join("kv_1.log").exists());
+    assert!(!file_storage_dir.path().join("kv_1.log").exists());
+
+    std::fs::write(&config_path, "tls = { cert = \"cert.pem\" }\n")?;
+    std::process::Command::cargo_bin("kvs_server")
+        .unwrap()
+        .args(["--config", config_path.to_str().unwrap()])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// SIGHUP should reload the log level from the config file without stopping the server.
+#[cfg(unix)]
+#[serial_test::serial]
+#[test]
+fn server_config_reload() -> models::Result<()> {
+    let config_dir = TempDir::new().expect("unable to create temporary working directory");
+    let storage_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config_path = config_dir.path().join("server.toml");
+    let config = |log_level: &str| {
+        format!(
+            "port = {}\npath = \"{}\"\nlog_level = \"{}\"\n",
+            PORT, storage_dir.path().display(), log_level,
+        )
+    };
+    std::fs::write(&config_path, config("error"))?;
+
+    let child = std::process::Command::cargo_bin("kvs_server")
+        .unwrap()
+        .args(["--config", config_path.to_str().unwrap()])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+
+    let result = (|| -> models::Result<()> {
+        let mut client = KvsClient::new();
+        client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+        let set = models::Command::Set { key: "before_reload".to_owned(), value: b"value".to_vec() };
+        client.execute_one(set, false)?;
+
+        std::fs::write(&config_path, config("info"))?;
+        std::process::Command::new("kill").args(["-HUP", &child.id().to_string()]).status()?;
+        std::thread::sleep(Duration::from_millis(500));
+
+        client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+        let set = models::Command::Set { key: "after_reload".to_owned(), value: b"value".to_vec() };
+        client.execute_one(set, false)?;
+        Ok(())
+    })();
+    std::process::Command::new("kill").args(["-TERM", &child.id().to_string()]).status()?;
+    let output = child.wait_with_output()?;
+    result?;
+
+    let output = String::from_utf8_lossy(&output.stdout);
+    assert!(output.contains("is reloaded"));
+    assert!(output.contains("Handling command Set<key=after_reload"));
+    assert!(!output.contains("before_reload"));
+    Ok(())
+}
+
+// A daemonized server should detach right away, log to the log file and remove its pid file on SIGTERM.
+#[cfg(unix)]
+#[serial_test::serial]
+#[test]
+fn server_cli_daemon() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let pid_path = temp_dir.path().join("kvs_server.pid");
+    let log_path = temp_dir.path().join("kvs_server.log");
+    let status = std::process::Command::cargo_bin("kvs_server")
+        .unwrap()
+        .args(["--port", &PORT.to_string(), "--daemonize", "--pid-file", "kvs_server.pid", "--log-file", "kvs_server.log"])
+        .current_dir(&temp_dir)
+        .status()?;
+    assert!(status.success());
+    std::thread::sleep(Duration::from_secs(1));
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    client.execute_one(set, false)?;
+
+    let pid = std::fs::read_to_string(&pid_path)?.trim().to_owned();
+    std::process::Command::new("kill").args(["-TERM", &pid]).status()?;
+    for _ in 0..50 {
+        if !pid_path.exists() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert!(!pid_path.exists());
+
+    let log = std::fs::read_to_string(&log_path)?;
+    assert!(log.contains(&format!("Server pid {} is written", pid)));
+    assert!(log.contains("Handling command Set<key=key1"));
+    assert!(log.contains("Server is stopped"));
+    let store = storage::KvLogStorage::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// Connections beyond the queue capacity of a bounded thread pool should get server busy errors.
+#[serial_test::serial]
+#[test]
+fn thread_pool_queue_full() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let storage_path = temp_dir.path().to_path_buf();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let server_thread = std::thread::spawn(move || {
+        let engine = storage::KvLogStorage::open(&storage_path).map_err(|e| e.to_string())?;
+        let thread_pool = Box::new(threads::shared::SharedThreadPool::bounded(1, 1));
+        let mut server = KvsServer::new(engine, thread_pool);
+        sender.send(server.shutdown_handle()).unwrap();
+        server.listen(HOST.to_owned(), PORT).map_err(|e| e.to_string())
+    });
+    let shutdown_handle = receiver.recv().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    // The only worker is kept busy by a keep-alive connection, the next connection waits in the queue.
+    let mut busy_client = KvsClient::new();
+    busy_client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    assert_eq!(busy_client.execute_one(set, true)?.commands, vec![models::ResponseCommand::Set {}]);
+    let _queued_stream = std::net::TcpStream::connect(format!("{}:{}", HOST, PORT))?;
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let response = client.execute(vec![get.clone(), get], false)?;
+    let statuses: Vec<models::StatusCode> = response.commands.iter().map(|command| command.status()).collect();
+    assert_eq!(statuses, vec![models::StatusCode::ServerBusy; 2]);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// The stats response should report the thread pool load.
+#[serial_test::serial]
+#[test]
+fn thread_pool_stats() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    assert_eq!(client.execute_one(set, false)?.commands, vec![models::ResponseCommand::Set {}]);
+    std::thread::sleep(Duration::from_millis(100));
+
+    // The first connection is completed, the stats connection is handled by a busy worker.
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    match client.execute_one(models::Command::Stats {}, false)?.commands.as_slice() {
+        [models::ResponseCommand::Stats { pool, .. }] => {
+            assert_eq!(pool.queue_depth, 0);
+            assert_eq!(pool.busy_workers, 1);
+            assert_eq!(pool.completed_jobs, 1);
+            assert_eq!(pool.panicked_jobs, 0);
+        },
+        response => panic!("unexpected response {:?}", response),
+    }
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// The info response should report the server version, the storage state and the compaction status.
+#[serial_test::serial]
+#[test]
+fn server_info() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (shutdown_handle, server_thread) = start_server(&temp_dir);
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let set1 = models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() };
+    let set2 = models::Command::Set { key: "key2".to_owned(), value: b"value2".to_vec() };
+    client.execute(vec![set1, set2, models::Command::PauseCompaction {}], true)?;
+    match client.execute_one(models::Command::Info {}, true)?.commands.as_slice() {
+        [models::ResponseCommand::Info { info }] => {
+            assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+            assert!(info.compaction_paused);
+            assert_eq!(info.stats.keys_count, 2);
+            assert!(info.stats.disk_size > 0);
+            assert_eq!(info.pool.busy_workers, 1);
+        },
+        response => panic!("unexpected response {:?}", response),
+    }
+
+    client.execute_one(models::Command::ResumeCompaction {}, true)?;
+    match client.execute_one(models::Command::Info {}, false)?.commands.as_slice() {
+        [models::ResponseCommand::Info { info }] => assert!(!info.compaction_paused),
+        response => panic!("unexpected response {:?}", response),
+    }
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// The injected faults should fail the given write, corrupt the given read and delay the commands.
+#[serial_test::serial]
+#[test]
+fn faulty_storage() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let storage_path = temp_dir.path().to_path_buf();
+    let engine = storage::FaultyStorage::new(storage::KvLogStorage::open(&storage_path)?)
+        .fail_write(2)
+        .corrupt_read(2)
+        .latency(Duration::from_millis(20));
+    let faults = engine.clone();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let server_thread = std::thread::spawn(move || {
+        let thread_pool = Box::new(threads::shared::SharedThreadPool::new(2));
+        let mut server = KvsServer::new(engine, thread_pool);
+        sender.send(server.shutdown_handle()).unwrap();
+        server.listen(HOST.to_owned(), PORT).map_err(|e| e.to_string())
+    });
+    let shutdown_handle = receiver.recv().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    let get = models::Command::Get { key: "key1".to_owned() };
+    let started = Instant::now();
+    let response = client.execute(vec![
+        models::Command::Set { key: "key1".to_owned(), value: b"value1".to_vec() },
+        get.clone(),
+        models::Command::Set { key: "key2".to_owned(), value: b"value2".to_vec() },
+        get.clone(),
+        get,
+        models::Command::Get { key: "key2".to_owned() },
+    ], false)?;
+    assert!(started.elapsed() >= Duration::from_millis(120));
+    let corrupted: Vec<u8> = b"value1".iter().map(|byte| !byte).collect();
+    assert_eq!(response.commands[0], models::ResponseCommand::Set {});
+    assert_eq!(response.commands[1], models::ResponseCommand::Get { value: Some(b"value1".to_vec()) });
+    assert_eq!(response.commands[2].status(), models::StatusCode::Internal);
+    assert_eq!(response.commands[3..], [
+        models::ResponseCommand::Get { value: Some(corrupted) },
+        models::ResponseCommand::Get { value: Some(b"value1".to_vec()) },
+        models::ResponseCommand::Get { value: None },
+    ]);
+    assert_eq!((faults.writes_count(), faults.reads_count()), (2, 4));
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+    Ok(())
+}
+
+// The server should serve the commands and the namespaces with the sled engine, online restores are rejected.
+#[serial_test::serial]
+#[test]
+fn sled_server() -> models::Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let storage_path = temp_dir.path().to_path_buf();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let server_thread = std::thread::spawn(move || {
+        let engine = storage::SledStorage::open(&storage_path).map_err(|e| e.to_string())?;
+        let thread_pool = Box::new(threads::shared::SharedThreadPool::new(2));
+        let mut server = KvsServer::new(engine, thread_pool);
+        sender.send(server.shutdown_handle()).unwrap();
+        server.listen(HOST.to_owned(), PORT).map_err(|e| e.to_string())
+    });
+    let shutdown_handle = receiver.recv().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let mut client = KvsClient::new();
+    client.connect(HOST.to_owned(), PORT, Duration::from_secs(5))?;
+    for idx in 0..10 {
+        client.queue(models::Command::Set { key: format!("key{}", idx), value: format!("value{}", idx).into_bytes() });
+    }
+    let remove_idx = client.queue(models::Command::Remove { key: "key3".to_owned() });
+    let get_idx = client.queue(models::Command::Get { key: "key7".to_owned() });
+    let stats_idx = client.queue(models::Command::Stats {});
+    let restore_idx = client.queue(models::Command::PrepareRestore { backup_dir: "backup".to_owned() });
+    let responses = client.flush_queue(true)?;
+    assert_eq!(responses[remove_idx], models::ResponseCommand::Remove {});
+    assert_eq!(responses[get_idx], models::ResponseCommand::Get { value: Some(b"value7".to_vec()) });
+    match &responses[stats_idx] {
+        models::ResponseCommand::Stats { stats, .. } => assert_eq!(stats.keys_count, 9),
+        response => panic!("unexpected response {:?}", response),
+    }
+    assert!(matches!(responses[restore_idx], models::ResponseCommand::Error { .. }));
+
+    client.set_namespace(Some("dataset".to_owned()));
+    let get = models::Command::Get { key: "key7".to_owned() };
+    let response = client.execute_one(get, false)?;
+    assert_eq!(response.commands, vec![models::ResponseCommand::Get { value: None }]);
+
+    shutdown_handle.shutdown();
+    server_thread.join().unwrap()?;
+
+    let mut engine = storage::SledStorage::open(temp_dir.path())?;
+    assert_eq!(engine.get_bytes("key7".to_owned())?, Some(b"value7".to_vec()));
+    assert!(!engine.remove("key3".to_owned())?);
+    Ok(())
+}
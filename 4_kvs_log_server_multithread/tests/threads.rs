@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use rust_kvs_server::threads::base::{JobHandle, ShutdownMode, ThreadPool};
+use rust_kvs_server::threads::naive::NaiveThreadPool;
+
+// Spawns `count` jobs blocked until the returned sender is dropped. Returns the handles of the jobs,
+// the number of the running jobs and the max number of the jobs seen running at once.
+fn spawn_blocked_jobs(
+    pool: &NaiveThreadPool,
+    count: usize,
+    thread_ids: &Arc<Mutex<HashSet<std::thread::ThreadId>>>,
+) -> (crossbeam::channel::Sender<()>, Vec<JobHandle>, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let running = Arc::new(AtomicUsize::new(0));
+    let max_running = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = crossbeam::channel::unbounded::<()>();
+    let handles = (0..count).map(|_| {
+        let (running, max_running, thread_ids) = (running.clone(), max_running.clone(), thread_ids.clone());
+        let receiver = receiver.clone();
+        pool.spawn(Box::new(move || {
+            let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now_running, Ordering::SeqCst);
+            thread_ids.lock().unwrap().insert(std::thread::current().id());
+            let _ = receiver.recv();
+            running.fetch_sub(1, Ordering::SeqCst);
+        })).unwrap()
+    }).collect();
+    (sender, handles, running, max_running)
+}
+
+// The capped pool should run at most `max_threads` jobs at once on the recycled threads
+// and complete all the queued jobs.
+#[test]
+fn naive_pool_max_threads() {
+    let pool = NaiveThreadPool::with_max_threads(2);
+    let thread_ids = Arc::new(Mutex::new(HashSet::new()));
+    let (sender, handles, running, max_running) = spawn_blocked_jobs(&pool, 10, &thread_ids);
+
+    // Wait for the first two jobs to start.
+    while running.load(Ordering::SeqCst) < 2 {
+        std::thread::yield_now();
+    }
+    assert_eq!(pool.metrics().queue_depth, 8);
+    assert_eq!(pool.metrics().busy_workers, 2);
+
+    drop(sender);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    assert_eq!(thread_ids.lock().unwrap().len(), 2);
+}
+
+// The pool without a cap should run every job on a thread of its own at once, queueing none.
+#[test]
+fn naive_pool_unlimited_threads() {
+    let pool = NaiveThreadPool::new();
+    let thread_ids = Arc::new(Mutex::new(HashSet::new()));
+    let (sender, handles, running, _) = spawn_blocked_jobs(&pool, 10, &thread_ids);
+
+    while running.load(Ordering::SeqCst) < 10 {
+        std::thread::yield_now();
+    }
+    assert_eq!(pool.metrics().queue_depth, 0);
+
+    drop(sender);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(thread_ids.lock().unwrap().len(), 10);
+
+    // The threads of the finished jobs are joined on the next spawns.
+    for _ in 0..10 {
+        pool.spawn(Box::new(|| {})).unwrap().join().unwrap();
+    }
+}
+
+// The immediate shutdown should drop the queued jobs and wait for the running ones.
+#[test]
+fn naive_pool_immediate_shutdown() {
+    let mut pool = NaiveThreadPool::with_max_threads(1);
+
+    let running = pool.spawn(Box::new(|| std::thread::sleep(std::time::Duration::from_millis(50)))).unwrap();
+    let queued = pool.spawn(Box::new(|| {})).unwrap();
+    pool.shutdown(ShutdownMode::Immediate).unwrap();
+
+    assert!(running.join().is_ok());
+    assert!(queued.join().is_err());
+    assert_eq!(pool.metrics().queue_depth, 0);
+    assert!(pool.spawn(Box::new(|| {})).is_err());
+}
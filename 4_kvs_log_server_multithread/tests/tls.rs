@@ -0,0 +1,128 @@
+use assert_cmd::prelude::*;
+use predicates::str::{contains};
+use std::process::Command;
+use std::time::Duration;
+use tempfile::TempDir;
+
+
+const HOST: &str = "127.0.0.1";
+const PORT: u32 = 4010;
+
+struct ServerGuard {
+    sender: std::sync::mpsc::SyncSender<()>,
+    handler: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        if let Some(handler) = self.handler.take() {
+            self.sender.send(()).unwrap();
+            handler.join().unwrap()
+        }
+    }
+}
+
+
+/// Generates a self-signed certificate for the test host and writes it with its key to `dir`.
+/// Returns certificate and key paths.
+fn write_certificate(dir: &tempfile::TempDir, name: &str) -> (String, String) {
+    let certified_key = rcgen::generate_simple_self_signed(vec![HOST.to_owned(), "localhost".to_owned()]).unwrap();
+    let cert_path = dir.path().join(format!("{}.pem", name));
+    let key_path = dir.path().join(format!("{}.key", name));
+    std::fs::write(&cert_path, certified_key.cert.pem()).unwrap();
+    std::fs::write(&key_path, certified_key.signing_key.serialize_pem()).unwrap();
+    (cert_path.to_string_lossy().into_owned(), key_path.to_string_lossy().into_owned())
+}
+
+
+fn run_tls_server(dir: &tempfile::TempDir, cert_path: &str, key_path: &str) -> ServerGuard {
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<()>(0);
+    let mut server = Command::cargo_bin("kvs_server").unwrap();
+    let mut child = server
+        .args(&[
+            "--host", HOST, "--port", &PORT.to_string(), "-l", "debug",
+            "--tls-cert", cert_path, "--tls-key", key_path,
+        ])
+        .current_dir(&dir)
+        .spawn()
+        .unwrap();
+
+    let handle = std::thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        print!("kill test server");
+    });
+    std::thread::sleep(Duration::from_secs(1));
+    ServerGuard{ sender: sender, handler: Some(handle) }
+}
+
+
+fn run_client_cmd(dir: &tempfile::TempDir, args: &[&str]) -> assert_cmd::assert::Assert {
+    let port_str = PORT.to_string();
+    let mut cmd_args = vec!["--host", HOST, "--port", &port_str, "--read-timeout", "2"];
+    cmd_args.extend_from_slice(&args);
+
+    Command::cargo_bin("kvs_client")
+        .unwrap()
+        .args(cmd_args.as_slice())
+        .current_dir(&dir)
+        .assert()
+}
+
+
+// Should set and get values over TLS verifying the server certificate.
+#[serial_test::serial]
+#[test]
+fn tls_set_get_value() {
+    let temp_dir = TempDir::new().unwrap();
+    let (cert_path, key_path) = write_certificate(&temp_dir, "server");
+    let _server_guard = run_tls_server(&temp_dir, &cert_path, &key_path);
+
+    run_client_cmd(&temp_dir, &["--tls-ca-cert", &cert_path, "set", "key1", "value1"])
+        .success()
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, &["--tls-ca-cert", &cert_path, "get", "key1"])
+        .success()
+        .stdout(contains("value1"));
+}
+
+
+// Should connect without the certificate verification if requested.
+#[serial_test::serial]
+#[test]
+fn tls_insecure() {
+    let temp_dir = TempDir::new().unwrap();
+    let (cert_path, key_path) = write_certificate(&temp_dir, "server");
+    let _server_guard = run_tls_server(&temp_dir, &cert_path, &key_path);
+
+    run_client_cmd(&temp_dir, &["--tls-insecure", "set", "key1", "value1"])
+        .success()
+        .stdout(contains("SET OK"));
+}
+
+
+// Should fail to connect if the server certificate is not signed by the trusted CA.
+#[serial_test::serial]
+#[test]
+fn tls_untrusted_certificate() {
+    let temp_dir = TempDir::new().unwrap();
+    let (cert_path, key_path) = write_certificate(&temp_dir, "server");
+    let (other_cert_path, _) = write_certificate(&temp_dir, "other");
+    let _server_guard = run_tls_server(&temp_dir, &cert_path, &key_path);
+
+    run_client_cmd(&temp_dir, &["--tls-ca-cert", &other_cert_path, "get", "key1"])
+        .failure();
+}
+
+
+// Should fail to talk to a TLS server over a plaintext connection.
+#[serial_test::serial]
+#[test]
+fn tls_plaintext_client() {
+    let temp_dir = TempDir::new().unwrap();
+    let (cert_path, key_path) = write_certificate(&temp_dir, "server");
+    let _server_guard = run_tls_server(&temp_dir, &cert_path, &key_path);
+
+    run_client_cmd(&temp_dir, &["get", "key1"])
+        .failure();
+}
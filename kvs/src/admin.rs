@@ -0,0 +1,23 @@
+use std::path::Path;
+
+use kvs_threaded::storage;
+
+use crate::Result;
+
+/// Backs up the storage at `path` to `backup_dir`.
+/// The storage must not be opened by a running server.
+pub fn backup(path: &Path, backup_dir: &Path) -> Result<storage::BackupManifest> {
+    let engine = storage::KvLogStorage::open(path)?;
+    engine.backup(backup_dir)
+}
+
+/// Restores the latest backup from `backup_dir` into an empty directory `path`.
+pub fn restore(backup_dir: &Path, path: &Path) -> Result<storage::BackupManifest> {
+    storage::restore_backup(backup_dir, path)
+}
+
+/// Rewrites the log files of the storage at `path` written in the older formats into the current format.
+/// Returns the number of rewritten log files. The storage must not be opened by a running server.
+pub fn migrate(path: &Path) -> Result<usize> {
+    storage::KvLogStorage::migrate(path)
+}
@@ -0,0 +1,544 @@
+use std::path::PathBuf;
+use std::time;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use kvs::{admin, client, serve, Result};
+use kvs_threaded::{models, tls};
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// Command to run
+    #[command(subcommand)]
+    command: Commands,
+    /// Set log level
+    #[arg(short, long, default_value = "info", global = true)]
+    log_level: LogLevel,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Run a KVS server
+    Serve(ServeArgs),
+    /// Execute a single command on a KVS server
+    Client(ClientArgs),
+    /// Storage administration commands
+    Admin {
+        #[command(subcommand)]
+        command: AdminCommands,
+    },
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Server implementation
+    #[arg(short, long, default_value = "threaded")]
+    mode: serve::Mode,
+    /// Server hostname
+    #[arg(short = 'H', long, default_value = "127.0.0.1")]
+    host: String,
+    /// Server port
+    #[arg(short = 'P', long, default_value = "4000")]
+    port: u32,
+    /// Storage path
+    #[arg(short, long, default_value = "./")]
+    path: PathBuf,
+    /// Storage engine type
+    #[arg(short, long, default_value = "kvs")]
+    engine: serve::EngineType,
+    /// Server handlers thread pool type (threaded mode)
+    #[arg(short = 't', long, default_value = "shared")]
+    thread_pool: serve::ThreadPoolType,
+    /// Server handlers thread pool size, the max number of threads for the naive pool (threaded mode).
+    /// Set to 0 for auto-selection.
+    #[arg(short = 's', long, default_value_t = 0)]
+    thread_pool_size: usize,
+    /// PEM-encoded TLS certificate chain (threaded mode). Enables TLS for all connections.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM-encoded TLS private key (threaded mode)
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Compress stored values larger than the given size in bytes (threaded mode)
+    #[arg(long)]
+    compression_threshold: Option<usize>,
+}
+
+#[derive(Args)]
+struct ClientArgs {
+    /// Command to run
+    #[command(subcommand)]
+    command: ClientCommands,
+    /// Server hostname
+    #[arg(short = 'H', long, default_value = "127.0.0.1")]
+    host: String,
+    /// Server port
+    #[arg(short = 'P', long, default_value = "4000")]
+    port: u32,
+    /// Read timeout in seconds
+    #[arg(short, long, default_value = "30")]
+    read_timeout: f32,
+    /// Request deadline in seconds (threaded mode). The server skips the request once the deadline is exceeded.
+    #[arg(long)]
+    deadline: Option<f32>,
+    /// Connect over TLS and verify the server certificate with a PEM-encoded CA certificate
+    #[arg(long, conflicts_with = "tls_insecure")]
+    tls_ca_cert: Option<PathBuf>,
+    /// Connect over TLS without server certificate verification
+    #[arg(long)]
+    tls_insecure: bool,
+    /// Server name to verify the TLS certificate against. The hostname is used by default.
+    #[arg(long)]
+    tls_server_name: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum ClientCommands {
+    /// Set value `value` for the key `key`
+    Set {
+        /// Key to set
+        key: String,
+        /// Value to set for the key
+        value: String,
+    },
+    /// Get value for the key `key`
+    Get {
+        /// Key to get the value for
+        key: String,
+    },
+    /// Append `suffix` to the value of the key `key` and print the new value length (threaded mode)
+    Append {
+        /// Key to append to, created if missing
+        key: String,
+        /// Suffix to append to the value
+        suffix: String,
+    },
+    /// Set value `value` for the key `key` and print the previous value (threaded mode)
+    GetSet {
+        /// Key to set
+        key: String,
+        /// Value to set for the key
+        value: String,
+    },
+    /// Set value `value` for the key `key` only if the key doesn't exist (threaded mode)
+    SetNx {
+        /// Key to set
+        key: String,
+        /// Value to set for the key
+        value: String,
+    },
+    /// Push `values` to the front of the list `key` and print the list length (threaded mode)
+    Lpush {
+        /// Key of the list, created if missing
+        key: String,
+        /// Values to push, the last one ends up first
+        #[arg(required = true)]
+        values: Vec<String>,
+    },
+    /// Push `values` to the back of the list `key` and print the list length (threaded mode)
+    Rpush {
+        /// Key of the list, created if missing
+        key: String,
+        /// Values to push
+        #[arg(required = true)]
+        values: Vec<String>,
+    },
+    /// Pop a value from the front of the list `key` (threaded mode)
+    Lpop {
+        /// Key of the list
+        key: String,
+    },
+    /// Pop a value from the back of the list `key` (threaded mode)
+    Rpop {
+        /// Key of the list
+        key: String,
+    },
+    /// Print the values of the list `key` between `start` and `stop` inclusive (threaded mode)
+    #[command(allow_negative_numbers = true)]
+    Lrange {
+        /// Key of the list
+        key: String,
+        /// Index of the first value, negative indexes count from the end
+        start: i64,
+        /// Index of the last value, negative indexes count from the end
+        stop: i64,
+    },
+    /// Set the field `field` of the hash `key` to `value` (threaded mode)
+    Hset {
+        /// Key of the hash, created if missing
+        key: String,
+        /// Field to set
+        field: String,
+        /// Value to set for the field
+        value: String,
+    },
+    /// Get the value of the field `field` of the hash `key` (threaded mode)
+    Hget {
+        /// Key of the hash
+        key: String,
+        /// Field to get the value for
+        field: String,
+    },
+    /// Remove the `fields` of the hash `key` and print the number of the removed fields (threaded mode)
+    Hdel {
+        /// Key of the hash
+        key: String,
+        /// Fields to remove
+        #[arg(required = true)]
+        fields: Vec<String>,
+    },
+    /// Print all the fields of the hash `key` (threaded mode)
+    Hgetall {
+        /// Key of the hash
+        key: String,
+    },
+    /// Add the `members` to the set `key` and print the number of the added members (threaded mode)
+    Sadd {
+        /// Key of the set, created if missing
+        key: String,
+        /// Members to add
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// Remove the `members` of the set `key` and print the number of the removed members (threaded mode)
+    Srem {
+        /// Key of the set
+        key: String,
+        /// Members to remove
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// Check whether `member` belongs to the set `key` (threaded mode)
+    Sismember {
+        /// Key of the set
+        key: String,
+        /// Member to check
+        member: String,
+    },
+    /// Print all the members of the set `key` (threaded mode)
+    Smembers {
+        /// Key of the set
+        key: String,
+    },
+    /// Add `member` with the score `score` to the sorted set `key` or update its score (threaded mode)
+    #[command(allow_negative_numbers = true)]
+    Zadd {
+        /// Key of the sorted set, created if missing
+        key: String,
+        /// Score of the member
+        score: f64,
+        /// Member to add
+        member: String,
+    },
+    /// Remove the `members` of the sorted set `key` and print the number of the removed members (threaded mode)
+    Zrem {
+        /// Key of the sorted set
+        key: String,
+        /// Members to remove
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// Print the members of the sorted set `key` with the scores between `min` and `max` inclusive (threaded mode)
+    #[command(allow_negative_numbers = true)]
+    Zrangebyscore {
+        /// Key of the sorted set
+        key: String,
+        /// Minimal score, `-inf` for no limit
+        #[arg(allow_hyphen_values = true)]
+        min: f64,
+        /// Maximal score, `inf` for no limit
+        #[arg(allow_hyphen_values = true)]
+        max: f64,
+    },
+    /// Remove the key `key`
+    Remove {
+        /// Key to remove
+        key: String,
+    },
+    /// Reset storage by removing all of the stored values
+    Reset {},
+    /// Remove all the keys starting with `prefix` and print the number of the removed keys (threaded mode)
+    ResetPrefix {
+        /// Prefix of the keys to remove
+        prefix: String,
+    },
+    /// Stage the latest backup from the server-side directory `backup_dir` and print a restore token (threaded mode)
+    PrepareRestore {
+        /// Backup directory on the server
+        backup_dir: String,
+    },
+    /// Switch the storage to the backup staged with `token` (threaded mode)
+    CommitRestore {
+        /// Restore token returned by `prepare-restore`
+        token: String,
+    },
+    /// Remove the backup staged with `token` (threaded mode)
+    AbortRestore {
+        /// Restore token returned by `prepare-restore`
+        token: String,
+    },
+    /// Compact all the sealed log files of the storage (threaded mode)
+    Compact {},
+    /// Print the storage statistics (threaded mode)
+    Stats {},
+    /// Print the server version, uptime, storage statistics, thread pool load and compaction status (threaded mode)
+    Info {},
+    /// Stop the background compaction of the storage until `resume-compaction` (threaded mode)
+    PauseCompaction {},
+    /// Resume the background compaction of the storage (threaded mode)
+    ResumeCompaction {},
+}
+
+#[derive(Subcommand)]
+enum AdminCommands {
+    /// Back up the storage segments changed since the previous backup
+    Backup {
+        /// Storage path
+        #[arg(short, long, default_value = "./")]
+        path: PathBuf,
+        /// Backup directory
+        #[arg(short, long)]
+        backup_dir: PathBuf,
+    },
+    /// Restore the latest backup into an empty storage directory
+    Restore {
+        /// Backup directory
+        #[arg(short, long)]
+        backup_dir: PathBuf,
+        /// Storage path
+        #[arg(short, long)]
+        path: PathBuf,
+    },
+    /// Rewrite the log files written in the older formats into the current format
+    Migrate {
+        /// Storage path
+        #[arg(short, long, default_value = "./")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Clone, ValueEnum)]
+enum LogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+fn run_client(args: ClientArgs) {
+    let command = match args.command {
+        ClientCommands::Set { key, value } => models::Command::Set { key, value: value.into_bytes() },
+        ClientCommands::Get { key } => models::Command::Get { key },
+        ClientCommands::Append { key, suffix } => models::Command::Append { key, suffix: suffix.into_bytes() },
+        ClientCommands::GetSet { key, value } => models::Command::GetSet { key, value: value.into_bytes() },
+        ClientCommands::SetNx { key, value } => models::Command::SetNx { key, value: value.into_bytes() },
+        ClientCommands::Lpush { key, values } => {
+            models::Command::ListPush { key, values: values.into_iter().map(String::into_bytes).collect(), front: true }
+        },
+        ClientCommands::Rpush { key, values } => {
+            models::Command::ListPush { key, values: values.into_iter().map(String::into_bytes).collect(), front: false }
+        },
+        ClientCommands::Lpop { key } => models::Command::ListPop { key, front: true },
+        ClientCommands::Rpop { key } => models::Command::ListPop { key, front: false },
+        ClientCommands::Lrange { key, start, stop } => models::Command::ListRange { key, start, stop },
+        ClientCommands::Hset { key, field, value } => {
+            models::Command::HashSet { key, fields: vec![(field, value.into_bytes())] }
+        },
+        ClientCommands::Hget { key, field } => models::Command::HashGet { key, field },
+        ClientCommands::Hdel { key, fields } => models::Command::HashDelete { key, fields },
+        ClientCommands::Hgetall { key } => models::Command::HashGetAll { key },
+        ClientCommands::Sadd { key, members } => {
+            models::Command::SetAdd { key, members: members.into_iter().map(String::into_bytes).collect() }
+        },
+        ClientCommands::Srem { key, members } => {
+            models::Command::SetRemove { key, members: members.into_iter().map(String::into_bytes).collect() }
+        },
+        ClientCommands::Sismember { key, member } => models::Command::SetIsMember { key, member: member.into_bytes() },
+        ClientCommands::Smembers { key } => models::Command::SetMembers { key },
+        ClientCommands::Zadd { key, score, member } => {
+            models::Command::SortedSetAdd { key, members: vec![(member.into_bytes(), score)] }
+        },
+        ClientCommands::Zrem { key, members } => {
+            models::Command::SortedSetRemove { key, members: members.into_iter().map(String::into_bytes).collect() }
+        },
+        ClientCommands::Zrangebyscore { key, min, max } => models::Command::SortedSetRangeByScore { key, min, max },
+        ClientCommands::Remove { key } => models::Command::Remove { key },
+        ClientCommands::Reset {} => models::Command::Reset {},
+        ClientCommands::ResetPrefix { prefix } => models::Command::ResetPrefix { prefix },
+        ClientCommands::PrepareRestore { backup_dir } => models::Command::PrepareRestore { backup_dir },
+        ClientCommands::CommitRestore { token } => models::Command::CommitRestore { token },
+        ClientCommands::AbortRestore { token } => models::Command::AbortRestore { token },
+        ClientCommands::Compact {} => models::Command::Compact {},
+        ClientCommands::Stats {} => models::Command::Stats {},
+        ClientCommands::Info {} => models::Command::Info {},
+        ClientCommands::PauseCompaction {} => models::Command::PauseCompaction {},
+        ClientCommands::ResumeCompaction {} => models::Command::ResumeCompaction {},
+    };
+
+    let verification = match (args.tls_ca_cert, args.tls_insecure) {
+        (Some(ca_cert), _) => Some(tls::TlsVerification::CaCert(ca_cert)),
+        (None, true) => Some(tls::TlsVerification::Insecure),
+        (None, false) => None,
+    };
+    let options = client::ClientOptions {
+        host: args.host,
+        port: args.port,
+        read_timeout: time::Duration::from_secs_f32(args.read_timeout),
+        tls: verification.map(|verification| tls::TlsClientOptions {
+            verification,
+            server_name: args.tls_server_name,
+        }),
+    };
+
+    let mut kvs_client = match client::connect(options) {
+        Ok(kvs_client) => kvs_client,
+        Err(err) => {
+            eprintln!("Failed to connect: {}", err);
+            std::process::exit(2);
+        },
+    };
+
+    let deadline = args.deadline.map(|seconds| time::SystemTime::now() + time::Duration::from_secs_f32(seconds));
+    match client::execute_one(&mut kvs_client, command, deadline) {
+        Ok(models::ResponseCommand::Set {}) => { log::info!("SET OK"); },
+        Ok(models::ResponseCommand::Append { length }) => { log::info!("APPEND OK {}", length); },
+        Ok(models::ResponseCommand::GetSet { value }) => {
+            match value {
+                Some(val) => log::info!("GET SET OK {}", String::from_utf8_lossy(&val)),
+                None => log::info!("GET SET NONE"),
+            }
+        },
+        Ok(models::ResponseCommand::SetNx { is_set: true }) => { log::info!("SET NX OK"); },
+        Ok(models::ResponseCommand::SetNx { is_set: false }) => { log::info!("SET NX EXISTS"); },
+        Ok(models::ResponseCommand::ListPush { length }) => { log::info!("LIST PUSH OK {}", length); },
+        Ok(models::ResponseCommand::ListPop { value }) => {
+            match value {
+                Some(val) => log::info!("LIST POP OK {}", String::from_utf8_lossy(&val)),
+                None => log::info!("LIST POP NONE"),
+            }
+        },
+        Ok(models::ResponseCommand::ListRange { values }) => {
+            let values: Vec<String> = values.iter().map(|value| String::from_utf8_lossy(value).into_owned()).collect();
+            log::info!("LIST RANGE OK {}", values.join(" "));
+        },
+        Ok(models::ResponseCommand::HashSet { added }) => { log::info!("HASH SET OK {}", added); },
+        Ok(models::ResponseCommand::HashGet { value }) => {
+            match value {
+                Some(val) => log::info!("HASH GET OK {}", String::from_utf8_lossy(&val)),
+                None => log::info!("HASH GET NONE"),
+            }
+        },
+        Ok(models::ResponseCommand::HashDelete { removed }) => { log::info!("HASH DELETE OK {}", removed); },
+        Ok(models::ResponseCommand::HashGetAll { fields }) => {
+            let fields: Vec<String> = fields.iter()
+                .map(|(field, value)| format!("{}={}", field, String::from_utf8_lossy(value)))
+                .collect();
+            log::info!("HASH GET ALL OK {}", fields.join(" "));
+        },
+        Ok(models::ResponseCommand::SetAdd { added }) => { log::info!("SET ADD OK {}", added); },
+        Ok(models::ResponseCommand::SetRemove { removed }) => { log::info!("SET REMOVE OK {}", removed); },
+        Ok(models::ResponseCommand::SetIsMember { is_member: true }) => { log::info!("SET IS MEMBER TRUE"); },
+        Ok(models::ResponseCommand::SetIsMember { is_member: false }) => { log::info!("SET IS MEMBER FALSE"); },
+        Ok(models::ResponseCommand::SetMembers { members }) => {
+            let members: Vec<String> = members.iter()
+                .map(|member| String::from_utf8_lossy(member).into_owned())
+                .collect();
+            log::info!("SET MEMBERS OK {}", members.join(" "));
+        },
+        Ok(models::ResponseCommand::SortedSetAdd { added }) => { log::info!("SORTED SET ADD OK {}", added); },
+        Ok(models::ResponseCommand::SortedSetRemove { removed }) => { log::info!("SORTED SET REMOVE OK {}", removed); },
+        Ok(models::ResponseCommand::SortedSetRangeByScore { members }) => {
+            let members: Vec<String> = members.iter()
+                .map(|(member, score)| format!("{}={}", String::from_utf8_lossy(member), score))
+                .collect();
+            log::info!("SORTED SET RANGE OK {}", members.join(" "));
+        },
+        Ok(models::ResponseCommand::Remove {}) => { log::info!("REMOVE OK"); },
+        Ok(models::ResponseCommand::Reset {}) => { log::info!("RESET OK"); },
+        Ok(models::ResponseCommand::ResetPrefix { removed }) => { log::info!("RESET PREFIX OK {}", removed); },
+        Ok(models::ResponseCommand::PrepareRestore { token }) => { log::info!("PREPARE RESTORE OK {}", token); },
+        Ok(models::ResponseCommand::CommitRestore {}) => { log::info!("COMMIT RESTORE OK"); },
+        Ok(models::ResponseCommand::AbortRestore {}) => { log::info!("ABORT RESTORE OK"); },
+        Ok(models::ResponseCommand::Compact {}) => { log::info!("COMPACT OK"); },
+        Ok(models::ResponseCommand::Stats { stats, pool }) => { log::info!("STATS OK {} {}", stats, pool); },
+        Ok(models::ResponseCommand::Info { info }) => { log::info!("INFO OK {}", info); },
+        Ok(models::ResponseCommand::PauseCompaction {}) => { log::info!("PAUSE COMPACTION OK"); },
+        Ok(models::ResponseCommand::ResumeCompaction {}) => { log::info!("RESUME COMPACTION OK"); },
+        Ok(models::ResponseCommand::Get { value }) => {
+            match value {
+                Some(val) => log::info!("GET OK {}", String::from_utf8_lossy(&val)),
+                None => log::info!("GET NONE"),
+            }
+        },
+        Ok(models::ResponseCommand::GetStream { chunk }) => {
+            match chunk {
+                Some(chunk) => log::info!("GET STREAM OK {}", String::from_utf8_lossy(&chunk)),
+                None => log::info!("GET STREAM NONE"),
+            }
+        },
+        Ok(models::ResponseCommand::Error { code, message }) => {
+            eprintln!("Command failed with code {}: {}", code, message);
+            std::process::exit(5);
+        },
+        Err(err) => {
+            eprintln!("Failed to handle request: {}", err);
+            std::process::exit(3);
+        },
+    }
+}
+
+fn run_admin(command: AdminCommands) -> Result<()> {
+    match command {
+        AdminCommands::Backup { path, backup_dir } => {
+            let manifest = admin::backup(&path, &backup_dir)?;
+            log::info!(
+                "BACKUP OK generation {}: {}/{} segments copied",
+                manifest.generation, manifest.copied_segments().len(), manifest.segments.len(),
+            );
+        },
+        AdminCommands::Restore { backup_dir, path } => {
+            let manifest = admin::restore(&backup_dir, &path)?;
+            log::info!("RESTORE OK generation {}: {} segments", manifest.generation, manifest.segments.len());
+        },
+        AdminCommands::Migrate { path } => {
+            let count = admin::migrate(&path)?;
+            log::info!("MIGRATE OK {} log files", count);
+        },
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let log_level = match cli.log_level {
+        LogLevel::Debug => log::LevelFilter::Debug,
+        LogLevel::Info => log::LevelFilter::Info,
+        LogLevel::Warning => log::LevelFilter::Warn,
+        LogLevel::Error => log::LevelFilter::Error,
+    };
+    simple_logger::SimpleLogger::new().with_level(log_level).init().unwrap();
+
+    match cli.command {
+        Commands::Serve(args) => {
+            serve::serve(serve::ServeOptions {
+                mode: args.mode,
+                host: args.host,
+                port: args.port,
+                path: args.path,
+                engine: args.engine,
+                thread_pool: args.thread_pool,
+                thread_pool_size: args.thread_pool_size,
+                tls_cert: args.tls_cert,
+                tls_key: args.tls_key,
+                compression_threshold: args.compression_threshold,
+            })
+        },
+        Commands::Client(args) => {
+            run_client(args);
+            Ok(())
+        },
+        Commands::Admin { command } => run_admin(command),
+    }
+}
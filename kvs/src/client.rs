@@ -0,0 +1,41 @@
+use std::time;
+
+use kvs_threaded::{models, tls, KvsClient};
+
+use crate::Result;
+
+/// Connection options of the client.
+/// The client protocol is shared by the sync and the threaded servers.
+pub struct ClientOptions {
+    pub host: String,
+    pub port: u32,
+    pub read_timeout: time::Duration,
+    /// Connect over TLS if set. Only the threaded server supports TLS.
+    pub tls: Option<tls::TlsClientOptions>,
+}
+
+/// Connects to the server over a plain TCP or a TLS connection.
+pub fn connect(options: ClientOptions) -> Result<KvsClient> {
+    let mut client = KvsClient::new();
+    match options.tls {
+        Some(tls_options) => client.connect_tls(options.host, options.port, options.read_timeout, tls_options)?,
+        None => client.connect(options.host, options.port, options.read_timeout)?,
+    }
+    Ok(client)
+}
+
+/// Executes a single command and closes the connection.
+/// A command failed on the server side is returned as `ResponseCommand::Error` with the error code,
+/// the errors are left for the request failures only.
+/// Only the threaded server supports the request `deadline`.
+pub fn execute_one(
+    client: &mut KvsClient,
+    command: models::Command,
+    deadline: Option<time::SystemTime>,
+) -> Result<models::ResponseCommand> {
+    let mut response = client.execute_with_deadline(vec![command], false, deadline)?;
+    match response.commands.pop() {
+        Some(response_command) => Ok(response_command),
+        None => Err(Box::from("Unable to get the server response")),
+    }
+}
@@ -0,0 +1,5 @@
+pub use kvs_threaded::Result;
+
+pub mod serve;
+pub mod client;
+pub mod admin;
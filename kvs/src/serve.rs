@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+
+use crate::Result;
+
+/// Server implementation stage.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Mode {
+    /// Single-threaded server handling one connection at a time
+    Sync,
+    /// Multithreaded server handling connections in a thread pool
+    Threaded,
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match &self {
+            Mode::Sync => "sync",
+            Mode::Threaded => "threaded",
+        })
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum EngineType {
+    /// Custom WAL-based key-value storage
+    Kvs,
+    /// Sled storage
+    Sled,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ThreadPoolType {
+    None,
+    Naive,
+    Shared,
+    Rayon,
+}
+
+/// Server options shared by all the modes.
+/// Options not supported by the selected mode are rejected instead of being silently ignored.
+pub struct ServeOptions {
+    pub mode: Mode,
+    pub host: String,
+    pub port: u32,
+    pub path: PathBuf,
+    pub engine: EngineType,
+    pub thread_pool: ThreadPoolType,
+    pub thread_pool_size: usize,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub compression_threshold: Option<usize>,
+}
+
+/// Runs the server implementation selected by `options.mode`. Blocks until the server stops.
+pub fn serve(options: ServeOptions) -> Result<()> {
+    log::info!(
+        "Starting {} server at {}:{} with storage at {}",
+        options.mode, options.host, options.port, options.path.display(),
+    );
+
+    match options.mode {
+        Mode::Sync => serve_sync(options),
+        Mode::Threaded => serve_threaded(options),
+    }
+}
+
+fn serve_sync(options: ServeOptions) -> Result<()> {
+    if options.tls_cert.is_some() {
+        return Err(Box::from("TLS is supported only in the threaded mode"));
+    }
+    if options.compression_threshold.is_some() {
+        return Err(Box::from("Compression is supported only in the threaded mode"));
+    }
+
+    let engine: Box<dyn kvs_sync::storage::KVStorage> = match options.engine {
+        EngineType::Kvs => Box::new(kvs_sync::storage::KvLogStorage::open(&options.path)?),
+        EngineType::Sled => Box::new(kvs_sync::storage::SledStorage::open(&options.path)?),
+    };
+
+    let mut server = kvs_sync::KvsServer::new(engine);
+    server.listen(options.host, options.port)
+}
+
+fn serve_threaded(options: ServeOptions) -> Result<()> {
+    if let (EngineType::Sled, Some(_)) = (options.engine, options.compression_threshold) {
+        return Err(Box::from("Compression is supported only by the kvs engine"));
+    }
+
+    let mut thread_pool_size = options.thread_pool_size;
+    if thread_pool_size == 0 {
+        thread_pool_size = num_cpus::get() * 2 + 1;
+    }
+
+    let thread_pool: Box<dyn kvs_threaded::threads::base::ThreadPool> = match options.thread_pool {
+        ThreadPoolType::None => Box::new(kvs_threaded::threads::none::NoneThreadPool::new()),
+        ThreadPoolType::Naive => Box::new(kvs_threaded::threads::naive::NaiveThreadPool::with_max_threads(thread_pool_size)),
+        ThreadPoolType::Shared => Box::new(kvs_threaded::threads::shared::SharedThreadPool::new(thread_pool_size)),
+        ThreadPoolType::Rayon => Box::new(kvs_threaded::threads::rayon::RayonThreadPool::new(thread_pool_size)?),
+    };
+
+    let mut server = match options.engine {
+        EngineType::Kvs => {
+            let mut engine = kvs_threaded::KvLogStorage::open(&options.path)?;
+            engine.set_compression_threshold(options.compression_threshold);
+            kvs_threaded::KvsServer::new(engine, thread_pool)
+        },
+        EngineType::Sled => {
+            let engine = kvs_threaded::storage::SledStorage::open(&options.path)?;
+            kvs_threaded::KvsServer::new(engine, thread_pool)
+        },
+    };
+    if let (Some(cert_path), Some(key_path)) = (&options.tls_cert, &options.tls_key) {
+        log::info!("TLS is enabled with certificate {}", cert_path.display());
+        server.set_tls_config(kvs_threaded::tls::load_server_config(cert_path, key_path)?);
+    }
+
+    // Stop the server gracefully on SIGINT/SIGTERM.
+    let shutdown_handle = server.shutdown_handle();
+    ctrlc::set_handler(move || shutdown_handle.shutdown())?;
+
+    server.listen(options.host, options.port)
+}
@@ -0,0 +1,227 @@
+use assert_cmd::prelude::*;
+use predicates::str::{contains};
+use std::process::Command;
+use std::time::Duration;
+use tempfile::TempDir;
+
+
+const HOST: &str = "127.0.0.1";
+const PORT: u32 = 4011;
+
+struct ServerGuard {
+    sender: std::sync::mpsc::SyncSender<()>,
+    handler: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        if let Some(handler) = self.handler.take() {
+            self.sender.send(()).unwrap();
+            handler.join().unwrap()
+        }
+    }
+}
+
+
+fn run_server(dir: &tempfile::TempDir, mode: &str) -> ServerGuard {
+    run_server_with_args(dir, &["--mode", mode])
+}
+
+fn run_server_with_args(dir: &tempfile::TempDir, args: &[&str]) -> ServerGuard {
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<()>(0);
+    let mut server = Command::cargo_bin("kvs").unwrap();
+    let mut child = server
+        .args(["serve", "--host", HOST, "--port", &PORT.to_string()])
+        .args(args)
+        .current_dir(dir)
+        .spawn()
+        .unwrap();
+
+    let handle = std::thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        let _ = child.wait();
+        print!("kill test server");
+    });
+    std::thread::sleep(Duration::from_secs(1));
+    ServerGuard{ sender, handler: Some(handle) }
+}
+
+
+fn run_client_cmd(dir: &tempfile::TempDir, args: &[&str]) -> assert_cmd::assert::Assert {
+    let port_str = PORT.to_string();
+    let mut cmd_args = vec!["client", "--host", HOST, "--port", &port_str];
+    cmd_args.extend_from_slice(args);
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(cmd_args.as_slice())
+        .current_dir(dir)
+        .assert()
+        .success()
+}
+
+
+// `kvs -V` should print the version
+#[test]
+fn cli_version() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["-V"])
+        .current_dir(&temp_dir)
+        .assert()
+        .stdout(contains(env!("CARGO_PKG_VERSION")));
+}
+
+// Unknown server modes should be rejected.
+#[test]
+fn cli_wrong_mode() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["serve", "--mode", "unknown"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// The async server is not a part of this build and should be rejected by the argument parser.
+#[test]
+fn cli_async_mode_unavailable() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["serve", "--mode", "async"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("invalid value 'async'"));
+}
+
+// A command failed by the server should exit with code 5 and print the server error code.
+#[test]
+#[serial_test::serial]
+fn cli_command_error_exit_code() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server_guard = run_server(&temp_dir, "threaded");
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["client", "--host", HOST, "--port", &PORT.to_string(), "commit-restore", "0123"])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(5)
+        .stderr(contains("Command failed with code 3"));
+}
+
+// Options of the threaded server should be rejected in the sync mode.
+#[test]
+fn cli_sync_mode_tls() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["serve", "--mode", "sync", "--tls-cert", "server.pem", "--tls-key", "server.key"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// Should serve the same client commands in every available mode.
+#[rstest::rstest]
+#[case("sync")]
+#[case("threaded")]
+#[serial_test::serial]
+fn serve_modes(#[case] mode: &str) {
+    let temp_dir = TempDir::new().unwrap();
+    let server_guard = run_server(&temp_dir, mode);
+
+    run_client_cmd(&temp_dir, &["set", "key1", "value1"])
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, &["get", "key1"])
+        .stdout(contains("value1"));
+    run_client_cmd(&temp_dir, &["remove", "key1"])
+        .stdout(contains("REMOVE OK"));
+    run_client_cmd(&temp_dir, &["get", "key1"])
+        .stdout(contains("GET NONE"));
+
+    drop(server_guard);
+}
+
+// Should serve the client commands with the sled engine in the threaded mode.
+#[serial_test::serial]
+#[test]
+fn serve_threaded_sled() {
+    let temp_dir = TempDir::new().unwrap();
+    let server_guard = run_server_with_args(&temp_dir, &["--mode", "threaded", "--engine", "sled"]);
+
+    run_client_cmd(&temp_dir, &["set", "key1", "value1"])
+        .stdout(contains("SET OK"));
+    run_client_cmd(&temp_dir, &["get", "key1"])
+        .stdout(contains("value1"));
+    run_client_cmd(&temp_dir, &["remove", "key1"])
+        .stdout(contains("REMOVE OK"));
+    run_client_cmd(&temp_dir, &["get", "key1"])
+        .stdout(contains("GET NONE"));
+
+    drop(server_guard);
+}
+
+// Should back up a storage and restore it into a new directory.
+#[serial_test::serial]
+#[test]
+fn admin_backup_restore() {
+    let temp_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+    let restore_dir = TempDir::new().unwrap();
+    let backup_path = backup_dir.path().to_string_lossy().into_owned();
+    let restore_path = restore_dir.path().to_string_lossy().into_owned();
+
+    let server_guard = run_server(&temp_dir, "threaded");
+    run_client_cmd(&temp_dir, &["set", "key1", "value1"])
+        .stdout(contains("SET OK"));
+    drop(server_guard);
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["admin", "backup", "--backup-dir", &backup_path])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("BACKUP OK"));
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["admin", "restore", "--backup-dir", &backup_path, "--path", &restore_path])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("RESTORE OK"));
+
+    let _server_guard = run_server(&restore_dir, "threaded");
+    run_client_cmd(&restore_dir, &["get", "key1"])
+        .stdout(contains("value1"));
+}
+
+// Should migrate a storage written by the single-threaded log engine and serve it in the threaded mode.
+#[serial_test::serial]
+#[test]
+fn admin_migrate() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut store = kvs_log::KvStore::open(temp_dir.path()).unwrap();
+    store.set("key1".to_owned(), "value1".to_owned()).unwrap();
+    store.set("key2".to_owned(), "value2".to_owned()).unwrap();
+    drop(store);
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["admin", "migrate"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("MIGRATE OK 1 log files"));
+
+    let _server_guard = run_server(&temp_dir, "threaded");
+    run_client_cmd(&temp_dir, &["get", "key2"])
+        .stdout(contains("value2"));
+}
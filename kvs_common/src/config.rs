@@ -0,0 +1,49 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+use crate::Result;
+
+/// Converts the options of a TOML configuration file into the command line arguments, e.g. `segment_size = 1000`
+/// into `--segment-size 1000`. A `true` option is passed as a flag without a value, a `false` one is omitted,
+/// and an array of strings is passed as a repeated option, e.g. `store = ["a=/data/a", "b=/data/b"]`.
+/// The arguments are meant to be parsed before the command line ones, so the command line options override them.
+pub fn config_args(path: &Path) -> Result<Vec<OsString>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read config file {}: {}", path.display(), err))?;
+    let document: toml_edit::DocumentMut = content.parse()
+        .map_err(|err| format!("Cannot parse config file {}: {}", path.display(), err))?;
+
+    let mut args = Vec::new();
+    for (key, item) in document.iter() {
+        if key == "config" {
+            return Err(Box::from("Config file cannot include another config file"));
+        }
+        let arg = OsString::from(format!("--{}", key.replace('_', "-")));
+        let value = match item.as_value() {
+            Some(toml_edit::Value::String(value)) => value.value().clone(),
+            Some(toml_edit::Value::Integer(value)) => value.value().to_string(),
+            Some(toml_edit::Value::Float(value)) => value.value().to_string(),
+            Some(toml_edit::Value::Boolean(value)) => {
+                if *value.value() {
+                    args.push(arg);
+                }
+                continue;
+            },
+            Some(toml_edit::Value::Array(values)) => {
+                for value in values.iter() {
+                    let value = value.as_str()
+                        .ok_or_else(|| format!("Config option {} must be an array of strings", key))?;
+                    args.push(arg.clone());
+                    args.push(OsString::from(value));
+                }
+                continue;
+            },
+            _ => return Err(Box::from(format!(
+                "Config option {} must be a string, a number, a boolean or an array of strings", key,
+            ))),
+        };
+        args.push(arg);
+        args.push(OsString::from(value));
+    }
+    Ok(args)
+}
@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use crate::Result;
+
+/// File keeping the name of the engine which created the storage directory.
+pub const ENGINE_FILE_NAME: &str = "ENGINE";
+
+/// Guesses the engine of a storage directory created before the engine file by the files it holds:
+/// sled keeps its `conf` and `db` files, the log storage keeps the `kv_<idx>.log` files.
+fn detect_engine(path: &Path) -> Result<Option<&'static str>> {
+    if path.join("conf").is_file() || path.join("db").is_file() {
+        return Ok(Some("sled"));
+    }
+    for entry in std::fs::read_dir(path)? {
+        let file_path = entry?.path();
+        let is_log_file = match file_path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.strip_prefix("kv_")
+                .and_then(|name| name.strip_suffix(".log"))
+                .is_some_and(|idx| idx.parse::<usize>().is_ok()),
+            None => false,
+        };
+        if is_log_file && file_path.is_file() {
+            return Ok(Some("kvs"));
+        }
+    }
+    Ok(None)
+}
+
+fn engine_mismatch_error(path: &Path, stored_engine: &str, engine: &str) -> Box<dyn std::error::Error> {
+    Box::from(format!(
+        "Storage {} was created by the {} engine, cannot open it with the {} engine",
+        path.display(), stored_engine, engine,
+    ))
+}
+
+/// Checks the storage directory `path` was created by the engine `engine`, so the data of one engine
+/// is never misread by another. A new directory is created and marked with the engine. A directory without
+/// the engine file is marked only if it holds no files of another engine.
+pub fn check_engine(path: &Path, engine: &str) -> Result<()> {
+    let engine_file_path = path.join(ENGINE_FILE_NAME);
+    match std::fs::read_to_string(&engine_file_path) {
+        Ok(content) => {
+            let stored_engine = content.trim();
+            if stored_engine != engine {
+                return Err(engine_mismatch_error(path, stored_engine, engine));
+            }
+            Ok(())
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            std::fs::create_dir_all(path)?;
+            match detect_engine(path)? {
+                Some(detected_engine) if detected_engine != engine => {
+                    return Err(engine_mismatch_error(path, detected_engine, engine));
+                },
+                _ => {},
+            }
+            std::fs::write(&engine_file_path, engine)?;
+            Ok(())
+        },
+        Err(err) => Err(Box::new(err)),
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{BufRead, Bytes, Write};
+
+use crate::Result;
+
+/// Key/value pairs file format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// A single JSON object mapping keys to values, one pair per line.
+    Json,
+    /// CSV with a `key,value` header, quoted as in RFC 4180.
+    Csv,
+}
+
+fn write_csv_field<W: Write>(writer: &mut W, value: &str) -> Result<()> {
+    if value.contains([',', '"', '\n', '\r']) {
+        write!(writer, "\"{}\"", value.replace('"', "\"\""))?;
+    } else {
+        writer.write_all(value.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes the key/value pairs to `writer` one by one. Returns the number of written pairs.
+pub fn write_records<W, I>(writer: &mut W, format: Format, records: I) -> Result<usize>
+where
+    W: Write,
+    I: Iterator<Item = Result<(String, String)>>,
+{
+    let mut count = 0;
+    match format {
+        Format::Json => writer.write_all(b"{")?,
+        Format::Csv => writer.write_all(b"key,value\n")?,
+    }
+    for record in records {
+        let (key, value) = record?;
+        match format {
+            Format::Json => {
+                writer.write_all(if count == 0 { b"\n  " } else { b",\n  " })?;
+                serde_json::to_writer(&mut *writer, &key)?;
+                writer.write_all(b": ")?;
+                serde_json::to_writer(&mut *writer, &value)?;
+            },
+            Format::Csv => {
+                write_csv_field(writer, &key)?;
+                writer.write_all(b",")?;
+                write_csv_field(writer, &value)?;
+                writer.write_all(b"\n")?;
+            },
+        }
+        count += 1;
+    }
+    if format == Format::Json {
+        writer.write_all(b"\n}\n")?;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Byte stream with a single byte lookahead.
+struct ByteReader<R: BufRead> {
+    bytes: Bytes<R>,
+    peeked: Option<u8>,
+}
+
+impl<R: BufRead> ByteReader<R> {
+    fn new(reader: R) -> Self {
+        ByteReader { bytes: reader.bytes(), peeked: None }
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        if self.peeked.is_none() {
+            self.peeked = self.bytes.next().transpose()?;
+        }
+        Ok(self.peeked)
+    }
+
+    fn next(&mut self) -> Result<Option<u8>> {
+        let byte = self.peek()?;
+        self.peeked = None;
+        Ok(byte)
+    }
+}
+
+/// Passes the entries of a JSON object to the record callback as they are parsed.
+struct RecordsVisitor<'a> {
+    on_record: &'a mut dyn FnMut(String, String) -> Result<()>,
+}
+
+impl<'de> serde::de::Visitor<'de> for RecordsVisitor<'_> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an object mapping keys to string values")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> std::result::Result<usize, A::Error> {
+        let mut count = 0;
+        while let Some((key, value)) = map.next_entry::<String, String>()? {
+            (self.on_record)(key, value).map_err(serde::de::Error::custom)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+fn read_json<R: BufRead>(reader: R, on_record: &mut dyn FnMut(String, String) -> Result<()>) -> Result<usize> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let count = serde::Deserializer::deserialize_map(&mut deserializer, RecordsVisitor { on_record })?;
+    deserializer.end()?;
+    Ok(count)
+}
+
+/// Reads a single CSV record. Returns `None` at the end of the file.
+fn read_csv_record<R: BufRead>(reader: &mut ByteReader<R>) -> Result<Option<Vec<String>>> {
+    if reader.peek()?.is_none() {
+        return Ok(None);
+    }
+
+    let mut fields = Vec::new();
+    let mut field = Vec::new();
+    let mut quoted = false;
+    loop {
+        match (reader.next()?, quoted) {
+            (Some(b'"'), true) => {
+                if reader.peek()? == Some(b'"') {
+                    reader.next()?;
+                    field.push(b'"');
+                } else {
+                    quoted = false;
+                }
+            },
+            (Some(b'"'), false) if field.is_empty() => quoted = true,
+            (Some(byte), true) => field.push(byte),
+            (None, true) => return Err(Box::from("Unterminated quoted CSV field")),
+            (Some(b','), false) => fields.push(String::from_utf8(std::mem::take(&mut field))?),
+            (Some(b'\r'), false) if reader.peek()? == Some(b'\n') => {},
+            (Some(b'\n'), false) | (None, false) => break,
+            (Some(byte), false) => field.push(byte),
+        }
+    }
+    fields.push(String::from_utf8(field)?);
+    Ok(Some(fields))
+}
+
+fn read_csv<R: BufRead>(reader: &mut ByteReader<R>, on_record: &mut dyn FnMut(String, String) -> Result<()>) -> Result<usize> {
+    match read_csv_record(reader)? {
+        Some(header) if header == ["key", "value"] => {},
+        _ => return Err(Box::from("Expected a key,value CSV header")),
+    }
+
+    let mut count = 0;
+    while let Some(mut fields) = read_csv_record(reader)? {
+        if fields.len() == 1 && fields[0].is_empty() {
+            continue;
+        }
+        if fields.len() != 2 {
+            return Err(Box::from(format!("Expected 2 CSV fields in record {}, got {}", count + 1, fields.len())));
+        }
+        let value = fields.pop().unwrap();
+        let key = fields.pop().unwrap();
+        on_record(key, value)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Reads the key/value pairs from `reader` and passes them to `on_record` one by one.
+/// Returns the number of read pairs.
+pub fn read_records<R: BufRead>(
+    reader: R,
+    format: Format,
+    mut on_record: impl FnMut(String, String) -> Result<()>,
+) -> Result<usize> {
+    match format {
+        Format::Json => read_json(reader, &mut on_record),
+        Format::Csv => read_csv(&mut ByteReader::new(reader), &mut on_record),
+    }
+}
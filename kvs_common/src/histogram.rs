@@ -0,0 +1,117 @@
+/// Number of the bits of a value kept exactly, so the recorded values are within 1/1024 of the actual ones,
+/// i.e. 3 significant decimal digits.
+const SUB_BUCKET_BITS: u32 = 11;
+const SUB_BUCKET_HALF_COUNT: usize = 1 << (SUB_BUCKET_BITS - 1);
+
+/// A high dynamic range histogram of non-negative values, e.g. latencies in microseconds.
+/// The values are counted in log-linear buckets: the small values exactly and the larger ones with
+/// a bounded relative error, so the percentiles of any range of values take a few kilobytes of counters.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total_count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+/// Returns the counter index of a value.
+fn index_of(value: u64) -> usize {
+    if value < 2 * SUB_BUCKET_HALF_COUNT as u64 {
+        return value as usize;
+    }
+    let shift = 63 - value.leading_zeros() - (SUB_BUCKET_BITS - 1);
+    shift as usize * SUB_BUCKET_HALF_COUNT + (value >> shift) as usize
+}
+
+/// Returns the highest value counted by the counter.
+fn highest_value_of(index: usize) -> u64 {
+    if index < 2 * SUB_BUCKET_HALF_COUNT {
+        return index as u64;
+    }
+    let shift = (index / SUB_BUCKET_HALF_COUNT - 1) as u32;
+    let sub_bucket = (index - shift as usize * SUB_BUCKET_HALF_COUNT) as u64;
+    // The bound of the top counter overflows to 0, so it ends at u64::MAX.
+    ((sub_bucket + 1) << shift).wrapping_sub(1)
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a single value.
+    pub fn record(&mut self, value: u64) {
+        let index = index_of(value);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.min = if self.total_count == 0 { value } else { self.min.min(value) };
+        self.max = self.max.max(value);
+        self.total_count += 1;
+        self.sum += value as u128;
+    }
+
+    /// Adds all the values counted by `other`.
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.total_count == 0 {
+            return;
+        }
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other_count;
+        }
+        self.min = if self.total_count == 0 { other.min } else { self.min.min(other.min) };
+        self.max = self.max.max(other.max);
+        self.total_count += other.total_count;
+        self.sum += other.sum;
+    }
+
+    /// Number of the counted values.
+    pub fn len(&self) -> u64 {
+        self.total_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_count == 0
+    }
+
+    /// The exact min value, 0 if empty.
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    /// The exact max value, 0 if empty.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// The exact mean value, 0 if empty.
+    pub fn mean(&self) -> f64 {
+        match self.total_count {
+            0 => 0.0,
+            count => self.sum as f64 / count as f64,
+        }
+    }
+
+    /// Returns the value at or below which `percentile` percent of the values are, 0 if empty.
+    /// The value is the upper bound of its bucket, capped by the max value.
+    pub fn value_at_percentile(&self, percentile: f64) -> u64 {
+        if self.total_count == 0 {
+            return 0;
+        }
+        // Rounded rather than ceiled as in HdrHistogram, so the float error does not skip to the next value.
+        let rank = ((percentile / 100.0 * self.total_count as f64).round() as u64).clamp(1, self.total_count);
+        let mut seen_count = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen_count += count;
+            if seen_count >= rank {
+                return highest_value_of(index).min(self.max);
+            }
+        }
+        self.max
+    }
+}
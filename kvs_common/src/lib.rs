@@ -0,0 +1,6 @@
+pub mod config;
+pub mod engine;
+pub mod export;
+pub mod histogram;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
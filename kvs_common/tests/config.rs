@@ -0,0 +1,28 @@
+use std::ffi::OsString;
+
+use kvs_common::Result;
+use kvs_common::config;
+
+// The options should be converted into the command line arguments, the false ones omitted.
+#[test]
+fn config_args() -> Result<()> {
+    let temp_dir = tempfile::TempDir::new().expect("unable to create temporary working directory");
+    let config_path = temp_dir.path().join("server.toml");
+    std::fs::write(
+        &config_path,
+        "segment_size = 1000\nengine = \"kvs\"\nrecord_sequence = true\ndaemonize = false\n\
+         store = [\"a=/data/a\", \"b=/data/b\"]\n",
+    )?;
+    let args: Vec<OsString> = ["--segment-size", "1000", "--engine", "kvs", "--record-sequence",
+        "--store", "a=/data/a", "--store", "b=/data/b"]
+        .iter()
+        .map(OsString::from)
+        .collect();
+    assert_eq!(config::config_args(&config_path)?, args);
+
+    std::fs::write(&config_path, "config = \"other.toml\"\n")?;
+    assert!(config::config_args(&config_path).is_err());
+    std::fs::write(&config_path, "[section]\nkey = 1\n")?;
+    assert!(config::config_args(&config_path).is_err());
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use tempfile::TempDir;
+
+use kvs_common::Result;
+use kvs_common::engine::{check_engine, ENGINE_FILE_NAME};
+
+// A new directory should be created and marked with its engine, which is the only one to open it afterwards.
+#[test]
+fn engine_marker() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().join("storage");
+    check_engine(&path, "kvs")?;
+    assert_eq!(std::fs::read_to_string(path.join(ENGINE_FILE_NAME))?, "kvs");
+
+    check_engine(&path, "kvs")?;
+    let err = check_engine(&path, "sled").unwrap_err();
+    assert!(err.to_string().contains("created by the kvs engine"), "{}", err);
+    Ok(())
+}
+
+// A directory without the engine file should be marked only if it holds no files of another engine.
+#[test]
+fn legacy_engine_detection() -> Result<()> {
+    let sled_dir = TempDir::new()?;
+    std::fs::write(sled_dir.path().join("conf"), "")?;
+    std::fs::write(sled_dir.path().join("db"), "")?;
+    let err = check_engine(sled_dir.path(), "kvs").unwrap_err();
+    assert!(err.to_string().contains("created by the sled engine"), "{}", err);
+    assert!(!sled_dir.path().join(ENGINE_FILE_NAME).exists());
+    check_engine(sled_dir.path(), "sled")?;
+    assert_eq!(std::fs::read_to_string(sled_dir.path().join(ENGINE_FILE_NAME))?, "sled");
+
+    let kvs_dir = TempDir::new()?;
+    std::fs::write(kvs_dir.path().join("kv_1.log"), "")?;
+    let err = check_engine(kvs_dir.path(), "sled").unwrap_err();
+    assert!(err.to_string().contains("created by the kvs engine"), "{}", err);
+    assert!(!kvs_dir.path().join(ENGINE_FILE_NAME).exists());
+    check_engine(kvs_dir.path(), "kvs")?;
+
+    // Unrelated files don't belong to an engine.
+    let other_dir = TempDir::new()?;
+    std::fs::write(other_dir.path().join("kv_backup.log"), "")?;
+    std::fs::write(other_dir.path().join("notes.txt"), "")?;
+    check_engine(other_dir.path(), "sled")?;
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use kvs_common::Result;
+use kvs_common::export::{self, Format};
+
+/// Values needing escaping in both formats.
+const RECORDS: [(&str, &str); 4] = [
+    ("key1", "value1"),
+    ("key,2", "value \"quoted\", with\nnew line"),
+    ("key3", "unicode \u{1F600} and \\ tab\t"),
+    ("", ""),
+];
+
+fn read_all(data: &[u8], format: Format) -> Result<Vec<(String, String)>> {
+    let mut records = Vec::new();
+    export::read_records(data, format, |key, value| {
+        records.push((key, value));
+        Ok(())
+    })?;
+    Ok(records)
+}
+
+// The written pairs should be read back unchanged in both formats.
+#[test]
+fn write_read_records() -> Result<()> {
+    for format in [Format::Json, Format::Csv] {
+        let mut data = Vec::new();
+        let records = RECORDS.iter().map(|(key, value)| Ok((key.to_string(), value.to_string())));
+        assert_eq!(export::write_records(&mut data, format, records)?, RECORDS.len());
+
+        let expected: Vec<(String, String)> = RECORDS.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(read_all(&data, format)?, expected);
+    }
+    Ok(())
+}
+
+// An empty keyspace should be written and read as an empty file of the format.
+#[test]
+fn write_read_empty() -> Result<()> {
+    for format in [Format::Json, Format::Csv] {
+        let mut data = Vec::new();
+        assert_eq!(export::write_records(&mut data, format, std::iter::empty())?, 0);
+        assert!(read_all(&data, format)?.is_empty());
+    }
+    Ok(())
+}
+
+// Malformed files should fail to read.
+#[test]
+fn read_invalid() {
+    assert!(read_all(b"{\"key1\": \"value1\", \"key2\"}", Format::Json).is_err());
+    assert!(read_all(b"{\"key1\": 1}", Format::Json).is_err());
+    assert!(read_all(b"{\"key1\": \"value1\"} trailing", Format::Json).is_err());
+    assert!(read_all(b"name,value\nkey1,value1\n", Format::Csv).is_err());
+    assert!(read_all(b"key,value\nkey1,value1,extra\n", Format::Csv).is_err());
+    assert!(read_all(b"key,value\n\"key1,value1\n", Format::Csv).is_err());
+}
+
+// A failure of the record callback should stop the reading.
+#[test]
+fn read_callback_error() {
+    let mut count = 0;
+    let result = export::read_records(&b"{\"key1\": \"value1\", \"key2\": \"value2\"}"[..], Format::Json, |_, _| {
+        count += 1;
+        Err(Box::from("storage is full"))
+    });
+    assert!(result.unwrap_err().to_string().contains("storage is full"));
+    assert_eq!(count, 1);
+}
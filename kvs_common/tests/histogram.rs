@@ -0,0 +1,32 @@
+use kvs_common::histogram::Histogram;
+
+// Histogram percentiles should be exact for small values and within 0.1% for large ones.
+#[test]
+fn histogram_percentiles() {
+    let mut histogram = Histogram::new();
+    assert_eq!(histogram.value_at_percentile(99.0), 0);
+
+    for value in 1..=1000 {
+        histogram.record(value);
+    }
+    assert_eq!(histogram.len(), 1000);
+    assert_eq!(histogram.min(), 1);
+    assert_eq!(histogram.max(), 1000);
+    assert_eq!(histogram.mean(), 500.5);
+    assert_eq!(histogram.value_at_percentile(50.0), 500);
+    assert_eq!(histogram.value_at_percentile(99.9), 999);
+    assert_eq!(histogram.value_at_percentile(100.0), 1000);
+
+    let mut large = Histogram::new();
+    large.record(1_000_000);
+    large.record(u64::MAX);
+    let value = large.value_at_percentile(50.0);
+    assert!((1_000_000..=1_001_000).contains(&value), "{}", value);
+    assert_eq!(large.value_at_percentile(100.0), u64::MAX);
+
+    histogram.merge(&large);
+    assert_eq!(histogram.len(), 1002);
+    assert_eq!(histogram.min(), 1);
+    assert_eq!(histogram.max(), u64::MAX);
+    assert_eq!(histogram.value_at_percentile(50.0), 501);
+}
@@ -0,0 +1,155 @@
+use std::path::Path;
+use std::time::Duration;
+
+use kvs_sync::storage::KVStorage;
+use kvs_threaded::storage::KvStorage;
+
+use crate::{Engine, Result};
+
+/// Number of attempts to open a sled database still locked by a dropped handle.
+const SLED_OPEN_ATTEMPTS: u32 = 10;
+
+/// Opens a sled storage with `open`, retrying with a growing delay while the database lock is held.
+/// Sled releases the lock only once its background flusher exits, which may happen a bit after the last handle
+/// is dropped, so a storage reopened right away can fail to lock its own database.
+fn open_sled<E>(open: impl Fn() -> Result<E>) -> Result<E> {
+    let mut delay = Duration::from_millis(10);
+    for _ in 1..SLED_OPEN_ATTEMPTS {
+        match open() {
+            Err(err) if err.to_string().contains("could not acquire lock") => {
+                std::thread::sleep(delay);
+                delay *= 2;
+            },
+            result => return result,
+        }
+    }
+    open()
+}
+
+/// Log storage of the single-threaded server.
+impl Engine for kvs_sync::storage::KvLogStorage {
+    fn open(path: &Path) -> Result<Self> {
+        kvs_sync::storage::KvLogStorage::open(path)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        KVStorage::set(self, key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        KVStorage::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<bool> {
+        KVStorage::remove(self, key)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        KVStorage::reset(self)
+    }
+}
+
+/// Sled storage of the single-threaded server.
+impl Engine for kvs_sync::storage::SledStorage {
+    fn open(path: &Path) -> Result<Self> {
+        open_sled(|| kvs_sync::storage::SledStorage::open(path))
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        KVStorage::set(self, key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        KVStorage::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<bool> {
+        KVStorage::remove(self, key)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        KVStorage::reset(self)
+    }
+}
+
+/// In-memory storage of the single-threaded server. It keeps no files, so the path is ignored.
+impl Engine for kvs_sync::storage::MemStorage {
+    const DURABLE: bool = false;
+
+    fn open(_path: &Path) -> Result<Self> {
+        Ok(kvs_sync::storage::MemStorage::new())
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        KVStorage::set(self, key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        KVStorage::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<bool> {
+        KVStorage::remove(self, key)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        KVStorage::reset(self)
+    }
+}
+
+/// Log storage of the multithreaded server. Its handles share the index and the log files.
+impl Engine for kvs_threaded::KvLogStorage {
+    fn open(path: &Path) -> Result<Self> {
+        kvs_threaded::KvLogStorage::open(path)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        kvs_threaded::KvLogStorage::set(self, key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        kvs_threaded::KvLogStorage::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<bool> {
+        kvs_threaded::KvLogStorage::remove(self, key)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        kvs_threaded::KvLogStorage::reset(self)
+    }
+
+    fn try_clone(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+/// Sled storage of the multithreaded server. Its handles share the database.
+impl Engine for kvs_threaded::storage::SledStorage {
+    fn open(path: &Path) -> Result<Self> {
+        open_sled(|| kvs_threaded::storage::SledStorage::open(path))
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        KvStorage::set_bytes(self, key, value.into_bytes())
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        match KvStorage::get_bytes(self, key)? {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&mut self, key: String) -> Result<bool> {
+        KvStorage::remove(self, key)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        KvStorage::reset(self)
+    }
+
+    fn try_clone(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::Path;
+
+pub mod engines;
+pub mod suite;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// A storage engine validated by the conformance suite.
+/// The methods follow the semantics of the `KVStorage` trait.
+pub trait Engine: Send + Sized + 'static {
+    /// Whether the data survives reopening the storage. A storage of a non-durable engine is empty once reopened,
+    /// and its path is not checked.
+    const DURABLE: bool = true;
+
+    /// Opens a directory as a storage. Creates the directory if it doesn't exist.
+    fn open(path: &Path) -> Result<Self>;
+
+    /// Set key `key` to value `value`.
+    fn set(&mut self, key: String, value: String) -> Result<()>;
+
+    /// Gets value with the key `key`. Returns `None` if the key doesn't exist in the storage.
+    fn get(&self, key: String) -> Result<Option<String>>;
+
+    /// Removes key `key` from the storage.
+    /// Returns `true` if the key existed.
+    fn remove(&mut self, key: String) -> Result<bool>;
+
+    /// Removes all records in the storage.
+    fn reset(&mut self) -> Result<()>;
+
+    /// Returns another handle to the same storage if the engine supports concurrent access.
+    /// Engines without concurrent handles are shared between threads behind a mutex.
+    fn try_clone(&self) -> Option<Self> {
+        None
+    }
+}
+
+/// Generates a test for each conformance check of the engine type `$engine` in a module `$name`.
+#[macro_export]
+macro_rules! conformance_tests {
+    ($name:ident, $engine:ty) => {
+        mod $name {
+            #[test]
+            fn persistence_across_reopen() -> $crate::Result<()> {
+                $crate::suite::persistence_across_reopen::<$engine>()
+            }
+
+            #[test]
+            fn overwrite_and_remove() -> $crate::Result<()> {
+                $crate::suite::overwrite_and_remove::<$engine>()
+            }
+
+            #[test]
+            fn concurrent_access() -> $crate::Result<()> {
+                $crate::suite::concurrent_access::<$engine>()
+            }
+
+            #[test]
+            fn compaction_invariants() -> $crate::Result<()> {
+                $crate::suite::compaction_invariants::<$engine>()
+            }
+
+            #[test]
+            fn reset_semantics() -> $crate::Result<()> {
+                $crate::suite::reset_semantics::<$engine>()
+            }
+
+            #[test]
+            fn error_taxonomy() -> $crate::Result<()> {
+                $crate::suite::error_taxonomy::<$engine>()
+            }
+        }
+    };
+}
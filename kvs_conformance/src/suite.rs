@@ -0,0 +1,215 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tempfile::TempDir;
+
+use crate::{Engine, Result};
+
+const THREADS_COUNT: usize = 4;
+const KEYS_PER_THREAD: usize = 100;
+/// Overwrites of this size span several log segments, so the log engines compact at least once.
+const COMPACTION_VALUE_SIZE: usize = 800_000;
+const COMPACTION_ROUNDS: usize = 6;
+
+fn check(condition: bool, message: &str) -> Result<()> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Box::from(message.to_owned()))
+    }
+}
+
+fn check_value<E: Engine>(engine: &E, key: &str, expected: Option<&str>) -> Result<()> {
+    let value = engine.get(key.to_owned())?;
+    if value.as_deref() == expected {
+        Ok(())
+    } else {
+        Err(Box::from(format!("Key {}: expected {:?}, got {:?}", key, expected, value)))
+    }
+}
+
+/// A storage handle used by a single thread.
+enum Handle<E: Engine> {
+    Owned(E),
+    Shared(Arc<Mutex<E>>),
+}
+
+impl<E: Engine> Handle<E> {
+    fn with<T>(&mut self, f: impl FnOnce(&mut E) -> Result<T>) -> Result<T> {
+        match self {
+            Handle::Owned(engine) => f(engine),
+            Handle::Shared(engine) => f(&mut engine.lock().unwrap_or_else(|e| e.into_inner())),
+        }
+    }
+}
+
+/// Sets the keys of a thread, reads them back and removes every second key.
+fn write_thread_keys<E: Engine>(handle: &mut Handle<E>, thread_idx: usize) -> Result<()> {
+    for key_idx in 0..KEYS_PER_THREAD {
+        let key = format!("key_{}_{}", thread_idx, key_idx);
+        let value = format!("value_{}_{}", thread_idx, key_idx);
+        handle.with(|engine| engine.set(key.clone(), value.clone()))?;
+        handle.with(|engine| check_value(engine, &key, Some(&value)))?;
+        if key_idx % 2 == 0 {
+            let existed = handle.with(|engine| engine.remove(key.clone()))?;
+            check(existed, "Removing an existing key should return true")?;
+        }
+    }
+    Ok(())
+}
+
+/// Written values are read back after the storage is reopened, a non-durable storage is empty instead.
+pub fn persistence_across_reopen<E: Engine>() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut engine = E::open(temp_dir.path())?;
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.set("key2".to_owned(), "value2".to_owned())?;
+    engine.set("key2".to_owned(), "value3".to_owned())?;
+    engine.set("key3".to_owned(), "value4".to_owned())?;
+    engine.remove("key3".to_owned())?;
+    drop(engine);
+
+    let engine = E::open(temp_dir.path())?;
+    check_value(&engine, "key1", Some("value1").filter(|_| E::DURABLE))?;
+    check_value(&engine, "key2", Some("value3").filter(|_| E::DURABLE))?;
+    check_value(&engine, "key3", None)?;
+    Ok(())
+}
+
+/// The latest write wins and `remove` reports whether the key existed.
+pub fn overwrite_and_remove<E: Engine>() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut engine = E::open(temp_dir.path())?;
+
+    engine.set("key".to_owned(), "value1".to_owned())?;
+    engine.set("key".to_owned(), "value2".to_owned())?;
+    check_value(&engine, "key", Some("value2"))?;
+
+    check(engine.remove("key".to_owned())?, "Removing an existing key should return true")?;
+    check(!engine.remove("key".to_owned())?, "Removing a removed key should return false")?;
+    check_value(&engine, "key", None)?;
+
+    engine.set("key".to_owned(), "value3".to_owned())?;
+    check_value(&engine, "key", Some("value3"))?;
+
+    // Empty keys and values are regular records.
+    engine.set("".to_owned(), "".to_owned())?;
+    check_value(&engine, "", Some(""))?;
+    Ok(())
+}
+
+/// Concurrent writers and readers see their own writes, and the final state is complete.
+pub fn concurrent_access<E: Engine>() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let engine = E::open(temp_dir.path())?;
+    let main_handle = match engine.try_clone() {
+        Some(_) => Handle::Owned(engine),
+        None => Handle::Shared(Arc::new(Mutex::new(engine))),
+    };
+
+    let mut threads = Vec::new();
+    for thread_idx in 0..THREADS_COUNT {
+        let mut handle = match &main_handle {
+            Handle::Owned(engine) => Handle::Owned(engine.try_clone().unwrap()),
+            Handle::Shared(engine) => Handle::Shared(engine.clone()),
+        };
+        threads.push(std::thread::spawn(move || -> std::result::Result<(), String> {
+            write_thread_keys(&mut handle, thread_idx).map_err(|e| e.to_string())
+        }));
+    }
+    for thread in threads {
+        thread.join().map_err(|_| "Storage thread panicked")??;
+    }
+
+    let mut main_handle = main_handle;
+    for thread_idx in 0..THREADS_COUNT {
+        for key_idx in 0..KEYS_PER_THREAD {
+            let key = format!("key_{}_{}", thread_idx, key_idx);
+            let value = format!("value_{}_{}", thread_idx, key_idx);
+            let expected = if key_idx % 2 == 0 { None } else { Some(value.as_str()) };
+            main_handle.with(|engine| check_value(engine, &key, expected))?;
+        }
+    }
+    Ok(())
+}
+
+/// Compaction never loses the latest values and never resurrects removed keys,
+/// both while it runs and after the storage is reopened.
+pub fn compaction_invariants<E: Engine>() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut engine = E::open(temp_dir.path())?;
+
+    engine.set("removed".to_owned(), "value".to_owned())?;
+    engine.set("stable".to_owned(), "value".to_owned())?;
+    let mut latest = String::new();
+    for round in 0..COMPACTION_ROUNDS {
+        latest = round.to_string().repeat(COMPACTION_VALUE_SIZE);
+        engine.set("overwritten".to_owned(), latest.clone())?;
+        if round == 1 {
+            engine.remove("removed".to_owned())?;
+        }
+    }
+
+    // The background compaction may still be running, the state should be the same at any moment.
+    for _ in 0..5 {
+        check_value(&engine, "overwritten", Some(&latest))?;
+        check_value(&engine, "stable", Some("value"))?;
+        check_value(&engine, "removed", None)?;
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    if !E::DURABLE {
+        return Ok(());
+    }
+
+    drop(engine);
+    let engine = E::open(temp_dir.path())?;
+    check_value(&engine, "overwritten", Some(&latest))?;
+    check_value(&engine, "stable", Some("value"))?;
+    check_value(&engine, "removed", None)?;
+    Ok(())
+}
+
+/// Reset removes all the records, persistently, and the storage is usable afterwards.
+pub fn reset_semantics<E: Engine>() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let mut engine = E::open(temp_dir.path())?;
+
+    // Reset of an empty storage is not an error.
+    engine.reset()?;
+
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.set("key2".to_owned(), "value2".to_owned())?;
+    engine.reset()?;
+    check_value(&engine, "key1", None)?;
+    check_value(&engine, "key2", None)?;
+    check(!engine.remove("key1".to_owned())?, "Removing a key after reset should return false")?;
+
+    engine.set("key3".to_owned(), "value3".to_owned())?;
+    drop(engine);
+
+    let engine = E::open(temp_dir.path())?;
+    check_value(&engine, "key1", None)?;
+    check_value(&engine, "key2", None)?;
+    check_value(&engine, "key3", Some("value3").filter(|_| E::DURABLE))?;
+    Ok(())
+}
+
+/// Missing keys are regular results, not errors. Invalid storage paths are errors, not panics.
+pub fn error_taxonomy<E: Engine>() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+
+    // A missing directory is created.
+    let mut engine = E::open(temp_dir.path().join("new").as_path())?;
+    check_value(&engine, "missing", None)?;
+    check(!engine.remove("missing".to_owned())?, "Removing a missing key should return false")?;
+    drop(engine);
+    if !E::DURABLE {
+        return Ok(());
+    }
+
+    // A regular file is not a storage.
+    let file_path = temp_dir.path().join("file");
+    std::fs::write(&file_path, b"not a storage")?;
+    check(E::open(&file_path).is_err(), "Opening a regular file should fail")?;
+    Ok(())
+}
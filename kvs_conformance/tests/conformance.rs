@@ -0,0 +1,5 @@
+kvs_conformance::conformance_tests!(sync_kv_log, kvs_sync::storage::KvLogStorage);
+kvs_conformance::conformance_tests!(sync_sled, kvs_sync::storage::SledStorage);
+kvs_conformance::conformance_tests!(sync_memory, kvs_sync::storage::MemStorage);
+kvs_conformance::conformance_tests!(threaded_kv_log, kvs_threaded::KvLogStorage);
+kvs_conformance::conformance_tests!(threaded_sled, kvs_threaded::storage::SledStorage);